@@ -31,12 +31,24 @@ class RustInterface private constructor() {
         object Gemma3 : ModelType(1)
     }
 
-    // Data class для конфигурации генерации текста
+    // Data class для конфигурации генерации текста;
+    // promptLookup — спекулятивное декодирование с черновиками из контекста промпта
     data class GenerationConfig(
         val maxTokens: Int = 512,
         val temperature: Float = 0.7f,
         val topP: Float = 0.9f,
-        val repeatPenalty: Float = 1.1f
+        val repeatPenalty: Float = 1.1f,
+        // Зерно выборки
+        val seed: Long = 299792458L,
+        val promptLookup: Boolean = false,
+        // DRY-штраф за повторы: dryMultiplier = 0 отключает; drySequenceBreakers — JSON-массив строк,
+        // null или пустая строка — разделители по умолчанию ["\n", ":", "\"", "*"]
+        val dryMultiplier: Float = 0f,
+        val dryBase: Float = 1.75f,
+        val dryAllowedLength: Int = 2,
+        val drySequenceBreakers: String? = null,
+        // Детектор циклов: "off" (или null), "stop" (остановка, getFinishReason вернёт "loop") или "escalate"
+        val loopDetection: String? = "off"
     )
 
     // Интерфейс для обратных вызовов при потоковой генерации
@@ -46,14 +58,136 @@ class RustInterface private constructor() {
         fun onError(error: String)
     }
 
+    // Интерфейс для отчёта о ходе загрузки модели (phase: download/checksum/header/tokenizer/tensors/convert/done)
+    interface LoadProgressCallback {
+        fun onProgress(phase: String, bytesLoaded: Long, bytesTotal: Long, tensorsLoaded: Int, tensorsTotal: Int)
+    }
+
+    // Интерфейс для результата горячей замены модели (modelType: "Qwen3"/"Gemma3")
+    interface SwapCallback {
+        fun onSwapped(modelType: String)
+        fun onFailed(error: String)
+    }
+
+    // Интерфейс для пакетной генерации (index — номер промпта во входном списке);
+    // onSequenceComplete вызывается, как только завершится эта последовательность
+    interface BatchCallback {
+        fun onToken(index: Int, token: String)
+        fun onSequenceComplete(index: Int, text: String)
+    }
+
     // Native method declarations
     external fun loadModel(modelType: Int, variant: String)
     external fun loadModelFromPath(modelPath: String)
+    external fun loadModelWithProgress(modelType: Int, variant: String, callback: LoadProgressCallback?)
+    external fun loadModelFromPathWithProgress(modelPath: String, callback: LoadProgressCallback?)
+    // Загрузка из дескриптора (ParcelFileDescriptor/AssetFileDescriptor) без копирования;
+    // length < 0 — до конца файла. Дескриптор дублируется, исходный можно закрыть
+    external fun loadModelFromFd(fd: Int, offset: Long, length: Long)
+    // Неквантованная модель Hugging Face: директория с config.json, tokenizer.json и *.safetensors;
+    // dtype — "f16", "bf16" или "f32", null — torch_dtype из config.json
+    external fun loadSafetensorsModel(modelDir: String, dtype: String?, callback: LoadProgressCallback?)
+    external fun cancelModelLoad()
+
+    // Загрузка из кеша Hugging Face Hub (models--org--name/snapshots/<rev>/);
+    // revision и fileName необязательны
+    external fun loadModelFromHfCache(repoId: String, revision: String?, fileName: String?, callback: LoadProgressCallback?)
+
+    // Явный tokenizer.json (или его директория) вместо найденного рядом с моделью; null — автовыбор
+    external fun setTokenizerOverride(tokenizerPath: String?)
+
+    // Подключение хранилища Ollama ("ollama") или LM Studio ("lm_studio"); модели используются без копирования
+    external fun addModelStore(kind: String, path: String)
+
     external fun switchModel(modelType: Int, variant: String)
-    external fun generateText(prompt: String, config: GenerationConfig, callback: StreamCallback?): String
+    // config = null — параметры активной персоны или заданные ранее
+    external fun generateText(prompt: String, config: GenerationConfig?, callback: StreamCallback?): String
+    // Варианты ответа: mode — JSON {"sample": {"n": 3}} или {"beam": {"width": 4, "length_penalty": 1.0}};
+    // возвращает JSON [{"text", "tokens", "logprob", "score", "finished"}] по убыванию score
+    external fun generateCandidates(prompt: String, mode: String): String
+    // Пакетная генерация: prompts — JSON-массив строк, maxBatchSize <= 0 — размер по умолчанию (8);
+    // i-й промпт использует seed + i; возвращает JSON-массив ответов в порядке промптов
+    external fun generateBatch(prompts: String, maxBatchSize: Int, callback: BatchCallback?): String
     external fun unloadModel()
+
+    // Пул моделей в памяти: идентификатор — канонический путь модели (см. listResidentModels);
+    // давно не использованные модели вытесняются при превышении бюджета RAM или числа моделей
+    external fun listResidentModels(): String
+    external fun selectModel(modelId: String)
+    external fun unloadResidentModel(modelId: String)
+    external fun setMaxResidentModels(maxModels: Int)
+    external fun generateTextWithModel(modelId: String, prompt: String, callback: StreamCallback?): String
+    // Горячая замена: target — JSON {"path"|"stored"|"resident": ...} или {"safetensors": {"dir", "dtype"}},
+    // policy — "drain" (дождаться начатых генераций, не дольше минуты) или "cancel"; возвращается сразу
+    external fun hotSwapModel(target: String, policy: String, callback: LoadProgressCallback?, swapCallback: SwapCallback?)
+
+    // Спекулятивное декодирование: черновая GGUF модель с тем же токенизатором предлагает
+    // draftTokens токенов за шаг; getSpeculativeStats — JSON {"drafted", "accepted",
+    // "target_passes", "generated", "acceptance_rate"} последней генерации или "null"
+    external fun setDraftModel(modelPath: String, draftTokens: Int, callback: LoadProgressCallback?)
+    external fun clearDraftModel()
+    external fun getSpeculativeStats(): String
+    // Причина завершения последней генерации: "eos", "length", "cancelled", "loop" или ""
+    external fun getFinishReason(): String
+
     external fun stopGeneration()
 
+    // Токенизатор загруженной модели: счётчик токенов и предпросмотр промпта.
+    // renderPrompt принимает JSON [{"role", "content"}] и возвращает JSON {"prompt", "token_count"}
+    external fun countTokens(text: String): Int
+    external fun tokenize(text: String): IntArray
+    external fun detokenize(ids: IntArray): String
+    external fun renderPrompt(messagesJson: String): String
+
+    // Каталог моделей (JSON с архитектурой, размером, квантованием и т.д.)
+    external fun listModels(): String
+    external fun getModelInfo(modelPath: String): String
+
+    // Проверка целостности GGUF (JSON отчёт); SHA-256 сверяется, если хеш передан
+    // или найден рядом с моделью. У разбитой модели проверяется каждая часть (shards).
+    // Отмена — cancelVerifyModel()
+    external fun verifyModel(modelPath: String, expectedSha256: String?, callback: LoadProgressCallback?): String
+    external fun cancelVerifyModel()
+
+    // Хранилище моделей: идентификаторы вида "qwen3/0.6b" (сегменты из [A-Za-z0-9._-])
+    external fun loadStoredModel(modelId: String, callback: LoadProgressCallback?)
+    external fun importModel(sourcePath: String, modelId: String)
+    external fun renameModel(fromId: String, toId: String)
+    // Удаление загруженной модели запрещено
+    external fun deleteModel(modelId: String)
+
+    // Загрузка из Hugging Face (resolve/<revision>/<fileName>) в хранилище под modelId с докачкой
+    // и проверкой SHA-256; возвращает JSON отчёт. Этапы прогресса: download, checksum, done
+    external fun downloadModel(repoId: String, revision: String?, fileName: String, modelId: String, expectedSha256: String?, callback: LoadProgressCallback?): String
+    external fun cancelDownload()
+    // JSON массив базовых адресов зеркал, например ["https://hf-mirror.com"]; [] — huggingface.co
+    external fun setDownloadMirrors(mirrorsJson: String)
+    // Конвертация GGUF или директории safetensors в GGUF под modelId; план — пресет llama.cpp
+    // с типами для классов тензоров, например "q4_k_m,embedding=q8_0". Этапы: header, convert, done
+    external fun convertModel(inputPath: String, modelId: String, plan: String, callback: LoadProgressCallback?): String
+    external fun cancelConversion()
+    // Метаданные GGUF: JSON список {key, value_type, value, array_len}; правка — JSON объект
+    // {"ключ": значение}, null удаляет ключ. Загруженную модель, модели Ollama, LM Studio
+    // и кеша Hugging Face изменить нельзя
+    external fun getModelMetadata(modelPath: String): String
+    external fun editModelMetadata(modelPath: String, editsJson: String)
+    external fun setModelChatTemplate(modelPath: String, template: String)
+    external fun setModelDisplayName(modelPath: String, name: String)
+
+    // Оценка памяти и бюджет RAM для всех моделей, включая черновую (budgetBytes <= 0
+    // отключает проверку)
+    external fun setMemoryBudget(budgetBytes: Long)
+    external fun estimateModelMemory(modelPath: String): String
+    external fun recommendQuantization(candidatesJson: String, availableRamBytes: Long): String
+
+    // Профили персон (JSON: name, system_prompt, generation, template_vars)
+    external fun listPersonas(): String
+    external fun getActivePersona(): String
+    external fun createPersona(personaJson: String)
+    external fun updatePersona(personaJson: String)
+    external fun deletePersona(name: String)
+    external fun selectPersona(name: String)
+
     // Suspend функция для асинхронной загрузки модели
     suspend fun loadModelSuspend(modelType: ModelType, variant: String) = withContext(Dispatchers.IO) {
         try {
@@ -76,6 +210,35 @@ class RustInterface private constructor() {
         }
     }
 
+    // Suspend функция для получения каталога установленных моделей
+    suspend fun listModelsSuspend(): String = withContext(Dispatchers.IO) {
+        try {
+            listModels()
+        } catch (e: Exception) {
+            Log.e(TAG, "Error listing models", e)
+            throw e
+        }
+    }
+
+    // Suspend функция для загрузки модели из файла с отчётом о прогрессе
+    suspend fun loadModelFromPathWithProgressSuspend(
+        modelPath: String,
+        onProgress: (phase: String, bytesLoaded: Long, bytesTotal: Long) -> Unit
+    ) = withContext(Dispatchers.IO) {
+        val callback = object : LoadProgressCallback {
+            override fun onProgress(phase: String, bytesLoaded: Long, bytesTotal: Long, tensorsLoaded: Int, tensorsTotal: Int) {
+                onProgress(phase, bytesLoaded, bytesTotal)
+            }
+        }
+        try {
+            loadModelFromPathWithProgress(modelPath, callback)
+            Log.d(TAG, "Model loaded successfully from path: $modelPath")
+        } catch (e: Exception) {
+            Log.e(TAG, "Error loading model from path: $modelPath", e)
+            throw e
+        }
+    }
+
     // Suspend функция для асинхронного переключения модели
     suspend fun switchModelSuspend(modelType: ModelType, variant: String) = withContext(Dispatchers.IO) {
         try {
@@ -101,7 +264,7 @@ class RustInterface private constructor() {
     // Suspend функция для потоковой генерации текста
     suspend fun generateTextStreaming(
         prompt: String,
-        config: GenerationConfig = GenerationConfig(),
+        config: GenerationConfig? = null,
         onToken: (String) -> Unit = {},
         onComplete: () -> Unit = {},
         onError: (String) -> Unit = {}
@@ -131,7 +294,7 @@ class RustInterface private constructor() {
     // Suspend функция для синхронной генерации текста (без потока)
     suspend fun generateTextSync(
         prompt: String,
-        config: GenerationConfig = GenerationConfig()
+        config: GenerationConfig? = null
     ): String = withContext(Dispatchers.IO) {
         try {
             val result = generateText(prompt, config, null) // Передаем null для callback
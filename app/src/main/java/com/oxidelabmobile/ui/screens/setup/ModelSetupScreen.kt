@@ -19,6 +19,7 @@ import androidx.compose.material3.Button
 import androidx.compose.material3.CircularProgressIndicator
 import androidx.compose.material3.TextButton
 import androidx.compose.runtime.Composable
+import androidx.compose.runtime.LaunchedEffect
 import androidx.compose.runtime.remember
 import androidx.compose.runtime.mutableStateOf
 import androidx.compose.runtime.getValue
@@ -45,6 +46,7 @@ import java.io.File
 import java.io.FileInputStream
 import java.io.FileOutputStream
 import java.nio.channels.FileChannel
+import org.json.JSONArray
 import android.Manifest
 import androidx.activity.compose.rememberLauncherForActivityResult
 import androidx.activity.result.contract.ActivityResultContracts
@@ -66,6 +68,16 @@ fun ModelSetupScreen(
     val context = LocalContext.current
     val prefs = remember { context.getSharedPreferences("oxide_prefs", Context.MODE_PRIVATE) }
     var pendingProceed by remember { mutableStateOf<(() -> Unit)?>(null) }
+    // Установленные модели берутся из каталога Rust (listModels), а не задаются здесь
+    var installedModels by remember { mutableStateOf<List<CatalogModel>>(emptyList()) }
+    LaunchedEffect(Unit) {
+        installedModels = try {
+            parseCatalog(RustInterface.instance.listModelsSuspend())
+        } catch (e: Throwable) {
+            Log.e("ModelSetupScreen", "Error listing installed models", e)
+            emptyList()
+        }
+    }
     val permissionLauncher = rememberLauncherForActivityResult(
         contract = ActivityResultContracts.RequestMultiplePermissions(),
         onResult = { results ->
@@ -128,15 +140,21 @@ fun ModelSetupScreen(
                 .padding(Spacing.Medium),
             verticalArrangement = Arrangement.spacedBy(Spacing.Large)
         ) {
-            // Available models
-            ModelInfoCard(
-                modelName = "Gemma 3 270M IT QAT ",
-                modelSize = "14 GB",
-                parameters = "70B",
-                isCompatible = true,
-                modifier = Modifier.fillMaxWidth(),
-                iconRes = R.drawable.gemma
-            )
+            // Installed models from the catalog
+            installedModels.forEach { model ->
+                ModelInfoCard(
+                    modelName = model.name,
+                    modelSize = formatSize(model.fileSize),
+                    parameters = formatParameters(model.parameterCount, model.quantization),
+                    isCompatible = model.modelType != null,
+                    modifier = Modifier.fillMaxWidth(),
+                    iconRes = when (model.modelType) {
+                        "Gemma3" -> R.drawable.gemma
+                        "Qwen3" -> R.drawable.qwen
+                        else -> null
+                    }
+                )
+            }
 
             QwenModelCard(
                 onDownloaded = { localPath ->
@@ -342,6 +360,43 @@ private fun QwenModelCard(
     }
 }
 
+// Запись каталога моделей (см. RustInterface.listModels)
+private data class CatalogModel(
+    val name: String,
+    val fileSize: Long,
+    val parameterCount: Long,
+    val quantization: List<String>,
+    val modelType: String?
+)
+
+private fun parseCatalog(json: String): List<CatalogModel> {
+    val array = JSONArray(json)
+    return (0 until array.length()).map { index ->
+        val entry = array.getJSONObject(index)
+        val quantization = entry.optJSONArray("quantization_types")
+        CatalogModel(
+            name = if (entry.isNull("name")) entry.getString("file_name") else entry.getString("name"),
+            fileSize = entry.getLong("file_size"),
+            parameterCount = entry.getLong("parameter_count"),
+            quantization = (0 until (quantization?.length() ?: 0)).map { quantization!!.getString(it) },
+            modelType = if (entry.isNull("model_type")) null else entry.getString("model_type")
+        )
+    }
+}
+
+private fun formatSize(bytes: Long): String = when {
+    bytes >= 1L shl 30 -> String.format("%.1f GB", bytes / (1L shl 30).toDouble())
+    else -> "${bytes / (1L shl 20)} MB"
+}
+
+private fun formatParameters(count: Long, quantization: List<String>): String {
+    val parameters = when {
+        count >= 1_000_000_000 -> String.format("%.1fB", count / 1e9)
+        else -> "${count / 1_000_000}M"
+    }
+    return if (quantization.isEmpty()) parameters else "$parameters • ${quantization.joinToString()}"
+}
+
 // Простая реализация прямой загрузки без внешних зависимостей
 @Throws(Exception::class)
 private fun downloadDirectly(context: Context, url: String): String {
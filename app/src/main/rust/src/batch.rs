@@ -0,0 +1,427 @@
+//! Пакетная генерация: несколько независимых промптов проходят через модель вместе.
+//! Промпты выравниваются отступом слева, у каждой последовательности свои позиции RoPE,
+//! маска скрывает отступ; условия остановки проверяются отдельно, а завершённые
+//! последовательности убираются из пакета.
+
+use candle_core::{Device, Result, Tensor};
+use candle_transformers::generation::LogitsProcessor;
+
+use crate::candidates::CausalLm;
+use crate::model_inference::GenerationConfig;
+use crate::quantized_qwen3::ModelWeights as QuantizedQwen3;
+use crate::repetition::RepetitionGuard;
+
+/// Сколько промптов по умолчанию обрабатывается одним пакетом.
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 8;
+
+/// Обработчик потоковой пакетной генерации; `index` — номер промпта во входном списке.
+pub trait BatchCallback: Send + Sync {
+    /// Новый фрагмент текста последовательности.
+    fn on_token(&self, index: usize, token: &str);
+    /// Последовательность завершена; `text` — полный ответ.
+    fn on_sequence_complete(&self, index: usize, text: &str);
+}
+
+/// Получатель токенов пакетной генерации; `index` — номер промпта в пакете.
+pub(crate) trait BatchSink {
+    /// Принимает выданный токен; `false` завершает последовательность.
+    fn emit(&mut self, index: usize, token: u32) -> bool;
+
+    /// Последовательность завершена, в том числе остановкой всей генерации.
+    fn complete(&mut self, _index: usize) {}
+}
+
+impl<F: FnMut(usize, u32) -> bool> BatchSink for F {
+    fn emit(&mut self, index: usize, token: u32) -> bool {
+        self(index, token)
+    }
+}
+
+/// Общие для последовательностей параметры остановки.
+pub(crate) struct StopConditions<'a> {
+    pub tokens: &'a [u32],
+    pub max_context: usize,
+    /// Защита от повторов; каждая последовательность получает свою копию.
+    pub repetition: &'a RepetitionGuard,
+}
+
+/// Состояние одной последовательности пакета.
+struct Sequence {
+    index: usize,
+    /// Промпт и сгенерированные токены.
+    tokens: Vec<u32>,
+    prompt_len: usize,
+    processor: LogitsProcessor,
+    guard: RepetitionGuard,
+    finished: bool,
+}
+
+impl Sequence {
+    /// Последовательность `index` выбирает токены с зерном `seed + index`, чтобы
+    /// ответы на одинаковые промпты различались.
+    fn new(index: usize, prompt: &[u32], config: &GenerationConfig, stop: &StopConditions) -> Self {
+        Self {
+            index,
+            tokens: prompt.to_vec(),
+            prompt_len: prompt.len(),
+            processor: LogitsProcessor::new(
+                config.seed.wrapping_add(index as u64),
+                Some(config.temperature as f64),
+                Some(config.top_p as f64),
+            ),
+            guard: stop.repetition.clone(),
+            finished: false,
+        }
+    }
+
+    fn generated(&self) -> &[u32] {
+        &self.tokens[self.prompt_len..]
+    }
+
+    /// Завершает последовательность и сообщает об этом `sink`.
+    fn finish(&mut self, sink: &mut impl BatchSink) {
+        self.finished = true;
+        sink.complete(self.index);
+    }
+
+    /// Выбирает следующий токен по логитам `[vocab]` и передаёт его `sink`; завершённая
+    /// последовательность сразу сообщается `sink`.
+    fn step(
+        &mut self,
+        logits: &Tensor,
+        config: &GenerationConfig,
+        stop: &StopConditions,
+        sink: &mut impl BatchSink,
+    ) -> Result<()> {
+        let logits = if config.repeat_penalty != 1.0 {
+            candle_transformers::utils::apply_repeat_penalty(
+                logits,
+                config.repeat_penalty,
+                &self.tokens,
+            )?
+        } else {
+            logits.clone()
+        };
+        let logits = self.guard.apply(&logits, &self.tokens, self.prompt_len)?;
+        let token = self.processor.sample(&logits)?;
+        if stop.tokens.contains(&token) {
+            self.finish(sink);
+            return Ok(());
+        }
+        self.tokens.push(token);
+        if !sink.emit(self.index, token)
+            || self.generated().len() >= config.max_tokens
+            || self.tokens.len() >= stop.max_context
+            || self.guard.observe(&self.tokens[self.prompt_len..])
+        {
+            self.finish(sink);
+        }
+        Ok(())
+    }
+}
+
+/// Генерирует ответы на `prompts` одним пакетом квантованной Qwen3. `sink` получает
+/// номер промпта и выданный токен; `should_stop` прерывает всю генерацию.
+/// Возвращает сгенерированные токены каждого промпта.
+pub(crate) fn generate(
+    model: &mut QuantizedQwen3,
+    prompts: &[Vec<u32>],
+    config: &GenerationConfig,
+    stop: &StopConditions,
+    should_stop: impl Fn() -> bool,
+    mut sink: impl BatchSink,
+) -> Result<Vec<Vec<u32>>> {
+    if prompts.iter().any(Vec::is_empty) {
+        candle_core::bail!("empty prompt in batch");
+    }
+    let device = model.device().clone();
+    let width = prompts.iter().map(Vec::len).max().unwrap_or(0);
+    let mut sequences: Vec<Sequence> = prompts
+        .iter()
+        .enumerate()
+        .map(|(index, prompt)| Sequence::new(index, prompt, config, stop))
+        .collect();
+    if sequences.is_empty() || config.max_tokens == 0 {
+        for sequence in &mut sequences {
+            sequence.finish(&mut sink);
+        }
+        return Ok(vec![Vec::new(); prompts.len()]);
+    }
+
+    // Отступ слева: токены промпта выровнены по правому краю
+    let pads: Vec<usize> = prompts.iter().map(|prompt| width - prompt.len()).collect();
+    let mut input = Vec::with_capacity(prompts.len() * width);
+    let mut positions = Vec::with_capacity(prompts.len() * width);
+    for (prompt, &pad) in prompts.iter().zip(&pads) {
+        input.extend(std::iter::repeat_n(0, pad).chain(prompt.iter().copied()));
+        positions.extend(std::iter::repeat_n(0, pad).chain(0..prompt.len() as u32));
+    }
+    let b = prompts.len();
+    let input = Tensor::from_vec(input, (b, width), &device)?;
+    let positions = Tensor::from_vec(positions, (b, width), &device)?;
+    model.clear_kv_cache();
+    let mask = padding_mask(&pads, width, 0, &device)?;
+    let mut logits = model.forward_batch(&input, &positions, &mask)?;
+    let mut kv_len = width;
+
+    // Строки пакета: индексы последовательностей, ещё участвующих в проходах
+    let mut rows: Vec<usize> = (0..b).collect();
+    loop {
+        for (row, &seq) in rows.iter().enumerate() {
+            let row_logits = logits.get(row)?;
+            sequences[seq].step(&row_logits, config, stop, &mut sink)?;
+        }
+        if should_stop() {
+            break;
+        }
+        let keep: Vec<u32> = (0..rows.len())
+            .filter(|&row| !sequences[rows[row]].finished)
+            .map(|row| row as u32)
+            .collect();
+        if keep.is_empty() {
+            break;
+        }
+        if keep.len() < rows.len() {
+            model.select_batch_rows(&Tensor::new(keep.as_slice(), &device)?)?;
+            rows = keep.iter().map(|&row| rows[row as usize]).collect();
+        }
+
+        let last: Vec<u32> = rows
+            .iter()
+            .map(|&seq| *sequences[seq].tokens.last().unwrap())
+            .collect();
+        let step_positions: Vec<u32> = rows
+            .iter()
+            .map(|&seq| sequences[seq].tokens.len() as u32 - 1)
+            .collect();
+        let row_pads: Vec<usize> = rows.iter().map(|&seq| pads[seq]).collect();
+        let n = rows.len();
+        let input = Tensor::from_vec(last, (n, 1), &device)?;
+        let positions = Tensor::from_vec(step_positions, (n, 1), &device)?;
+        let mask = padding_mask(&row_pads, 1, kv_len, &device)?;
+        logits = model.forward_batch(&input, &positions, &mask)?;
+        kv_len += 1;
+    }
+    for sequence in sequences.iter_mut().filter(|sequence| !sequence.finished) {
+        sequence.finish(&mut sink);
+    }
+    Ok(sequences
+        .into_iter()
+        .map(|seq| seq.generated().to_vec())
+        .collect())
+}
+
+/// Генерирует ответы по одному промпту для моделей без пакетного прохода;
+/// поведение совпадает с `generate`.
+pub(crate) fn generate_serial<M: CausalLm>(
+    model: &mut M,
+    device: &Device,
+    prompts: &[Vec<u32>],
+    config: &GenerationConfig,
+    stop: &StopConditions,
+    should_stop: impl Fn() -> bool,
+    mut sink: impl BatchSink,
+) -> Result<Vec<Vec<u32>>> {
+    let mut results = Vec::with_capacity(prompts.len());
+    for (index, prompt) in prompts.iter().enumerate() {
+        let mut sequence = Sequence::new(index, prompt, config, stop);
+        if !prompt.is_empty() && config.max_tokens > 0 && !should_stop() {
+            model.clear_kv_cache();
+            let input = Tensor::new(prompt.as_slice(), device)?.unsqueeze(0)?;
+            let mut logits = model.forward(&input, 0)?;
+            loop {
+                sequence.step(&logits.get(0)?, config, stop, &mut sink)?;
+                if sequence.finished || should_stop() {
+                    break;
+                }
+                let position = sequence.tokens.len() - 1;
+                let input = Tensor::new(&sequence.tokens[position..], device)?.unsqueeze(0)?;
+                logits = model.forward(&input, position)?;
+            }
+        }
+        if !sequence.finished {
+            sequence.finish(&mut sink);
+        }
+        results.push(sequence.generated().to_vec());
+    }
+    Ok(results)
+}
+
+/// Аддитивная маска `[batch, 1, len, offset + len]` для новых `len` токенов после `offset`
+/// уже обработанных: скрывает отступ слева и будущие позиции. Токены отступа видят
+/// только себя, чтобы softmax оставался определён.
+fn padding_mask(pads: &[usize], len: usize, offset: usize, device: &Device) -> Result<Tensor> {
+    let total = offset + len;
+    let mut mask = Vec::with_capacity(pads.len() * len * total);
+    for &pad in pads {
+        for q in 0..len {
+            let query = offset + q;
+            mask.extend((0..total).map(|key| {
+                if (key <= query && key >= pad) || key == query {
+                    0f32
+                } else {
+                    f32::NEG_INFINITY
+                }
+            }));
+        }
+    }
+    Tensor::from_vec(mask, (pads.len(), 1, len, total), device)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_weights::Qwen3Weights;
+    use crate::test_support::write_qwen3_gguf;
+    use candle_core::quantized::gguf_file;
+    use std::io::Cursor;
+
+    fn load_model(dir: &std::path::Path) -> QuantizedQwen3 {
+        let path = dir.join("qwen3.gguf");
+        write_qwen3_gguf(&path, 0);
+        let mut reader = Cursor::new(std::fs::read(&path).unwrap());
+        let content = gguf_file::Content::read(&mut reader).unwrap();
+        QuantizedQwen3::from_gguf(content, &mut reader, &Device::Cpu).unwrap()
+    }
+
+    #[test]
+    fn test_batch_matches_serial_generation() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut model = load_model(tmp.path());
+        let config = GenerationConfig {
+            max_tokens: 6,
+            temperature: 0.0,
+            ..GenerationConfig::default()
+        };
+        let stop = StopConditions {
+            tokens: &[7],
+            max_context: 2048,
+            repetition: &RepetitionGuard::default(),
+        };
+        let prompts = vec![vec![1, 2, 3, 4, 5], vec![6], vec![2, 2, 4]];
+
+        let mut streamed = vec![Vec::new(); prompts.len()];
+        let batched = generate(
+            &mut model,
+            &prompts,
+            &config,
+            &stop,
+            || false,
+            |index: usize, token: u32| {
+                streamed[index].push(token);
+                true
+            },
+        )
+        .unwrap();
+        let serial = generate_serial(
+            &mut Qwen3Weights::Quantized(model.clone()),
+            &Device::Cpu,
+            &prompts,
+            &config,
+            &stop,
+            || false,
+            |_, _| true,
+        )
+        .unwrap();
+        assert_eq!(batched, serial);
+        assert_eq!(streamed, batched);
+        assert!(batched.iter().all(|tokens| tokens.len() <= 6));
+        assert!(batched.iter().flatten().all(|&token| token != 7));
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<(usize, Option<u32>)>,
+    }
+
+    impl BatchSink for &mut Recorder {
+        fn emit(&mut self, index: usize, token: u32) -> bool {
+            self.events.push((index, Some(token)));
+            // Вторая последовательность останавливается на первом токене
+            index != 1
+        }
+
+        fn complete(&mut self, index: usize) {
+            self.events.push((index, None));
+        }
+    }
+
+    #[test]
+    fn test_sequences_complete_individually_with_own_seeds() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut model = load_model(tmp.path());
+        let config = GenerationConfig {
+            max_tokens: 6,
+            temperature: 1.0,
+            top_p: 1.0,
+            ..GenerationConfig::default()
+        };
+        let stop = StopConditions {
+            tokens: &[],
+            max_context: 2048,
+            repetition: &RepetitionGuard::default(),
+        };
+        let prompts = vec![vec![1, 2, 3]; 3];
+
+        let mut recorder = Recorder::default();
+        let batched = generate(
+            &mut model,
+            &prompts,
+            &config,
+            &stop,
+            || false,
+            &mut recorder,
+        )
+        .unwrap();
+        // Одинаковые промпты с разными зёрнами дают разные ответы
+        assert_ne!(batched[0], batched[2]);
+
+        // Завершение приходит сразу, а не после всего пакета
+        let completed_at = |index| {
+            let events = &recorder.events;
+            let position = events.iter().position(|&event| event == (index, None));
+            assert_eq!(
+                events
+                    .iter()
+                    .filter(|&&event| event == (index, None))
+                    .count(),
+                1
+            );
+            position.unwrap()
+        };
+        let last_token = recorder
+            .events
+            .iter()
+            .rposition(|&(index, token)| index == 0 && token.is_some());
+        assert!(completed_at(1) < last_token.unwrap());
+        assert!(completed_at(0) > last_token.unwrap());
+        completed_at(2);
+    }
+
+    #[test]
+    fn test_padding_mask_hides_padding_and_future() {
+        let mask = padding_mask(&[2, 0], 3, 0, &Device::Cpu).unwrap();
+        let visible = |row: usize, query: usize| -> Vec<bool> {
+            mask.get(row)
+                .unwrap()
+                .get(0)
+                .unwrap()
+                .get(query)
+                .unwrap()
+                .to_vec1::<f32>()
+                .unwrap()
+                .into_iter()
+                .map(|value| value == 0.0)
+                .collect()
+        };
+        assert_eq!(visible(0, 0), [true, false, false]);
+        assert_eq!(visible(0, 2), [false, false, true]);
+        assert_eq!(visible(1, 1), [true, true, false]);
+
+        // Шаг декодирования: ключи отступа скрыты
+        let step = padding_mask(&[1], 1, 3, &Device::Cpu).unwrap();
+        let row = step.flatten_all().unwrap().to_vec1::<f32>().unwrap();
+        assert_eq!(row[0], f32::NEG_INFINITY);
+        assert!(row[1..].iter().all(|&value| value == 0.0));
+    }
+}
@@ -0,0 +1,617 @@
+//! Несколько вариантов ответа на один промпт: `n` независимых выборок или beam search.
+//! Промпт обрабатывается один раз; каждый вариант продолжает копию модели с общим
+//! KV-кешем промпта (тензоры весов и кеша разделяются, копируются только ссылки).
+//! Кеш полной Gemma3 предвыделен, и копии записывают в общие тензоры, поэтому
+//! её вариант перед продолжением заново обрабатывает свой контекст со своим кешем.
+
+use candle_core::{Device, Result, Tensor};
+use candle_transformers::generation::LogitsProcessor;
+use serde::{Deserialize, Serialize};
+
+use crate::batch::StopConditions;
+use crate::model_inference::GenerationConfig;
+use crate::model_weights::{Gemma3Weights, Qwen3Weights};
+use crate::repetition::RepetitionGuard;
+
+/// Модель, возвращающая логиты последней позиции `[batch, vocab]`.
+pub(crate) trait CausalLm: Clone {
+    fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor>;
+    fn clear_kv_cache(&mut self);
+
+    /// Можно ли продолжать копии модели независимо друг от друга.
+    fn clones_share_cache_safely(&self) -> bool {
+        true
+    }
+}
+
+impl CausalLm for Qwen3Weights {
+    fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        Qwen3Weights::forward(self, input, offset)
+    }
+
+    fn clear_kv_cache(&mut self) {
+        Qwen3Weights::clear_kv_cache(self)
+    }
+}
+
+impl CausalLm for Gemma3Weights {
+    fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        Gemma3Weights::forward(self, input, offset)
+    }
+
+    fn clear_kv_cache(&mut self) {
+        Gemma3Weights::clear_kv_cache(self)
+    }
+
+    fn clones_share_cache_safely(&self) -> bool {
+        !matches!(self, Gemma3Weights::Full(_))
+    }
+}
+
+/// Способ получения вариантов.
+/// В JSON: `{"sample": {"n": 3}}` или `{"beam": {"width": 4, "length_penalty": 1.0}}`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateMode {
+    /// `n` независимых выборок с параметрами генерации; выборка `i` использует `seed + i`.
+    Sample { n: usize },
+    /// Beam search: `width` лучших гипотез на каждом шаге. Итоговая оценка —
+    /// `logprob / len^length_penalty`; значения больше нуля поощряют длинные ответы.
+    Beam {
+        width: usize,
+        #[serde(default = "default_length_penalty")]
+        length_penalty: f32,
+    },
+}
+
+fn default_length_penalty() -> f32 {
+    1.0
+}
+
+/// Вариант ответа.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Candidate {
+    pub text: String,
+    pub tokens: Vec<u32>,
+    /// Суммарная логарифмическая вероятность токенов ответа (после штрафа за повтор,
+    /// при температуре 1).
+    pub logprob: f32,
+    /// Оценка для сортировки: `logprob` с учётом штрафа длины в beam search.
+    pub score: f32,
+    /// Закончился ли ответ токеном конца, а не лимитом длины.
+    pub finished: bool,
+}
+
+/// Токены варианта с оценками; текст добавляет движок.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Scored {
+    pub tokens: Vec<u32>,
+    pub logprob: f32,
+    pub score: f32,
+    pub finished: bool,
+}
+
+/// Гипотеза: копия модели с кешем её токенов, логиты следующей позиции
+/// и состояние защиты от повторов.
+struct Hypothesis<M> {
+    model: M,
+    tokens: Vec<u32>,
+    logits: Tensor,
+    logprob: f32,
+    guard: RepetitionGuard,
+}
+
+/// Генерирует варианты продолжения `prompt`. Токены `stop.tokens` завершают вариант
+/// и в него не входят; обнаруженный цикл или `stop.max_context` обрывают вариант,
+/// `should_stop` прерывает генерацию всех вариантов.
+pub(crate) fn generate<M: CausalLm>(
+    model: &mut M,
+    device: &Device,
+    prompt: &[u32],
+    config: &GenerationConfig,
+    mode: CandidateMode,
+    stop: &StopConditions,
+    should_stop: impl Fn() -> bool,
+) -> Result<Vec<Scored>> {
+    if prompt.is_empty() {
+        return Ok(Vec::new());
+    }
+    model.clear_kv_cache();
+    let input = Tensor::new(prompt, device)?.unsqueeze(0)?;
+    let logits = model.forward(&input, 0)?.squeeze(0)?;
+    let root = Hypothesis {
+        model: model.clone(),
+        tokens: Vec::new(),
+        logits,
+        logprob: 0.0,
+        guard: stop.repetition.clone(),
+    };
+    let ctx = Context {
+        device,
+        prompt,
+        config,
+        stop,
+    };
+    match mode {
+        CandidateMode::Sample { n } => (0..n.max(1) as u64)
+            .map(|i| ctx.sample(&root, config.seed.wrapping_add(i), &should_stop))
+            .collect(),
+        CandidateMode::Beam {
+            width,
+            length_penalty,
+        } => ctx.beam_search(root, width.max(1), length_penalty, &should_stop),
+    }
+}
+
+struct Context<'a> {
+    device: &'a Device,
+    prompt: &'a [u32],
+    config: &'a GenerationConfig,
+    stop: &'a StopConditions<'a>,
+}
+
+impl Context<'_> {
+    /// Логиты гипотезы после штрафа за повтор и DRY по промпту и её токенам.
+    fn penalized<M>(&self, hypothesis: &Hypothesis<M>) -> Result<Tensor> {
+        let mut context = self.prompt.to_vec();
+        context.extend_from_slice(&hypothesis.tokens);
+        let logits = if self.config.repeat_penalty != 1.0 {
+            candle_transformers::utils::apply_repeat_penalty(
+                &hypothesis.logits,
+                self.config.repeat_penalty,
+                &context,
+            )?
+        } else {
+            hypothesis.logits.clone()
+        };
+        hypothesis.guard.apply(&logits, &context, self.prompt.len())
+    }
+
+    /// Может ли гипотеза продолжаться: не достигнут предел контекста.
+    fn has_room<M>(&self, hypothesis: &Hypothesis<M>) -> bool {
+        self.prompt.len() + hypothesis.tokens.len() < self.stop.max_context
+    }
+
+    /// Копия модели гипотезы, которую можно продолжать независимо от других копий.
+    fn fork<M: CausalLm>(&self, parent: &Hypothesis<M>) -> Result<M> {
+        let mut model = parent.model.clone();
+        if !model.clones_share_cache_safely() {
+            // Сброс отвязывает копию от общих тензоров кеша
+            model.clear_kv_cache();
+            let mut context = self.prompt.to_vec();
+            context.extend_from_slice(&parent.tokens);
+            let input = Tensor::new(context.as_slice(), self.device)?.unsqueeze(0)?;
+            model.forward(&input, 0)?;
+        }
+        Ok(model)
+    }
+
+    /// Продолжает гипотезу токеном; `model` — копия модели `parent`, которую больше
+    /// никто не продолжает.
+    fn extend<M: CausalLm>(
+        &self,
+        parent: &Hypothesis<M>,
+        mut model: M,
+        token: u32,
+        logprob: f32,
+    ) -> Result<Hypothesis<M>> {
+        let input = Tensor::new(&[token], self.device)?.unsqueeze(0)?;
+        let logits = model
+            .forward(&input, self.prompt.len() + parent.tokens.len())?
+            .squeeze(0)?;
+        let mut tokens = parent.tokens.clone();
+        tokens.push(token);
+        Ok(Hypothesis {
+            model,
+            tokens,
+            logits,
+            logprob,
+            guard: parent.guard.clone(),
+        })
+    }
+
+    fn sample<M: CausalLm>(
+        &self,
+        root: &Hypothesis<M>,
+        seed: u64,
+        should_stop: &impl Fn() -> bool,
+    ) -> Result<Scored> {
+        let mut processor = LogitsProcessor::new(
+            seed,
+            Some(self.config.temperature as f64),
+            Some(self.config.top_p as f64),
+        );
+        let mut current = Hypothesis {
+            model: self.fork(root)?,
+            tokens: Vec::new(),
+            logits: root.logits.clone(),
+            logprob: 0.0,
+            guard: root.guard.clone(),
+        };
+        while current.tokens.len() < self.config.max_tokens
+            && self.has_room(&current)
+            && !should_stop()
+        {
+            let logits = self.penalized(&current)?;
+            let token = processor.sample(&logits)?;
+            let logprob = current.logprob + token_logprob(&logits, token)?;
+            if self.stop.tokens.contains(&token) {
+                return Ok(Scored {
+                    tokens: current.tokens,
+                    logprob,
+                    score: logprob,
+                    finished: true,
+                });
+            }
+            // Цепочка выборки линейна: прежнюю гипотезу больше никто не продолжает
+            current = self.extend(&current, current.model.clone(), token, logprob)?;
+            if current.guard.observe(&current.tokens) {
+                break;
+            }
+        }
+        Ok(Scored {
+            tokens: current.tokens,
+            logprob: current.logprob,
+            score: current.logprob,
+            finished: false,
+        })
+    }
+
+    fn beam_search<M: CausalLm>(
+        &self,
+        root: Hypothesis<M>,
+        width: usize,
+        length_penalty: f32,
+        should_stop: &impl Fn() -> bool,
+    ) -> Result<Vec<Scored>> {
+        let score = |logprob: f32, len: usize| logprob / (len.max(1) as f32).powf(length_penalty);
+        let mut beams = vec![root];
+        let mut finished = Vec::new();
+        for step in 0..self.config.max_tokens {
+            if beams.is_empty() || finished.len() >= width || should_stop() {
+                break;
+            }
+            // Лучшие продолжения каждой гипотезы, затем лучшие среди всех
+            let mut expansions = Vec::new();
+            for (index, beam) in beams.iter().enumerate() {
+                let logits = self.penalized(beam)?;
+                let logprobs = candle_nn::ops::log_softmax(&logits, 0)?.to_vec1::<f32>()?;
+                let mut order: Vec<usize> = (0..logprobs.len()).collect();
+                order.sort_by(|&a, &b| logprobs[b].total_cmp(&logprobs[a]));
+                for token in order.into_iter().take(width) {
+                    expansions.push((index, token as u32, beam.logprob + logprobs[token]));
+                }
+            }
+            expansions.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+            let mut next = Vec::with_capacity(width);
+            for (index, token, logprob) in expansions {
+                if next.len() + finished.len() >= width {
+                    break;
+                }
+                let parent = &beams[index];
+                if self.stop.tokens.contains(&token) {
+                    let len = parent.tokens.len();
+                    finished.push(Scored {
+                        tokens: parent.tokens.clone(),
+                        logprob,
+                        score: score(logprob, len),
+                        finished: true,
+                    });
+                    continue;
+                }
+                let mut child = self.extend(parent, self.fork(parent)?, token, logprob)?;
+                // Зациклившаяся гипотеза или упёршаяся в контекст дальше не продолжается
+                if child.guard.observe(&child.tokens) || !self.has_room(&child) {
+                    finished.push(Scored {
+                        score: score(child.logprob, child.tokens.len()),
+                        tokens: child.tokens,
+                        logprob: child.logprob,
+                        finished: false,
+                    });
+                } else {
+                    next.push(child);
+                }
+            }
+            beams = next;
+            log::debug!("Beam search: шаг {}, гипотез {}", step, beams.len());
+        }
+        finished.extend(beams.into_iter().map(|beam| Scored {
+            score: score(beam.logprob, beam.tokens.len()),
+            tokens: beam.tokens,
+            logprob: beam.logprob,
+            finished: false,
+        }));
+        finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+        finished.truncate(width);
+        Ok(finished)
+    }
+}
+
+/// Логарифмическая вероятность токена при логитах `logits` (температура 1).
+fn token_logprob(logits: &Tensor, token: u32) -> Result<f32> {
+    candle_nn::ops::log_softmax(logits, 0)?
+        .get(token as usize)?
+        .to_scalar::<f32>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repetition::LoopDetection;
+
+    /// Модель-таблица: логиты зависят только от последнего токена.
+    #[derive(Clone)]
+    struct TableLm {
+        table: Vec<Vec<f32>>,
+        forwards: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl CausalLm for TableLm {
+        fn forward(&mut self, input: &Tensor, _offset: usize) -> Result<Tensor> {
+            self.forwards.set(self.forwards.get() + 1);
+            let tokens = input.squeeze(0)?.to_vec1::<u32>()?;
+            let last = *tokens.last().unwrap() as usize;
+            Tensor::new(self.table[last].as_slice(), &Device::Cpu)?.unsqueeze(0)
+        }
+
+        fn clear_kv_cache(&mut self) {}
+    }
+
+    fn table() -> TableLm {
+        let ln = |p: f32| p.ln();
+        TableLm {
+            // 0 — промпт, 1 и 2 — слова, 3 — конец ответа
+            table: vec![
+                vec![-1e9, ln(0.6), ln(0.4), -1e9],
+                vec![-1e9, -1e9, ln(0.5), ln(0.5)],
+                vec![-1e9, -1e9, -1e9, 0.0],
+                vec![-1e9, -1e9, -1e9, 0.0],
+            ],
+            forwards: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_beam_search_finds_most_probable_sequences() {
+        let config = GenerationConfig {
+            max_tokens: 5,
+            repeat_penalty: 1.0,
+            ..GenerationConfig::default()
+        };
+        let mut model = table();
+        let mode = CandidateMode::Beam {
+            width: 2,
+            length_penalty: 0.0,
+        };
+        let stop = StopConditions {
+            tokens: &[3],
+            max_context: 2048,
+            repetition: &RepetitionGuard::default(),
+        };
+        let beams = generate(&mut model, &Device::Cpu, &[0], &config, mode, &stop, || {
+            false
+        })
+        .unwrap();
+        // [2] = 0.4 лучше [1] = 0.3 и [1, 2] = 0.3
+        assert_eq!(beams.len(), 2);
+        assert_eq!(beams[0].tokens, vec![2]);
+        assert!((beams[0].logprob - 0.4f32.ln()).abs() < 1e-4);
+        assert!(beams[0].finished);
+        assert_eq!(beams[1].tokens, vec![1, 2]);
+        assert!((beams[1].logprob - 0.3f32.ln()).abs() < 1e-4);
+
+        let mode: CandidateMode = serde_json::from_str(r#"{"beam": {"width": 3}}"#).unwrap();
+        assert_eq!(
+            mode,
+            CandidateMode::Beam {
+                width: 3,
+                length_penalty: 1.0
+            }
+        );
+    }
+
+    #[test]
+    fn test_samples_share_prompt_prefill() {
+        let config = GenerationConfig {
+            max_tokens: 5,
+            temperature: 1.0,
+            top_p: 1.0,
+            repeat_penalty: 1.0,
+            ..GenerationConfig::default()
+        };
+        let mut model = table();
+        let forwards = model.forwards.clone();
+        let prompt = [0u32, 0, 0];
+        let stop = StopConditions {
+            tokens: &[3],
+            max_context: 2048,
+            repetition: &RepetitionGuard::default(),
+        };
+        let samples = generate(
+            &mut model,
+            &Device::Cpu,
+            &prompt,
+            &config,
+            CandidateMode::Sample { n: 4 },
+            &stop,
+            || false,
+        )
+        .unwrap();
+        assert_eq!(samples.len(), 4);
+        for sample in &samples {
+            assert!(sample.finished);
+            let expected: f32 = match sample.tokens.as_slice() {
+                [2] => 0.4f32.ln(),
+                [1] => 0.3f32.ln(),
+                [1, 2] => 0.3f32.ln(),
+                other => panic!("unexpected {other:?}"),
+            };
+            assert!((sample.logprob - expected).abs() < 1e-4);
+        }
+        // Проход промпта один; каждый токен варианта — один проход
+        let tokens: usize = samples.iter().map(|sample| sample.tokens.len()).sum();
+        assert_eq!(forwards.get(), 1 + tokens);
+    }
+
+    #[test]
+    fn test_loop_detection_cuts_candidates() {
+        let config = GenerationConfig {
+            max_tokens: 50,
+            temperature: 1.0,
+            top_p: 1.0,
+            repeat_penalty: 1.0,
+            ..GenerationConfig::default()
+        };
+        // 1 и 2 сменяют друг друга бесконечно
+        let mut model = TableLm {
+            table: vec![
+                vec![-1e9, 0.0, -1e9, -1e9],
+                vec![-1e9, -1e9, 0.0, -1e9],
+                vec![-1e9, 0.0, -1e9, -1e9],
+            ],
+            forwards: Default::default(),
+        };
+        let tokenizer = tokenizers::Tokenizer::new(tokenizers::models::bpe::BPE::default());
+        let guard = RepetitionGuard::new(None, Some(LoopDetection::default()), &tokenizer);
+        let stop = StopConditions {
+            tokens: &[3],
+            max_context: 2048,
+            repetition: &guard,
+        };
+        let beam = CandidateMode::Beam {
+            width: 1,
+            length_penalty: 0.0,
+        };
+        for mode in [CandidateMode::Sample { n: 2 }, beam] {
+            let candidates = generate(&mut model, &Device::Cpu, &[0], &config, mode, &stop, || {
+                false
+            })
+            .unwrap();
+            for candidate in &candidates {
+                // Цикл периода 2 обнаруживается, когда повторы покрывают min_span = 12
+                assert_eq!(candidate.tokens.len(), 12);
+                assert!(!candidate.finished);
+            }
+        }
+    }
+
+    /// Полная Gemma3 со случайными весами; часть слоёв со скользящим окном.
+    fn full_gemma3() -> Gemma3Weights {
+        use candle_transformers::models::gemma3;
+
+        let config: gemma3::Config = serde_json::from_value(serde_json::json!({
+            "attention_bias": false,
+            "head_dim": 8,
+            "hidden_activation": "gelu_pytorch_tanh",
+            "hidden_size": 16,
+            "intermediate_size": 32,
+            "num_attention_heads": 2,
+            "num_hidden_layers": 2,
+            "num_key_value_heads": 1,
+            "rms_norm_eps": 1e-6,
+            "rope_theta": 10000.0,
+            "rope_local_base_freq": 10000.0,
+            "vocab_size": 12,
+            "final_logit_softcapping": null,
+            "attn_logit_softcapping": null,
+            "query_pre_attn_scalar": 8,
+            "sliding_window": 16,
+            "sliding_window_pattern": 2,
+            "max_position_embeddings": 64
+        }))
+        .unwrap();
+        let varmap = candle_nn::VarMap::new();
+        let vb = candle_nn::VarBuilder::from_varmap(&varmap, candle_core::DType::F32, &Device::Cpu);
+        Gemma3Weights::Full(gemma3::Model::new(false, &config, vb).unwrap())
+    }
+
+    /// Логарифмическая вероятность ответа при отдельном проходе без общих копий.
+    fn independent_logprob(model: &Gemma3Weights, prompt: &[u32], tokens: &[u32]) -> f32 {
+        let mut model = model.clone();
+        model.clear_kv_cache();
+        let input = Tensor::new(prompt, &Device::Cpu)
+            .unwrap()
+            .unsqueeze(0)
+            .unwrap();
+        let mut logits = model.forward(&input, 0).unwrap().squeeze(0).unwrap();
+        let mut logprob = 0.0;
+        for (i, &token) in tokens.iter().enumerate() {
+            logprob += token_logprob(&logits, token).unwrap();
+            let input = Tensor::new(&[[token]], &Device::Cpu).unwrap();
+            logits = model
+                .forward(&input, prompt.len() + i)
+                .unwrap()
+                .squeeze(0)
+                .unwrap();
+        }
+        logprob
+    }
+
+    #[test]
+    fn test_full_gemma3_candidates_match_independent_runs() {
+        let config = GenerationConfig {
+            max_tokens: 6,
+            temperature: 1.0,
+            top_p: 1.0,
+            repeat_penalty: 1.0,
+            ..GenerationConfig::default()
+        };
+        let mut model = full_gemma3();
+        let prompt = [1u32, 4, 7, 2];
+        let stop = StopConditions {
+            tokens: &[],
+            max_context: 2048,
+            repetition: &RepetitionGuard::default(),
+        };
+        let beam = CandidateMode::Beam {
+            width: 3,
+            length_penalty: 0.0,
+        };
+        let beams = generate(
+            &mut model,
+            &Device::Cpu,
+            &prompt,
+            &config,
+            beam,
+            &stop,
+            || false,
+        )
+        .unwrap();
+        assert_eq!(beams.len(), 3);
+        for candidate in &beams {
+            let expected = independent_logprob(&model, &prompt, &candidate.tokens);
+            assert!((candidate.logprob - expected).abs() < 1e-3);
+        }
+
+        // Выборка `i` из n совпадает с отдельной выборкой с зерном `seed + i`
+        let samples = generate(
+            &mut model,
+            &Device::Cpu,
+            &prompt,
+            &config,
+            CandidateMode::Sample { n: 3 },
+            &stop,
+            || false,
+        )
+        .unwrap();
+        for (i, sample) in samples.iter().enumerate() {
+            let single = GenerationConfig {
+                seed: config.seed + i as u64,
+                ..config.clone()
+            };
+            let mode = CandidateMode::Sample { n: 1 };
+            let expected = generate(
+                &mut model,
+                &Device::Cpu,
+                &prompt,
+                &single,
+                mode,
+                &stop,
+                || false,
+            )
+            .unwrap();
+            assert_eq!(sample.tokens, expected[0].tokens);
+        }
+    }
+}
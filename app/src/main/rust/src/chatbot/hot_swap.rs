@@ -0,0 +1,340 @@
+//! Горячая замена модели: новая модель загружается в фоне, пока прежняя обслуживает
+//! запросы, затем движок подменяется атомарно. Начатые генерации держат свою копию
+//! движка и завершаются на прежней модели либо отменяются — по выбранной политике;
+//! их ожидание не мешает другим сменам модели.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+
+use crate::load_progress::LoadProgressCallback;
+use crate::model_inference::InferenceEngine;
+use crate::model_manager::{ModelManager, ModelManagerError, ModelType};
+use crate::model_store::ModelId;
+use crate::safetensors_model::dtype_from_name;
+
+/// Интервал проверки завершения генераций на прежней модели.
+const RELEASE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Сколько `Drain` ждёт генерации на прежней модели, прежде чем остановить их.
+pub(super) const DRAIN_TIMEOUT: Duration = Duration::from_secs(60);
+/// Сколько ждать освобождения прежнего движка после остановки генераций.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Что делать с генерациями, начатыми на прежней модели.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapPolicy {
+    /// Дождаться их завершения; не завершившиеся за `DRAIN_TIMEOUT` останавливаются.
+    Drain,
+    /// Остановить их.
+    Cancel,
+}
+
+impl SwapPolicy {
+    /// Политика по имени: `drain` или `cancel`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "drain" => Some(SwapPolicy::Drain),
+            "cancel" => Some(SwapPolicy::Cancel),
+            _ => None,
+        }
+    }
+}
+
+/// Модель, на которую переключается чат-бот.
+/// В JSON: `{"path": "..."}`, `{"stored": "qwen3/0.6b"}`, `{"resident": "<id>"}`
+/// или `{"safetensors": {"dir": "...", "dtype": "bf16"}}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapTarget {
+    /// GGUF файл.
+    Path(PathBuf),
+    /// Модель хранилища по идентификатору.
+    Stored(String),
+    /// Директория safetensors с необязательным типом весов.
+    Safetensors {
+        dir: PathBuf,
+        #[serde(default)]
+        dtype: Option<String>,
+    },
+    /// Модель, уже находящаяся в памяти.
+    Resident(String),
+}
+
+impl SwapTarget {
+    /// Загружает модель и делает её активной в менеджере; движок для неё устанавливает
+    /// наблюдатель менеджера вместе со сменой активной модели.
+    fn load(
+        &self,
+        manager: &ModelManager,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
+        match self {
+            SwapTarget::Path(path) => manager.load_model_from_path_with_progress(path, progress),
+            SwapTarget::Stored(id) => manager.load_stored_model(&ModelId::parse(id)?, progress),
+            SwapTarget::Safetensors { dir, dtype } => {
+                let dtype = dtype.as_deref().map(dtype_from_name).transpose()?;
+                manager.load_safetensors_model(dir, dtype, progress)
+            }
+            SwapTarget::Resident(id) => manager.activate_model(id),
+        }
+    }
+}
+
+/// Уведомления о горячей замене модели.
+pub trait SwapCallback: Send + Sync {
+    /// Новая модель обслуживает запросы, генерации на прежней завершены или отменены.
+    fn on_swapped(&self, model_type: ModelType);
+    /// Загрузка не удалась; прежняя модель продолжает работать.
+    fn on_failed(&self, error: &ModelManagerError);
+}
+
+/// Общее состояние для фоновой замены.
+pub(super) struct HotSwap {
+    pub manager: Arc<ModelManager>,
+    pub engine: Arc<RwLock<Option<Arc<InferenceEngine>>>>,
+    /// Замены выполняются по одной; блокировка снимается до ожидания прежних генераций.
+    pub lock: Arc<Mutex<()>>,
+    /// Сколько ждать генерации на прежней модели при `Drain`.
+    pub drain_timeout: Duration,
+}
+
+impl HotSwap {
+    /// Запускает замену в фоновом потоке.
+    pub fn spawn(
+        self,
+        target: SwapTarget,
+        policy: SwapPolicy,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+        callback: Option<Arc<dyn SwapCallback>>,
+    ) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            let guard = self.lock.lock();
+            let previous = self.engine.read().clone();
+            let loaded = target.load(&self.manager, progress);
+            drop(guard);
+            match loaded {
+                Ok(model_type) => {
+                    if let Some(previous) = previous.filter(|previous| !self.is_serving(previous)) {
+                        retire(previous, policy, self.drain_timeout);
+                    }
+                    log::info!("Горячая замена завершена: {:?}", model_type);
+                    if let Some(callback) = callback {
+                        callback.on_swapped(model_type);
+                    }
+                }
+                Err(err) => {
+                    log::warn!("Горячая замена не удалась: {}", err);
+                    if let Some(callback) = callback {
+                        callback.on_failed(&err);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Остался ли `engine` активным, например при замене на уже активную модель.
+    fn is_serving(&self, engine: &Arc<InferenceEngine>) -> bool {
+        self.engine
+            .read()
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, engine))
+    }
+}
+
+/// Ждёт, пока прежний движок перестанет использоваться. При `Cancel`, а при `Drain` —
+/// по истечении `drain_timeout`, останавливает генерацию; если движок и после этого
+/// не освобождён за `STOP_TIMEOUT`, замена завершается без ожидания.
+fn retire(engine: Arc<InferenceEngine>, policy: SwapPolicy, drain_timeout: Duration) {
+    let mut stopped = policy == SwapPolicy::Cancel;
+    if stopped {
+        engine.cancel_generations();
+    }
+    let mut deadline = Instant::now() + if stopped { STOP_TIMEOUT } else { drain_timeout };
+    while Arc::strong_count(&engine) > 1 {
+        if Instant::now() >= deadline {
+            if stopped {
+                log::warn!("Прежний движок всё ещё используется; замена завершена без ожидания");
+                return;
+            }
+            log::warn!(
+                "Генерации на прежней модели не завершились за {:?} и будут остановлены",
+                drain_timeout
+            );
+            engine.cancel_generations();
+            stopped = true;
+            deadline = Instant::now() + STOP_TIMEOUT;
+        }
+        std::thread::sleep(RELEASE_POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatbot::persona::PersonaProfile;
+    use crate::chatbot::ChatBot;
+    use crate::test_support::write_qwen3_safetensors;
+    use candle_core::Device;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tempfile::tempdir;
+
+    #[derive(Default)]
+    struct Recorder {
+        swapped: AtomicBool,
+        failed: AtomicBool,
+    }
+
+    impl SwapCallback for Recorder {
+        fn on_swapped(&self, _model_type: ModelType) {
+            self.swapped.store(true, Ordering::SeqCst);
+        }
+
+        fn on_failed(&self, _error: &ModelManagerError) {
+            self.failed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_swap_waits_for_in_flight_generation() {
+        let tmp = tempdir().unwrap();
+        let (old_dir, new_dir) = (tmp.path().join("old"), tmp.path().join("new"));
+        write_qwen3_safetensors(&old_dir);
+        write_qwen3_safetensors(&new_dir);
+        let bot = ChatBot::with_root(tmp.path(), Device::Cpu);
+        bot.load_safetensors_model(&old_dir, None, None).unwrap();
+
+        // Генерация на прежней модели держит копию движка
+        let in_flight = bot.get_engine().read().clone().unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let target = SwapTarget::Safetensors {
+            dir: new_dir,
+            dtype: None,
+        };
+        let handle = bot.hot_swap(target, SwapPolicy::Drain, None, Some(recorder.clone()));
+
+        while Arc::ptr_eq(&in_flight, bot.get_engine().read().as_ref().unwrap()) {
+            std::thread::sleep(RELEASE_POLL_INTERVAL);
+        }
+        std::thread::sleep(RELEASE_POLL_INTERVAL * 3);
+        assert!(!recorder.swapped.load(Ordering::SeqCst));
+        assert!(!in_flight.is_stop_requested());
+
+        drop(in_flight);
+        handle.join().unwrap();
+        assert!(recorder.swapped.load(Ordering::SeqCst));
+        assert_eq!(bot.list_resident_models()[0].id, {
+            let dir = tmp.path().join("new").canonicalize().unwrap();
+            dir.to_string_lossy().into_owned()
+        });
+    }
+
+    #[test]
+    fn test_swap_lock_is_released_while_draining() {
+        let tmp = tempdir().unwrap();
+        let (old_dir, new_dir) = (tmp.path().join("old"), tmp.path().join("new"));
+        write_qwen3_safetensors(&old_dir);
+        write_qwen3_safetensors(&new_dir);
+        let bot = ChatBot::with_root(tmp.path(), Device::Cpu);
+        bot.load_safetensors_model(&old_dir, None, None).unwrap();
+        let mut coder = PersonaProfile::new("coder", "You write Rust.");
+        coder.generation.temperature = 0.2;
+        bot.create_persona(coder).unwrap();
+
+        let in_flight = bot.get_engine().read().clone().unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let target = SwapTarget::Safetensors {
+            dir: new_dir.clone(),
+            dtype: None,
+        };
+        let handle = bot.hot_swap(target, SwapPolicy::Drain, None, Some(recorder.clone()));
+        while Arc::ptr_eq(&in_flight, bot.get_engine().read().as_ref().unwrap()) {
+            std::thread::sleep(RELEASE_POLL_INTERVAL);
+        }
+
+        // Менеджер уже сообщает о новой модели, а её движок установлен
+        let active = bot
+            .list_resident_models()
+            .into_iter()
+            .find(|model| model.active);
+        let new_id = new_dir
+            .canonicalize()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        assert_eq!(active.unwrap().id, new_id);
+
+        // Пока прежняя генерация не завершилась, модель и персону можно менять
+        bot.set_max_resident_models(2);
+        bot.select_persona("coder").unwrap();
+        assert!(!recorder.swapped.load(Ordering::SeqCst));
+        let engine = bot.get_engine().read().clone().unwrap();
+        assert!((engine.generation_params().temperature - 0.2).abs() < f32::EPSILON);
+
+        drop(in_flight);
+        handle.join().unwrap();
+        assert!(recorder.swapped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_drain_stops_generation_after_timeout() {
+        let tmp = tempdir().unwrap();
+        let (old_dir, new_dir) = (tmp.path().join("old"), tmp.path().join("new"));
+        write_qwen3_safetensors(&old_dir);
+        write_qwen3_safetensors(&new_dir);
+        let bot = ChatBot::with_root(tmp.path(), Device::Cpu);
+        bot.load_safetensors_model(&old_dir, None, None).unwrap();
+
+        let in_flight = bot.get_engine().read().clone().unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let target = SwapTarget::Safetensors {
+            dir: new_dir,
+            dtype: None,
+        };
+        let swap = HotSwap {
+            drain_timeout: RELEASE_POLL_INTERVAL,
+            ..bot.hot_swap_state()
+        };
+        let handle = swap.spawn(target, SwapPolicy::Drain, None, Some(recorder.clone()));
+
+        // Генерация не завершилась вовремя — её останавливают
+        while !in_flight.is_stop_requested() {
+            std::thread::sleep(RELEASE_POLL_INTERVAL);
+        }
+        drop(in_flight);
+        handle.join().unwrap();
+        assert!(recorder.swapped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_failed_swap_keeps_serving_model() {
+        let tmp = tempdir().unwrap();
+        write_qwen3_safetensors(&tmp.path().join("old"));
+        let bot = ChatBot::with_root(tmp.path(), Device::Cpu);
+        bot.load_safetensors_model(&tmp.path().join("old"), None, None)
+            .unwrap();
+        let serving = bot.get_engine().read().clone().unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let target = SwapTarget::Path(tmp.path().join("missing.gguf"));
+        bot.hot_swap(target, SwapPolicy::Cancel, None, Some(recorder.clone()))
+            .join()
+            .unwrap();
+        assert!(recorder.failed.load(Ordering::SeqCst));
+        assert!(Arc::ptr_eq(
+            &serving,
+            bot.get_engine().read().as_ref().unwrap()
+        ));
+
+        let target: SwapTarget =
+            serde_json::from_str(r#"{"safetensors": {"dir": "/models/qwen3"}}"#).unwrap();
+        assert!(matches!(
+            target,
+            SwapTarget::Safetensors { dtype: None, .. }
+        ));
+        assert_eq!(SwapPolicy::parse("cancel"), Some(SwapPolicy::Cancel));
+    }
+}
@@ -1,15 +1,89 @@
+pub mod hot_swap;
+pub mod persona;
+
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
+use candle_core::quantized::gguf_file;
 use candle_core::Device;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+
+use crate::batch::BatchCallback;
+use crate::candidates::{Candidate, CandidateMode};
+use crate::external_stores::{default_stores, ExternalStore, StoreKind};
+use crate::gguf_editor::{
+    metadata_entries, parse_edits, MetadataEdit, MetadataEntry, CHAT_TEMPLATE_KEY, NAME_KEY,
+};
+use crate::load_progress::LoadProgressCallback;
+use crate::model_catalog::ModelCatalogEntry;
+use crate::model_convert::{ConversionReport, QuantizationPlan};
+use crate::model_download::{DownloadReport, DownloadRequest, HttpClient};
+use crate::model_inference::{
+    ChatMessage, FinishReason, GenerationConfig, InferenceEngine, InferenceError, RenderedPrompt,
+    StreamCallback,
+};
+use crate::model_integrity::VerifyReport;
+use crate::model_manager::{
+    ActiveModelListener, LoadedModelSnapshot, ModelManager, ModelManagerError, ModelType,
+    ResidentModel,
+};
+use crate::model_memory::{MemoryEstimate, QuantizationRecommendation};
+use crate::model_source::ModelSource;
+use crate::model_store::ModelId;
+use crate::safetensors_model::dtype_from_name;
+use crate::speculative::{DraftModel, SpeculativeStats};
+use hot_swap::{HotSwap, SwapCallback, SwapPolicy, SwapTarget};
+use persona::{PersonaError, PersonaProfile, PersonaStore};
+
+/// Устанавливает движок для активной модели менеджера. Как наблюдатель вызывается под
+/// блокировкой пула, поэтому активная модель и движок сменяются одновременно; персона,
+/// параметры и черновая модель читаются в момент установки.
+struct EngineInstaller {
+    engine: Arc<RwLock<Option<Arc<InferenceEngine>>>>,
+    personas: Arc<RwLock<PersonaStore>>,
+    generation: Arc<RwLock<Option<GenerationConfig>>>,
+    draft: Arc<RwLock<Option<DraftModel>>>,
+    stop_flag: Arc<AtomicBool>,
+}
+
+impl EngineInstaller {
+    fn install(&self, model: Option<LoadedModelSnapshot>) {
+        let engine = model.map(|snapshot| {
+            let mut engine = InferenceEngine::new(snapshot);
+            ChatBot::configure_engine(
+                &mut engine,
+                self.personas.read().active(),
+                self.generation.read().as_ref(),
+            );
+            engine.set_draft_model(self.draft.read().clone());
+            engine.set_stop_flag(self.stop_flag.clone());
+            Arc::new(engine)
+        });
+        *self.engine.write() = engine;
+    }
+}
 
-use crate::model_inference::{GenerationConfig, InferenceEngine, InferenceError, StreamCallback};
-use crate::model_manager::{ModelManager, ModelManagerError, ModelType};
+impl ActiveModelListener for EngineInstaller {
+    fn on_active_model_changed(&self, model: Option<LoadedModelSnapshot>) {
+        self.install(model);
+    }
+}
 
 /// ChatBot управляет загрузкой моделей и выполнением инференса.
 pub struct ChatBot {
     model_manager: Arc<ModelManager>,
-    engine: RwLock<Option<InferenceEngine>>,
+    /// Активный движок; генерации берут копию `Arc` и не блокируют замену модели.
+    engine: Arc<RwLock<Option<Arc<InferenceEngine>>>>,
+    personas: Arc<RwLock<PersonaStore>>,
+    swap_lock: Arc<Mutex<()>>,
+    /// Черновая модель спекулятивного декодирования для всех движков.
+    draft: Arc<RwLock<Option<DraftModel>>>,
+    /// Параметры генерации, заданные пользователем поверх персоны; переживают
+    /// загрузку и замену модели.
+    generation: Arc<RwLock<Option<GenerationConfig>>>,
+    /// Флаг остановки, общий для всех движков: `stopGeneration` действует и после
+    /// пересоздания движка.
+    stop_flag: Arc<AtomicBool>,
 }
 
 impl Default for ChatBot {
@@ -19,27 +93,58 @@ impl Default for ChatBot {
 }
 
 impl ChatBot {
-    /// Создаёт новый чат-бот с указанным устройством.
+    /// Создаёт новый чат-бот с указанным устройством и подключает хранилища Ollama
+    /// и LM Studio, найденные в домашней директории.
     pub fn new(device: Device) -> Self {
         let root_dir = std::env::var("RUST_MODEL_ROOT").unwrap_or_else(|_| "/data/local/tmp/models".into());
+        let bot = Self::with_root(root_dir, device);
+        for store in default_stores() {
+            bot.model_manager.add_external_store(store);
+        }
+        bot
+    }
+
+    /// Создаёт чат-бот с корневой директорией моделей `root_dir`; внешние хранилища
+    /// не подключаются.
+    pub fn with_root(root_dir: impl AsRef<std::path::Path>, device: Device) -> Self {
         let manager = ModelManager::new(root_dir, device);
-        Self {
+        let personas = PersonaStore::open(manager.root_dir());
+        let bot = Self {
             model_manager: Arc::new(manager),
-            engine: RwLock::new(None),
-        }
+            engine: Arc::new(RwLock::new(None)),
+            personas: Arc::new(RwLock::new(personas)),
+            swap_lock: Arc::new(Mutex::new(())),
+            draft: Arc::new(RwLock::new(None)),
+            generation: Arc::new(RwLock::new(None)),
+            stop_flag: Arc::new(AtomicBool::new(false)),
+        };
+        bot.model_manager
+            .set_active_model_listener(Some(Arc::new(bot.engine_installer())));
+        bot
     }
 
     /// Загружает модель Qwen3 указанного варианта (например, "0.6b").
     pub async fn load_qwen3(&self, variant: &str) -> Result<(), ModelManagerError> {
-        self.model_manager.load_model(ModelType::Qwen3, variant)?;
-        self.refresh_engine();
+        self.change_model(|manager| manager.load_model(ModelType::Qwen3, variant))?;
         Ok(())
     }
 
     /// Загружает модель Gemma3 указанного варианта.
     pub async fn load_gemma3(&self, variant: &str) -> Result<(), ModelManagerError> {
-        self.model_manager.load_model(ModelType::Gemma3, variant)?;
-        self.refresh_engine();
+        self.change_model(|manager| manager.load_model(ModelType::Gemma3, variant))?;
+        Ok(())
+    }
+
+    /// Загружает модель указанного типа с отчётом о прогрессе.
+    pub async fn load_model_with_progress(
+        &self,
+        model_type: ModelType,
+        variant: &str,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<(), ModelManagerError> {
+        self.change_model(|manager| {
+            manager.load_model_with_progress(model_type, variant, progress)
+        })?;
         Ok(())
     }
 
@@ -49,69 +154,563 @@ impl ChatBot {
         model_path: &std::path::Path,
     ) -> Result<ModelType, ModelManagerError> {
         log::info!("ChatBot::load_model_from_path called with path: {:?}", model_path);
-        let model_type = self.model_manager.load_model_from_path(model_path)?;
-        self.refresh_engine();
-        Ok(model_type)
+        self.change_model(|manager| manager.load_model_from_path(model_path))
+    }
+
+    /// Загружает модель из GGUF файла с отчётом о прогрессе.
+    pub fn load_model_from_path_with_progress(
+        &self,
+        model_path: &std::path::Path,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
+        self.change_model(|manager| {
+            manager.load_model_from_path_with_progress(model_path, progress)
+        })
     }
 
-    /// Переключает модель, выгружая текущую и загружая новую.
+    /// Загружает модель из пути, файлового дескриптора или буфера в памяти.
+    pub fn load_model_from_source(
+        &self,
+        source: ModelSource,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
+        self.change_model(|manager| manager.load_model_from_source(source, progress))
+    }
+
+    /// Загружает неквантованную модель Hugging Face из директории с safetensors.
+    /// `dtype` — `f16`, `bf16` или `f32`; без него используется `torch_dtype` модели.
+    pub fn load_safetensors_model(
+        &self,
+        model_dir: &std::path::Path,
+        dtype: Option<&str>,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
+        let dtype = dtype.map(dtype_from_name).transpose()?;
+        self.change_model(|manager| manager.load_safetensors_model(model_dir, dtype, progress))
+    }
+
+    /// Загружает модель хранилища по идентификатору (например, `qwen3/0.6b`).
+    pub fn load_stored_model(
+        &self,
+        model_id: &str,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
+        let id = ModelId::parse(model_id)?;
+        self.change_model(|manager| manager.load_stored_model(&id, progress))
+    }
+
+    /// Загружает GGUF модель из кеша Hugging Face Hub (`models--org--name/snapshots/<rev>/`).
+    pub fn load_model_from_hf_cache(
+        &self,
+        repo_id: &str,
+        revision: Option<&str>,
+        file_name: Option<&str>,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
+        let model_path = self
+            .model_manager
+            .resolve_hf_model(repo_id, revision, file_name)?;
+        self.load_model_from_path_with_progress(&model_path, progress)
+    }
+
+    /// Подключает хранилище Ollama или LM Studio: его модели появляются в каталоге
+    /// и загружаются на месте, без копирования.
+    pub fn add_model_store(
+        &self,
+        kind: StoreKind,
+        root: impl Into<std::path::PathBuf>,
+    ) -> Result<(), ModelManagerError> {
+        let store = ExternalStore::new(kind, root);
+        if !store.root.is_dir() {
+            return Err(ModelManagerError::ModelFileMissing(
+                store.root.to_string_lossy().into_owned(),
+            ));
+        }
+        self.model_manager.add_external_store(store);
+        Ok(())
+    }
+
+    /// Задаёт путь к токенизатору для следующих загрузок; `None` включает автоматический выбор.
+    pub fn set_tokenizer_override(&self, path: Option<std::path::PathBuf>) {
+        self.model_manager.set_tokenizer_override(path);
+    }
+
+    /// Отменяет текущую загрузку модели; ранее загруженная модель сохраняется.
+    pub fn cancel_model_load(&self) {
+        self.model_manager.cancel_load();
+    }
+
+    /// Переключает модель. Модель, уже находящаяся в памяти, активируется без перезагрузки;
+    /// прежняя остаётся в пуле, пока не будет вытеснена.
     pub async fn switch_model(
         &self,
         model_type: ModelType,
         variant: &str,
     ) -> Result<(), ModelManagerError> {
-        self.model_manager.unload_model();
-        self.model_manager.load_model(model_type, variant)?;
-        self.refresh_engine();
+        self.change_model(|manager| manager.load_model(model_type, variant))?;
         Ok(())
     }
 
+    /// Переключает модель без остановки обслуживания: новая модель загружается в фоне,
+    /// прежняя отвечает на запросы до атомарной замены движка. Начатые генерации
+    /// завершаются на прежней модели (`Drain`) или останавливаются (`Cancel`);
+    /// `callback` вызывается, когда замена вступила в силу.
+    pub fn hot_swap(
+        &self,
+        target: SwapTarget,
+        policy: SwapPolicy,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+        callback: Option<Arc<dyn SwapCallback>>,
+    ) -> std::thread::JoinHandle<()> {
+        self.hot_swap_state()
+            .spawn(target, policy, progress, callback)
+    }
+
+    fn hot_swap_state(&self) -> HotSwap {
+        HotSwap {
+            manager: self.model_manager.clone(),
+            engine: self.engine.clone(),
+            lock: self.swap_lock.clone(),
+            drain_timeout: hot_swap::DRAIN_TIMEOUT,
+        }
+    }
+
     /// Выгружает текущую модель из памяти.
     pub fn unload_model(&self) {
         log::info!("ChatBot::unload_model called");
-        self.model_manager.unload_model();
-        self.refresh_engine();
+        let _ = self.change_model(|manager| {
+            manager.unload_model();
+            Ok(())
+        });
+    }
+
+    /// Модели, находящиеся в памяти, от недавно использованных к давно не использованным.
+    pub fn list_resident_models(&self) -> Vec<ResidentModel> {
+        self.model_manager.resident_models()
+    }
+
+    /// Делает модель из памяти активной без повторной загрузки.
+    pub fn select_model(&self, model_id: &str) -> Result<ModelType, ModelManagerError> {
+        self.change_model(|manager| manager.activate_model(model_id))
+    }
+
+    /// Выгружает модель из памяти по идентификатору.
+    pub fn unload_resident_model(&self, model_id: &str) -> Result<(), ModelManagerError> {
+        self.change_model(|manager| manager.unload_resident_model(model_id))
+    }
+
+    /// Задаёт, сколько моделей может одновременно находиться в памяти.
+    pub fn set_max_resident_models(&self, max_models: usize) {
+        let _ = self.change_model(|manager| {
+            manager.set_max_resident_models(max_models);
+            Ok(())
+        });
     }
 
-    /// Устанавливает параметры генерации.
+    /// Устанавливает параметры генерации поверх параметров персоны; они сохраняются
+    /// до выбора другой персоны или `reset_generation_params`.
     pub fn set_generation_params(&self, config: GenerationConfig) {
+        *self.generation.write() = Some(config.clone());
         if let Some(engine) = self.engine.write().as_mut() {
-            engine.set_generation_params(config);
+            Arc::make_mut(engine).set_generation_params(config);
         }
     }
 
+    /// Возвращает параметры генерации активной персоны.
+    pub fn reset_generation_params(&self) {
+        *self.generation.write() = None;
+        self.apply_persona(self.personas.read().active());
+    }
+
+    /// Включает спекулятивное декодирование: GGUF модель `model_path` с тем же
+    /// токенизатором, что и основная, предлагает `draft_tokens` токенов за шаг.
+    pub fn set_draft_model(
+        &self,
+        model_path: &std::path::Path,
+        draft_tokens: usize,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<(), ModelManagerError> {
+        let _guard = self.swap_lock.lock();
+        let snapshot = self.model_manager.load_draft_model(model_path, progress)?;
+        *self.draft.write() = Some(DraftModel::new(snapshot, draft_tokens));
+        self.refresh_engine();
+        Ok(())
+    }
+
+    /// Отключает спекулятивное декодирование и выгружает черновую модель.
+    pub fn clear_draft_model(&self) {
+        let _guard = self.swap_lock.lock();
+        *self.draft.write() = None;
+        self.model_manager.unload_draft_model();
+        self.refresh_engine();
+    }
+
+    /// Статистика последней спекулятивной генерации активного движка.
+    pub fn speculative_stats(&self) -> Option<SpeculativeStats> {
+        self.engine.read().as_ref()?.speculative_stats()
+    }
+
+    /// Причина завершения последней генерации активного движка.
+    pub fn finish_reason(&self) -> Option<FinishReason> {
+        self.engine.read().as_ref()?.finish_reason()
+    }
+
     /// Вызывает инференс, возвращая полный ответ.
     pub fn generate_text(
         &self,
         prompt: &str,
         callback: Option<Arc<dyn StreamCallback>>,
     ) -> Result<String, InferenceError> {
-        let engine_guard = self.engine.read();
-        let engine = engine_guard
-            .as_ref()
-            .ok_or(InferenceError::ModelNotLoaded)?;
+        let engine = self.active_engine()?;
         engine.generate_blocking(prompt, callback)
     }
 
-    fn refresh_engine(&self) {
-        match self.model_manager.current_model() {
-            Some(snapshot) => {
-                let engine = InferenceEngine::new(snapshot);
-                *self.engine.write() = Some(engine);
-            }
+    /// Генерирует несколько вариантов ответа с суммарными логарифмическими вероятностями.
+    pub fn generate_candidates(
+        &self,
+        prompt: &str,
+        mode: CandidateMode,
+    ) -> Result<Vec<Candidate>, InferenceError> {
+        let engine = self.active_engine()?;
+        engine.generate_candidates(prompt, mode)
+    }
+
+    /// Генерирует ответы на несколько промптов пакетами; текст стримится по номеру промпта.
+    pub fn generate_batch(
+        &self,
+        prompts: &[String],
+        max_batch_size: usize,
+        callback: Option<Arc<dyn BatchCallback>>,
+    ) -> Result<Vec<String>, InferenceError> {
+        let engine = self.active_engine()?;
+        engine.generate_batch(prompts, max_batch_size, callback)
+    }
+
+    /// Генерирует ответ моделью из памяти с идентификатором `model_id`, не меняя активную.
+    /// Используются параметры генерации и системный промпт активного движка.
+    pub fn generate_text_with_model(
+        &self,
+        model_id: &str,
+        prompt: &str,
+        callback: Option<Arc<dyn StreamCallback>>,
+    ) -> Result<String, InferenceError> {
+        let snapshot = self
+            .model_manager
+            .resident_model(model_id)
+            .map_err(|_| InferenceError::ModelNotLoaded)?;
+        let engine = match self.engine.read().as_ref() {
+            Some(engine) => engine.with_model(snapshot),
             None => {
-                *self.engine.write() = None;
+                let mut engine = InferenceEngine::new(snapshot);
+                Self::configure_engine(
+                    &mut engine,
+                    self.personas.read().active(),
+                    self.generation.read().as_ref(),
+                );
+                engine.set_stop_flag(self.stop_flag.clone());
+                engine
             }
+        };
+        engine.generate_blocking(prompt, callback)
+    }
+
+    /// Считает токены текста токенизатором загруженной модели.
+    pub fn count_tokens(&self, text: &str) -> Result<usize, InferenceError> {
+        self.with_engine(|engine| engine.count_tokens(text))
+    }
+
+    /// Кодирует текст в идентификаторы токенов.
+    pub fn tokenize(&self, text: &str) -> Result<Vec<u32>, InferenceError> {
+        self.with_engine(|engine| engine.tokenize(text))
+    }
+
+    /// Декодирует идентификаторы токенов в текст.
+    pub fn detokenize(&self, ids: &[u32]) -> Result<String, InferenceError> {
+        self.with_engine(|engine| engine.detokenize(ids))
+    }
+
+    /// Возвращает промпт, который получит модель для указанных сообщений, и число его токенов.
+    pub fn render_prompt(
+        &self,
+        messages: &[ChatMessage],
+    ) -> Result<RenderedPrompt, InferenceError> {
+        self.with_engine(|engine| engine.render_prompt(messages))
+    }
+
+    fn with_engine<R>(
+        &self,
+        f: impl FnOnce(&InferenceEngine) -> Result<R, InferenceError>,
+    ) -> Result<R, InferenceError> {
+        let engine = self.active_engine()?;
+        f(&engine)
+    }
+
+    /// Копия активного движка: блокировка не удерживается на время генерации.
+    fn active_engine(&self) -> Result<Arc<InferenceEngine>, InferenceError> {
+        self.engine
+            .read()
+            .clone()
+            .ok_or(InferenceError::ModelNotLoaded)
+    }
+
+    /// Возвращает список профилей персон.
+    pub fn list_personas(&self) -> Vec<PersonaProfile> {
+        self.personas.read().list().to_vec()
+    }
+
+    /// Возвращает активный профиль персоны.
+    pub fn active_persona(&self) -> PersonaProfile {
+        self.personas.read().active().clone()
+    }
+
+    /// Создаёт новый профиль персоны.
+    pub fn create_persona(&self, profile: PersonaProfile) -> Result<(), PersonaError> {
+        self.personas.write().create(profile)
+    }
+
+    /// Обновляет профиль персоны; изменения активного профиля применяются сразу.
+    pub fn update_persona(&self, profile: PersonaProfile) -> Result<(), PersonaError> {
+        let mut personas = self.personas.write();
+        personas.update(profile)?;
+        self.apply_persona(personas.active());
+        Ok(())
+    }
+
+    /// Удаляет профиль персоны.
+    pub fn delete_persona(&self, name: &str) -> Result<(), PersonaError> {
+        let mut personas = self.personas.write();
+        personas.delete(name)?;
+        self.apply_persona(personas.active());
+        Ok(())
+    }
+
+    /// Делает профиль активным и применяет его к движку.
+    pub fn select_persona(&self, name: &str) -> Result<(), PersonaError> {
+        let mut personas = self.personas.write();
+        let active = personas.select(name)?;
+        // Выбор персоны возвращает её параметры генерации
+        *self.generation.write() = None;
+        self.apply_persona(active);
+        Ok(())
+    }
+
+    fn apply_persona(&self, persona: &PersonaProfile) {
+        if let Some(engine) = self.engine.write().as_mut() {
+            Self::configure_engine(
+                Arc::make_mut(engine),
+                persona,
+                self.generation.read().as_ref(),
+            );
+        }
+    }
+
+    /// Настраивает движок по персоне; `generation` — параметры пользователя поверх неё.
+    fn configure_engine(
+        engine: &mut InferenceEngine,
+        persona: &PersonaProfile,
+        generation: Option<&GenerationConfig>,
+    ) {
+        engine.set_generation_params(generation.unwrap_or(&persona.generation).clone());
+        engine.set_system_prompt(persona.system_prompt.clone(), persona.template_vars.clone());
+    }
+
+    /// Меняет активную модель под `swap_lock`, чтобы не пересечься с горячей заменой;
+    /// движок пересоздаёт [`EngineInstaller`] вместе со сменой модели.
+    fn change_model<T>(
+        &self,
+        change: impl FnOnce(&ModelManager) -> Result<T, ModelManagerError>,
+    ) -> Result<T, ModelManagerError> {
+        let _guard = self.swap_lock.lock();
+        change(&self.model_manager)
+    }
+
+    /// Пересоздаёт движок активной модели; вызывается под `swap_lock`.
+    fn refresh_engine(&self) {
+        self.engine_installer()
+            .install(self.model_manager.current_model());
+    }
+
+    fn engine_installer(&self) -> EngineInstaller {
+        EngineInstaller {
+            engine: self.engine.clone(),
+            personas: self.personas.clone(),
+            generation: self.generation.clone(),
+            draft: self.draft.clone(),
+            stop_flag: self.stop_flag.clone(),
         }
     }
 
+    /// Возвращает список установленных моделей.
+    pub fn list_models(&self) -> Vec<ModelCatalogEntry> {
+        self.model_manager.list_models()
+    }
+
+    /// Проверяет целостность GGUF файла; SHA-256 сверяется, если хеш передан или найден
+    /// рядом с моделью (`.sha256`, кеш Hugging Face).
+    pub fn verify_model(
+        &self,
+        model_path: &std::path::Path,
+        expected_sha256: Option<&str>,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<VerifyReport, ModelManagerError> {
+        self.model_manager
+            .verify_model(model_path, expected_sha256, progress)
+    }
+
+    /// Отменяет текущую проверку целостности.
+    pub fn cancel_verification(&self) {
+        self.model_manager.cancel_verification();
+    }
+
+    /// Копирует GGUF файл в хранилище моделей под указанным идентификатором.
+    pub fn import_model(
+        &self,
+        source: &std::path::Path,
+        model_id: &str,
+    ) -> Result<std::path::PathBuf, ModelManagerError> {
+        self.model_manager
+            .import_model(source, &ModelId::parse(model_id)?)
+    }
+
+    /// Скачивает файл из репозитория Hugging Face в хранилище моделей.
+    /// Прерванная или отменённая загрузка продолжается при следующем вызове.
+    pub fn download_model(
+        &self,
+        request: &DownloadRequest,
+        client: &dyn HttpClient,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<DownloadReport, ModelManagerError> {
+        self.model_manager.download_model(request, client, progress)
+    }
+
+    /// Отменяет текущую загрузку модели из сети.
+    pub fn cancel_download(&self) {
+        self.model_manager.cancel_download();
+    }
+
+    /// Задаёт зеркала Hugging Face для загрузки моделей.
+    pub fn set_download_mirrors(&self, mirrors: Vec<String>) {
+        self.model_manager.set_download_mirrors(mirrors);
+    }
+
+    /// Конвертирует GGUF или модель safetensors в GGUF с квантизацией по плану
+    /// (например `q4_k_m` или `q4_k_m,embedding=q8_0`) и сохраняет её под `model_id`.
+    pub fn convert_model(
+        &self,
+        input: &std::path::Path,
+        model_id: &str,
+        plan: &str,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ConversionReport, ModelManagerError> {
+        let plan = QuantizationPlan::parse(plan)?;
+        self.model_manager
+            .convert_model(input, &ModelId::parse(model_id)?, &plan, progress)
+    }
+
+    /// Отменяет текущую конвертацию модели.
+    pub fn cancel_conversion(&self) {
+        self.model_manager.cancel_conversion();
+    }
+
+    /// Возвращает метаданные GGUF модели для отображения.
+    pub fn get_model_metadata(
+        &self,
+        model_path: &std::path::Path,
+    ) -> Result<Vec<MetadataEntry>, ModelManagerError> {
+        Ok(metadata_entries(
+            &self.model_manager.model_metadata(model_path)?,
+        ))
+    }
+
+    /// Изменяет метаданные модели по JSON объекту `{"ключ": значение}`; `null` удаляет ключ.
+    pub fn edit_model_metadata(
+        &self,
+        model_path: &std::path::Path,
+        edits_json: &str,
+    ) -> Result<(), ModelManagerError> {
+        let current = self.model_manager.model_metadata(model_path)?;
+        let edits = parse_edits(edits_json, &current)?;
+        self.model_manager.edit_metadata(model_path, &edits)?;
+        Ok(())
+    }
+
+    /// Заменяет chat template модели; шаблон проверяется перед записью.
+    pub fn set_model_chat_template(
+        &self,
+        model_path: &std::path::Path,
+        template: &str,
+    ) -> Result<(), ModelManagerError> {
+        let edit = MetadataEdit::Set(
+            CHAT_TEMPLATE_KEY.to_string(),
+            gguf_file::Value::String(template.to_string()),
+        );
+        self.model_manager.edit_metadata(model_path, &[edit])?;
+        Ok(())
+    }
+
+    /// Задаёт отображаемое имя модели (`general.name`).
+    pub fn set_model_display_name(
+        &self,
+        model_path: &std::path::Path,
+        name: &str,
+    ) -> Result<(), ModelManagerError> {
+        let edit = MetadataEdit::Set(
+            NAME_KEY.to_string(),
+            gguf_file::Value::String(name.to_string()),
+        );
+        self.model_manager.edit_metadata(model_path, &[edit])?;
+        Ok(())
+    }
+
+    /// Переименовывает модель в хранилище.
+    pub fn rename_model(&self, from: &str, to: &str) -> Result<(), ModelManagerError> {
+        self.model_manager
+            .rename_model(&ModelId::parse(from)?, &ModelId::parse(to)?)
+    }
+
+    /// Удаляет модель из хранилища; загруженную модель удалить нельзя.
+    pub fn delete_model(&self, model_id: &str) -> Result<(), ModelManagerError> {
+        self.model_manager.delete_model(&ModelId::parse(model_id)?)
+    }
+
+    /// Возвращает сведения о модели по пути к GGUF файлу.
+    pub fn get_model_info(
+        &self,
+        model_path: &std::path::Path,
+    ) -> Result<ModelCatalogEntry, ModelManagerError> {
+        self.model_manager.model_info(model_path)
+    }
+
+    /// Задаёт бюджет памяти для загрузки моделей; `None` отключает проверку.
+    pub fn set_memory_budget(&self, budget: Option<u64>) {
+        self.model_manager.set_memory_budget(budget);
+    }
+
+    /// Оценивает память, необходимую для загрузки модели.
+    pub fn estimate_model_memory(
+        &self,
+        model_path: &std::path::Path,
+    ) -> Result<MemoryEstimate, ModelManagerError> {
+        self.model_manager.estimate_memory(model_path)
+    }
+
+    /// Подбирает квантование, помещающееся в доступную RAM.
+    pub fn recommend_quantization(
+        &self,
+        candidates: &[std::path::PathBuf],
+        available_ram: u64,
+    ) -> Result<QuantizationRecommendation, ModelManagerError> {
+        self.model_manager
+            .recommend_quantization(candidates, available_ram)
+    }
+
     /// Проверяет, загружена ли модель.
     pub fn is_model_loaded(&self) -> bool {
         self.model_manager.is_loaded()
     }
 
     /// Возвращает ссылку на engine для JNI.
-    pub fn get_engine(&self) -> &RwLock<Option<InferenceEngine>> {
+    pub fn get_engine(&self) -> &RwLock<Option<Arc<InferenceEngine>>> {
         &self.engine
     }
 }
@@ -125,4 +724,115 @@ mod tests {
         let bot = ChatBot::default();
         assert!(!bot.is_model_loaded());
     }
+
+    #[test]
+    fn test_generation_params_survive_engine_rebuild() {
+        use crate::test_support::write_qwen3_gguf;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("qwen/model.gguf");
+        write_qwen3_gguf(&path, 0);
+        let bot = ChatBot::with_root(tmp.path(), Device::Cpu);
+        let config = GenerationConfig {
+            max_tokens: 7,
+            temperature: 0.2,
+            ..GenerationConfig::default()
+        };
+        let engine_params = |bot: &ChatBot| {
+            bot.with_engine(|engine| Ok(engine.generation_params().clone()))
+                .unwrap()
+        };
+
+        // Параметры, заданные до загрузки, применяются к новому движку
+        bot.set_generation_params(config.clone());
+        bot.load_model_from_path(&path).unwrap();
+        assert_eq!(engine_params(&bot), config);
+        bot.clear_draft_model();
+        bot.set_max_resident_models(2);
+        assert_eq!(engine_params(&bot), config);
+
+        // Выбор персоны возвращает её параметры
+        bot.select_persona(persona::DEFAULT_PERSONA_NAME).unwrap();
+        assert_eq!(engine_params(&bot), bot.active_persona().generation);
+    }
+
+    #[test]
+    fn test_stop_reaches_engine_rebuilt_during_generation() {
+        use crate::test_support::write_qwen3_gguf;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("qwen/model.gguf");
+        write_qwen3_gguf(&path, 0);
+        let bot = ChatBot::with_root(tmp.path(), Device::Cpu);
+        bot.load_model_from_path(&path).unwrap();
+
+        // Генерация держит прежний движок, а JNI останавливает текущий
+        let in_flight = bot.active_engine().unwrap();
+        bot.clear_draft_model();
+        assert!(!Arc::ptr_eq(&in_flight, &bot.active_engine().unwrap()));
+        bot.get_engine().read().as_ref().unwrap().stop_generation();
+        assert!(in_flight.is_stop_requested());
+
+        // Вывод движка из работы не останавливает генерации других движков
+        in_flight.reset_stop_flag();
+        in_flight.cancel_generations();
+        assert!(in_flight.is_stop_requested());
+        assert!(!bot.active_engine().unwrap().is_stop_requested());
+    }
+
+    #[test]
+    fn test_draft_model_speculative_generation() {
+        use crate::test_support::{write_qwen3_gguf, write_qwen3_safetensors};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("target/model.gguf");
+        let draft = tmp.path().join("draft/model.gguf");
+        write_qwen3_gguf(&target, 0);
+        write_qwen3_gguf(&draft, 1);
+        let other = tmp.path().join("other/model.gguf");
+        write_qwen3_gguf(&other, 2);
+        write_qwen3_safetensors(&tmp.path().join("hf"));
+        std::fs::copy(
+            tmp.path().join("hf/tokenizer.json"),
+            tmp.path().join("other/tokenizer.json"),
+        )
+        .unwrap();
+        let bot = ChatBot::with_root(tmp.path(), Device::Cpu);
+        bot.load_model_from_path(&target).unwrap();
+
+        // Токенизатор с другим словарём
+        assert!(matches!(
+            bot.set_draft_model(&other, 4, None),
+            Err(ModelManagerError::IncompatibleDraftModel(_))
+        ));
+        bot.set_draft_model(&draft, 3, None).unwrap();
+        bot.set_generation_params(GenerationConfig {
+            max_tokens: 6,
+            ..GenerationConfig::default()
+        });
+        bot.generate_text("hello a b", None).unwrap();
+        let stats = bot.speculative_stats().unwrap();
+        assert!(stats.generated > 0 && stats.generated <= 6);
+        assert!(stats.drafted >= stats.accepted);
+        assert_eq!(bot.list_resident_models().len(), 1);
+
+        bot.clear_draft_model();
+        assert!(bot.speculative_stats().is_none());
+
+        // Статистика относится только к последней генерации
+        let lookup = GenerationConfig {
+            max_tokens: 6,
+            prompt_lookup: Some(crate::speculative::PromptLookup::default()),
+            ..GenerationConfig::default()
+        };
+        bot.set_generation_params(lookup.clone());
+        bot.generate_text("hello a b", None).unwrap();
+        assert!(bot.speculative_stats().is_some());
+        bot.set_generation_params(GenerationConfig {
+            prompt_lookup: None,
+            ..lookup
+        });
+        bot.generate_text("hello a b", None).unwrap();
+        assert!(bot.speculative_stats().is_none());
+    }
 }
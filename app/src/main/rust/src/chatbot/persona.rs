@@ -0,0 +1,281 @@
+//! Профили персон: системный промпт, параметры генерации по умолчанию
+//! и дополнительные переменные chat template.
+//! Профили хранятся в JSON-файле в корневой директории моделей.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::model_inference::{GenerationConfig, DEFAULT_SYSTEM_PROMPT};
+
+/// Имя файла с профилями внутри корневой директории моделей.
+pub const PERSONAS_FILE_NAME: &str = "personas.json";
+
+/// Имя встроенного профиля, используемого по умолчанию.
+pub const DEFAULT_PERSONA_NAME: &str = "default";
+
+/// Ошибки работы с профилями персон.
+#[derive(Debug, Error)]
+pub enum PersonaError {
+    #[error("Имя профиля не может быть пустым")]
+    EmptyName,
+    #[error("Профиль '{0}' уже существует")]
+    AlreadyExists(String),
+    #[error("Профиль '{0}' не найден")]
+    NotFound(String),
+    #[error("Встроенный профиль '{0}' нельзя удалить")]
+    BuiltIn(String),
+    #[error("Ошибка ввода-вывода профилей: {0}")]
+    Io(String),
+    #[error("Ошибка формата профилей: {0}")]
+    Format(String),
+}
+
+impl From<std::io::Error> for PersonaError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for PersonaError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Format(err.to_string())
+    }
+}
+
+/// Именованный профиль персоны.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PersonaProfile {
+    pub name: String,
+    pub system_prompt: String,
+    #[serde(default)]
+    pub generation: GenerationConfig,
+    /// Дополнительные переменные, передаваемые в chat template
+    /// (например, `enable_thinking`).
+    #[serde(default)]
+    pub template_vars: BTreeMap<String, serde_json::Value>,
+}
+
+impl PersonaProfile {
+    /// Создаёт профиль с параметрами генерации по умолчанию.
+    pub fn new(name: impl Into<String>, system_prompt: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            system_prompt: system_prompt.into(),
+            generation: GenerationConfig::default(),
+            template_vars: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for PersonaProfile {
+    fn default() -> Self {
+        Self::new(DEFAULT_PERSONA_NAME, DEFAULT_SYSTEM_PROMPT)
+    }
+}
+
+/// Содержимое файла профилей.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PersonaFile {
+    #[serde(default)]
+    active: Option<String>,
+    #[serde(default)]
+    profiles: Vec<PersonaProfile>,
+}
+
+/// Хранилище профилей, синхронизируемое с JSON-файлом.
+pub struct PersonaStore {
+    path: PathBuf,
+    data: PersonaFile,
+}
+
+impl PersonaStore {
+    /// Открывает хранилище в корневой директории моделей.
+    /// Отсутствующий или повреждённый файл заменяется встроенным профилем.
+    pub fn open(root_dir: impl AsRef<Path>) -> Self {
+        let path = root_dir.as_ref().join(PERSONAS_FILE_NAME);
+        let data = match Self::read_file(&path) {
+            Ok(data) => data,
+            Err(err) => {
+                if path.exists() {
+                    log::warn!("Не удалось прочитать профили {:?}: {}", path, err);
+                }
+                PersonaFile::default()
+            }
+        };
+
+        let mut store = Self { path, data };
+        store.ensure_default();
+        store
+    }
+
+    fn read_file(path: &Path) -> Result<PersonaFile, PersonaError> {
+        let bytes = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    fn ensure_default(&mut self) {
+        if self.find(DEFAULT_PERSONA_NAME).is_none() {
+            self.data.profiles.insert(0, PersonaProfile::default());
+        }
+        if let Some(active) = &self.data.active {
+            if self.find(active).is_none() {
+                self.data.active = None;
+            }
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.data.profiles.iter().position(|p| p.name == name)
+    }
+
+    /// Сохраняет профили на диск через временный файл.
+    fn save(&self, data: &PersonaFile) -> Result<(), PersonaError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec_pretty(data)?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, json)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Применяет изменение к копии профилей и принимает его только после
+    /// успешного сохранения, чтобы память и диск не расходились.
+    fn commit(&mut self, change: impl FnOnce(&mut PersonaFile)) -> Result<(), PersonaError> {
+        let mut data = self.data.clone();
+        change(&mut data);
+        self.save(&data)?;
+        self.data = data;
+        Ok(())
+    }
+
+    /// Возвращает все профили.
+    pub fn list(&self) -> &[PersonaProfile] {
+        &self.data.profiles
+    }
+
+    /// Возвращает профиль по имени.
+    pub fn get(&self, name: &str) -> Option<&PersonaProfile> {
+        self.find(name).map(|idx| &self.data.profiles[idx])
+    }
+
+    /// Возвращает активный профиль (встроенный, если выбор не сделан).
+    pub fn active(&self) -> &PersonaProfile {
+        self.data
+            .active
+            .as_deref()
+            .and_then(|name| self.get(name))
+            .or_else(|| self.get(DEFAULT_PERSONA_NAME))
+            .expect("default persona is always present")
+    }
+
+    /// Добавляет новый профиль.
+    pub fn create(&mut self, profile: PersonaProfile) -> Result<(), PersonaError> {
+        if profile.name.trim().is_empty() {
+            return Err(PersonaError::EmptyName);
+        }
+        if self.find(&profile.name).is_some() {
+            return Err(PersonaError::AlreadyExists(profile.name));
+        }
+        self.commit(|data| data.profiles.push(profile))
+    }
+
+    /// Заменяет существующий профиль с тем же именем.
+    pub fn update(&mut self, profile: PersonaProfile) -> Result<(), PersonaError> {
+        let idx = self
+            .find(&profile.name)
+            .ok_or_else(|| PersonaError::NotFound(profile.name.clone()))?;
+        self.commit(|data| data.profiles[idx] = profile)
+    }
+
+    /// Удаляет профиль. Если он был активным, активным становится встроенный.
+    pub fn delete(&mut self, name: &str) -> Result<(), PersonaError> {
+        if name == DEFAULT_PERSONA_NAME {
+            return Err(PersonaError::BuiltIn(name.to_string()));
+        }
+        let idx = self
+            .find(name)
+            .ok_or_else(|| PersonaError::NotFound(name.to_string()))?;
+        self.commit(|data| {
+            data.profiles.remove(idx);
+            if data.active.as_deref() == Some(name) {
+                data.active = None;
+            }
+        })
+    }
+
+    /// Делает профиль активным.
+    pub fn select(&mut self, name: &str) -> Result<&PersonaProfile, PersonaError> {
+        if self.find(name).is_none() {
+            return Err(PersonaError::NotFound(name.to_string()));
+        }
+        self.commit(|data| data.active = Some(name.to_string()))?;
+        Ok(self.active())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_default_persona_present() {
+        let tmp = tempdir().unwrap();
+        let store = PersonaStore::open(tmp.path());
+        assert_eq!(store.list().len(), 1);
+        assert_eq!(store.active().system_prompt, DEFAULT_SYSTEM_PROMPT);
+    }
+
+    #[test]
+    fn test_persona_crud_persists() {
+        let tmp = tempdir().unwrap();
+        let mut store = PersonaStore::open(tmp.path());
+
+        let mut coder = PersonaProfile::new("coder", "You write Rust.");
+        coder.generation.temperature = 0.2;
+        store.create(coder.clone()).unwrap();
+        assert!(matches!(
+            store.create(coder.clone()),
+            Err(PersonaError::AlreadyExists(_))
+        ));
+        store.select("coder").unwrap();
+
+        coder.system_prompt = "You write idiomatic Rust.".into();
+        store.update(coder).unwrap();
+
+        let reopened = PersonaStore::open(tmp.path());
+        assert_eq!(reopened.active().name, "coder");
+        assert_eq!(reopened.active().system_prompt, "You write idiomatic Rust.");
+        assert!((reopened.active().generation.temperature - 0.2).abs() < f32::EPSILON);
+
+        let mut reopened = reopened;
+        assert!(matches!(
+            reopened.delete(DEFAULT_PERSONA_NAME),
+            Err(PersonaError::BuiltIn(_))
+        ));
+        reopened.delete("coder").unwrap();
+        assert_eq!(reopened.active().name, DEFAULT_PERSONA_NAME);
+    }
+
+    #[test]
+    fn test_failed_save_keeps_profiles_unchanged() {
+        let tmp = tempdir().unwrap();
+        // Корень — обычный файл, поэтому сохранить профили невозможно
+        let root = tmp.path().join("root");
+        std::fs::write(&root, b"").unwrap();
+        let mut store = PersonaStore::open(&root);
+
+        assert!(matches!(
+            store.create(PersonaProfile::new("coder", "You write Rust.")),
+            Err(PersonaError::Io(_))
+        ));
+        assert!(store.get("coder").is_none());
+        assert!(store.select(DEFAULT_PERSONA_NAME).is_err());
+        assert_eq!(store.list().len(), 1);
+    }
+}
@@ -0,0 +1,331 @@
+//! Поиск моделей во внешних локальных хранилищах (Ollama, LM Studio).
+//! Файлы не копируются: каталог ссылается на блоб или GGUF внутри хранилища,
+//! и модель загружается из него напрямую.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::model_catalog::collect_gguf_files;
+
+/// Тип внешнего хранилища моделей.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreKind {
+    /// `~/.ollama/models`: манифесты и блобы по sha256.
+    Ollama,
+    /// `~/.lmstudio/models`: дерево `publisher/repo/*.gguf`.
+    LmStudio,
+}
+
+impl StoreKind {
+    /// Разбирает имя хранилища, переданное из Kotlin.
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "ollama" => Some(StoreKind::Ollama),
+            "lm_studio" | "lmstudio" => Some(StoreKind::LmStudio),
+            _ => None,
+        }
+    }
+}
+
+/// Внешнее хранилище: тип и корневая директория.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalStore {
+    pub kind: StoreKind,
+    pub root: PathBuf,
+}
+
+/// Шаблон, системный промпт и параметры из слоёв манифеста Ollama.
+/// Шаблон Ollama написан на Go templates и хранится для справки:
+/// для генерации используется chat template из метаданных GGUF.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OllamaLayers {
+    pub template: Option<String>,
+    pub system: Option<String>,
+    pub params: Option<serde_json::Value>,
+}
+
+/// Модель, найденная во внешнем хранилище.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredModel {
+    /// Путь к GGUF данным (для Ollama — блоб без расширения).
+    pub path: PathBuf,
+    pub kind: StoreKind,
+    /// Имя модели в хранилище: `qwen3:0.6b` или `publisher/repo/file.gguf`.
+    pub name: String,
+    /// Репозиторий `publisher/repo` для LM Studio.
+    pub repo_id: Option<String>,
+    pub ollama: Option<OllamaLayers>,
+}
+
+impl ExternalStore {
+    /// Создаёт описание хранилища.
+    pub fn new(kind: StoreKind, root: impl Into<PathBuf>) -> Self {
+        Self {
+            kind,
+            root: root.into(),
+        }
+    }
+
+    /// Находит модели в хранилище. Повреждённые манифесты пропускаются.
+    pub fn discover(&self) -> Vec<DiscoveredModel> {
+        match self.kind {
+            StoreKind::Ollama => ollama::discover(&self.root),
+            StoreKind::LmStudio => lm_studio::discover(&self.root),
+        }
+    }
+}
+
+/// Стандартные расположения хранилищ в домашней директории, которые существуют на устройстве.
+/// Для Ollama учитывается переменная `OLLAMA_MODELS`.
+pub fn default_stores() -> Vec<ExternalStore> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let mut candidates = Vec::new();
+    match std::env::var_os("OLLAMA_MODELS") {
+        Some(dir) => candidates.push(ExternalStore::new(StoreKind::Ollama, dir)),
+        None => candidates.extend(
+            home.iter()
+                .map(|home| ExternalStore::new(StoreKind::Ollama, home.join(".ollama/models"))),
+        ),
+    }
+    for dir in [".lmstudio/models", ".cache/lm-studio/models"] {
+        candidates.extend(
+            home.iter()
+                .map(|home| ExternalStore::new(StoreKind::LmStudio, home.join(dir))),
+        );
+    }
+    candidates
+        .into_iter()
+        .filter(|store| store.root.is_dir())
+        .collect()
+}
+
+mod ollama {
+    use super::*;
+
+    const MANIFESTS_DIR: &str = "manifests";
+    const BLOBS_DIR: &str = "blobs";
+    /// Реестр и пространство имён по умолчанию, которые Ollama не показывает в имени модели.
+    const DEFAULT_REGISTRY: &str = "registry.ollama.ai";
+    const DEFAULT_NAMESPACE: &str = "library";
+    /// Глубина `host/namespace/model/tag`.
+    const MANIFEST_DEPTH: usize = 4;
+    /// Ограничение на размер текстовых слоёв (шаблон, системный промпт, параметры).
+    const MAX_TEXT_LAYER_BYTES: u64 = 1024 * 1024;
+
+    const MODEL_MEDIA_TYPE: &str = "application/vnd.ollama.image.model";
+    const TEMPLATE_MEDIA_TYPE: &str = "application/vnd.ollama.image.template";
+    const SYSTEM_MEDIA_TYPE: &str = "application/vnd.ollama.image.system";
+    const PARAMS_MEDIA_TYPE: &str = "application/vnd.ollama.image.params";
+
+    #[derive(Deserialize)]
+    struct Manifest {
+        layers: Vec<Layer>,
+    }
+
+    #[derive(Deserialize)]
+    struct Layer {
+        #[serde(rename = "mediaType")]
+        media_type: String,
+        digest: String,
+    }
+
+    pub(super) fn discover(root: &Path) -> Vec<DiscoveredModel> {
+        let mut manifests = Vec::new();
+        collect_manifests(&root.join(MANIFESTS_DIR), 0, &mut manifests);
+        manifests.sort();
+
+        manifests
+            .iter()
+            .filter_map(|manifest_path| {
+                let model = read_manifest(root, manifest_path);
+                if model.is_none() {
+                    log::warn!("Пропуск манифеста Ollama {:?}", manifest_path);
+                }
+                model
+            })
+            .collect()
+    }
+
+    fn collect_manifests(dir: &Path, depth: usize, out: &mut Vec<PathBuf>) {
+        let Ok(read_dir) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in read_dir.flatten() {
+            let path = entry.path();
+            if depth + 1 < MANIFEST_DEPTH {
+                if path.is_dir() {
+                    collect_manifests(&path, depth + 1, out);
+                }
+            } else if path.is_file() {
+                out.push(path);
+            }
+        }
+    }
+
+    fn read_manifest(root: &Path, manifest_path: &Path) -> Option<DiscoveredModel> {
+        let manifest: Manifest =
+            serde_json::from_slice(&std::fs::read(manifest_path).ok()?).ok()?;
+        let layer = |media_type: &str| {
+            manifest
+                .layers
+                .iter()
+                .find(|layer| layer.media_type == media_type)
+                .and_then(|layer| blob_path(root, &layer.digest))
+        };
+
+        let path = layer(MODEL_MEDIA_TYPE)?;
+        let layers = OllamaLayers {
+            template: layer(TEMPLATE_MEDIA_TYPE).and_then(|path| read_text_layer(&path)),
+            system: layer(SYSTEM_MEDIA_TYPE).and_then(|path| read_text_layer(&path)),
+            params: layer(PARAMS_MEDIA_TYPE)
+                .and_then(|path| read_text_layer(&path))
+                .and_then(|params| serde_json::from_str(&params).ok()),
+        };
+        Some(DiscoveredModel {
+            path,
+            kind: StoreKind::Ollama,
+            name: model_name(&root.join(MANIFESTS_DIR), manifest_path)?,
+            repo_id: None,
+            ollama: Some(layers),
+        })
+    }
+
+    /// `registry.ollama.ai/library/qwen3/0.6b` -> `qwen3:0.6b`,
+    /// прочие реестры сохраняют полный путь: `hf.co/org/repo:Q4_K_M`.
+    fn model_name(manifests_dir: &Path, manifest_path: &Path) -> Option<String> {
+        let parts: Vec<String> = manifest_path
+            .strip_prefix(manifests_dir)
+            .ok()?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let (tag, model) = parts.split_last()?;
+        let model = match model {
+            [registry, namespace, name @ ..]
+                if registry == DEFAULT_REGISTRY && namespace == DEFAULT_NAMESPACE =>
+            {
+                name.join("/")
+            }
+            _ => model.join("/"),
+        };
+        Some(format!("{model}:{tag}"))
+    }
+
+    /// Путь к блобу по дайджесту `sha256:<hex>`.
+    /// Новые версии Ollama называют файлы `sha256-<hex>`, старые — `sha256:<hex>`.
+    fn blob_path(root: &Path, digest: &str) -> Option<PathBuf> {
+        let hex = digest.strip_prefix("sha256:")?;
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let blobs = root.join(BLOBS_DIR);
+        [format!("sha256-{hex}"), format!("sha256:{hex}")]
+            .into_iter()
+            .map(|name| blobs.join(name))
+            .find(|path| path.is_file())
+    }
+
+    fn read_text_layer(path: &Path) -> Option<String> {
+        let mut text = String::new();
+        std::fs::File::open(path)
+            .ok()?
+            .take(MAX_TEXT_LAYER_BYTES)
+            .read_to_string(&mut text)
+            .ok()?;
+        Some(text)
+    }
+}
+
+mod lm_studio {
+    use super::*;
+
+    /// Проекторы мультимодальных моделей лежат рядом с весами, но не являются LLM.
+    const PROJECTOR_PREFIX: &str = "mmproj";
+
+    pub(super) fn discover(root: &Path) -> Vec<DiscoveredModel> {
+        let mut files = Vec::new();
+        collect_gguf_files(root, 0, &mut files);
+        files.sort();
+
+        files
+            .into_iter()
+            .filter(|path| {
+                !path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_lowercase())
+                    .is_some_and(|name| name.starts_with(PROJECTOR_PREFIX))
+            })
+            .filter_map(|path| {
+                let relative = path.strip_prefix(root).ok()?;
+                let parts: Vec<String> = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                let repo_id = (parts.len() >= 3).then(|| format!("{}/{}", parts[0], parts[1]));
+                Some(DiscoveredModel {
+                    name: parts.join("/"),
+                    path,
+                    kind: StoreKind::LmStudio,
+                    repo_id,
+                    ollama: None,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{basic_metadata, write_ollama_store, write_test_gguf};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_discovers_ollama_manifest_layers() {
+        let tmp = tempdir().unwrap();
+        let model_blob = write_ollama_store(tmp.path());
+
+        let models = ExternalStore::new(StoreKind::Ollama, tmp.path()).discover();
+        assert_eq!(models.len(), 1);
+        let model = &models[0];
+        assert_eq!(model.name, "qwen3:0.6b");
+        assert_eq!(model.path, model_blob);
+        let layers = model.ollama.as_ref().unwrap();
+        assert_eq!(layers.template.as_deref(), Some("{{ .Prompt }}"));
+        assert_eq!(layers.params.as_ref().unwrap()["temperature"], 0.6);
+        assert!(layers.system.is_none());
+    }
+
+    #[test]
+    fn test_discovers_lm_studio_tree_without_projectors() {
+        let tmp = tempdir().unwrap();
+        let repo = tmp
+            .path()
+            .join("lmstudio-community")
+            .join("gemma-3-1b-it-GGUF");
+        write_test_gguf(
+            &repo.join("gemma-3-1b-it-Q4_K_M.gguf"),
+            &basic_metadata("gemma3"),
+            &[("w", &[4])],
+        );
+        write_test_gguf(
+            &repo.join("mmproj-model-f16.gguf"),
+            &basic_metadata("clip"),
+            &[("w", &[4])],
+        );
+
+        let models = ExternalStore::new(StoreKind::LmStudio, tmp.path()).discover();
+        assert_eq!(models.len(), 1);
+        assert_eq!(
+            models[0].name,
+            "lmstudio-community/gemma-3-1b-it-GGUF/gemma-3-1b-it-Q4_K_M.gguf"
+        );
+        assert_eq!(
+            models[0].repo_id.as_deref(),
+            Some("lmstudio-community/gemma-3-1b-it-GGUF")
+        );
+    }
+}
@@ -0,0 +1,461 @@
+//! Редактирование метаданных GGUF без изменения весов: исправление chat template,
+//! идентификаторов специальных токенов, имени модели.
+//! Файл переписывается целиком — новый заголовок и однократное копирование данных
+//! тензоров из mmap — во временный файл рядом с исходным, который затем атомарно
+//! заменяет его. У разбитой модели метаданные хранятся в первой части, поэтому
+//! редактируется только она.
+
+use std::collections::HashMap;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use candle_core::quantized::gguf_file;
+use serde::Serialize;
+use serde_json::Value as Json;
+
+use crate::gguf_limits::GgufLimits;
+use crate::gguf_reader::read_content;
+use crate::gguf_split::SplitName;
+use crate::gguf_writer::{GgufWriter, TensorLayout};
+use crate::load_progress::tensor_size_in_bytes;
+use crate::model_manager::ModelManagerError;
+use crate::model_source::ModelSource;
+
+/// Ключ chat template.
+pub const CHAT_TEMPLATE_KEY: &str = "tokenizer.chat_template";
+/// Ключ отображаемого имени модели.
+pub const NAME_KEY: &str = "general.name";
+/// Массивы длиннее этого (например, словарь токенизатора) не передаются целиком.
+const MAX_ARRAY_PREVIEW: usize = 64;
+
+/// Изменение одного ключа метаданных.
+#[derive(Debug, Clone)]
+pub enum MetadataEdit {
+    Set(String, gguf_file::Value),
+    Remove(String),
+}
+
+/// Ключ метаданных в виде для отображения в приложении.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetadataEntry {
+    pub key: String,
+    pub value_type: &'static str,
+    /// Значение; `null` для длинных массивов.
+    pub value: Json,
+    /// Длина массива, если значение — массив.
+    pub array_len: Option<usize>,
+}
+
+/// Файл, в котором хранятся метаданные модели: для разбитой модели — первая часть.
+pub fn header_file(path: &Path) -> PathBuf {
+    match SplitName::parse(path) {
+        Some(split) => split.shard_paths().swap_remove(0),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Читает метаданные модели.
+pub fn read_metadata(
+    path: &Path,
+    limits: &GgufLimits,
+) -> Result<HashMap<String, gguf_file::Value>, ModelManagerError> {
+    let bytes = ModelSource::Path(header_file(path)).into_bytes()?;
+    Ok(read_content(&bytes, limits)?.metadata)
+}
+
+/// Метаданные в виде списка для отображения, отсортированные по ключу.
+pub fn metadata_entries(metadata: &HashMap<String, gguf_file::Value>) -> Vec<MetadataEntry> {
+    let mut entries: Vec<MetadataEntry> = metadata
+        .iter()
+        .map(|(key, value)| {
+            let array_len = match value {
+                gguf_file::Value::Array(items) => Some(items.len()),
+                _ => None,
+            };
+            MetadataEntry {
+                key: key.clone(),
+                value_type: type_name(value),
+                value: match array_len {
+                    Some(len) if len > MAX_ARRAY_PREVIEW => Json::Null,
+                    _ => value_to_json(value),
+                },
+                array_len,
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    entries
+}
+
+/// Разбирает изменения из JSON объекта `{"ключ": значение}`; `null` удаляет ключ.
+/// Числа записываются в том же типе, что и текущее значение ключа; для новых ключей
+/// целые становятся `u32`/`i32` (или 64-битными, если не помещаются), дробные — `f32`.
+pub fn parse_edits(
+    json: &str,
+    current: &HashMap<String, gguf_file::Value>,
+) -> Result<Vec<MetadataEdit>, ModelManagerError> {
+    let object: serde_json::Map<String, Json> = serde_json::from_str(json)
+        .map_err(|e| ModelManagerError::InvalidMetadata(e.to_string()))?;
+    object
+        .into_iter()
+        .map(|(key, value)| match value {
+            Json::Null => Ok(MetadataEdit::Remove(key)),
+            value => {
+                let value = value_from_json(&key, &value, current.get(&key))?;
+                Ok(MetadataEdit::Set(key, value))
+            }
+        })
+        .collect()
+}
+
+/// Применяет изменения к метаданным модели по пути `path` и возвращает путь
+/// переписанного файла.
+pub fn edit_metadata(
+    path: &Path,
+    edits: &[MetadataEdit],
+    limits: &GgufLimits,
+) -> Result<PathBuf, ModelManagerError> {
+    let file = header_file(path);
+    let bytes = ModelSource::Path(file.clone()).into_bytes()?;
+    let content = read_content(&bytes, limits)?;
+
+    let mut metadata = content.metadata.clone();
+    for edit in edits {
+        match edit {
+            MetadataEdit::Set(key, value) => {
+                validate(key, value)?;
+                metadata.insert(key.clone(), value.clone());
+            }
+            MetadataEdit::Remove(key) => {
+                validate_key(key)?;
+                metadata.remove(key);
+            }
+        }
+    }
+    let mut metadata: Vec<(String, gguf_file::Value)> = metadata.into_iter().collect();
+    metadata.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut infos: Vec<_> = content.tensor_infos.iter().collect();
+    infos.sort_by_key(|(_, info)| info.offset);
+    let layouts = infos
+        .iter()
+        .map(|(name, info)| TensorLayout {
+            name: (*name).clone(),
+            dtype: info.ggml_dtype,
+            shape: info.shape.dims().to_vec(),
+        })
+        .collect();
+
+    let file_name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let temp = file.with_file_name(format!(".{file_name}.edit"));
+    let written = write_copy(&temp, &metadata, layouts, &infos, &bytes, &content);
+    drop(bytes);
+    if let Err(err) = written.and_then(|_| {
+        std::fs::rename(&temp, &file).map_err(|e| ModelManagerError::Io(e.to_string()))
+    }) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(err);
+    }
+    log::info!("Metadata of {:?} updated ({} edits)", file, edits.len());
+    Ok(file)
+}
+
+/// Записывает новый заголовок и копирует данные тензоров из исходных байтов файла.
+fn write_copy(
+    temp: &Path,
+    metadata: &[(String, gguf_file::Value)],
+    layouts: Vec<TensorLayout>,
+    infos: &[(&String, &gguf_file::TensorInfo)],
+    bytes: &[u8],
+    content: &gguf_file::Content,
+) -> Result<(), ModelManagerError> {
+    let out = std::fs::File::create(temp).map_err(|e| ModelManagerError::Io(e.to_string()))?;
+    let mut writer = GgufWriter::new(BufWriter::new(out), metadata, layouts)?;
+    for (name, info) in infos {
+        let start = (content.tensor_data_offset + info.offset) as usize;
+        let end = start + tensor_size_in_bytes(info);
+        writer.write_tensor(name, &bytes[start..end])?;
+    }
+    let (out, _) = writer.finish()?;
+    out.into_inner()
+        .map_err(|e| ModelManagerError::Io(e.to_string()))?
+        .sync_all()
+        .map_err(|e| ModelManagerError::Io(e.to_string()))
+}
+
+fn validate_key(key: &str) -> Result<(), ModelManagerError> {
+    if key.is_empty() {
+        return Err(ModelManagerError::InvalidMetadata(
+            "пустой ключ".to_string(),
+        ));
+    }
+    // Ключи разбивки описывают файлы на диске и меняются только вместе с ними
+    if key.starts_with("split.") {
+        return Err(ModelManagerError::InvalidMetadata(format!(
+            "ключ '{key}' нельзя изменять"
+        )));
+    }
+    Ok(())
+}
+
+fn validate(key: &str, value: &gguf_file::Value) -> Result<(), ModelManagerError> {
+    validate_key(key)?;
+    let expect_string =
+        || ModelManagerError::InvalidMetadata(format!("значение '{key}' должно быть строкой"));
+    match key {
+        CHAT_TEMPLATE_KEY => {
+            let gguf_file::Value::String(template) = value else {
+                return Err(expect_string());
+            };
+            minijinja::Environment::new()
+                .template_from_str(template)
+                .map_err(|e| {
+                    ModelManagerError::InvalidMetadata(format!("chat template не разбирается: {e}"))
+                })?;
+        }
+        NAME_KEY | "general.architecture" if !matches!(value, gguf_file::Value::String(_)) => {
+            return Err(expect_string());
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn value_from_json(
+    key: &str,
+    json: &Json,
+    current: Option<&gguf_file::Value>,
+) -> Result<gguf_file::Value, ModelManagerError> {
+    use gguf_file::Value;
+    let invalid = || {
+        ModelManagerError::InvalidMetadata(format!(
+            "значение {json} не подходит для '{key}'{}",
+            current
+                .map(|value| format!(" (тип {})", type_name(value)))
+                .unwrap_or_default()
+        ))
+    };
+    Ok(match json {
+        Json::String(value) => Value::String(value.clone()),
+        Json::Bool(value) => Value::Bool(*value),
+        Json::Array(items) => {
+            // Все элементы массива GGUF одного типа: для нового массива он выводится по элементам
+            let inferred = array_element_type(items);
+            let element = match current {
+                Some(Value::Array(values)) if !values.is_empty() => values.first(),
+                _ => inferred.as_ref(),
+            };
+            Value::Array(
+                items
+                    .iter()
+                    .map(|item| value_from_json(key, item, element))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        Json::Number(number) => {
+            let int = number.as_i64();
+            let uint = number.as_u64();
+            match current {
+                Some(Value::U8(_)) => {
+                    Value::U8(uint.and_then(|v| v.try_into().ok()).ok_or_else(invalid)?)
+                }
+                Some(Value::I8(_)) => {
+                    Value::I8(int.and_then(|v| v.try_into().ok()).ok_or_else(invalid)?)
+                }
+                Some(Value::U16(_)) => {
+                    Value::U16(uint.and_then(|v| v.try_into().ok()).ok_or_else(invalid)?)
+                }
+                Some(Value::I16(_)) => {
+                    Value::I16(int.and_then(|v| v.try_into().ok()).ok_or_else(invalid)?)
+                }
+                Some(Value::U32(_)) => {
+                    Value::U32(uint.and_then(|v| v.try_into().ok()).ok_or_else(invalid)?)
+                }
+                Some(Value::I32(_)) => {
+                    Value::I32(int.and_then(|v| v.try_into().ok()).ok_or_else(invalid)?)
+                }
+                Some(Value::U64(_)) => Value::U64(uint.ok_or_else(invalid)?),
+                Some(Value::I64(_)) => Value::I64(int.ok_or_else(invalid)?),
+                Some(Value::F64(_)) => Value::F64(number.as_f64().ok_or_else(invalid)?),
+                Some(Value::F32(_)) => Value::F32(number.as_f64().ok_or_else(invalid)? as f32),
+                Some(_) => return Err(invalid()),
+                None => match (uint, int) {
+                    (Some(v), _) => u32::try_from(v).map(Value::U32).unwrap_or(Value::U64(v)),
+                    (None, Some(v)) => i32::try_from(v).map(Value::I32).unwrap_or(Value::I64(v)),
+                    (None, None) => Value::F32(number.as_f64().ok_or_else(invalid)? as f32),
+                },
+            }
+        }
+        Json::Null | Json::Object(_) => return Err(invalid()),
+    })
+}
+
+fn array_element_type(items: &[Json]) -> Option<gguf_file::Value> {
+    use gguf_file::Value;
+    if items.iter().any(Json::is_f64) {
+        return Some(Value::F32(0.0));
+    }
+    let signed: Vec<i64> = items.iter().filter_map(Json::as_i64).collect();
+    if !signed.iter().any(|v| *v < 0) {
+        return None;
+    }
+    Some(if signed.iter().all(|v| i32::try_from(*v).is_ok()) {
+        Value::I32(0)
+    } else {
+        Value::I64(0)
+    })
+}
+
+fn value_to_json(value: &gguf_file::Value) -> Json {
+    use gguf_file::Value;
+    match value {
+        Value::U8(v) => Json::from(*v),
+        Value::I8(v) => Json::from(*v),
+        Value::U16(v) => Json::from(*v),
+        Value::I16(v) => Json::from(*v),
+        Value::U32(v) => Json::from(*v),
+        Value::I32(v) => Json::from(*v),
+        Value::U64(v) => Json::from(*v),
+        Value::I64(v) => Json::from(*v),
+        Value::F32(v) => Json::from(*v),
+        Value::F64(v) => Json::from(*v),
+        Value::Bool(v) => Json::from(*v),
+        Value::String(v) => Json::from(v.as_str()),
+        Value::Array(items) => Json::Array(items.iter().map(value_to_json).collect()),
+    }
+}
+
+fn type_name(value: &gguf_file::Value) -> &'static str {
+    use gguf_file::Value;
+    match value {
+        Value::U8(_) => "u8",
+        Value::I8(_) => "i8",
+        Value::U16(_) => "u16",
+        Value::I16(_) => "i16",
+        Value::U32(_) => "u32",
+        Value::I32(_) => "i32",
+        Value::U64(_) => "u64",
+        Value::I64(_) => "i64",
+        Value::F32(_) => "f32",
+        Value::F64(_) => "f64",
+        Value::Bool(_) => "bool",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gguf_reader::MappedGguf;
+    use crate::test_support::{basic_metadata, write_split_gguf, write_test_gguf};
+    use candle_core::Device;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_edits_metadata_and_keeps_tensors() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("model.gguf");
+        let mut metadata = basic_metadata("qwen3");
+        metadata.push(("obsolete".into(), gguf_file::Value::Bool(true)));
+        write_test_gguf(&path, &metadata, &[("a", &[4, 4]), ("b", &[8])]);
+        let before = MappedGguf::open(&path).unwrap();
+        let tensor_bytes = |gguf: &MappedGguf, name: &str| {
+            let info = &gguf.content.tensor_infos[name];
+            let start = (gguf.content.tensor_data_offset + info.offset) as usize;
+            gguf.reader.header_bytes()[start..start + tensor_size_in_bytes(info)].to_vec()
+        };
+
+        let edits = vec![
+            MetadataEdit::Set(
+                CHAT_TEMPLATE_KEY.into(),
+                gguf_file::Value::String(
+                    "{% for m in messages %}{{ m.content }}{% endfor %}".into(),
+                ),
+            ),
+            MetadataEdit::Set(
+                NAME_KEY.into(),
+                gguf_file::Value::String("Моя модель".into()),
+            ),
+            MetadataEdit::Remove("obsolete".into()),
+        ];
+        assert_eq!(
+            edit_metadata(&path, &edits, &Default::default()).unwrap(),
+            path
+        );
+
+        let mut after = MappedGguf::open(&path).unwrap();
+        let metadata = &after.content.metadata;
+        assert_eq!(metadata[NAME_KEY].to_string().unwrap(), "Моя модель");
+        assert!(metadata.contains_key(CHAT_TEMPLATE_KEY));
+        assert!(!metadata.contains_key("obsolete"));
+        for name in ["a", "b"] {
+            assert_eq!(tensor_bytes(&before, name), tensor_bytes(&after, name));
+        }
+        assert!(after
+            .content
+            .tensor(&mut after.reader, "a", &Device::Cpu)
+            .is_ok());
+
+        // Некорректный шаблон отклоняется, файл не меняется
+        let broken = [MetadataEdit::Set(
+            CHAT_TEMPLATE_KEY.into(),
+            gguf_file::Value::String("{% for m in messages %}".into()),
+        )];
+        assert!(matches!(
+            edit_metadata(&path, &broken, &Default::default()),
+            Err(ModelManagerError::InvalidMetadata(_))
+        ));
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_parses_json_edits_with_existing_types() {
+        let tmp = tempdir().unwrap();
+        let shards = write_split_gguf(tmp.path(), "model", &[&[("a", &[4])], &[("b", &[4])]]);
+        let mut current = read_metadata(&shards[1], &Default::default()).unwrap();
+        current.insert(
+            "tokenizer.ggml.eos_token_id".into(),
+            gguf_file::Value::U32(2),
+        );
+
+        let edits = parse_edits(
+            r#"{"tokenizer.ggml.eos_token_id": 106, "custom.ids": [1, -2], "general.name": null}"#,
+            &current,
+        )
+        .unwrap();
+        assert!(edits.iter().any(|edit| matches!(
+            edit,
+            MetadataEdit::Set(key, gguf_file::Value::U32(106)) if key == "tokenizer.ggml.eos_token_id"
+        )));
+        assert!(edits
+            .iter()
+            .any(|edit| matches!(edit, MetadataEdit::Remove(key) if key == NAME_KEY)));
+        assert!(parse_edits(r#"{"tokenizer.ggml.eos_token_id": -1}"#, &current).is_err());
+
+        // Метаданные разбитой модели редактируются в первой части
+        let edited = edit_metadata(&shards[1], &edits, &Default::default()).unwrap();
+        assert_eq!(edited, shards[0]);
+        let gguf = MappedGguf::open(&shards[0]).unwrap();
+        assert_eq!(gguf.content.tensor_infos.len(), 2);
+        assert_eq!(
+            gguf.content.metadata["tokenizer.ggml.eos_token_id"]
+                .to_u32()
+                .unwrap(),
+            106
+        );
+        let split_edit = [MetadataEdit::Remove("split.count".into())];
+        assert!(edit_metadata(&shards[0], &split_edit, &Default::default()).is_err());
+
+        let entries = metadata_entries(&gguf.content.metadata);
+        let ids = entries
+            .iter()
+            .find(|entry| entry.key == "custom.ids")
+            .unwrap();
+        assert_eq!(ids.array_len, Some(2));
+        assert_eq!(ids.value, serde_json::json!([1, -2]));
+    }
+}
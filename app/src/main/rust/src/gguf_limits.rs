@@ -0,0 +1,406 @@
+//! Ограниченный предварительный разбор заголовка GGUF.
+//! `gguf_file::Content::read` выделяет память по длинам из файла, поэтому заголовок
+//! сначала обходится без выделений: каждая длина сверяется с лимитами и с оставшимся
+//! размером файла, и только после этого файл передаётся candle.
+
+use thiserror::Error;
+
+/// Максимальное число измерений тензора в ggml.
+const GGML_MAX_DIMS: u32 = 4;
+
+/// Ограничения на объявленные в заголовке размеры.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GgufLimits {
+    pub max_metadata_entries: u64,
+    /// Длина ключа метаданных и имени тензора.
+    pub max_key_len: u64,
+    pub max_string_len: u64,
+    pub max_array_len: u64,
+    /// Глубина вложенности массивов.
+    pub max_array_depth: usize,
+    pub max_tensor_count: u64,
+}
+
+impl Default for GgufLimits {
+    fn default() -> Self {
+        Self {
+            max_metadata_entries: 1 << 16,
+            max_key_len: 1 << 16,
+            // Встроенный tokenizer.huggingface.json может занимать десятки мегабайт
+            max_string_len: 64 << 20,
+            // Словари и списки слияний современных моделей — сотни тысяч элементов
+            max_array_len: 1 << 24,
+            max_array_depth: 2,
+            max_tensor_count: 1 << 16,
+        }
+    }
+}
+
+/// Причина отказа в разборе заголовка.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum GgufFormatError {
+    #[error("отсутствует сигнатура GGUF")]
+    BadMagic,
+    #[error("неподдерживаемая версия GGUF {0}")]
+    UnsupportedVersion(u32),
+    #[error("заголовок обрывается на смещении {offset}")]
+    Truncated { offset: u64 },
+    #[error("слишком много записей метаданных: {count} (лимит {limit})")]
+    TooManyMetadataEntries { count: u64, limit: u64 },
+    #[error("слишком длинный ключ: {len} байт (лимит {limit})")]
+    KeyTooLong { len: u64, limit: u64 },
+    #[error("слишком длинная строка: {len} байт (лимит {limit})")]
+    StringTooLong { len: u64, limit: u64 },
+    #[error("слишком длинный массив: {len} элементов (лимит {limit})")]
+    ArrayTooLong { len: u64, limit: u64 },
+    #[error("слишком глубокая вложенность массивов")]
+    ArrayTooDeep,
+    #[error("неизвестный тип значения {0}")]
+    UnknownValueType(u32),
+    #[error("некорректное выравнивание {0}")]
+    InvalidAlignment(u64),
+    #[error("слишком много тензоров: {count} (лимит {limit})")]
+    TooManyTensors { count: u64, limit: u64 },
+    #[error("тензор с {0} измерениями")]
+    TooManyDimensions(u32),
+}
+
+/// Сведения о заголовке, прошедшем проверку.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderSummary {
+    pub version: u32,
+    pub metadata_entries: u64,
+    pub tensor_count: u64,
+    /// Смещение конца описаний тензоров.
+    pub header_len: u64,
+}
+
+/// Проверяет заголовок GGUF в `bytes` (обычно весь отображённый файл).
+pub fn preparse(bytes: &[u8], limits: &GgufLimits) -> Result<HeaderSummary, GgufFormatError> {
+    let mut cursor = HeaderCursor {
+        bytes,
+        pos: 0,
+        version: 0,
+    };
+    if cursor.take(4)? != b"GGUF" {
+        return Err(GgufFormatError::BadMagic);
+    }
+    cursor.version = cursor.u32()?;
+    if !(1..=3).contains(&cursor.version) {
+        return Err(GgufFormatError::UnsupportedVersion(cursor.version));
+    }
+
+    let tensor_count = cursor.count()?;
+    if tensor_count > limits.max_tensor_count {
+        return Err(GgufFormatError::TooManyTensors {
+            count: tensor_count,
+            limit: limits.max_tensor_count,
+        });
+    }
+    let metadata_entries = cursor.count()?;
+    if metadata_entries > limits.max_metadata_entries {
+        return Err(GgufFormatError::TooManyMetadataEntries {
+            count: metadata_entries,
+            limit: limits.max_metadata_entries,
+        });
+    }
+
+    for _ in 0..metadata_entries {
+        let key = cursor.key(limits)?;
+        let value_type = cursor.u32()?;
+        if key == b"general.alignment" {
+            cursor.alignment(value_type, limits)?;
+        } else {
+            cursor.value(value_type, limits, 0)?;
+        }
+    }
+
+    for _ in 0..tensor_count {
+        cursor.key(limits)?;
+        let dims = cursor.u32()?;
+        if dims > GGML_MAX_DIMS {
+            return Err(GgufFormatError::TooManyDimensions(dims));
+        }
+        let dim_size = if cursor.version == 1 { 4 } else { 8 };
+        cursor.skip(dims as u64 * dim_size)?;
+        // Тип ggml и смещение данных
+        cursor.skip(4 + 8)?;
+    }
+
+    Ok(HeaderSummary {
+        version: cursor.version,
+        metadata_entries,
+        tensor_count,
+        header_len: cursor.pos as u64,
+    })
+}
+
+struct HeaderCursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    version: u32,
+}
+
+impl<'a> HeaderCursor<'a> {
+    fn take(&mut self, len: u64) -> Result<&'a [u8], GgufFormatError> {
+        let truncated = GgufFormatError::Truncated {
+            offset: self.pos as u64,
+        };
+        let len = usize::try_from(len).map_err(|_| truncated.clone())?;
+        let end = self.pos.checked_add(len).ok_or(truncated.clone())?;
+        let slice = self.bytes.get(self.pos..end).ok_or(truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn skip(&mut self, len: u64) -> Result<(), GgufFormatError> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u32(&mut self) -> Result<u32, GgufFormatError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, GgufFormatError> {
+        let bytes = self.take(8)?;
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Счётчики и длины: `u32` в GGUF v1, `u64` в последующих версиях.
+    fn count(&mut self) -> Result<u64, GgufFormatError> {
+        if self.version == 1 {
+            self.u32().map(u64::from)
+        } else {
+            self.u64()
+        }
+    }
+
+    fn key(&mut self, limits: &GgufLimits) -> Result<&'a [u8], GgufFormatError> {
+        let len = self.count()?;
+        if len > limits.max_key_len {
+            return Err(GgufFormatError::KeyTooLong {
+                len,
+                limit: limits.max_key_len,
+            });
+        }
+        self.take(len)
+    }
+
+    fn string(&mut self, limits: &GgufLimits) -> Result<(), GgufFormatError> {
+        let len = self.count()?;
+        if len > limits.max_string_len {
+            return Err(GgufFormatError::StringTooLong {
+                len,
+                limit: limits.max_string_len,
+            });
+        }
+        self.skip(len)
+    }
+
+    fn value(
+        &mut self,
+        value_type: u32,
+        limits: &GgufLimits,
+        depth: usize,
+    ) -> Result<(), GgufFormatError> {
+        if let Some(size) = scalar_size(value_type) {
+            return self.skip(size);
+        }
+        match value_type {
+            8 => self.string(limits),
+            9 => {
+                if depth >= limits.max_array_depth {
+                    return Err(GgufFormatError::ArrayTooDeep);
+                }
+                let element_type = self.u32()?;
+                let len = self.count()?;
+                if len > limits.max_array_len {
+                    return Err(GgufFormatError::ArrayTooLong {
+                        len,
+                        limit: limits.max_array_len,
+                    });
+                }
+                match scalar_size(element_type) {
+                    Some(size) => {
+                        self.skip(len.checked_mul(size).ok_or(GgufFormatError::Truncated {
+                            offset: self.pos as u64,
+                        })?)
+                    }
+                    None => {
+                        for _ in 0..len {
+                            self.value(element_type, limits, depth + 1)?;
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            other => Err(GgufFormatError::UnknownValueType(other)),
+        }
+    }
+
+    /// `general.alignment`: candle делит на него смещение данных, поэтому ноль недопустим.
+    /// Отрицательные и прочие значения candle игнорирует и берёт выравнивание по умолчанию.
+    fn alignment(&mut self, value_type: u32, limits: &GgufLimits) -> Result<(), GgufFormatError> {
+        let alignment: i64 = match value_type {
+            0 => i64::from(self.take(1)?[0]),
+            1 => i64::from(self.take(1)?[0] as i8),
+            2 => {
+                let bytes = self.take(2)?;
+                i64::from(u16::from_le_bytes([bytes[0], bytes[1]]))
+            }
+            3 => {
+                let bytes = self.take(2)?;
+                i64::from(i16::from_le_bytes([bytes[0], bytes[1]]))
+            }
+            4 => i64::from(self.u32()?),
+            5 => i64::from(self.u32()? as i32),
+            other => return self.value(other, limits, 0),
+        };
+        if alignment >= 0 && !(alignment as u64).is_power_of_two() {
+            return Err(GgufFormatError::InvalidAlignment(alignment as u64));
+        }
+        Ok(())
+    }
+}
+
+/// Размер скалярного значения по типу GGUF.
+fn scalar_size(value_type: u32) -> Option<u64> {
+    match value_type {
+        0 | 1 | 7 => Some(1),
+        2 | 3 => Some(2),
+        4..=6 => Some(4),
+        10..=12 => Some(8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gguf_reader::read_content;
+    use crate::model_manager::ModelManagerError;
+    use crate::test_support::{basic_metadata, write_test_gguf};
+    use candle_core::quantized::gguf_file;
+    use tempfile::tempdir;
+
+    /// Заголовок GGUF v3 с одной записью метаданных, заданной сырыми байтами.
+    fn header_with_entry(key: &str, value_type: u32, value: &[u8]) -> Vec<u8> {
+        let mut bytes = b"GGUF".to_vec();
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend(0u64.to_le_bytes());
+        bytes.extend(1u64.to_le_bytes());
+        bytes.extend((key.len() as u64).to_le_bytes());
+        bytes.extend(key.as_bytes());
+        bytes.extend(value_type.to_le_bytes());
+        bytes.extend(value);
+        bytes
+    }
+
+    #[test]
+    fn test_rejects_oversized_declarations() {
+        let limits = GgufLimits::default();
+        let huge = u64::MAX.to_le_bytes();
+
+        let string = header_with_entry("general.name", 8, &huge);
+        assert!(matches!(
+            preparse(&string, &limits),
+            Err(GgufFormatError::StringTooLong { .. })
+        ));
+
+        let mut array = 4u32.to_le_bytes().to_vec();
+        array.extend(huge);
+        assert!(matches!(
+            preparse(
+                &header_with_entry("tokenizer.ggml.tokens", 9, &array),
+                &limits
+            ),
+            Err(GgufFormatError::ArrayTooLong { .. })
+        ));
+
+        // Длина в пределах лимита, но больше файла
+        let mut short = 4u32.to_le_bytes().to_vec();
+        short.extend(1000u64.to_le_bytes());
+        assert!(matches!(
+            preparse(
+                &header_with_entry("tokenizer.ggml.scores", 9, &short),
+                &limits
+            ),
+            Err(GgufFormatError::Truncated { .. })
+        ));
+
+        assert_eq!(
+            preparse(&header_with_entry("general.alignment", 4, &[0; 4]), &limits),
+            Err(GgufFormatError::InvalidAlignment(0))
+        );
+
+        let mut tensors = b"GGUF".to_vec();
+        tensors.extend(3u32.to_le_bytes());
+        tensors.extend(huge);
+        assert!(matches!(
+            preparse(&tensors, &limits),
+            Err(GgufFormatError::TooManyTensors { .. })
+        ));
+
+        // Отказ происходит до того, как candle начнёт выделять память
+        assert!(matches!(
+            read_content(&string, &limits),
+            Err(ModelManagerError::InvalidGguf(
+                GgufFormatError::StringTooLong { .. }
+            ))
+        ));
+    }
+
+    /// Простой детерминированный генератор для мутаций.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_fuzz_mutated_headers_never_panic() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("model.gguf");
+        let mut metadata = basic_metadata("qwen3");
+        metadata.push((
+            "tokenizer.ggml.tokens".into(),
+            gguf_file::Value::Array(vec![
+                gguf_file::Value::String("a".into()),
+                gguf_file::Value::String("bc".into()),
+            ]),
+        ));
+        write_test_gguf(&path, &metadata, &[("w", &[4, 4]), ("b", &[4])]);
+        let original = std::fs::read(&path).unwrap();
+        let limits = GgufLimits {
+            max_string_len: 1 << 12,
+            max_array_len: 1 << 12,
+            ..GgufLimits::default()
+        };
+        let header_len = preparse(&original, &limits).unwrap().header_len as usize;
+        assert!(read_content(&original, &limits).is_ok());
+
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..5000 {
+            let mut bytes = original.clone();
+            match rng.next() % 4 {
+                0 => bytes.truncate(rng.next() as usize % header_len),
+                _ => {
+                    for _ in 0..1 + rng.next() % 8 {
+                        let index = rng.next() as usize % header_len;
+                        bytes[index] = rng.next() as u8;
+                    }
+                }
+            }
+            // Любой результат допустим, кроме паники или гигантского выделения памяти
+            let _ = read_content(&bytes, &limits);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Чтение GGUF через отображение файла в память.
+//! Заголовок разбирается один раз, а веса читаются напрямую из mmap,
+//! без буферизованных `read` и повторного открытия файла.
+//! Кроме путей поддерживаются файловые дескрипторы и буферы в памяти (см. [`ModelSource`]),
+//! а модели из нескольких частей читаются как один поток (см. [`crate::gguf_split`]).
+
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use candle_core::quantized::gguf_file;
+
+use crate::gguf_limits::{preparse, GgufLimits};
+use crate::gguf_split::{merge_shards, split_info, SplitName};
+use crate::model_integrity::{check_header, check_tensor_bounds};
+use crate::model_manager::ModelManagerError;
+use crate::model_source::{GgufBytes, ModelSource};
+
+/// GGUF файл, отображённый в память, вместе с разобранным заголовком.
+pub struct MappedGguf {
+    pub content: gguf_file::Content,
+    pub reader: GgufReader,
+}
+
+impl MappedGguf {
+    /// Открывает файл, отображает его в память и разбирает заголовок
+    /// с ограничениями по умолчанию.
+    pub fn open(path: &Path) -> Result<Self, ModelManagerError> {
+        Self::open_with_limits(path, &GgufLimits::default())
+    }
+
+    /// Открывает файл, отображает его в память и разбирает заголовок.
+    /// Сигнатура, версия и границы тензоров проверяются до чтения весов, поэтому
+    /// недокачанный файл отклоняется ошибкой `Corrupted`.
+    pub fn open_with_limits(path: &Path, limits: &GgufLimits) -> Result<Self, ModelManagerError> {
+        Self::from_source(ModelSource::Path(path.to_path_buf()), limits)
+    }
+
+    /// Открывает GGUF из любого источника и разбирает заголовок.
+    /// Путь к любой части разбитой модели открывает все её части.
+    pub fn from_source(
+        source: ModelSource,
+        limits: &GgufLimits,
+    ) -> Result<Self, ModelManagerError> {
+        if let Some(split) = source.path().and_then(SplitName::parse) {
+            return Self::open_split(&split, limits);
+        }
+        let bytes = source.into_bytes()?;
+        let content = read_content(&bytes, limits)?;
+        if let Some((count, no)) = split_info(&content.metadata).filter(|(count, _)| *count > 1) {
+            return Err(ModelManagerError::InvalidSplit(format!(
+                "открыта только часть {} из {count}",
+                no + 1
+            )));
+        }
+        Ok(Self {
+            content,
+            reader: GgufReader::new(vec![bytes]),
+        })
+    }
+
+    fn open_split(split: &SplitName, limits: &GgufLimits) -> Result<Self, ModelManagerError> {
+        let mut shards = Vec::with_capacity(split.count as usize);
+        for path in split.shard_paths() {
+            if !path.is_file() {
+                return Err(ModelManagerError::ModelFileMissing(
+                    path.to_string_lossy().into_owned(),
+                ));
+            }
+            let bytes = ModelSource::Path(path).into_bytes()?;
+            let content = read_content(&bytes, limits)?;
+            shards.push((content, bytes));
+        }
+        let (content, parts) = merge_shards(shards)?;
+        Ok(Self {
+            content,
+            reader: GgufReader::new(parts),
+        })
+    }
+
+    /// Размер отображённого файла (всех частей) в байтах.
+    pub fn file_len(&self) -> u64 {
+        self.reader.len
+    }
+}
+
+/// Чтение одной или нескольких частей GGUF как единого потока.
+/// Тензоры не пересекают границы частей, поэтому чтение идёт прямо из mmap каждой части.
+pub struct GgufReader {
+    parts: Vec<GgufBytes>,
+    /// Смещение начала каждой части в общем потоке.
+    starts: Vec<u64>,
+    len: u64,
+    position: u64,
+}
+
+impl GgufReader {
+    pub fn new(parts: Vec<GgufBytes>) -> Self {
+        let mut starts = Vec::with_capacity(parts.len());
+        let mut len = 0u64;
+        for part in &parts {
+            starts.push(len);
+            len += part.len() as u64;
+        }
+        Self {
+            parts,
+            starts,
+            len,
+            position: 0,
+        }
+    }
+
+    /// Байты первой части, с которой начинается заголовок модели.
+    pub fn header_bytes(&self) -> &[u8] {
+        self.parts.first().map(|part| &part[..]).unwrap_or_default()
+    }
+}
+
+impl Read for GgufReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.len {
+            return Ok(0);
+        }
+        let index = self.starts.partition_point(|start| *start <= self.position) - 1;
+        let part = &self.parts[index][(self.position - self.starts[index]) as usize..];
+        let read = part.len().min(buf.len());
+        buf[..read].copy_from_slice(&part[..read]);
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for GgufReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid seek position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Разбирает заголовок GGUF из байтов файла. Заголовок сначала проходит ограниченный
+/// предварительный разбор, и только затем передаётся candle.
+pub(crate) fn read_content(
+    bytes: &[u8],
+    limits: &GgufLimits,
+) -> Result<gguf_file::Content, ModelManagerError> {
+    check_header(bytes)?;
+    preparse(bytes, limits)?;
+    let content = gguf_file::Content::read(&mut Cursor::new(bytes))
+        .map_err(|e| ModelManagerError::Corrupted(e.to_string()))?;
+    check_tensor_bounds(&content, bytes.len() as u64)?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{basic_metadata, write_test_gguf};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_mapped_gguf_reads_tensors() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("model.gguf");
+        write_test_gguf(&path, &basic_metadata("qwen3"), &[("w", &[2, 3])]);
+
+        let mut mapped = MappedGguf::open(&path).unwrap();
+        assert_eq!(mapped.file_len(), std::fs::metadata(&path).unwrap().len());
+        let tensor = mapped
+            .content
+            .tensor(&mut mapped.reader, "w", &candle_core::Device::Cpu)
+            .unwrap();
+        assert_eq!(tensor.shape().dims(), &[2, 3]);
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let tmp = tempdir().unwrap();
+        let result = MappedGguf::open(&tmp.path().join("absent.gguf"));
+        assert!(matches!(
+            result,
+            Err(ModelManagerError::ModelFileMissing(_))
+        ));
+    }
+}
@@ -0,0 +1,247 @@
+//! Модели, разбитые на несколько GGUF файлов (`model-00001-of-00003.gguf`, формат `gguf-split`).
+//! Каждая часть — самостоятельный GGUF с ключами `split.no`/`split.count`; части
+//! объединяются в один заголовок, где смещения тензоров отсчитываются от начала
+//! общего потока всех частей.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use candle_core::quantized::gguf_file;
+
+use crate::model_manager::ModelManagerError;
+use crate::model_source::GgufBytes;
+
+/// Номер части (с нуля).
+pub const SPLIT_NO_KEY: &str = "split.no";
+/// Общее число частей.
+pub const SPLIT_COUNT_KEY: &str = "split.count";
+/// Общее число тензоров во всех частях (записывается в первую часть).
+pub const SPLIT_TENSORS_COUNT_KEY: &str = "split.tensors.count";
+
+const SPLIT_SEPARATOR: &str = "-of-";
+const GGUF_EXTENSION: &str = ".gguf";
+
+/// Имя части разбитой модели: `<prefix>-<index>-of-<count>.gguf`, `index` с единицы.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitName {
+    /// Путь без суффикса `-NNNNN-of-NNNNN.gguf`.
+    pub prefix: PathBuf,
+    pub index: u32,
+    pub count: u32,
+}
+
+impl SplitName {
+    /// Разбирает имя файла части. Файлы с одной частью не считаются разбитыми.
+    pub fn parse(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let stem = name.strip_suffix(GGUF_EXTENSION)?;
+        let (rest, count) = stem.rsplit_once(SPLIT_SEPARATOR)?;
+        let (prefix, index) = rest.rsplit_once('-')?;
+        let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if prefix.is_empty() || !digits(index) || !digits(count) {
+            return None;
+        }
+        let (index, count): (u32, u32) = (index.parse().ok()?, count.parse().ok()?);
+        if count < 2 || index == 0 || index > count {
+            return None;
+        }
+        Some(Self {
+            prefix: path.with_file_name(prefix),
+            index,
+            count,
+        })
+    }
+
+    /// Пути всех частей по порядку.
+    pub fn shard_paths(&self) -> Vec<PathBuf> {
+        let prefix = self
+            .prefix
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        (1..=self.count)
+            .map(|index| {
+                self.prefix
+                    .with_file_name(shard_file_name(&prefix, index, self.count))
+            })
+            .collect()
+    }
+}
+
+/// Имя файла части в формате `gguf-split`.
+pub fn shard_file_name(prefix: &str, index: u32, count: u32) -> String {
+    format!("{prefix}-{index:05}{SPLIT_SEPARATOR}{count:05}{GGUF_EXTENSION}")
+}
+
+/// Файл является второй или последующей частью разбитой модели.
+/// Такие файлы не показываются отдельно: модель открывается по любой части.
+pub fn is_secondary_shard(path: &Path) -> bool {
+    SplitName::parse(path).is_some_and(|split| split.index > 1)
+}
+
+/// `split.count` и `split.no` из метаданных части.
+pub fn split_info(metadata: &HashMap<String, gguf_file::Value>) -> Option<(u64, u64)> {
+    Some((
+        metadata_u64(metadata, SPLIT_COUNT_KEY)?,
+        metadata_u64(metadata, SPLIT_NO_KEY)?,
+    ))
+}
+
+fn metadata_u64(metadata: &HashMap<String, gguf_file::Value>, key: &str) -> Option<u64> {
+    let value = metadata.get(key)?;
+    value
+        .to_u64()
+        .ok()
+        .or_else(|| value.to_i32().ok().and_then(|v| u64::try_from(v).ok()))
+}
+
+/// Объединяет разобранные части в один заголовок. Части должны идти по порядку;
+/// проверяются номера частей, отсутствие повторяющихся тензоров и общее число тензоров.
+pub(crate) fn merge_shards(
+    shards: Vec<(gguf_file::Content, GgufBytes)>,
+) -> Result<(gguf_file::Content, Vec<GgufBytes>), ModelManagerError> {
+    let count = shards.len() as u64;
+    let mut merged: Option<gguf_file::Content> = None;
+    let mut parts = Vec::with_capacity(shards.len());
+    let mut base = 0u64;
+
+    for (index, (content, bytes)) in shards.into_iter().enumerate() {
+        let shard_no = index + 1;
+        match split_info(&content.metadata) {
+            Some((split_count, split_no)) if split_count == count && split_no == index as u64 => {}
+            Some((split_count, split_no)) => {
+                return Err(ModelManagerError::InvalidSplit(format!(
+                    "часть {shard_no} из {count} помечена как {} из {split_count}",
+                    split_no + 1
+                )))
+            }
+            None => {
+                return Err(ModelManagerError::InvalidSplit(format!(
+                    "в части {shard_no} нет ключей {SPLIT_NO_KEY}/{SPLIT_COUNT_KEY}"
+                )))
+            }
+        }
+
+        let data_start = base + content.tensor_data_offset;
+        let gguf_file::Content {
+            magic,
+            metadata,
+            tensor_infos,
+            ..
+        } = content;
+        let target = merged.get_or_insert_with(|| gguf_file::Content {
+            magic,
+            metadata: HashMap::new(),
+            tensor_infos: HashMap::new(),
+            tensor_data_offset: 0,
+        });
+        for (name, mut info) in tensor_infos {
+            info.offset += data_start;
+            if target.tensor_infos.insert(name.clone(), info).is_some() {
+                return Err(ModelManagerError::InvalidSplit(format!(
+                    "тензор '{name}' встречается в нескольких частях"
+                )));
+            }
+        }
+        // Метаданные модели хранятся в первой части; из остальных добавляются только новые ключи
+        for (key, value) in metadata {
+            target.metadata.entry(key).or_insert(value);
+        }
+
+        base += bytes.len() as u64;
+        parts.push(bytes);
+    }
+
+    let merged =
+        merged.ok_or_else(|| ModelManagerError::InvalidSplit("нет ни одной части".to_string()))?;
+    if let Some(expected) = metadata_u64(&merged.metadata, SPLIT_TENSORS_COUNT_KEY) {
+        let found = merged.tensor_infos.len() as u64;
+        if expected != found {
+            return Err(ModelManagerError::InvalidSplit(format!(
+                "ожидалось {expected} тензоров, найдено {found}"
+            )));
+        }
+    }
+    Ok((merged, parts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gguf_reader::MappedGguf;
+    use crate::model_source::ModelSource;
+    use crate::test_support::write_split_gguf;
+    use candle_core::Device;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parses_split_names() {
+        let split = SplitName::parse(Path::new("/m/Qwen3-8B-Q4_K_M-00002-of-00003.gguf")).unwrap();
+        assert_eq!(split.prefix, Path::new("/m/Qwen3-8B-Q4_K_M"));
+        assert_eq!((split.index, split.count), (2, 3));
+        assert_eq!(
+            split.shard_paths()[0],
+            Path::new("/m/Qwen3-8B-Q4_K_M-00001-of-00003.gguf")
+        );
+        assert!(is_secondary_shard(Path::new("a-00003-of-00003.gguf")));
+        assert!(!is_secondary_shard(Path::new("a-00001-of-00003.gguf")));
+
+        for name in [
+            "model.gguf",
+            "a-00004-of-00003.gguf",
+            "a-00001-of-00001.gguf",
+            "-00001-of-00002.gguf",
+            "a-x1-of-00002.gguf",
+        ] {
+            assert_eq!(SplitName::parse(Path::new(name)), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_loads_all_shards_as_one_model() {
+        let tmp = tempdir().unwrap();
+        let shards = write_split_gguf(
+            tmp.path(),
+            "model",
+            &[&[("a", &[4, 4])], &[("b", &[8])], &[("c", &[2])]],
+        );
+
+        // Модель открывается по любой части
+        let mut gguf = MappedGguf::open(&shards[1]).unwrap();
+        assert_eq!(gguf.content.tensor_infos.len(), 3);
+        let total: u64 = shards
+            .iter()
+            .map(|path| std::fs::metadata(path).unwrap().len())
+            .sum();
+        assert_eq!(gguf.file_len(), total);
+        for (name, value) in [("a", 1.0f32), ("b", 2.0), ("c", 3.0)] {
+            let tensor = gguf
+                .content
+                .tensor(&mut gguf.reader, name, &Device::Cpu)
+                .unwrap()
+                .dequantize(&Device::Cpu)
+                .unwrap();
+            let values = tensor.flatten_all().unwrap().to_vec1::<f32>().unwrap();
+            assert!(values.iter().all(|v| *v == value), "{name}");
+        }
+
+        // Часть без остальных частей отклоняется
+        let single = ModelSource::Buffer(std::fs::read(&shards[0]).unwrap());
+        assert!(matches!(
+            MappedGguf::from_source(single, &Default::default()),
+            Err(ModelManagerError::InvalidSplit(_))
+        ));
+
+        std::fs::remove_file(&shards[2]).unwrap();
+        assert!(matches!(
+            MappedGguf::open(&shards[0]),
+            Err(ModelManagerError::ModelFileMissing(_))
+        ));
+        // Часть от другой разбивки: номер не совпадает с именем файла
+        std::fs::copy(&shards[1], &shards[2]).unwrap();
+        assert!(matches!(
+            MappedGguf::open(&shards[0]),
+            Err(ModelManagerError::InvalidSplit(_))
+        ));
+    }
+}
@@ -0,0 +1,750 @@
+//! Восстановление токенизатора из метаданных GGUF.
+//! Конвейер повторяет исходный `tokenizer.json`: нормализацию, пре-токенизацию,
+//! добавленные токены и пост-обработку, поэтому строки кодируются в те же id,
+//! что и оригинальным токенизатором модели.
+
+use std::collections::HashMap;
+
+use candle_core::quantized::gguf_file;
+use tokenizers::decoders::byte_fallback::ByteFallback;
+use tokenizers::decoders::byte_level::ByteLevel as ByteLevelDecoder;
+use tokenizers::decoders::fuse::Fuse;
+use tokenizers::decoders::sequence::Sequence as DecoderSequence;
+use tokenizers::decoders::strip::Strip;
+use tokenizers::models::bpe::{Vocab, BPE};
+use tokenizers::normalizers::{Prepend, Replace, Sequence as NormalizerSequence, NFC};
+use tokenizers::pre_tokenizers::byte_level::ByteLevel;
+use tokenizers::pre_tokenizers::sequence::Sequence as PreTokenizerSequence;
+use tokenizers::pre_tokenizers::split::{Split, SplitPattern};
+use tokenizers::processors::template::TemplateProcessing;
+use tokenizers::{
+    AddedToken, DecoderWrapper, NormalizerWrapper, PaddingParams, PreTokenizerWrapper,
+    SplitDelimiterBehavior, Tokenizer,
+};
+
+use crate::model_manager::ModelManagerError;
+
+/// Маркер пробела в словарях SentencePiece.
+const SPM_SPACE: &str = "\u{2581}";
+
+/// Регулярное выражение пре-токенизатора Qwen2 (`tokenizer.ggml.pre = qwen2`).
+const QWEN2_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// Регулярное выражение пре-токенизатора Llama 3: числа группируются по три цифры.
+const LLAMA3_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// Тип токена из `tokenizer.ggml.token_type` (нумерация llama.cpp).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenType {
+    Normal,
+    Unknown,
+    Control,
+    UserDefined,
+    Unused,
+    Byte,
+}
+
+impl TokenType {
+    fn from_value(value: i64) -> Self {
+        match value {
+            2 => TokenType::Unknown,
+            3 => TokenType::Control,
+            4 => TokenType::UserDefined,
+            5 => TokenType::Unused,
+            6 => TokenType::Byte,
+            _ => TokenType::Normal,
+        }
+    }
+}
+
+/// Словарь и параметры токенизатора, прочитанные из GGUF.
+struct GgufVocab {
+    model: String,
+    pre: Option<String>,
+    tokens: Vec<String>,
+    scores: Vec<f32>,
+    token_types: Vec<TokenType>,
+    merges: Vec<(String, String)>,
+    bos_id: Option<u32>,
+    eos_id: Option<u32>,
+    unk_id: Option<u32>,
+    pad_id: Option<u32>,
+    add_bos: bool,
+    add_eos: bool,
+    add_space_prefix: bool,
+}
+
+impl GgufVocab {
+    fn from_metadata(
+        metadata: &HashMap<String, gguf_file::Value>,
+    ) -> Result<Self, ModelManagerError> {
+        let tokens = string_array(metadata, "tokenizer.ggml.tokens").ok_or_else(|| {
+            ModelManagerError::TokenizerMissing("tokenizer.ggml.tokens".to_string())
+        })?;
+        let merges = string_array(metadata, "tokenizer.ggml.merges")
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|merge| {
+                merge
+                    .split_once(' ')
+                    .map(|(left, right)| (left.to_string(), right.to_string()))
+            })
+            .collect::<Vec<_>>();
+        let model = string_value(metadata, "tokenizer.ggml.model")
+            .unwrap_or_else(|| if merges.is_empty() { "llama" } else { "gpt2" }.to_string());
+
+        let scores = match metadata.get("tokenizer.ggml.scores") {
+            Some(gguf_file::Value::Array(values)) => values
+                .iter()
+                .map(|value| value.to_f32().unwrap_or(0.0))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let token_types = match metadata.get("tokenizer.ggml.token_type") {
+            Some(gguf_file::Value::Array(values)) => values
+                .iter()
+                .map(|value| TokenType::from_value(integer_value(value).unwrap_or(1)))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let token_id = |key: &str| {
+            metadata
+                .get(key)
+                .and_then(integer_value)
+                .and_then(|id| u32::try_from(id).ok())
+                .filter(|id| (*id as usize) < tokens.len())
+        };
+        // llama.cpp по умолчанию добавляет BOS и префиксный пробел только для SentencePiece
+        let is_spm = model == "llama";
+
+        Ok(Self {
+            pre: string_value(metadata, "tokenizer.ggml.pre"),
+            bos_id: token_id("tokenizer.ggml.bos_token_id"),
+            eos_id: token_id("tokenizer.ggml.eos_token_id"),
+            unk_id: token_id("tokenizer.ggml.unknown_token_id"),
+            pad_id: token_id("tokenizer.ggml.padding_token_id"),
+            add_bos: bool_value(metadata, "tokenizer.ggml.add_bos_token").unwrap_or(is_spm),
+            add_eos: bool_value(metadata, "tokenizer.ggml.add_eos_token").unwrap_or(false),
+            add_space_prefix: bool_value(metadata, "tokenizer.ggml.add_space_prefix")
+                .unwrap_or(is_spm),
+            model,
+            tokens,
+            scores,
+            token_types,
+            merges,
+        })
+    }
+
+    fn token_type(&self, id: usize) -> TokenType {
+        self.token_types
+            .get(id)
+            .copied()
+            .unwrap_or(TokenType::Normal)
+    }
+
+    fn vocab_map(&self) -> Vocab {
+        self.tokens
+            .iter()
+            .enumerate()
+            .map(|(id, token)| (token.clone(), id as u32))
+            .collect()
+    }
+
+    /// Восстанавливает правила слияния SentencePiece BPE по оценкам токенов:
+    /// токен с большей оценкой сливается раньше. Алгоритм совпадает с
+    /// `SentencePieceExtractor`, которым Hugging Face строит `tokenizer.json`.
+    fn merges_from_scores(&self) -> Vec<(String, String)> {
+        let vocab: HashMap<&str, usize> = self
+            .tokens
+            .iter()
+            .enumerate()
+            .map(|(id, token)| (token.as_str(), id))
+            .collect();
+        let score = |id: usize| self.scores.get(id).copied().unwrap_or(0.0);
+
+        let mut order: Vec<usize> = (0..self.tokens.len()).collect();
+        order.sort_by(|a, b| score(*b).total_cmp(&score(*a)));
+
+        let mut merges = Vec::new();
+        for id in order {
+            let piece = &self.tokens[id];
+            let mut local: Vec<(usize, usize)> = piece
+                .char_indices()
+                .skip(1)
+                .filter_map(|(split, _)| {
+                    let (left, right) = piece.split_at(split);
+                    Some((*vocab.get(left)?, *vocab.get(right)?))
+                })
+                .collect();
+            local.sort_unstable();
+            merges.extend(
+                local
+                    .into_iter()
+                    .map(|(left, right)| (self.tokens[left].clone(), self.tokens[right].clone())),
+            );
+        }
+        merges
+    }
+}
+
+/// Строит токенизатор по метаданным GGUF.
+/// Поддерживаются словари BPE (`gpt2`) и SentencePiece (`llama`, включая Gemma);
+/// если в файле сохранён исходный `tokenizer.huggingface.json`, используется он.
+pub fn tokenizer_from_metadata(
+    metadata: &HashMap<String, gguf_file::Value>,
+) -> Result<Tokenizer, ModelManagerError> {
+    if let Some(json) = string_value(metadata, "tokenizer.huggingface.json") {
+        log::info!("Using tokenizer.huggingface.json embedded in GGUF");
+        return Tokenizer::from_bytes(json.as_bytes())
+            .map_err(|e| ModelManagerError::Initialization(e.to_string()));
+    }
+
+    let vocab = GgufVocab::from_metadata(metadata)?;
+    log::info!(
+        "Building {} tokenizer from GGUF metadata: {} tokens, pre-tokenizer {:?}",
+        vocab.model,
+        vocab.tokens.len(),
+        vocab.pre
+    );
+    let mut tokenizer = match vocab.model.as_str() {
+        "gpt2" => build_byte_level_bpe(&vocab)?,
+        "llama" => build_sentencepiece_bpe(&vocab)?,
+        other => {
+            return Err(ModelManagerError::Initialization(format!(
+                "Неподдерживаемый тип токенизатора GGUF: {other}"
+            )))
+        }
+    };
+    register_added_tokens(&mut tokenizer, &vocab);
+    configure_special_tokens(&mut tokenizer, &vocab)?;
+    Ok(tokenizer)
+}
+
+/// Байтовый BPE (GPT-2, Qwen2, Llama 3).
+fn build_byte_level_bpe(vocab: &GgufVocab) -> Result<Tokenizer, ModelManagerError> {
+    let (pattern, normalizer, ignore_merges) = match vocab.pre.as_deref() {
+        Some("qwen2") => (Some(QWEN2_PATTERN), Some(NFC), false),
+        Some("llama3" | "llama-v3" | "llama-bpe") => (Some(LLAMA3_PATTERN), None, true),
+        Some("default" | "gpt2" | "gpt-2") | None => (None, None, false),
+        Some(other) => {
+            log::warn!("Unknown GGUF pre-tokenizer '{other}', falling back to GPT-2 regex");
+            (None, None, false)
+        }
+    };
+
+    let bpe = BPE::builder()
+        .vocab_and_merges(vocab.vocab_map(), vocab.merges.clone())
+        .ignore_merges(ignore_merges)
+        .build()
+        .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
+    let mut tokenizer = Tokenizer::new(bpe);
+    tokenizer.with_normalizer(normalizer);
+
+    match pattern {
+        Some(pattern) => {
+            let split = Split::new(
+                SplitPattern::Regex(pattern.to_string()),
+                SplitDelimiterBehavior::Isolated,
+                false,
+            )
+            .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
+            tokenizer.with_pre_tokenizer(Some(PreTokenizerSequence::new(vec![
+                PreTokenizerWrapper::Split(split),
+                PreTokenizerWrapper::ByteLevel(ByteLevel::new(false, true, false)),
+            ])));
+        }
+        None => {
+            tokenizer.with_pre_tokenizer(Some(ByteLevel::new(false, true, true)));
+        }
+    }
+    tokenizer.with_decoder(Some(ByteLevelDecoder::default()));
+    Ok(tokenizer)
+}
+
+/// SentencePiece BPE с откатом на байтовые токены `<0xNN>` (Llama, Gemma).
+fn build_sentencepiece_bpe(vocab: &GgufVocab) -> Result<Tokenizer, ModelManagerError> {
+    let mut builder = BPE::builder()
+        .vocab_and_merges(vocab.vocab_map(), vocab.merges_from_scores())
+        .byte_fallback(true)
+        .fuse_unk(true);
+    if let Some(unk_id) = vocab.unk_id {
+        builder = builder.unk_token(vocab.tokens[unk_id as usize].clone());
+    }
+    let bpe = builder
+        .build()
+        .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
+    let mut tokenizer = Tokenizer::new(bpe);
+
+    let replace_space = Replace::new(" ", SPM_SPACE)
+        .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
+    let mut normalizers = Vec::new();
+    if vocab.add_space_prefix {
+        normalizers.push(NormalizerWrapper::Prepend(Prepend::new(
+            SPM_SPACE.to_string(),
+        )));
+    }
+    normalizers.push(NormalizerWrapper::Replace(replace_space));
+    tokenizer.with_normalizer(Some(NormalizerSequence::new(normalizers)));
+
+    let replace_marker = Replace::new(SPM_SPACE, " ")
+        .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
+    let mut decoders = vec![
+        DecoderWrapper::Replace(replace_marker),
+        DecoderWrapper::ByteFallback(ByteFallback::new()),
+        DecoderWrapper::Fuse(Fuse::new()),
+    ];
+    if vocab.add_space_prefix {
+        decoders.push(DecoderWrapper::Strip(Strip::new(' ', 1, 0)));
+    }
+    tokenizer.with_decoder(Some(DecoderSequence::new(decoders)));
+    Ok(tokenizer)
+}
+
+/// Регистрирует управляющие и пользовательские токены как добавленные,
+/// чтобы они распознавались в тексте целиком, а не разбивались моделью.
+fn register_added_tokens(tokenizer: &mut Tokenizer, vocab: &GgufVocab) {
+    let mut special = Vec::new();
+    let mut user_defined = Vec::new();
+    for (id, token) in vocab.tokens.iter().enumerate() {
+        match vocab.token_type(id) {
+            TokenType::Control | TokenType::Unknown => {
+                special.push(AddedToken::from(token.clone(), true).normalized(false))
+            }
+            TokenType::UserDefined => {
+                user_defined.push(AddedToken::from(token.clone(), false).normalized(false))
+            }
+            TokenType::Normal | TokenType::Unused | TokenType::Byte => {}
+        }
+    }
+    tokenizer.add_special_tokens(&special);
+    tokenizer.add_tokens(&user_defined);
+}
+
+/// Настраивает добавление BOS/EOS и паддинг по идентификаторам из GGUF.
+fn configure_special_tokens(
+    tokenizer: &mut Tokenizer,
+    vocab: &GgufVocab,
+) -> Result<(), ModelManagerError> {
+    let bos = vocab
+        .bos_id
+        .filter(|_| vocab.add_bos)
+        .map(|id| (vocab.tokens[id as usize].clone(), id));
+    let eos = vocab
+        .eos_id
+        .filter(|_| vocab.add_eos)
+        .map(|id| (vocab.tokens[id as usize].clone(), id));
+
+    if bos.is_some() || eos.is_some() {
+        let wrap = |sequence: &str, type_id: u32| {
+            let mut pieces = Vec::new();
+            if let Some((token, _)) = &bos {
+                pieces.push(format!("{token}:{type_id}"));
+            }
+            pieces.push(format!("{sequence}:{type_id}"));
+            if let Some((token, _)) = &eos {
+                pieces.push(format!("{token}:{type_id}"));
+            }
+            pieces.join(" ")
+        };
+        let processor = TemplateProcessing::builder()
+            .try_single(wrap("$A", 0))
+            .and_then(|builder| builder.try_pair(format!("{} {}", wrap("$A", 0), wrap("$B", 1))))
+            .map_err(ModelManagerError::Initialization)?
+            .special_tokens(bos.iter().chain(eos.iter()).cloned().collect::<Vec<_>>())
+            .build()
+            .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
+        tokenizer.with_post_processor(Some(processor));
+    }
+
+    if let Some(pad_id) = vocab.pad_id {
+        tokenizer.with_padding(Some(PaddingParams {
+            pad_id,
+            pad_token: vocab.tokens[pad_id as usize].clone(),
+            ..Default::default()
+        }));
+    }
+    Ok(())
+}
+
+fn string_value(metadata: &HashMap<String, gguf_file::Value>, key: &str) -> Option<String> {
+    match metadata.get(key) {
+        Some(gguf_file::Value::String(value)) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+fn string_array(metadata: &HashMap<String, gguf_file::Value>, key: &str) -> Option<Vec<String>> {
+    match metadata.get(key) {
+        Some(gguf_file::Value::Array(values)) => Some(
+            values
+                .iter()
+                .map(|value| value.to_string().cloned().unwrap_or_default())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn bool_value(metadata: &HashMap<String, gguf_file::Value>, key: &str) -> Option<bool> {
+    metadata.get(key).and_then(|value| value.to_bool().ok())
+}
+
+/// Читает целое значение любой разрядности (конвертеры пишут id как U32 или I32).
+fn integer_value(value: &gguf_file::Value) -> Option<i64> {
+    match value {
+        gguf_file::Value::U8(v) => Some(*v as i64),
+        gguf_file::Value::I8(v) => Some(*v as i64),
+        gguf_file::Value::U16(v) => Some(*v as i64),
+        gguf_file::Value::I16(v) => Some(*v as i64),
+        gguf_file::Value::U32(v) => Some(*v as i64),
+        gguf_file::Value::I32(v) => Some(*v as i64),
+        gguf_file::Value::U64(v) => i64::try_from(*v).ok(),
+        gguf_file::Value::I64(v) => Some(*v),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gguf_reader::MappedGguf;
+    use crate::test_support::{basic_metadata, write_test_gguf};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    /// Токен эталонного словаря: текст и тип по нумерации llama.cpp.
+    type VocabEntry = (String, i32);
+
+    fn reference_added_tokens(vocab: &[VocabEntry]) -> Vec<serde_json::Value> {
+        vocab
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, token_type))| matches!(token_type, 2..=4))
+            .map(|(id, (content, token_type))| {
+                json!({
+                    "id": id,
+                    "content": content,
+                    "single_word": false,
+                    "lstrip": false,
+                    "rstrip": false,
+                    "normalized": false,
+                    "special": *token_type != 4,
+                })
+            })
+            .collect()
+    }
+
+    fn bpe_model(vocab: &[VocabEntry], merges: &[(&str, &str)], spm: bool) -> serde_json::Value {
+        let vocab_map: serde_json::Map<String, serde_json::Value> = vocab
+            .iter()
+            .enumerate()
+            .map(|(id, (token, _))| (token.clone(), json!(id)))
+            .collect();
+        json!({
+            "type": "BPE",
+            "dropout": null,
+            "unk_token": if spm { json!("<unk>") } else { json!(null) },
+            "continuing_subword_prefix": null,
+            "end_of_word_suffix": null,
+            "fuse_unk": spm,
+            "byte_fallback": spm,
+            "ignore_merges": false,
+            "vocab": vocab_map,
+            "merges": merges,
+        })
+    }
+
+    /// Записывает словарь в GGUF и восстанавливает по нему токенизатор.
+    fn tokenizer_via_gguf(
+        extra: Vec<(String, gguf_file::Value)>,
+        vocab: &[VocabEntry],
+    ) -> Tokenizer {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("model.gguf");
+        let mut metadata = basic_metadata("qwen3");
+        metadata.extend(extra);
+        metadata.push((
+            "tokenizer.ggml.tokens".into(),
+            gguf_file::Value::Array(
+                vocab
+                    .iter()
+                    .map(|(token, _)| gguf_file::Value::String(token.clone()))
+                    .collect(),
+            ),
+        ));
+        metadata.push((
+            "tokenizer.ggml.token_type".into(),
+            gguf_file::Value::Array(
+                vocab
+                    .iter()
+                    .map(|(_, token_type)| gguf_file::Value::I32(*token_type))
+                    .collect(),
+            ),
+        ));
+        write_test_gguf(&path, &metadata, &[("w", &[1])]);
+        tokenizer_from_metadata(&MappedGguf::open(&path).unwrap().content.metadata).unwrap()
+    }
+
+    fn assert_same_ids(reference: &Tokenizer, rebuilt: &Tokenizer, samples: &[&str]) {
+        for sample in samples {
+            let expected = reference.encode(*sample, true).unwrap();
+            let actual = rebuilt.encode(*sample, true).unwrap();
+            assert_eq!(actual.get_ids(), expected.get_ids(), "sample {sample:?}");
+            assert_eq!(
+                rebuilt.decode(actual.get_ids(), true).unwrap(),
+                reference.decode(expected.get_ids(), true).unwrap(),
+                "sample {sample:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_qwen2_bpe_round_trip() {
+        let mut alphabet: Vec<char> = ByteLevel::alphabet().into_iter().collect();
+        alphabet.sort_unstable();
+        let merges = [
+            ("h", "e"),
+            ("l", "l"),
+            ("he", "ll"),
+            ("hell", "o"),
+            ("Ġ", "w"),
+            ("o", "r"),
+            ("Ġw", "or"),
+            ("l", "d"),
+            ("Ġwor", "ld"),
+            ("1", "2"),
+            ("Ċ", "Ċ"),
+        ];
+        let mut vocab: Vec<VocabEntry> = alphabet.iter().map(|c| (c.to_string(), 1)).collect();
+        vocab.extend(merges.iter().map(|(l, r)| (format!("{l}{r}"), 1)));
+        vocab.extend([
+            ("<|endoftext|>".to_string(), 3),
+            ("<|im_start|>".to_string(), 3),
+            ("<|im_end|>".to_string(), 3),
+            ("<think>".to_string(), 4),
+            ("</think>".to_string(), 4),
+        ]);
+
+        let byte_level = json!({
+            "type": "ByteLevel",
+            "add_prefix_space": false,
+            "trim_offsets": false,
+            "use_regex": false,
+        });
+        let reference = Tokenizer::from_bytes(
+            json!({
+                "version": "1.0",
+                "truncation": null,
+                "padding": null,
+                "added_tokens": reference_added_tokens(&vocab),
+                "normalizer": {"type": "NFC"},
+                "pre_tokenizer": {
+                    "type": "Sequence",
+                    "pretokenizers": [
+                        {
+                            "type": "Split",
+                            "pattern": {"Regex": QWEN2_PATTERN},
+                            "behavior": "Isolated",
+                            "invert": false,
+                        },
+                        byte_level,
+                    ],
+                },
+                "post_processor": byte_level,
+                "decoder": byte_level,
+                "model": bpe_model(&vocab, &merges, false),
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let rebuilt = tokenizer_via_gguf(
+            vec![
+                (
+                    "tokenizer.ggml.model".into(),
+                    gguf_file::Value::String("gpt2".into()),
+                ),
+                (
+                    "tokenizer.ggml.pre".into(),
+                    gguf_file::Value::String("qwen2".into()),
+                ),
+                (
+                    "tokenizer.ggml.merges".into(),
+                    gguf_file::Value::Array(
+                        merges
+                            .iter()
+                            .map(|(l, r)| gguf_file::Value::String(format!("{l} {r}")))
+                            .collect(),
+                    ),
+                ),
+                (
+                    "tokenizer.ggml.eos_token_id".into(),
+                    gguf_file::Value::U32(vocab.len() as u32 - 3),
+                ),
+                (
+                    "tokenizer.ggml.add_bos_token".into(),
+                    gguf_file::Value::Bool(false),
+                ),
+            ],
+            &vocab,
+        );
+
+        assert_same_ids(
+            &reference,
+            &rebuilt,
+            &[
+                "hello world",
+                "Hello world 1212",
+                "hello\n\n\nworld  ",
+                "cafe\u{301} привет, мир! 🦀",
+                "<|im_start|>user\nhello<|im_end|>\n<|im_start|>assistant\n",
+                "<think>12</think>world<|endoftext|>",
+                "it's 'll",
+            ],
+        );
+        // Каждая цифра — отдельный токен, как в регулярном выражении Qwen2
+        let digits = rebuilt.encode("12", false).unwrap();
+        assert_eq!(digits.get_ids().len(), 2);
+        let im_end = rebuilt.encode("<|im_end|>", false).unwrap();
+        assert_eq!(im_end.get_ids(), &[vocab.len() as u32 - 3]);
+    }
+
+    fn sentencepiece_round_trip(add_space_prefix: bool) {
+        let merges = [
+            ("l", "l"),
+            ("h", "e"),
+            ("▁", "he"),
+            ("ll", "o"),
+            ("▁he", "llo"),
+            ("▁", "w"),
+            ("o", "r"),
+            ("▁w", "or"),
+            ("l", "d"),
+            ("▁wor", "ld"),
+        ];
+        let chars = ["▁", "h", "e", "l", "o", "w", "r", "d"];
+
+        let mut vocab: Vec<VocabEntry> = vec![
+            ("<unk>".to_string(), 2),
+            ("<s>".to_string(), 3),
+            ("</s>".to_string(), 3),
+        ];
+        vocab.extend((0..=255u8).map(|byte| (format!("<0x{byte:02X}>"), 6)));
+        vocab.extend(merges.iter().map(|(l, r)| (format!("{l}{r}"), 1)));
+        vocab.extend(chars.iter().map(|c| (c.to_string(), 1)));
+        vocab.push(("<start_of_turn>".to_string(), 3));
+
+        // Оценки как в модели SentencePiece BPE: чем раньше слияние, тем выше оценка
+        let merged_start = 3 + 256;
+        let scores: Vec<gguf_file::Value> = (0..vocab.len())
+            .map(|id| {
+                let score = if id < merged_start {
+                    0.0
+                } else if id < merged_start + merges.len() {
+                    -((id - merged_start) as f32)
+                } else {
+                    -1000.0 - id as f32
+                };
+                gguf_file::Value::F32(score)
+            })
+            .collect();
+
+        let mut normalizers = Vec::new();
+        let mut decoders = vec![
+            json!({"type": "Replace", "pattern": {"String": "▁"}, "content": " "}),
+            json!({"type": "ByteFallback"}),
+            json!({"type": "Fuse"}),
+        ];
+        if add_space_prefix {
+            normalizers.push(json!({"type": "Prepend", "prepend": "▁"}));
+            decoders.push(json!({"type": "Strip", "content": " ", "start": 1, "stop": 0}));
+        }
+        normalizers.push(json!({"type": "Replace", "pattern": {"String": " "}, "content": "▁"}));
+        let reference = Tokenizer::from_bytes(
+            json!({
+                "version": "1.0",
+                "truncation": null,
+                "padding": null,
+                "added_tokens": reference_added_tokens(&vocab),
+                "normalizer": {"type": "Sequence", "normalizers": normalizers},
+                "pre_tokenizer": null,
+                "post_processor": {
+                    "type": "TemplateProcessing",
+                    "single": [
+                        {"SpecialToken": {"id": "<s>", "type_id": 0}},
+                        {"Sequence": {"id": "A", "type_id": 0}},
+                    ],
+                    "pair": [
+                        {"SpecialToken": {"id": "<s>", "type_id": 0}},
+                        {"Sequence": {"id": "A", "type_id": 0}},
+                        {"SpecialToken": {"id": "<s>", "type_id": 1}},
+                        {"Sequence": {"id": "B", "type_id": 1}},
+                    ],
+                    "special_tokens": {"<s>": {"id": "<s>", "ids": [1], "tokens": ["<s>"]}},
+                },
+                "decoder": {"type": "Sequence", "decoders": decoders},
+                "model": bpe_model(&vocab, &merges, true),
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let rebuilt = tokenizer_via_gguf(
+            vec![
+                (
+                    "tokenizer.ggml.model".into(),
+                    gguf_file::Value::String("llama".into()),
+                ),
+                (
+                    "tokenizer.ggml.scores".into(),
+                    gguf_file::Value::Array(scores),
+                ),
+                (
+                    "tokenizer.ggml.bos_token_id".into(),
+                    gguf_file::Value::U32(1),
+                ),
+                (
+                    "tokenizer.ggml.eos_token_id".into(),
+                    gguf_file::Value::U32(2),
+                ),
+                (
+                    "tokenizer.ggml.unknown_token_id".into(),
+                    gguf_file::Value::U32(0),
+                ),
+                (
+                    "tokenizer.ggml.add_space_prefix".into(),
+                    gguf_file::Value::Bool(add_space_prefix),
+                ),
+            ],
+            &vocab,
+        );
+
+        assert_same_ids(
+            &reference,
+            &rebuilt,
+            &[
+                "hello world",
+                "world hello",
+                "hello\nworld",
+                "  hellohello  ",
+                "привет",
+                "<start_of_turn>hello</s>",
+            ],
+        );
+        // Перевод строки кодируется байтовым токеном, а не отдельным костылём
+        let newline = rebuilt.encode("\n", false).unwrap();
+        assert!(newline.get_tokens().contains(&"<0x0A>".to_string()));
+        assert_eq!(rebuilt.encode("hello", true).unwrap().get_ids()[0], 1);
+    }
+
+    #[test]
+    fn test_llama_sentencepiece_round_trip() {
+        sentencepiece_round_trip(true);
+    }
+
+    #[test]
+    fn test_gemma_sentencepiece_round_trip() {
+        sentencepiece_round_trip(false);
+    }
+}
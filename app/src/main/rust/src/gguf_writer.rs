@@ -0,0 +1,333 @@
+//! Потоковая запись GGUF (версия 3). Заголовок записывается сразу по описаниям
+//! тензоров, а данные — по одному тензору, поэтому в памяти не нужно держать
+//! всю модель целиком, в отличие от `gguf_file::write` из candle.
+
+use std::io::Write;
+
+use candle_core::quantized::{gguf_file, GgmlDType};
+
+use crate::model_manager::ModelManagerError;
+
+const GGUF_MAGIC: u32 = 0x4655_4747;
+const GGUF_VERSION: u32 = 3;
+/// Ключ метаданных с выравниванием данных тензоров.
+pub const ALIGNMENT_KEY: &str = "general.alignment";
+const DEFAULT_ALIGNMENT: u64 = 32;
+
+/// Описание тензора в заголовке: имя, тип и форма в порядке candle (строки, затем столбцы).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorLayout {
+    pub name: String,
+    pub dtype: GgmlDType,
+    pub shape: Vec<usize>,
+}
+
+impl TensorLayout {
+    /// Размер данных тензора в файле.
+    pub fn size_in_bytes(&self) -> usize {
+        let elements: usize = self.shape.iter().product();
+        elements / self.dtype.block_size() * self.dtype.type_size()
+    }
+}
+
+/// Запись GGUF: тензоры передаются в [`GgufWriter::write_tensor`] в порядке описаний.
+pub struct GgufWriter<W: Write> {
+    out: W,
+    layouts: Vec<TensorLayout>,
+    alignment: u64,
+    next: usize,
+    written: u64,
+}
+
+impl<W: Write> GgufWriter<W> {
+    /// Записывает заголовок с метаданными и описаниями всех тензоров.
+    pub fn new(
+        out: W,
+        metadata: &[(String, gguf_file::Value)],
+        layouts: Vec<TensorLayout>,
+    ) -> Result<Self, ModelManagerError> {
+        let alignment = metadata
+            .iter()
+            .find(|(key, _)| key == ALIGNMENT_KEY)
+            .map(|(_, value)| value.to_u32().map(u64::from))
+            .transpose()
+            .map_err(|e| ModelManagerError::Conversion(format!("{ALIGNMENT_KEY}: {e}")))?
+            .unwrap_or(DEFAULT_ALIGNMENT);
+        if alignment == 0 || !alignment.is_power_of_two() {
+            return Err(ModelManagerError::Conversion(format!(
+                "некорректное выравнивание {alignment}"
+            )));
+        }
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&GGUF_MAGIC.to_le_bytes());
+        header.extend_from_slice(&GGUF_VERSION.to_le_bytes());
+        header.extend_from_slice(&(layouts.len() as u64).to_le_bytes());
+        header.extend_from_slice(&(metadata.len() as u64).to_le_bytes());
+        for (key, value) in metadata {
+            write_string(&mut header, key);
+            header.extend_from_slice(&value_type(value).to_le_bytes());
+            write_value(&mut header, value)?;
+        }
+        let mut offset = 0u64;
+        for layout in &layouts {
+            write_string(&mut header, &layout.name);
+            header.extend_from_slice(&(layout.shape.len() as u32).to_le_bytes());
+            // В GGUF размерности хранятся от самой быстрой к самой медленной
+            for dim in layout.shape.iter().rev() {
+                header.extend_from_slice(&(*dim as u64).to_le_bytes());
+            }
+            header.extend_from_slice(&ggml_type(layout.dtype).to_le_bytes());
+            header.extend_from_slice(&offset.to_le_bytes());
+            offset += padded(layout.size_in_bytes() as u64, alignment);
+        }
+        let header_len = header.len() as u64;
+        header.resize(padded(header_len, alignment) as usize, 0);
+
+        let mut writer = Self {
+            out,
+            layouts,
+            alignment,
+            next: 0,
+            written: 0,
+        };
+        writer.write_all(&header)?;
+        Ok(writer)
+    }
+
+    /// Описание следующего ожидаемого тензора.
+    pub fn next_layout(&self) -> Option<&TensorLayout> {
+        self.layouts.get(self.next)
+    }
+
+    /// Записывает данные следующего тензора. Имя и размер должны совпадать с описанием.
+    pub fn write_tensor(&mut self, name: &str, data: &[u8]) -> Result<(), ModelManagerError> {
+        let layout = self
+            .layouts
+            .get(self.next)
+            .ok_or_else(|| ModelManagerError::Conversion(format!("лишний тензор '{name}'")))?;
+        if layout.name != name || layout.size_in_bytes() != data.len() {
+            return Err(ModelManagerError::Conversion(format!(
+                "ожидался тензор '{}' размером {} байт, получен '{name}' размером {} байт",
+                layout.name,
+                layout.size_in_bytes(),
+                data.len()
+            )));
+        }
+        let padding = padded(data.len() as u64, self.alignment) - data.len() as u64;
+        self.write_all(data)?;
+        self.write_all(&vec![0u8; padding as usize])?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Проверяет, что записаны все тензоры, и возвращает поток с числом записанных байт.
+    pub fn finish(mut self) -> Result<(W, u64), ModelManagerError> {
+        if let Some(layout) = self.layouts.get(self.next) {
+            return Err(ModelManagerError::Conversion(format!(
+                "тензор '{}' не записан",
+                layout.name
+            )));
+        }
+        self.out
+            .flush()
+            .map_err(|e| ModelManagerError::Io(e.to_string()))?;
+        Ok((self.out, self.written))
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), ModelManagerError> {
+        self.out
+            .write_all(bytes)
+            .map_err(|e| ModelManagerError::Io(e.to_string()))?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+fn padded(len: u64, alignment: u64) -> u64 {
+    len.div_ceil(alignment) * alignment
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// Код типа значения метаданных в GGUF.
+fn value_type(value: &gguf_file::Value) -> u32 {
+    use gguf_file::Value;
+    match value {
+        Value::U8(_) => 0,
+        Value::I8(_) => 1,
+        Value::U16(_) => 2,
+        Value::I16(_) => 3,
+        Value::U32(_) => 4,
+        Value::I32(_) => 5,
+        Value::F32(_) => 6,
+        Value::Bool(_) => 7,
+        Value::String(_) => 8,
+        Value::Array(_) => 9,
+        Value::U64(_) => 10,
+        Value::I64(_) => 11,
+        Value::F64(_) => 12,
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: &gguf_file::Value) -> Result<(), ModelManagerError> {
+    use gguf_file::Value;
+    match value {
+        Value::U8(v) => out.push(*v),
+        Value::I8(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::U16(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::I16(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::U32(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::I32(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::F32(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::Bool(v) => out.push(u8::from(*v)),
+        Value::String(v) => write_string(out, v),
+        Value::U64(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::I64(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::F64(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::Array(items) => {
+            let element_type = match items.first() {
+                Some(first) => value_type(first),
+                None => 0,
+            };
+            for item in items {
+                if value_type(item) != element_type {
+                    return Err(ModelManagerError::Conversion(
+                        "массив метаданных содержит значения разных типов".to_string(),
+                    ));
+                }
+            }
+            out.extend_from_slice(&element_type.to_le_bytes());
+            out.extend_from_slice(&(items.len() as u64).to_le_bytes());
+            for item in items {
+                write_value(out, item)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Код типа тензора в GGUF (`ggml_type`).
+fn ggml_type(dtype: GgmlDType) -> u32 {
+    match dtype {
+        GgmlDType::F32 => 0,
+        GgmlDType::F16 => 1,
+        GgmlDType::Q4_0 => 2,
+        GgmlDType::Q4_1 => 3,
+        GgmlDType::Q5_0 => 6,
+        GgmlDType::Q5_1 => 7,
+        GgmlDType::Q8_0 => 8,
+        GgmlDType::Q8_1 => 9,
+        GgmlDType::Q2K => 10,
+        GgmlDType::Q3K => 11,
+        GgmlDType::Q4K => 12,
+        GgmlDType::Q5K => 13,
+        GgmlDType::Q6K => 14,
+        GgmlDType::Q8K => 15,
+        GgmlDType::BF16 => 30,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::quantized::QTensor;
+    use candle_core::{Device, Tensor};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_written_file_is_readable_by_candle() {
+        let metadata = vec![
+            (
+                "general.architecture".to_string(),
+                gguf_file::Value::String("qwen3".into()),
+            ),
+            (
+                "tokenizer.ggml.tokens".to_string(),
+                gguf_file::Value::Array(vec![
+                    gguf_file::Value::String("a".into()),
+                    gguf_file::Value::String("b".into()),
+                ]),
+            ),
+            ("empty".to_string(), gguf_file::Value::Array(Vec::new())),
+            (ALIGNMENT_KEY.to_string(), gguf_file::Value::U32(64)),
+        ];
+        let source = Tensor::arange(0f32, 64., &Device::Cpu)
+            .unwrap()
+            .reshape((2, 32))
+            .unwrap();
+        let q8 = QTensor::quantize(&source, GgmlDType::Q8_0).unwrap();
+        let norm = QTensor::quantize(
+            &Tensor::ones(3, candle_core::DType::F32, &Device::Cpu).unwrap(),
+            GgmlDType::F32,
+        )
+        .unwrap();
+        let layouts = vec![
+            TensorLayout {
+                name: "w".into(),
+                dtype: GgmlDType::Q8_0,
+                shape: vec![2, 32],
+            },
+            TensorLayout {
+                name: "norm".into(),
+                dtype: GgmlDType::F32,
+                shape: vec![3],
+            },
+        ];
+
+        let mut writer = GgufWriter::new(Vec::new(), &metadata, layouts).unwrap();
+        writer.write_tensor("w", &q8.data().unwrap()).unwrap();
+        writer.write_tensor("norm", &norm.data().unwrap()).unwrap();
+        let (bytes, written) = writer.finish().unwrap();
+        assert_eq!(written, bytes.len() as u64);
+
+        let mut cursor = Cursor::new(bytes);
+        let content = gguf_file::Content::read(&mut cursor).unwrap();
+        assert_eq!(content.tensor_data_offset % 64, 0);
+        let tokens = content.metadata["tokenizer.ggml.tokens"].to_vec().unwrap();
+        assert_eq!(tokens.len(), 2);
+        let w = content.tensor(&mut cursor, "w", &Device::Cpu).unwrap();
+        assert_eq!(w.dtype(), GgmlDType::Q8_0);
+        assert_eq!(w.shape().dims(), &[2, 32]);
+        let restored = w.dequantize(&Device::Cpu).unwrap();
+        let diff = (restored - &source)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .max_all()
+            .unwrap();
+        assert!(diff.to_scalar::<f32>().unwrap() < 0.5);
+        let norm = content.tensor(&mut cursor, "norm", &Device::Cpu).unwrap();
+        assert_eq!(
+            norm.dequantize(&Device::Cpu)
+                .unwrap()
+                .to_vec1::<f32>()
+                .unwrap(),
+            [1.0; 3]
+        );
+    }
+
+    #[test]
+    fn test_rejects_tensors_out_of_order() {
+        let layouts = vec![TensorLayout {
+            name: "a".into(),
+            dtype: GgmlDType::F32,
+            shape: vec![4],
+        }];
+        let mut writer = GgufWriter::new(Vec::new(), &[], layouts.clone()).unwrap();
+        assert!(matches!(
+            writer.write_tensor("b", &[0; 16]),
+            Err(ModelManagerError::Conversion(_))
+        ));
+        assert!(writer.write_tensor("a", &[0; 8]).is_err());
+
+        let writer = GgufWriter::new(Vec::new(), &[], layouts).unwrap();
+        assert!(matches!(
+            writer.finish(),
+            Err(ModelManagerError::Conversion(_))
+        ));
+    }
+}
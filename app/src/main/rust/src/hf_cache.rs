@@ -0,0 +1,211 @@
+//! Поиск моделей в кеше Hugging Face Hub.
+//! Раскладка кеша: `models--{org}--{name}/refs/{revision}` содержит хеш коммита,
+//! а файлы лежат в `models--{org}--{name}/snapshots/{commit}/` (обычно как симлинки на `blobs/`).
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::model_catalog::collect_gguf_files;
+use crate::model_manager::ModelManagerError;
+
+/// Префикс директорий репозиториев моделей в кеше.
+const REPO_DIR_PREFIX: &str = "models--";
+
+/// Ревизия по умолчанию.
+pub const DEFAULT_REVISION: &str = "main";
+
+/// Имя директории кеша для репозитория `org/name`.
+pub fn repo_dir_name(repo_id: &str) -> Result<String, ModelManagerError> {
+    let valid = repo_id.split('/').count() <= 2
+        && repo_id.split('/').all(|part| {
+            !part.is_empty()
+                && part != "."
+                && part != ".."
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        });
+    if !valid {
+        return Err(ModelManagerError::InvalidModelId(repo_id.to_string()));
+    }
+    Ok(format!("{REPO_DIR_PREFIX}{}", repo_id.replace('/', "--")))
+}
+
+/// Восстанавливает идентификатор репозитория по пути внутри кеша.
+pub fn repo_id_from_path(path: &Path) -> Option<String> {
+    path.components().find_map(|component| {
+        let name = component.as_os_str().to_str()?;
+        let repo = name.strip_prefix(REPO_DIR_PREFIX)?;
+        Some(repo.replacen("--", "/", 1))
+    })
+}
+
+/// Возвращает директорию снапшота для ревизии (ветка, тег или хеш коммита).
+pub fn resolve_snapshot(
+    cache_dir: &Path,
+    repo_id: &str,
+    revision: Option<&str>,
+) -> Result<PathBuf, ModelManagerError> {
+    let repo_dir = cache_dir.join(repo_dir_name(repo_id)?);
+    let revision = revision.unwrap_or(DEFAULT_REVISION);
+    if !is_relative_path(Path::new(revision)) {
+        return Err(ModelManagerError::InvalidModelId(revision.to_string()));
+    }
+
+    let commit = match std::fs::read_to_string(repo_dir.join("refs").join(revision)) {
+        Ok(commit) => commit.trim().to_string(),
+        Err(_) => revision.to_string(),
+    };
+    let snapshot = repo_dir.join("snapshots").join(&commit);
+    if !is_relative_path(Path::new(&commit)) || !snapshot.is_dir() {
+        return Err(ModelManagerError::ModelFileMissing(
+            snapshot.to_string_lossy().into_owned(),
+        ));
+    }
+    Ok(snapshot)
+}
+
+/// Находит GGUF файл модели в снапшоте.
+/// Без `file_name` снапшот должен содержать ровно один GGUF файл.
+/// Возвращается путь внутри снапшота (без разыменования симлинка), чтобы рядом
+/// находились `tokenizer.json` и `tokenizer_config.json` того же коммита.
+pub fn resolve_model_file(
+    cache_dir: &Path,
+    repo_id: &str,
+    revision: Option<&str>,
+    file_name: Option<&str>,
+) -> Result<PathBuf, ModelManagerError> {
+    let snapshot = resolve_snapshot(cache_dir, repo_id, revision)?;
+    if let Some(file_name) = file_name {
+        if !is_relative_path(Path::new(file_name)) {
+            return Err(ModelManagerError::InvalidModelId(file_name.to_string()));
+        }
+        let path = snapshot.join(file_name);
+        if !path.is_file() {
+            return Err(ModelManagerError::ModelFileMissing(
+                path.to_string_lossy().into_owned(),
+            ));
+        }
+        return Ok(path);
+    }
+
+    let mut files = Vec::new();
+    collect_gguf_files(&snapshot, 0, &mut files);
+    match files.len() {
+        0 => Err(ModelManagerError::ModelFileMissing(
+            snapshot.to_string_lossy().into_owned(),
+        )),
+        1 => Ok(files.remove(0)),
+        count => Err(ModelManagerError::Initialization(format!(
+            "В снапшоте {repo_id} найдено {count} GGUF файлов, укажите имя файла"
+        ))),
+    }
+}
+
+/// SHA-256 файла из кеша по имени LFS блоба, на который ссылается файл снапшота.
+pub fn lfs_sha256(path: &Path) -> Option<String> {
+    let target = std::fs::read_link(path).ok()?;
+    let in_blobs = target
+        .parent()
+        .and_then(Path::file_name)
+        .is_some_and(|dir| dir == "blobs");
+    let name = target.file_name()?.to_str()?;
+    if !in_blobs || repo_id_from_path(path).is_none() {
+        return None;
+    }
+    crate::model_integrity::normalize_sha256(name)
+}
+
+/// Путь состоит только из обычных компонентов (без `..`, корня и префиксов).
+fn is_relative_path(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{basic_metadata, write_test_gguf};
+    use tempfile::tempdir;
+
+    const COMMIT: &str = "0123456789abcdef";
+
+    fn write_snapshot(cache: &Path, files: &[&str]) -> PathBuf {
+        let repo = cache.join("models--Qwen--Qwen3-0.6B-GGUF");
+        std::fs::create_dir_all(repo.join("refs")).unwrap();
+        std::fs::write(repo.join("refs").join("main"), format!("{COMMIT}\n")).unwrap();
+        let snapshot = repo.join("snapshots").join(COMMIT);
+        for file in files {
+            write_test_gguf(
+                &snapshot.join(file),
+                &basic_metadata("qwen3"),
+                &[("w", &[4])],
+            );
+        }
+        snapshot
+    }
+
+    #[test]
+    fn test_resolves_main_ref_to_snapshot_file() {
+        let tmp = tempdir().unwrap();
+        let snapshot = write_snapshot(tmp.path(), &["Qwen3-0.6B-Q8_0.gguf"]);
+
+        let path = resolve_model_file(tmp.path(), "Qwen/Qwen3-0.6B-GGUF", None, None).unwrap();
+        assert_eq!(path, snapshot.join("Qwen3-0.6B-Q8_0.gguf"));
+        assert_eq!(
+            repo_id_from_path(&path).as_deref(),
+            Some("Qwen/Qwen3-0.6B-GGUF")
+        );
+        // Хеш коммита можно указать напрямую
+        let by_commit =
+            resolve_model_file(tmp.path(), "Qwen/Qwen3-0.6B-GGUF", Some(COMMIT), None).unwrap();
+        assert_eq!(by_commit, path);
+    }
+
+    #[test]
+    fn test_requires_file_name_for_several_files() {
+        let tmp = tempdir().unwrap();
+        let snapshot = write_snapshot(tmp.path(), &["a-Q4_K_M.gguf", "a-Q8_0.gguf"]);
+
+        assert!(resolve_model_file(tmp.path(), "Qwen/Qwen3-0.6B-GGUF", None, None).is_err());
+        let path = resolve_model_file(
+            tmp.path(),
+            "Qwen/Qwen3-0.6B-GGUF",
+            None,
+            Some("a-Q8_0.gguf"),
+        )
+        .unwrap();
+        assert_eq!(path, snapshot.join("a-Q8_0.gguf"));
+
+        for (repo, file) in [
+            ("../etc", None),
+            ("Qwen/Qwen3-0.6B-GGUF", Some("../x.gguf")),
+        ] {
+            assert!(matches!(
+                resolve_model_file(tmp.path(), repo, None, file),
+                Err(ModelManagerError::InvalidModelId(_))
+            ));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lfs_blob_name_is_sha256() {
+        let tmp = tempdir().unwrap();
+        let repo = tmp.path().join("models--Qwen--Qwen3-0.6B-GGUF");
+        let hash = "ab".repeat(32);
+        write_test_gguf(
+            &repo.join("blobs").join(&hash),
+            &basic_metadata("qwen3"),
+            &[("w", &[4])],
+        );
+        let snapshot = repo.join("snapshots").join(COMMIT);
+        std::fs::create_dir_all(&snapshot).unwrap();
+        let link = snapshot.join("model.gguf");
+        std::os::unix::fs::symlink(format!("../../blobs/{hash}"), &link).unwrap();
+
+        assert_eq!(lfs_sha256(&link), Some(hash));
+        assert_eq!(lfs_sha256(&repo.join("blobs").join("ab".repeat(32))), None);
+    }
+}
@@ -1,14 +1,31 @@
+use std::collections::HashMap;
+use std::io::Read;
 use std::ptr;
 use std::sync::Arc;
 
-use jni::objects::{GlobalRef, JClass, JObject, JString, JValue};
-use jni::sys::{jint, jstring};
+use jni::objects::{
+    GlobalRef, JByteArray, JClass, JIntArray, JObject, JString, JValue, JValueOwned,
+};
+use jni::sys::{jint, jintArray, jlong, jstring};
 use jni::JNIEnv;
 use once_cell::sync::Lazy;
 
+use crate::batch::{BatchCallback, DEFAULT_MAX_BATCH_SIZE};
+use crate::candidates::CandidateMode;
+use crate::chatbot::hot_swap::{SwapCallback, SwapPolicy, SwapTarget};
+use crate::chatbot::persona::PersonaProfile;
 use crate::chatbot::ChatBot;
-use crate::model_inference::{GenerationConfig, InferenceError, StreamCallback};
-use crate::model_manager::ModelType;
+use crate::external_stores::StoreKind;
+use crate::load_progress::{LoadProgress, LoadProgressCallback};
+use crate::model_download::{DownloadRequest, HttpClient, HttpResponse, ResumeFrom};
+use crate::model_inference::{
+    ChatMessage, FinishReason, GenerationConfig, InferenceError, StreamCallback,
+};
+use crate::model_manager::{ModelManagerError, ModelType};
+use crate::model_source::ModelSource;
+use crate::model_store::ModelId;
+use crate::repetition::{DryConfig, LoopAction, LoopDetection};
+use crate::speculative::{PromptLookup, DEFAULT_DRAFT_TOKENS};
 
 fn with_bot<F, R>(f: F) -> R
 where
@@ -69,6 +86,351 @@ impl StreamCallback for JniStreamCallback {
     }
 }
 
+/// Колбэк прогресса загрузки модели, вызывающий `onProgress` Java-объекта.
+struct JniLoadProgressCallback {
+    java_vm: jni::JavaVM,
+    callback: GlobalRef,
+}
+
+impl LoadProgressCallback for JniLoadProgressCallback {
+    fn on_progress(&self, progress: &LoadProgress) {
+        if let Ok(mut env) = self.java_vm.attach_current_thread() {
+            let Ok(phase) = env.new_string(progress.phase.as_str()) else {
+                return;
+            };
+            let _ = env.call_method(
+                self.callback.as_obj(),
+                "onProgress",
+                "(Ljava/lang/String;JJII)V",
+                &[
+                    JValue::Object(&JObject::from(phase)),
+                    JValue::Long(progress.bytes_loaded as i64),
+                    JValue::Long(progress.bytes_total as i64),
+                    JValue::Int(progress.tensors_loaded as i32),
+                    JValue::Int(progress.tensors_total as i32),
+                ],
+            );
+        }
+    }
+}
+
+/// Колбэк горячей замены модели, вызывающий методы Kotlin `SwapCallback`.
+struct JniSwapCallback {
+    java_vm: jni::JavaVM,
+    callback: GlobalRef,
+}
+
+impl JniSwapCallback {
+    fn call(&self, method: &str, argument: &str) {
+        if let Ok(mut env) = self.java_vm.attach_current_thread() {
+            let Ok(argument) = env.new_string(argument) else {
+                return;
+            };
+            let _ = env.call_method(
+                self.callback.as_obj(),
+                method,
+                "(Ljava/lang/String;)V",
+                &[JValue::Object(&JObject::from(argument))],
+            );
+        }
+    }
+}
+
+impl SwapCallback for JniSwapCallback {
+    fn on_swapped(&self, model_type: ModelType) {
+        self.call("onSwapped", model_type.as_str());
+    }
+
+    fn on_failed(&self, error: &ModelManagerError) {
+        self.call("onFailed", &error.to_string());
+    }
+}
+
+/// Колбэк пакетной генерации, вызывающий методы Kotlin `BatchCallback`.
+struct JniBatchCallback {
+    java_vm: jni::JavaVM,
+    callback: GlobalRef,
+}
+
+impl JniBatchCallback {
+    fn call(&self, method: &str, index: usize, text: &str) {
+        if let Ok(mut env) = self.java_vm.attach_current_thread() {
+            let Ok(text) = env.new_string(text) else {
+                return;
+            };
+            let _ = env.call_method(
+                self.callback.as_obj(),
+                method,
+                "(ILjava/lang/String;)V",
+                &[
+                    JValue::Int(index as i32),
+                    JValue::Object(&JObject::from(text)),
+                ],
+            );
+        }
+    }
+}
+
+impl BatchCallback for JniBatchCallback {
+    fn on_token(&self, index: usize, token: &str) {
+        self.call("onToken", index, token);
+    }
+
+    fn on_sequence_complete(&self, index: usize, text: &str) {
+        self.call("onSequenceComplete", index, text);
+    }
+}
+
+fn load_progress_callback(
+    env: &mut JNIEnv,
+    callback: JObject,
+) -> Option<Arc<dyn LoadProgressCallback>> {
+    if callback.is_null() {
+        return None;
+    }
+    let global = env.new_global_ref(callback).ok()?;
+    let java_vm = env.get_java_vm().ok()?;
+    Some(Arc::new(JniLoadProgressCallback {
+        java_vm,
+        callback: global,
+    }))
+}
+
+/// Оборачивает Kotlin `StreamCallback` для потоковой выдачи токенов; `null` — без потока.
+fn stream_callback(env: &mut JNIEnv, callback: JObject) -> Option<Arc<dyn StreamCallback>> {
+    if callback.is_null() {
+        return None;
+    }
+    let global = match env.new_global_ref(callback) {
+        Ok(global) => global,
+        Err(_) => {
+            jni_exception(env, "Не удалось создать глобальную ссылку на callback");
+            return None;
+        }
+    };
+    match env.get_java_vm() {
+        Ok(java_vm) => Some(Arc::new(JniStreamCallback {
+            java_vm,
+            callback: global,
+        })),
+        Err(_) => {
+            jni_exception(env, "Не удалось получить JavaVM");
+            None
+        }
+    }
+}
+
+/// Таймаут соединения и чтения HTTP, мс.
+const HTTP_TIMEOUT_MS: i32 = 30_000;
+/// Размер Java-буфера для чтения тела ответа.
+const HTTP_READ_BUFFER: usize = 256 * 1024;
+/// Заголовки ответа, нужные загрузчику моделей.
+const HTTP_HEADERS: [&str; 6] = [
+    "content-length",
+    "content-range",
+    "etag",
+    "last-modified",
+    "location",
+    "x-linked-etag",
+];
+
+/// HTTP транспорт загрузчика моделей на основе `java.net.HttpURLConnection`:
+/// системный сетевой стек Android обеспечивает HTTPS, прокси и доверенные сертификаты.
+struct JavaHttpClient {
+    java_vm: jni::JavaVM,
+}
+
+impl HttpClient for JavaHttpClient {
+    fn get(
+        &self,
+        url: &str,
+        resume: Option<ResumeFrom>,
+    ) -> Result<HttpResponse, ModelManagerError> {
+        let mut env = self
+            .java_vm
+            .attach_current_thread()
+            .map_err(|e| ModelManagerError::Download(e.to_string()))?;
+        env.with_local_frame(32, |env| open_connection(env, url, resume))
+            .map_err(|err| {
+                let message = take_java_exception(&mut env).unwrap_or_else(|| err.to_string());
+                ModelManagerError::Download(format!("{url}: {message}"))
+            })
+    }
+}
+
+fn open_connection(
+    env: &mut JNIEnv,
+    url: &str,
+    resume: Option<ResumeFrom>,
+) -> jni::errors::Result<HttpResponse> {
+    let url_string = env.new_string(url)?;
+    let url_object = env.new_object(
+        "java/net/URL",
+        "(Ljava/lang/String;)V",
+        &[JValue::Object(&url_string)],
+    )?;
+    let connection = env
+        .call_method(
+            &url_object,
+            "openConnection",
+            "()Ljava/net/URLConnection;",
+            &[],
+        )?
+        .l()?;
+    // Перенаправления обрабатывает загрузчик, чтобы видеть заголовки Hugging Face
+    env.call_method(
+        &connection,
+        "setInstanceFollowRedirects",
+        "(Z)V",
+        &[JValue::Bool(0)],
+    )?;
+    env.call_method(
+        &connection,
+        "setConnectTimeout",
+        "(I)V",
+        &[JValue::Int(HTTP_TIMEOUT_MS)],
+    )?;
+    env.call_method(
+        &connection,
+        "setReadTimeout",
+        "(I)V",
+        &[JValue::Int(HTTP_TIMEOUT_MS)],
+    )?;
+    if let Some(resume) = resume {
+        let range = format!("bytes={}-", resume.offset);
+        for (name, value) in [("Range", range.as_str()), ("If-Range", resume.validator)] {
+            let name = env.new_string(name)?;
+            let value = env.new_string(value)?;
+            env.call_method(
+                &connection,
+                "setRequestProperty",
+                "(Ljava/lang/String;Ljava/lang/String;)V",
+                &[JValue::Object(&name), JValue::Object(&value)],
+            )?;
+        }
+    }
+
+    let status = env
+        .call_method(&connection, "getResponseCode", "()I", &[])?
+        .i()?;
+    let mut headers = HashMap::new();
+    for name in HTTP_HEADERS {
+        let jname = env.new_string(name)?;
+        let value = env
+            .call_method(
+                &connection,
+                "getHeaderField",
+                "(Ljava/lang/String;)Ljava/lang/String;",
+                &[JValue::Object(&jname)],
+            )?
+            .l()?;
+        if !value.is_null() {
+            let value: String = env.get_string(&JString::from(value))?.into();
+            headers.insert(name.to_string(), value);
+        }
+    }
+
+    let body: Box<dyn Read + Send> = if matches!(status, 200 | 206) {
+        let stream = env
+            .call_method(
+                &connection,
+                "getInputStream",
+                "()Ljava/io/InputStream;",
+                &[],
+            )?
+            .l()?;
+        let buffer = env.new_byte_array(HTTP_READ_BUFFER as i32)?;
+        Box::new(JavaInputStream {
+            java_vm: env.get_java_vm()?,
+            stream: env.new_global_ref(stream)?,
+            buffer: env.new_global_ref(buffer)?,
+            scratch: vec![0; HTTP_READ_BUFFER],
+        })
+    } else {
+        env.call_method(&connection, "disconnect", "()V", &[])?;
+        Box::new(std::io::empty())
+    };
+    Ok(HttpResponse {
+        status: status as u16,
+        headers,
+        body,
+    })
+}
+
+/// Тело HTTP ответа: читает `java.io.InputStream` через общий Java-буфер.
+struct JavaInputStream {
+    java_vm: jni::JavaVM,
+    stream: GlobalRef,
+    buffer: GlobalRef,
+    scratch: Vec<i8>,
+}
+
+impl Read for JavaInputStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut env = self
+            .java_vm
+            .attach_current_thread()
+            .map_err(std::io::Error::other)?;
+        let len = buf.len().min(HTTP_READ_BUFFER);
+        let read = env
+            .call_method(
+                self.stream.as_obj(),
+                "read",
+                "([BII)I",
+                &[
+                    JValue::Object(self.buffer.as_obj()),
+                    JValue::Int(0),
+                    JValue::Int(len as i32),
+                ],
+            )
+            .and_then(|value| value.i());
+        let read = match read {
+            Ok(read) if read > 0 => read as usize,
+            Ok(_) => return Ok(0),
+            Err(err) => {
+                let message = take_java_exception(&mut env).unwrap_or_else(|| err.to_string());
+                return Err(std::io::Error::other(message));
+            }
+        };
+        let array: &JByteArray = self.buffer.as_obj().into();
+        env.get_byte_array_region(array, 0, &mut self.scratch[..read])
+            .map_err(std::io::Error::other)?;
+        for (dst, src) in buf.iter_mut().zip(&self.scratch[..read]) {
+            *dst = *src as u8;
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for JavaInputStream {
+    fn drop(&mut self) {
+        if let Ok(mut env) = self.java_vm.attach_current_thread() {
+            if env
+                .call_method(self.stream.as_obj(), "close", "()V", &[])
+                .is_err()
+            {
+                take_java_exception(&mut env);
+            }
+        }
+    }
+}
+
+/// Снимает ожидающее Java-исключение и возвращает его текст.
+fn take_java_exception(env: &mut JNIEnv) -> Option<String> {
+    if !env.exception_check().unwrap_or(false) {
+        return None;
+    }
+    let throwable = env.exception_occurred().ok();
+    let _ = env.exception_clear();
+    let message = env
+        .call_method(throwable?, "toString", "()Ljava/lang/String;", &[])
+        .and_then(|value| value.l())
+        .ok()?;
+    env.get_string(&JString::from(message))
+        .ok()
+        .map(String::from)
+}
+
 fn jni_exception(env: &mut JNIEnv, message: &str) {
     let _ = env.throw_new("java/lang/RuntimeException", message);
 }
@@ -84,16 +446,128 @@ fn map_generation_config(env: &mut JNIEnv, config_obj: JObject) -> Option<Genera
     let repeat_penalty = env
         .call_method(&config_obj, "getRepeatPenalty", "()F", &[])
         .ok()?;
+    let prompt_lookup = optional_field(env, &config_obj, "getPromptLookup", "()Z");
+    let seed = optional_field(env, &config_obj, "getSeed", "()J");
+    let dry_multiplier = optional_field(env, &config_obj, "getDryMultiplier", "()F");
+    let dry_base = optional_field(env, &config_obj, "getDryBase", "()F");
+    let dry_allowed_length = optional_field(env, &config_obj, "getDryAllowedLength", "()I");
+    let dry_breakers = optional_string_field(env, &config_obj, "getDrySequenceBreakers");
+    let loop_detection = optional_string_field(env, &config_obj, "getLoopDetection");
+
+    // Нулевой множитель отключает DRY; некорректный JSON разделителей — разделители по умолчанию
+    let dry_multiplier = dry_multiplier
+        .and_then(|value| value.f().ok())
+        .unwrap_or(0.0);
+    let dry = (dry_multiplier > 0.0).then(|| {
+        let defaults = DryConfig::default();
+        DryConfig {
+            multiplier: dry_multiplier,
+            base: dry_base
+                .and_then(|value| value.f().ok())
+                .unwrap_or(defaults.base),
+            allowed_length: dry_allowed_length
+                .and_then(|value| value.i().ok())
+                .map_or(defaults.allowed_length, |length| length.max(0) as usize),
+            sequence_breakers: dry_breakers
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .unwrap_or(defaults.sequence_breakers),
+        }
+    });
+    let loop_detection = match loop_detection.as_deref() {
+        Some("stop") => Some(LoopAction::Stop),
+        Some("escalate") => Some(LoopAction::Escalate),
+        _ => None,
+    }
+    .map(|action| LoopDetection {
+        action,
+        ..LoopDetection::default()
+    });
 
     Some(GenerationConfig {
         max_tokens: max_tokens.i().unwrap_or(512) as usize,
         temperature: temperature.f().unwrap_or(0.7),
         top_p: top_p.f().unwrap_or(0.9),
         repeat_penalty: repeat_penalty.f().unwrap_or(1.1),
-        seed: 299792458,
+        seed: seed
+            .and_then(|value| value.j().ok())
+            .map_or(GenerationConfig::default().seed, |seed| seed as u64),
+        prompt_lookup: prompt_lookup
+            .and_then(|value| value.z().ok())
+            .unwrap_or(false)
+            .then(PromptLookup::default),
+        dry,
+        loop_detection,
     })
 }
 
+/// Читает необязательное поле конфигурации. Если геттера нет (класс старой версии),
+/// исключение снимается и возвращается `None` — подставляется значение по умолчанию.
+fn optional_field<'local>(
+    env: &mut JNIEnv<'local>,
+    obj: &JObject,
+    getter: &str,
+    signature: &str,
+) -> Option<JValueOwned<'local>> {
+    match env.call_method(obj, getter, signature, &[]) {
+        Ok(value) => Some(value),
+        Err(_) => {
+            take_java_exception(env);
+            None
+        }
+    }
+}
+
+/// Читает необязательное строковое поле конфигурации: отсутствующий геттер, `null`,
+/// пустая или нечитаемая строка дают `None`.
+fn optional_string_field(env: &mut JNIEnv, obj: &JObject, getter: &str) -> Option<String> {
+    let value = optional_field(env, obj, getter, "()Ljava/lang/String;")?
+        .l()
+        .ok()?;
+    let value = JString::from(value);
+    if value.is_null() {
+        return None;
+    }
+    let text = match env.get_string(&value) {
+        Ok(s) => String::from(s),
+        Err(_) => {
+            take_java_exception(env);
+            return None;
+        }
+    };
+    (!text.is_empty()).then_some(text)
+}
+
+fn read_jstring(env: &mut JNIEnv, value: &JString, name: &str) -> Option<String> {
+    match env.get_string(value) {
+        Ok(s) => Some(s.to_str().unwrap_or("").to_owned()),
+        Err(_) => {
+            jni_exception(env, &format!("Не удалось прочитать {}", name));
+            None
+        }
+    }
+}
+
+/// Читает необязательную строку: `null` и пустая строка дают `None`.
+fn read_optional_jstring(env: &mut JNIEnv, value: &JString, name: &str) -> Option<Option<String>> {
+    if value.is_null() {
+        return Some(None);
+    }
+    read_jstring(env, value, name).map(|s| (!s.is_empty()).then_some(s))
+}
+
+fn json_to_jstring<T: serde::Serialize>(env: &mut JNIEnv, value: &T) -> jstring {
+    match serde_json::to_string(value) {
+        Ok(json) => env
+            .new_string(json)
+            .map(|s| s.into_raw())
+            .unwrap_or(ptr::null_mut()),
+        Err(err) => {
+            jni_exception(env, &format!("Ошибка сериализации: {}", err));
+            ptr::null_mut()
+        }
+    }
+}
+
 fn model_type_from_jint(model_type: jint) -> Option<ModelType> {
     match model_type {
         0 => Some(ModelType::Qwen3),
@@ -192,31 +666,14 @@ pub extern "system" fn Java_com_oxidelabmobile_RustInterface_generateText(
         }
     };
 
-    if let Some(config) = map_generation_config(&mut env, config) {
-        with_bot(|bot| bot.set_generation_params(config));
+    // `null` — оставить параметры персоны или заданные ранее
+    if !config.is_null() {
+        if let Some(config) = map_generation_config(&mut env, config) {
+            with_bot(|bot| bot.set_generation_params(config));
+        }
     }
 
-    let callback_arc = if !callback.is_null() {
-        match env.new_global_ref(callback) {
-            Ok(global) => {
-                if let Ok(vm) = env.get_java_vm() {
-                    Some(Arc::new(JniStreamCallback {
-                        java_vm: vm,
-                        callback: global,
-                    }) as Arc<dyn StreamCallback>)
-                } else {
-                    jni_exception(&mut env, "Не удалось получить JavaVM");
-                    None
-                }
-            }
-            Err(_) => {
-                jni_exception(&mut env, "Не удалось создать глобальную ссылку на callback");
-                None
-            }
-        }
-    } else {
-        None
-    };
+    let callback_arc = stream_callback(&mut env, callback);
 
     let result = with_bot(|bot| bot.generate_text(&prompt_str, callback_arc));
     match result {
@@ -261,36 +718,1133 @@ pub extern "system" fn Java_com_oxidelabmobile_RustInterface_loadModelFromPath(
     }
 }
 
-/// JNI функция для выгрузки модели из памяти.
+/// Загружает модель из открытого файлового дескриптора (например, из
+/// `ParcelFileDescriptor` для `content://` URI) без копирования файла.
+/// Модель занимает `length` байт начиная с `offset`; `length < 0` — до конца файла.
+/// Дескриптор дублируется, закрыть исходный можно сразу после вызова.
 #[no_mangle]
-pub extern "system" fn Java_com_oxidelabmobile_RustInterface_unloadModel(
-    _env: JNIEnv,
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_loadModelFromFd(
+    mut env: JNIEnv,
     _class: JClass,
+    fd: jint,
+    offset: jlong,
+    length: jlong,
 ) {
-    with_bot(|bot| bot.unload_model());
-    log::info!("Модель выгружена из памяти");
+    if fd < 0 || offset < 0 {
+        jni_exception(&mut env, "Некорректный дескриптор или смещение");
+        return;
+    }
+    let source = ModelSource::Fd {
+        fd,
+        offset: offset as u64,
+        length: (length >= 0).then_some(length as u64),
+    };
+    match with_bot(|bot| bot.load_model_from_source(source, None)) {
+        Ok(model_type) => {
+            log::info!("Модель {:?} загружена из дескриптора {}", model_type, fd);
+        }
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка загрузки модели: {}", err));
+        }
+    }
 }
 
-/// Останавливает текущую генерацию текста.
+/// Загружает модель (Qwen3/Gemma3) с отчётом о прогрессе.
 #[no_mangle]
-pub extern "system" fn Java_com_oxidelabmobile_RustInterface_stopGeneration(
-    _env: JNIEnv,
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_loadModelWithProgress(
+    mut env: JNIEnv,
     _class: JClass,
+    model_type: jint,
+    variant: JString,
+    callback: JObject,
 ) {
-    log::info!("JNI stopGeneration called");
-    with_bot(|bot| {
-        if let Some(engine) = bot.get_engine().read().as_ref() {
-            log::info!("Setting stop flag on inference engine");
-            engine.stop_generation();
+    let Some(model_type) = model_type_from_jint(model_type) else {
+        jni_exception(&mut env, "Неизвестный тип модели");
+        return;
+    };
+    let Some(variant_str) = read_jstring(&mut env, &variant, "variant") else {
+        return;
+    };
+    let progress = load_progress_callback(&mut env, callback);
+
+    let result = with_bot(|bot| {
+        futures::executor::block_on(bot.load_model_with_progress(
+            model_type,
+            &variant_str,
+            progress,
+        ))
+    });
+    if let Err(err) = result {
+        jni_exception(&mut env, &format!("Ошибка загрузки модели: {}", err));
+    }
+}
+
+/// Загружает модель из GGUF файла с отчётом о прогрессе.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_loadModelFromPathWithProgress(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_path: JString,
+    callback: JObject,
+) {
+    let Some(model_path_str) = read_jstring(&mut env, &model_path, "model_path") else {
+        return;
+    };
+    let progress = load_progress_callback(&mut env, callback);
+
+    let result = with_bot(|bot| {
+        bot.load_model_from_path_with_progress(std::path::Path::new(&model_path_str), progress)
+    });
+    match result {
+        Ok(model_type) => {
             log::info!(
-                "Stop flag set, current state: {}",
-                engine.is_stop_requested()
+                "Модель {:?} загружена из пути: {}",
+                model_type,
+                model_path_str
             );
-            log::info!("Generation stop requested successfully");
-        } else {
-            log::warn!("No active engine to stop generation");
         }
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка загрузки модели: {}", err));
+        }
+    }
+}
+
+/// Загружает неквантованную модель Hugging Face (директория с `config.json`,
+/// `tokenizer.json` и `*.safetensors`) с отчётом о прогрессе.
+/// `dtype` (`f16`, `bf16`, `f32`) необязателен: по умолчанию — `torch_dtype` модели.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_loadSafetensorsModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_dir: JString,
+    dtype: JString,
+    callback: JObject,
+) {
+    let Some(model_dir) = read_jstring(&mut env, &model_dir, "model_dir") else {
+        return;
+    };
+    let Some(dtype) = read_optional_jstring(&mut env, &dtype, "dtype") else {
+        return;
+    };
+    let progress = load_progress_callback(&mut env, callback);
+
+    let result = with_bot(|bot| {
+        bot.load_safetensors_model(std::path::Path::new(&model_dir), dtype.as_deref(), progress)
+    });
+    match result {
+        Ok(model_type) => log::info!(
+            "Модель {:?} загружена из safetensors: {}",
+            model_type,
+            model_dir
+        ),
+        Err(err) => jni_exception(&mut env, &format!("Ошибка загрузки модели: {}", err)),
+    }
+}
+
+/// Загружает модель хранилища по идентификатору с отчётом о прогрессе.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_loadStoredModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_id: JString,
+    callback: JObject,
+) {
+    let Some(model_id) = read_jstring(&mut env, &model_id, "model_id") else {
+        return;
+    };
+    let progress = load_progress_callback(&mut env, callback);
+
+    match with_bot(|bot| bot.load_stored_model(&model_id, progress)) {
+        Ok(model_type) => log::info!("Модель {:?} загружена: {}", model_type, model_id),
+        Err(err) => jni_exception(&mut env, &format!("Ошибка загрузки модели: {}", err)),
+    }
+}
+
+/// Загружает модель из кеша Hugging Face Hub с отчётом о прогрессе.
+/// `revision` и `file_name` необязательны: по умолчанию используется ветка `main`
+/// и единственный GGUF файл снапшота.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_loadModelFromHfCache(
+    mut env: JNIEnv,
+    _class: JClass,
+    repo_id: JString,
+    revision: JString,
+    file_name: JString,
+    callback: JObject,
+) {
+    let Some(repo_id) = read_jstring(&mut env, &repo_id, "repo_id") else {
+        return;
+    };
+    let Some(revision) = read_optional_jstring(&mut env, &revision, "revision") else {
+        return;
+    };
+    let Some(file_name) = read_optional_jstring(&mut env, &file_name, "file_name") else {
+        return;
+    };
+    let progress = load_progress_callback(&mut env, callback);
+
+    let result = with_bot(|bot| {
+        bot.load_model_from_hf_cache(
+            &repo_id,
+            revision.as_deref(),
+            file_name.as_deref(),
+            progress,
+        )
     });
+    match result {
+        Ok(model_type) => {
+            log::info!("Модель {:?} загружена из кеша Hub: {}", model_type, repo_id);
+        }
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка загрузки модели: {}", err));
+        }
+    }
+}
+
+/// Задаёт путь к tokenizer.json (или его директории) для следующих загрузок.
+/// `null` или пустая строка возвращают автоматический выбор токенизатора.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_setTokenizerOverride(
+    mut env: JNIEnv,
+    _class: JClass,
+    tokenizer_path: JString,
+) {
+    let Some(tokenizer_path) = read_optional_jstring(&mut env, &tokenizer_path, "tokenizer_path")
+    else {
+        return;
+    };
+    with_bot(|bot| bot.set_tokenizer_override(tokenizer_path.map(std::path::PathBuf::from)));
+}
+
+/// Подключает внешнее хранилище моделей (`ollama` или `lm_studio`).
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_addModelStore(
+    mut env: JNIEnv,
+    _class: JClass,
+    kind: JString,
+    path: JString,
+) {
+    let Some(kind) = read_jstring(&mut env, &kind, "kind") else {
+        return;
+    };
+    let Some(path) = read_jstring(&mut env, &path, "path") else {
+        return;
+    };
+    let Some(kind) = StoreKind::parse(&kind) else {
+        jni_exception(&mut env, &format!("Неизвестный тип хранилища: {}", kind));
+        return;
+    };
+    if let Err(err) = with_bot(|bot| bot.add_model_store(kind, path)) {
+        jni_exception(&mut env, &format!("Ошибка подключения хранилища: {}", err));
+    }
+}
+
+/// Отменяет текущую загрузку модели.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_cancelModelLoad(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    with_bot(|bot| bot.cancel_model_load());
+}
+
+/// Отменяет текущую проверку целостности модели.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_cancelVerifyModel(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    with_bot(|bot| bot.cancel_verification());
+}
+
+/// JNI функция для выгрузки модели из памяти.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_unloadModel(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    with_bot(|bot| bot.unload_model());
+    log::info!("Модель выгружена из памяти");
+}
+
+/// Генерирует ответ моделью из памяти с идентификатором `model_id`, не меняя активную.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_generateTextWithModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_id: JString,
+    prompt: JString,
+    callback: JObject,
+) -> jstring {
+    let Some(model_id) = read_jstring(&mut env, &model_id, "model_id") else {
+        return ptr::null_mut();
+    };
+    let Some(prompt) = read_jstring(&mut env, &prompt, "prompt") else {
+        return ptr::null_mut();
+    };
+    let callback = stream_callback(&mut env, callback);
+
+    match with_bot(|bot| bot.generate_text_with_model(&model_id, &prompt, callback)) {
+        Ok(text) => env
+            .new_string(text)
+            .map(|s| s.into_raw())
+            .unwrap_or(ptr::null_mut()),
+        Err(err) => handle_inference_error(&mut env, err),
+    }
+}
+
+/// Генерирует несколько вариантов ответа. `mode` — JSON `{"sample": {"n"}}` или
+/// `{"beam": {"width", "length_penalty"}}`; результат — JSON
+/// `[{"text", "tokens", "logprob", "score", "finished"}]` по убыванию оценки.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_generateCandidates(
+    mut env: JNIEnv,
+    _class: JClass,
+    prompt: JString,
+    mode: JString,
+) -> jstring {
+    let Some(prompt) = read_jstring(&mut env, &prompt, "prompt") else {
+        return ptr::null_mut();
+    };
+    let Some(mode) = read_jstring(&mut env, &mode, "mode") else {
+        return ptr::null_mut();
+    };
+    let mode: CandidateMode = match serde_json::from_str(&mode) {
+        Ok(mode) => mode,
+        Err(err) => {
+            jni_exception(&mut env, &format!("Некорректный режим вариантов: {}", err));
+            return ptr::null_mut();
+        }
+    };
+
+    match with_bot(|bot| bot.generate_candidates(&prompt, mode)) {
+        Ok(candidates) => json_to_jstring(&mut env, &candidates),
+        Err(err) => handle_inference_error(&mut env, err),
+    }
+}
+
+/// Генерирует ответы на несколько промптов пакетами. `prompts` — JSON-массив строк,
+/// `max_batch_size <= 0` — размер пакета по умолчанию; результат — JSON-массив ответов
+/// в порядке промптов.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_generateBatch(
+    mut env: JNIEnv,
+    _class: JClass,
+    prompts: JString,
+    max_batch_size: jint,
+    callback: JObject,
+) -> jstring {
+    let Some(prompts) = read_jstring(&mut env, &prompts, "prompts") else {
+        return ptr::null_mut();
+    };
+    let prompts: Vec<String> = match serde_json::from_str(&prompts) {
+        Ok(prompts) => prompts,
+        Err(err) => {
+            jni_exception(&mut env, &format!("Некорректный список промптов: {}", err));
+            return ptr::null_mut();
+        }
+    };
+    let max_batch_size = if max_batch_size > 0 {
+        max_batch_size as usize
+    } else {
+        DEFAULT_MAX_BATCH_SIZE
+    };
+    let callback = if callback.is_null() {
+        None
+    } else {
+        let global = env.new_global_ref(callback).ok();
+        let java_vm = env.get_java_vm().ok();
+        global.zip(java_vm).map(|(callback, java_vm)| {
+            Arc::new(JniBatchCallback { java_vm, callback }) as Arc<dyn BatchCallback>
+        })
+    };
+
+    match with_bot(|bot| bot.generate_batch(&prompts, max_batch_size, callback)) {
+        Ok(texts) => json_to_jstring(&mut env, &texts),
+        Err(err) => handle_inference_error(&mut env, err),
+    }
+}
+
+/// Возвращает JSON со списком моделей в памяти (от недавно использованных).
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_listResidentModels(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let models = with_bot(|bot| bot.list_resident_models());
+    json_to_jstring(&mut env, &models)
+}
+
+/// Делает модель из памяти активной без повторной загрузки.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_selectModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_id: JString,
+) {
+    let Some(model_id) = read_jstring(&mut env, &model_id, "model_id") else {
+        return;
+    };
+    match with_bot(|bot| bot.select_model(&model_id)) {
+        Ok(model_type) => log::info!("Активна модель {:?}: {}", model_type, model_id),
+        Err(err) => jni_exception(&mut env, &format!("Ошибка выбора модели: {}", err)),
+    }
+}
+
+/// Переключает модель в фоне, не прерывая обслуживание: `target` — JSON
+/// (`{"path": ...}`, `{"stored": ...}`, `{"resident": ...}` или `{"safetensors": {"dir", "dtype"}}`),
+/// `policy` — `drain` или `cancel` для генераций на прежней модели.
+/// Возвращается сразу; результат приходит в `swapCallback`.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_hotSwapModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    target: JString,
+    policy: JString,
+    callback: JObject,
+    swap_callback: JObject,
+) {
+    let Some(target) = read_jstring(&mut env, &target, "target") else {
+        return;
+    };
+    let Some(policy) = read_jstring(&mut env, &policy, "policy") else {
+        return;
+    };
+    let target: SwapTarget = match serde_json::from_str(&target) {
+        Ok(target) => target,
+        Err(err) => {
+            jni_exception(
+                &mut env,
+                &format!("Некорректная модель для замены: {}", err),
+            );
+            return;
+        }
+    };
+    let Some(policy) = SwapPolicy::parse(&policy) else {
+        jni_exception(
+            &mut env,
+            &format!("Неизвестная политика замены '{}'", policy),
+        );
+        return;
+    };
+    let progress = load_progress_callback(&mut env, callback);
+    let swap_callback = if swap_callback.is_null() {
+        None
+    } else {
+        let global = env.new_global_ref(swap_callback).ok();
+        let java_vm = env.get_java_vm().ok();
+        global.zip(java_vm).map(|(callback, java_vm)| {
+            Arc::new(JniSwapCallback { java_vm, callback }) as Arc<dyn SwapCallback>
+        })
+    };
+
+    // Поток замены работает независимо; результат сообщается через колбэк
+    drop(with_bot(|bot| {
+        bot.hot_swap(target, policy, progress, swap_callback)
+    }));
+}
+
+/// Выгружает модель из памяти по идентификатору.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_unloadResidentModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_id: JString,
+) {
+    let Some(model_id) = read_jstring(&mut env, &model_id, "model_id") else {
+        return;
+    };
+    if let Err(err) = with_bot(|bot| bot.unload_resident_model(&model_id)) {
+        jni_exception(&mut env, &format!("Ошибка выгрузки модели: {}", err));
+    }
+}
+
+/// Задаёт, сколько моделей может одновременно находиться в памяти (не меньше одной).
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_setMaxResidentModels(
+    _env: JNIEnv,
+    _class: JClass,
+    max_models: jint,
+) {
+    let max_models = usize::try_from(max_models).unwrap_or(1);
+    with_bot(|bot| bot.set_max_resident_models(max_models));
+}
+
+/// Включает спекулятивное декодирование с черновой GGUF моделью `modelPath`;
+/// `draftTokens` — число токенов, предлагаемых за шаг.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_setDraftModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_path: JString,
+    draft_tokens: jint,
+    callback: JObject,
+) {
+    let Some(model_path) = read_jstring(&mut env, &model_path, "model_path") else {
+        return;
+    };
+    let draft_tokens = usize::try_from(draft_tokens).unwrap_or(DEFAULT_DRAFT_TOKENS);
+    let progress = load_progress_callback(&mut env, callback);
+
+    let result = with_bot(|bot| {
+        bot.set_draft_model(std::path::Path::new(&model_path), draft_tokens, progress)
+    });
+    match result {
+        Ok(()) => log::info!("Черновая модель загружена: {}", model_path),
+        Err(err) => jni_exception(
+            &mut env,
+            &format!("Ошибка загрузки черновой модели: {}", err),
+        ),
+    }
+}
+
+/// Отключает спекулятивное декодирование.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_clearDraftModel(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    with_bot(|bot| bot.clear_draft_model());
+}
+
+/// Статистика последней спекулятивной генерации в JSON или `null`.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_getSpeculativeStats(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let stats = with_bot(|bot| bot.speculative_stats());
+    json_to_jstring(&mut env, &stats)
+}
+
+/// Причина завершения последней генерации: `eos`, `length`, `cancelled`, `loop`
+/// или пустая строка, если она неизвестна.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_getFinishReason(
+    env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let reason = with_bot(|bot| bot.finish_reason());
+    env.new_string(reason.as_ref().map(FinishReason::as_str).unwrap_or(""))
+        .map(|s| s.into_raw())
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Останавливает текущую генерацию текста.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_stopGeneration(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    log::info!("JNI stopGeneration called");
+    with_bot(|bot| {
+        if let Some(engine) = bot.get_engine().read().as_ref() {
+            log::info!("Setting stop flag on inference engine");
+            engine.stop_generation();
+            log::info!(
+                "Stop flag set, current state: {}",
+                engine.is_stop_requested()
+            );
+            log::info!("Generation stop requested successfully");
+        } else {
+            log::warn!("No active engine to stop generation");
+        }
+    });
+}
+
+/// Возвращает число токенов текста для загруженной модели.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_countTokens(
+    mut env: JNIEnv,
+    _class: JClass,
+    text: JString,
+) -> jint {
+    let Some(text) = read_jstring(&mut env, &text, "text") else {
+        return 0;
+    };
+    match with_bot(|bot| bot.count_tokens(&text)) {
+        Ok(count) => count as jint,
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка инференса: {}", err));
+            0
+        }
+    }
+}
+
+/// Кодирует текст в массив идентификаторов токенов.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_tokenize(
+    mut env: JNIEnv,
+    _class: JClass,
+    text: JString,
+) -> jintArray {
+    let Some(text) = read_jstring(&mut env, &text, "text") else {
+        return ptr::null_mut();
+    };
+    let ids = match with_bot(|bot| bot.tokenize(&text)) {
+        Ok(ids) => ids,
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка инференса: {}", err));
+            return ptr::null_mut();
+        }
+    };
+    let ids: Vec<jint> = ids.into_iter().map(|id| id as jint).collect();
+    let result = env
+        .new_int_array(ids.len() as i32)
+        .and_then(|array| env.set_int_array_region(&array, 0, &ids).map(|_| array));
+    match result {
+        Ok(array) => array.into_raw(),
+        Err(err) => {
+            jni_exception(
+                &mut env,
+                &format!("Не удалось создать массив токенов: {}", err),
+            );
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Декодирует массив идентификаторов токенов в текст.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_detokenize(
+    mut env: JNIEnv,
+    _class: JClass,
+    ids: JIntArray,
+) -> jstring {
+    let ids = env.get_array_length(&ids).and_then(|len| {
+        let mut buffer = vec![0 as jint; len as usize];
+        env.get_int_array_region(&ids, 0, &mut buffer)
+            .map(|_| buffer)
+    });
+    let ids: Vec<u32> = match ids {
+        Ok(ids) => ids.into_iter().map(|id| id as u32).collect(),
+        Err(_) => {
+            jni_exception(&mut env, "Не удалось прочитать ids");
+            return ptr::null_mut();
+        }
+    };
+    match with_bot(|bot| bot.detokenize(&ids)) {
+        Ok(text) => env
+            .new_string(text)
+            .map(|s| s.into_raw())
+            .unwrap_or(ptr::null_mut()),
+        Err(err) => handle_inference_error(&mut env, err),
+    }
+}
+
+/// Рендерит chat template для JSON-массива сообщений (`[{"role", "content"}]`)
+/// и возвращает JSON `{"prompt", "token_count"}` без запуска генерации.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_renderPrompt(
+    mut env: JNIEnv,
+    _class: JClass,
+    messages_json: JString,
+) -> jstring {
+    let Some(messages_json) = read_jstring(&mut env, &messages_json, "messages_json") else {
+        return ptr::null_mut();
+    };
+    let messages: Vec<ChatMessage> = match serde_json::from_str(&messages_json) {
+        Ok(messages) => messages,
+        Err(err) => {
+            jni_exception(&mut env, &format!("Некорректный список сообщений: {}", err));
+            return ptr::null_mut();
+        }
+    };
+    match with_bot(|bot| bot.render_prompt(&messages)) {
+        Ok(rendered) => json_to_jstring(&mut env, &rendered),
+        Err(err) => handle_inference_error(&mut env, err),
+    }
+}
+
+/// Возвращает JSON-массив установленных моделей.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_listModels(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let models = with_bot(|bot| bot.list_models());
+    json_to_jstring(&mut env, &models)
+}
+
+/// Проверяет целостность GGUF файла и возвращает JSON отчёт.
+/// `expected_sha256` необязателен: без него используется `.sha256` рядом с моделью
+/// или хеш блоба кеша Hugging Face, если они есть.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_verifyModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_path: JString,
+    expected_sha256: JString,
+    callback: JObject,
+) -> jstring {
+    let Some(model_path) = read_jstring(&mut env, &model_path, "model_path") else {
+        return ptr::null_mut();
+    };
+    let Some(expected_sha256) =
+        read_optional_jstring(&mut env, &expected_sha256, "expected_sha256")
+    else {
+        return ptr::null_mut();
+    };
+    let progress = load_progress_callback(&mut env, callback);
+
+    match with_bot(|bot| {
+        bot.verify_model(
+            std::path::Path::new(&model_path),
+            expected_sha256.as_deref(),
+            progress,
+        )
+    }) {
+        Ok(report) => json_to_jstring(&mut env, &report),
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка проверки модели: {}", err));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Копирует GGUF файл в хранилище моделей под идентификатором `model_id`.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_importModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    source_path: JString,
+    model_id: JString,
+) {
+    let Some(source_path) = read_jstring(&mut env, &source_path, "source_path") else {
+        return;
+    };
+    let Some(model_id) = read_jstring(&mut env, &model_id, "model_id") else {
+        return;
+    };
+    if let Err(err) =
+        with_bot(|bot| bot.import_model(std::path::Path::new(&source_path), &model_id))
+    {
+        jni_exception(&mut env, &format!("Ошибка импорта модели: {}", err));
+    }
+}
+
+/// Скачивает файл `file_name` из репозитория Hugging Face `repo_id` в хранилище
+/// под идентификатором `model_id` и возвращает JSON с отчётом о загрузке.
+/// Прерванная загрузка продолжается с сохранённого места; `revision` и
+/// `expected_sha256` необязательны.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_downloadModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    repo_id: JString,
+    revision: JString,
+    file_name: JString,
+    model_id: JString,
+    expected_sha256: JString,
+    callback: JObject,
+) -> jstring {
+    let Some(repo_id) = read_jstring(&mut env, &repo_id, "repo_id") else {
+        return ptr::null_mut();
+    };
+    let Some(revision) = read_optional_jstring(&mut env, &revision, "revision") else {
+        return ptr::null_mut();
+    };
+    let Some(file_name) = read_jstring(&mut env, &file_name, "file_name") else {
+        return ptr::null_mut();
+    };
+    let Some(model_id) = read_jstring(&mut env, &model_id, "model_id") else {
+        return ptr::null_mut();
+    };
+    let Some(expected_sha256) =
+        read_optional_jstring(&mut env, &expected_sha256, "expected_sha256")
+    else {
+        return ptr::null_mut();
+    };
+    let model_id = match ModelId::parse(&model_id) {
+        Ok(model_id) => model_id,
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка загрузки модели: {}", err));
+            return ptr::null_mut();
+        }
+    };
+    let java_vm = match env.get_java_vm() {
+        Ok(java_vm) => java_vm,
+        Err(err) => {
+            jni_exception(&mut env, &format!("Не удалось получить JavaVM: {}", err));
+            return ptr::null_mut();
+        }
+    };
+    let progress = load_progress_callback(&mut env, callback);
+
+    let request = DownloadRequest {
+        repo_id,
+        revision,
+        file_name,
+        model_id,
+        expected_sha256,
+    };
+    let client = JavaHttpClient { java_vm };
+    match with_bot(|bot| bot.download_model(&request, &client, progress)) {
+        Ok(report) => json_to_jstring(&mut env, &report),
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка загрузки модели: {}", err));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Отменяет текущую загрузку модели из сети; скачанная часть сохраняется для докачки.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_cancelDownload(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    with_bot(|bot| bot.cancel_download());
+}
+
+/// Задаёт зеркала Hugging Face (JSON массив базовых адресов в порядке приоритета).
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_setDownloadMirrors(
+    mut env: JNIEnv,
+    _class: JClass,
+    mirrors_json: JString,
+) {
+    let Some(mirrors_json) = read_jstring(&mut env, &mirrors_json, "mirrors_json") else {
+        return;
+    };
+    match serde_json::from_str::<Vec<String>>(&mirrors_json) {
+        Ok(mirrors) => with_bot(|bot| bot.set_download_mirrors(mirrors)),
+        Err(err) => jni_exception(&mut env, &format!("Некорректный список зеркал: {}", err)),
+    }
+}
+
+/// Конвертирует GGUF или модель safetensors в GGUF с выбранной квантизацией
+/// и сохраняет её в хранилище; возвращает JSON отчёт.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_convertModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    input_path: JString,
+    model_id: JString,
+    plan: JString,
+    callback: JObject,
+) -> jstring {
+    let Some(input_path) = read_jstring(&mut env, &input_path, "input_path") else {
+        return ptr::null_mut();
+    };
+    let Some(model_id) = read_jstring(&mut env, &model_id, "model_id") else {
+        return ptr::null_mut();
+    };
+    let Some(plan) = read_jstring(&mut env, &plan, "plan") else {
+        return ptr::null_mut();
+    };
+    let progress = load_progress_callback(&mut env, callback);
+
+    match with_bot(|bot| {
+        bot.convert_model(
+            std::path::Path::new(&input_path),
+            &model_id,
+            &plan,
+            progress,
+        )
+    }) {
+        Ok(report) => json_to_jstring(&mut env, &report),
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка конвертации модели: {}", err));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Отменяет текущую конвертацию модели.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_cancelConversion(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    with_bot(|bot| bot.cancel_conversion());
+}
+
+/// Возвращает JSON список метаданных GGUF модели (длинные массивы без значений).
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_getModelMetadata(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_path: JString,
+) -> jstring {
+    let Some(model_path) = read_jstring(&mut env, &model_path, "model_path") else {
+        return ptr::null_mut();
+    };
+    match with_bot(|bot| bot.get_model_metadata(std::path::Path::new(&model_path))) {
+        Ok(entries) => json_to_jstring(&mut env, &entries),
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка чтения метаданных: {}", err));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Изменяет метаданные GGUF модели по JSON объекту `{"ключ": значение}`; `null` удаляет ключ.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_editModelMetadata(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_path: JString,
+    edits_json: JString,
+) {
+    let Some(model_path) = read_jstring(&mut env, &model_path, "model_path") else {
+        return;
+    };
+    let Some(edits_json) = read_jstring(&mut env, &edits_json, "edits_json") else {
+        return;
+    };
+    if let Err(err) =
+        with_bot(|bot| bot.edit_model_metadata(std::path::Path::new(&model_path), &edits_json))
+    {
+        jni_exception(&mut env, &format!("Ошибка изменения метаданных: {}", err));
+    }
+}
+
+/// Заменяет chat template модели.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_setModelChatTemplate(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_path: JString,
+    template: JString,
+) {
+    let Some(model_path) = read_jstring(&mut env, &model_path, "model_path") else {
+        return;
+    };
+    let Some(template) = read_jstring(&mut env, &template, "template") else {
+        return;
+    };
+    if let Err(err) =
+        with_bot(|bot| bot.set_model_chat_template(std::path::Path::new(&model_path), &template))
+    {
+        jni_exception(&mut env, &format!("Ошибка изменения шаблона: {}", err));
+    }
+}
+
+/// Задаёт отображаемое имя модели.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_setModelDisplayName(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_path: JString,
+    name: JString,
+) {
+    let Some(model_path) = read_jstring(&mut env, &model_path, "model_path") else {
+        return;
+    };
+    let Some(name) = read_jstring(&mut env, &name, "name") else {
+        return;
+    };
+    if let Err(err) =
+        with_bot(|bot| bot.set_model_display_name(std::path::Path::new(&model_path), &name))
+    {
+        jni_exception(&mut env, &format!("Ошибка изменения имени модели: {}", err));
+    }
+}
+
+/// Переименовывает модель в хранилище.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_renameModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    from_id: JString,
+    to_id: JString,
+) {
+    let Some(from_id) = read_jstring(&mut env, &from_id, "from_id") else {
+        return;
+    };
+    let Some(to_id) = read_jstring(&mut env, &to_id, "to_id") else {
+        return;
+    };
+    if let Err(err) = with_bot(|bot| bot.rename_model(&from_id, &to_id)) {
+        jni_exception(&mut env, &format!("Ошибка переименования модели: {}", err));
+    }
+}
+
+/// Удаляет модель из хранилища.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_deleteModel(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_id: JString,
+) {
+    let Some(model_id) = read_jstring(&mut env, &model_id, "model_id") else {
+        return;
+    };
+    if let Err(err) = with_bot(|bot| bot.delete_model(&model_id)) {
+        jni_exception(&mut env, &format!("Ошибка удаления модели: {}", err));
+    }
+}
+
+/// Возвращает JSON со сведениями о GGUF файле.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_getModelInfo(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_path: JString,
+) -> jstring {
+    let Some(model_path) = read_jstring(&mut env, &model_path, "model_path") else {
+        return ptr::null_mut();
+    };
+    match with_bot(|bot| bot.get_model_info(std::path::Path::new(&model_path))) {
+        Ok(info) => json_to_jstring(&mut env, &info),
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка чтения модели: {}", err));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Задаёт бюджет памяти для загрузки моделей (0 или меньше отключает проверку).
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_setMemoryBudget(
+    _env: JNIEnv,
+    _class: JClass,
+    budget_bytes: jlong,
+) {
+    let budget = (budget_bytes > 0).then_some(budget_bytes as u64);
+    with_bot(|bot| bot.set_memory_budget(budget));
+}
+
+/// Возвращает JSON с оценкой памяти для загрузки GGUF файла.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_estimateModelMemory(
+    mut env: JNIEnv,
+    _class: JClass,
+    model_path: JString,
+) -> jstring {
+    let Some(model_path) = read_jstring(&mut env, &model_path, "model_path") else {
+        return ptr::null_mut();
+    };
+    match with_bot(|bot| bot.estimate_model_memory(std::path::Path::new(&model_path))) {
+        Ok(estimate) => json_to_jstring(&mut env, &estimate),
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка оценки памяти: {}", err));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Рекомендует квантование из JSON-массива путей к кандидатам.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_recommendQuantization(
+    mut env: JNIEnv,
+    _class: JClass,
+    candidates_json: JString,
+    available_ram: jlong,
+) -> jstring {
+    let Some(candidates_json) = read_jstring(&mut env, &candidates_json, "candidates_json") else {
+        return ptr::null_mut();
+    };
+    let candidates: Vec<std::path::PathBuf> = match serde_json::from_str(&candidates_json) {
+        Ok(candidates) => candidates,
+        Err(err) => {
+            jni_exception(
+                &mut env,
+                &format!("Некорректный список кандидатов: {}", err),
+            );
+            return ptr::null_mut();
+        }
+    };
+    let available_ram = available_ram.max(0) as u64;
+    match with_bot(|bot| bot.recommend_quantization(&candidates, available_ram)) {
+        Ok(recommendation) => json_to_jstring(&mut env, &recommendation),
+        Err(err) => {
+            jni_exception(&mut env, &format!("Ошибка подбора квантования: {}", err));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Возвращает JSON-массив профилей персон.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_listPersonas(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let personas = with_bot(|bot| bot.list_personas());
+    json_to_jstring(&mut env, &personas)
+}
+
+/// Возвращает JSON активного профиля персоны.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_getActivePersona(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let persona = with_bot(|bot| bot.active_persona());
+    json_to_jstring(&mut env, &persona)
+}
+
+fn parse_persona(env: &mut JNIEnv, persona_json: &JString) -> Option<PersonaProfile> {
+    let json = read_jstring(env, persona_json, "persona_json")?;
+    match serde_json::from_str(&json) {
+        Ok(profile) => Some(profile),
+        Err(err) => {
+            jni_exception(env, &format!("Некорректный профиль персоны: {}", err));
+            None
+        }
+    }
+}
+
+/// Создаёт профиль персоны из JSON.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_createPersona(
+    mut env: JNIEnv,
+    _class: JClass,
+    persona_json: JString,
+) {
+    let Some(profile) = parse_persona(&mut env, &persona_json) else {
+        return;
+    };
+    if let Err(err) = with_bot(|bot| bot.create_persona(profile)) {
+        jni_exception(&mut env, &format!("Ошибка создания профиля: {}", err));
+    }
+}
+
+/// Обновляет профиль персоны из JSON.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_updatePersona(
+    mut env: JNIEnv,
+    _class: JClass,
+    persona_json: JString,
+) {
+    let Some(profile) = parse_persona(&mut env, &persona_json) else {
+        return;
+    };
+    if let Err(err) = with_bot(|bot| bot.update_persona(profile)) {
+        jni_exception(&mut env, &format!("Ошибка обновления профиля: {}", err));
+    }
+}
+
+/// Удаляет профиль персоны по имени.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_deletePersona(
+    mut env: JNIEnv,
+    _class: JClass,
+    name: JString,
+) {
+    let Some(name) = read_jstring(&mut env, &name, "name") else {
+        return;
+    };
+    if let Err(err) = with_bot(|bot| bot.delete_persona(&name)) {
+        jni_exception(&mut env, &format!("Ошибка удаления профиля: {}", err));
+    }
+}
+
+/// Делает профиль персоны активным.
+#[no_mangle]
+pub extern "system" fn Java_com_oxidelabmobile_RustInterface_selectPersona(
+    mut env: JNIEnv,
+    _class: JClass,
+    name: JString,
+) {
+    let Some(name) = read_jstring(&mut env, &name, "name") else {
+        return;
+    };
+    if let Err(err) = with_bot(|bot| bot.select_persona(&name)) {
+        jni_exception(&mut env, &format!("Ошибка выбора профиля: {}", err));
+    }
 }
 
 #[no_mangle]
@@ -1,11 +1,40 @@
+#[cfg(target_os = "android")]
 use android_activity::AndroidApp;
 use log::*;
 
+pub mod batch;
+pub mod candidates;
 pub mod chatbot;
+pub mod external_stores;
+pub mod gguf_editor;
+pub mod gguf_limits;
+pub mod gguf_reader;
+pub mod gguf_split;
+pub mod gguf_tokenizer;
+pub mod gguf_writer;
+pub mod hf_cache;
 pub mod jni_bridge;
+pub mod load_progress;
+pub mod model_catalog;
+pub mod model_convert;
+pub mod model_download;
 pub mod model_inference;
+pub mod model_integrity;
 pub mod model_manager;
+pub mod model_memory;
+pub mod model_pool;
+pub mod model_source;
+pub mod model_store;
+pub mod model_weights;
+pub mod quantized_qwen3;
+pub mod repetition;
+pub mod safetensors_model;
+pub mod sha256;
+pub mod speculative;
+#[cfg(test)]
+mod test_support;
 pub mod tests;
+pub mod tokenizer_source;
 use chatbot::ChatBot;
 
 /// Initialize logging for Android
@@ -18,6 +47,7 @@ pub fn init_logging() {
     info!("Rust logging initialized for Oxide Lab Mobile");
 }
 
+#[cfg(target_os = "android")]
 #[no_mangle]
 fn android_main(app: AndroidApp) {
     android_logger::init_once(
@@ -0,0 +1,347 @@
+//! Отчёт о ходе загрузки модели и её отмена.
+//! Прогресс по тензорам собирается обёрткой над reader-ом, через которую
+//! candle читает веса, поэтому отдельной поддержки в моделях не требуется.
+
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use candle_core::quantized::gguf_file;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+/// Этап загрузки модели.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadPhase {
+    /// Загрузка файла модели из сети.
+    Download,
+    /// Проверка контрольной суммы файла.
+    Checksum,
+    /// Разбор заголовка GGUF.
+    Header,
+    /// Подготовка токенизатора.
+    Tokenizer,
+    /// Чтение весов.
+    Tensors,
+    /// Конвертация и квантизация весов в новый GGUF.
+    Convert,
+    /// Модель загружена и активирована.
+    Done,
+}
+
+impl LoadPhase {
+    /// Возвращает имя этапа для передачи в Kotlin.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoadPhase::Download => "download",
+            LoadPhase::Checksum => "checksum",
+            LoadPhase::Header => "header",
+            LoadPhase::Tokenizer => "tokenizer",
+            LoadPhase::Tensors => "tensors",
+            LoadPhase::Convert => "convert",
+            LoadPhase::Done => "done",
+        }
+    }
+}
+
+/// Состояние загрузки на момент отчёта.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LoadProgress {
+    pub phase: LoadPhase,
+    pub bytes_loaded: u64,
+    pub bytes_total: u64,
+    pub tensors_loaded: usize,
+    pub tensors_total: usize,
+}
+
+/// Обработчик прогресса загрузки модели.
+pub trait LoadProgressCallback: Send + Sync {
+    /// Вызывается при смене этапа и после чтения каждого тензора.
+    fn on_progress(&self, progress: &LoadProgress);
+}
+
+/// Флаг отмены, разделяемый между загрузчиком и вызывающей стороной.
+#[derive(Debug, Clone, Default)]
+pub struct LoadCancellation(Arc<AtomicBool>);
+
+impl LoadCancellation {
+    /// Запрашивает отмену текущей загрузки.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Сбрасывает флаг перед новой загрузкой.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    /// Проверяет, запрошена ли отмена.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Отмена операций одного вида. Каждая операция получает собственный флаг, поэтому
+/// начало новой операции не сбрасывает запрошенную отмену уже идущих, а `cancel`
+/// действует только на операции, выполняющиеся в момент вызова.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationGroup(Arc<Mutex<Vec<LoadCancellation>>>);
+
+impl CancellationGroup {
+    /// Флаг новой операции; группа забывает о нём, когда операция его освобождает.
+    pub fn start(&self) -> LoadCancellation {
+        let cancellation = LoadCancellation::default();
+        let mut running = self.0.lock();
+        running.retain(|running| Arc::strong_count(&running.0) > 1);
+        running.push(cancellation.clone());
+        cancellation
+    }
+
+    /// Запрашивает отмену всех выполняющихся операций группы.
+    pub fn cancel(&self) {
+        for running in self.0.lock().iter() {
+            running.cancel();
+        }
+    }
+}
+
+/// Отслеживает загрузку одной модели и рассылает отчёты.
+pub struct LoadTracker {
+    callback: Option<Arc<dyn LoadProgressCallback>>,
+    cancellation: LoadCancellation,
+    progress: LoadProgress,
+}
+
+impl LoadTracker {
+    /// Создаёт трекер для новой загрузки.
+    pub fn new(
+        callback: Option<Arc<dyn LoadProgressCallback>>,
+        cancellation: LoadCancellation,
+    ) -> Self {
+        Self {
+            callback,
+            cancellation,
+            progress: LoadProgress {
+                phase: LoadPhase::Header,
+                bytes_loaded: 0,
+                bytes_total: 0,
+                tensors_loaded: 0,
+                tensors_total: 0,
+            },
+        }
+    }
+
+    /// Переходит к этапу и сообщает о нём.
+    pub fn enter(&mut self, phase: LoadPhase) {
+        self.progress.phase = phase;
+        self.report();
+    }
+
+    /// Запоминает общий объём весов по заголовку GGUF.
+    pub fn set_totals(&mut self, content: &gguf_file::Content) {
+        self.progress.tensors_total = content.tensor_infos.len();
+        self.progress.bytes_total = content
+            .tensor_infos
+            .values()
+            .map(|info| tensor_size_in_bytes(info) as u64)
+            .sum();
+    }
+
+    /// Начинает этап, прогресс которого измеряется только в байтах.
+    pub fn enter_bytes_phase(&mut self, phase: LoadPhase, bytes_total: u64) {
+        self.progress.bytes_loaded = 0;
+        self.progress.bytes_total = bytes_total;
+        self.enter(phase);
+    }
+
+    /// Начинает этап, прогресс которого измеряется в тензорах и байтах.
+    pub fn enter_tensor_phase(&mut self, phase: LoadPhase, tensors_total: usize, bytes_total: u64) {
+        self.progress.tensors_loaded = 0;
+        self.progress.tensors_total = tensors_total;
+        self.enter_bytes_phase(phase, bytes_total);
+    }
+
+    /// Учитывает обработанный тензор и сообщает о прогрессе.
+    pub fn add_tensor(&mut self, bytes: u64) {
+        self.progress.tensors_loaded += 1;
+        self.add_bytes(bytes);
+    }
+
+    /// Учитывает обработанные байты и сообщает о прогрессе.
+    pub fn add_bytes(&mut self, bytes: u64) {
+        self.progress.bytes_loaded += bytes;
+        self.report();
+    }
+
+    /// Проверяет, запрошена ли отмена.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Текущее состояние загрузки.
+    pub fn progress(&self) -> LoadProgress {
+        self.progress
+    }
+
+    fn report(&self) {
+        if let Some(callback) = &self.callback {
+            callback.on_progress(&self.progress);
+        }
+    }
+}
+
+/// Размер данных тензора в файле.
+pub(crate) fn tensor_size_in_bytes(info: &gguf_file::TensorInfo) -> usize {
+    info.shape.elem_count() / info.ggml_dtype.block_size() * info.ggml_dtype.type_size()
+}
+
+/// Reader, считающий прочитанные тензоры и прерывающий чтение при отмене.
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    tracker: &'a mut LoadTracker,
+    /// Абсолютное смещение начала тензора -> его размер в байтах.
+    tensor_sizes: HashMap<u64, usize>,
+    /// Сколько байт осталось дочитать у текущего тензора.
+    remaining_in_tensor: usize,
+}
+
+impl<'a, R: Read + Seek> ProgressReader<'a, R> {
+    /// Оборачивает reader для загрузки тензоров из `content`.
+    pub fn new(inner: R, content: &gguf_file::Content, tracker: &'a mut LoadTracker) -> Self {
+        let tensor_sizes = content
+            .tensor_infos
+            .values()
+            .map(|info| {
+                (
+                    content.tensor_data_offset + info.offset,
+                    tensor_size_in_bytes(info),
+                )
+            })
+            .collect();
+        Self {
+            inner,
+            tracker,
+            tensor_sizes,
+            remaining_in_tensor: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.tracker.is_cancelled() {
+            return Err(std::io::Error::other("model loading cancelled"));
+        }
+        let read = self.inner.read(buf)?;
+        if self.remaining_in_tensor > 0 {
+            let counted = read.min(self.remaining_in_tensor);
+            self.remaining_in_tensor -= counted;
+            self.tracker.progress.bytes_loaded += counted as u64;
+            if self.remaining_in_tensor == 0 {
+                self.tracker.progress.tensors_loaded += 1;
+                self.tracker.report();
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for ProgressReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = self.inner.seek(pos)?;
+        self.remaining_in_tensor = self.tensor_sizes.get(&position).copied().unwrap_or(0);
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gguf_reader::MappedGguf;
+    use crate::test_support::{basic_metadata, write_test_gguf};
+    use parking_lot::Mutex;
+    use tempfile::tempdir;
+
+    struct Recorder(Mutex<Vec<LoadProgress>>);
+
+    impl LoadProgressCallback for Recorder {
+        fn on_progress(&self, progress: &LoadProgress) {
+            self.0.lock().push(*progress);
+        }
+    }
+
+    #[test]
+    fn test_progress_reader_counts_tensors() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("model.gguf");
+        write_test_gguf(
+            &path,
+            &basic_metadata("qwen3"),
+            &[("a", &[4, 4]), ("b", &[8])],
+        );
+
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        let mut tracker = LoadTracker::new(Some(recorder.clone()), LoadCancellation::default());
+        let MappedGguf {
+            content,
+            mut reader,
+        } = MappedGguf::open(&path).unwrap();
+        tracker.set_totals(&content);
+        tracker.enter(LoadPhase::Tensors);
+
+        let mut progress_reader = ProgressReader::new(&mut reader, &content, &mut tracker);
+        for name in ["a", "b"] {
+            content
+                .tensor(&mut progress_reader, name, &candle_core::Device::Cpu)
+                .unwrap();
+        }
+
+        let progress = tracker.progress();
+        assert_eq!(progress.tensors_total, 2);
+        assert_eq!(progress.tensors_loaded, 2);
+        assert_eq!(progress.bytes_loaded, (16 + 8) * 4);
+        assert_eq!(progress.bytes_loaded, progress.bytes_total);
+        assert_eq!(recorder.0.lock().len(), 3);
+    }
+
+    #[test]
+    fn test_cancelled_reader_fails() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("model.gguf");
+        write_test_gguf(&path, &basic_metadata("qwen3"), &[("a", &[4])]);
+
+        let cancellation = LoadCancellation::default();
+        let mut tracker = LoadTracker::new(None, cancellation.clone());
+        let MappedGguf {
+            content,
+            mut reader,
+        } = MappedGguf::open(&path).unwrap();
+        cancellation.cancel();
+
+        let mut progress_reader = ProgressReader::new(&mut reader, &content, &mut tracker);
+        assert!(content
+            .tensor(&mut progress_reader, "a", &candle_core::Device::Cpu)
+            .is_err());
+    }
+
+    #[test]
+    fn test_cancellation_group_isolates_operations() {
+        let group = CancellationGroup::default();
+        let first = group.start();
+        group.cancel();
+        // Новая операция не сбрасывает отмену уже идущей и не наследует её
+        let second = group.start();
+        assert!(first.is_cancelled());
+        assert!(!second.is_cancelled());
+
+        group.cancel();
+        assert!(second.is_cancelled());
+
+        // Завершённые операции забываются при следующем запуске
+        drop(first);
+        let _third = group.start();
+        assert_eq!(group.0.lock().len(), 2);
+    }
+}
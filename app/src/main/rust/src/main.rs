@@ -1,5 +1,78 @@
-fn main() {
-    println!("Oxide Lab Mobile Rust library - Android only");
-    println!("This binary is not intended for direct execution.");
-    println!("Use the Android app to interact with the library.");
-}
\ No newline at end of file
+//! Утилита для хоста: конвертация и переквантизация моделей без Android приложения.
+//!
+//! `oxide_lab_mobile convert <input> <output.gguf> [plan]`, где `input` — GGUF файл или
+//! директория модели Hugging Face с safetensors, а `plan` — пресет llama.cpp с типами
+//! для классов тензоров (по умолчанию `q4_k_m`).
+
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use oxide_lab_mobile::gguf_limits::GgufLimits;
+use oxide_lab_mobile::load_progress::{
+    LoadCancellation, LoadProgress, LoadProgressCallback, LoadTracker,
+};
+use oxide_lab_mobile::model_convert::{self, QuantizationPlan};
+
+const USAGE: &str = "usage: oxide_lab_mobile convert <input> <output.gguf> [plan]
+  input  GGUF file or Hugging Face model directory with *.safetensors
+  plan   f32, f16, bf16, q8_0, q4_0, q4_1, q5_0, q5_1, q4_k_m, q5_k_m, q6_k (default q4_k_m)
+         with optional per-class types: q4_k_m,embedding=q8_0,output=q6_k
+         classes: embedding, output, attention, attn_v, feed_forward, ffn_down, other";
+
+/// Печатает прогресс конвертации в stderr.
+struct StderrProgress;
+
+impl LoadProgressCallback for StderrProgress {
+    fn on_progress(&self, progress: &LoadProgress) {
+        if progress.tensors_total > 0 {
+            eprint!(
+                "\r{}: {}/{} tensors",
+                progress.phase.as_str(),
+                progress.tensors_loaded,
+                progress.tensors_total
+            );
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (input, output, plan) = match args.as_slice() {
+        [command, input, output] if command == "convert" => (input, output, "q4_k_m"),
+        [command, input, output, plan] if command == "convert" => (input, output, plan.as_str()),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let plan = match QuantizationPlan::parse(plan) {
+        Ok(plan) => plan,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let mut tracker = LoadTracker::new(Some(Arc::new(StderrProgress)), LoadCancellation::default());
+    match model_convert::convert(
+        Path::new(input),
+        Path::new(output),
+        &plan,
+        &GgufLimits::default(),
+        &mut tracker,
+    ) {
+        Ok(report) => {
+            eprintln!();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).unwrap_or_default()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("\n{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! Каталог установленных моделей.
+//! Сканирует корневую директорию в поисках GGUF файлов и извлекает
+//! сведения о каждой модели из заголовка. Результаты кешируются по mtime.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use candle_core::quantized::gguf_file;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::external_stores::{DiscoveredModel, ExternalStore, OllamaLayers, StoreKind};
+use crate::gguf_reader::MappedGguf;
+use crate::gguf_split::is_secondary_shard;
+use crate::hf_cache::repo_id_from_path;
+use crate::model_manager::{chat_template_from_metadata, ModelManagerError, ModelType};
+use crate::model_store::{is_first_shard, ModelId, MODEL_FILE_NAME};
+
+/// Максимальная глубина рекурсивного обхода корневой директории.
+const MAX_SCAN_DEPTH: usize = 6;
+
+/// Сведения об установленной модели.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelCatalogEntry {
+    pub path: PathBuf,
+    pub file_name: String,
+    pub file_size: u64,
+    /// Время модификации файла в секундах с начала эпохи Unix.
+    pub modified: u64,
+    pub architecture: Option<String>,
+    /// Тип модели, если архитектура поддерживается (`Qwen3`/`Gemma3`).
+    pub model_type: Option<&'static str>,
+    pub name: Option<String>,
+    pub license: Option<String>,
+    pub parameter_count: u64,
+    pub quantization_types: Vec<String>,
+    pub context_length: Option<u64>,
+    pub has_chat_template: bool,
+    pub has_embedded_tokenizer: bool,
+    /// Репозиторий Hugging Face, если файл лежит в кеше Hub.
+    pub repo_id: Option<String>,
+    /// Идентификатор в хранилище моделей (`qwen3/0.6b`) для файлов `<id>/model.gguf`
+    /// и разбитых моделей `<id>/model-00001-of-0000M.gguf`.
+    pub model_id: Option<String>,
+    /// Внешнее хранилище (Ollama, LM Studio), из которого модель используется на месте.
+    pub store: Option<StoreKind>,
+    /// Имя модели во внешнем хранилище.
+    pub store_name: Option<String>,
+    /// Шаблон и параметры из манифеста Ollama.
+    pub ollama: Option<OllamaLayers>,
+}
+
+impl ModelCatalogEntry {
+    /// Строит запись из уже прочитанного заголовка GGUF.
+    pub fn from_content(
+        path: &Path,
+        file_size: u64,
+        modified: u64,
+        content: &gguf_file::Content,
+    ) -> Self {
+        let metadata = &content.metadata;
+        let architecture = metadata_string(metadata, "general.architecture");
+        let context_length = architecture
+            .as_ref()
+            .and_then(|arch| metadata.get(&format!("{arch}.context_length")))
+            .and_then(|value| value.to_u64().ok());
+
+        let parameter_count = content
+            .tensor_infos
+            .values()
+            .map(|info| info.shape.elem_count() as u64)
+            .sum();
+        let quantization_types = content
+            .tensor_infos
+            .values()
+            .map(|info| format!("{:?}", info.ggml_dtype))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        Self {
+            path: path.to_path_buf(),
+            file_name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            file_size,
+            modified,
+            model_type: architecture
+                .as_deref()
+                .and_then(ModelType::from_architecture)
+                .map(|t| t.as_str()),
+            architecture,
+            name: metadata_string(metadata, "general.name"),
+            license: metadata_string(metadata, "general.license"),
+            parameter_count,
+            quantization_types,
+            context_length,
+            has_chat_template: chat_template_from_metadata(metadata).is_some(),
+            has_embedded_tokenizer: has_embedded_tokenizer(metadata),
+            repo_id: repo_id_from_path(path),
+            model_id: None,
+            store: None,
+            store_name: None,
+            ollama: None,
+        }
+    }
+
+    fn with_store_model(mut self, model: DiscoveredModel) -> Self {
+        self.store = Some(model.kind);
+        self.store_name = Some(model.name);
+        self.ollama = model.ollama;
+        if model.repo_id.is_some() {
+            self.repo_id = model.repo_id;
+        }
+        self
+    }
+}
+
+fn metadata_string(metadata: &HashMap<String, gguf_file::Value>, key: &str) -> Option<String> {
+    metadata
+        .get(key)
+        .and_then(|value| value.to_string().ok())
+        .cloned()
+}
+
+fn has_embedded_tokenizer(metadata: &HashMap<String, gguf_file::Value>) -> bool {
+    matches!(
+        metadata.get("tokenizer.ggml.tokens"),
+        Some(gguf_file::Value::Array(tokens)) if !tokens.is_empty()
+    ) || metadata.contains_key("tokenizer.huggingface.json")
+}
+
+/// Ключ кеша: время модификации и размер файла.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CacheStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+/// Каталог моделей с кешированием по mtime.
+pub struct ModelCatalog {
+    root_dir: PathBuf,
+    cache: Mutex<HashMap<PathBuf, (CacheStamp, ModelCatalogEntry)>>,
+    stores: Mutex<Vec<ExternalStore>>,
+}
+
+impl ModelCatalog {
+    /// Создаёт каталог для указанной корневой директории.
+    pub fn new(root_dir: impl AsRef<Path>) -> Self {
+        Self {
+            root_dir: root_dir.as_ref().to_path_buf(),
+            cache: Mutex::new(HashMap::new()),
+            stores: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Подключает внешнее хранилище моделей. Повторное подключение игнорируется.
+    pub fn add_store(&self, store: ExternalStore) {
+        let mut stores = self.stores.lock();
+        if !stores.contains(&store) {
+            stores.push(store);
+        }
+    }
+
+    /// Подключённые внешние хранилища.
+    pub fn stores(&self) -> Vec<ExternalStore> {
+        self.stores.lock().clone()
+    }
+
+    /// Сканирует корневую директорию и подключённые хранилища и возвращает записи
+    /// для всех GGUF файлов. Файлы, которые не удалось разобрать, пропускаются с предупреждением.
+    pub fn scan(&self) -> Vec<ModelCatalogEntry> {
+        let mut files = Vec::new();
+        collect_gguf_files(&self.root_dir, 0, &mut files);
+        files.sort();
+
+        let mut entries = Vec::with_capacity(files.len());
+        for path in &files {
+            match self.inspect(path) {
+                Ok(mut entry) => {
+                    entry.model_id = self.model_id(path);
+                    entries.push(entry);
+                }
+                Err(err) => log::warn!("Пропуск модели {:?}: {}", path, err),
+            }
+        }
+
+        let stores = self.stores();
+        for model in stores.iter().flat_map(ExternalStore::discover) {
+            match self.inspect(&model.path) {
+                Ok(entry) => {
+                    files.push(model.path.clone());
+                    entries.push(entry.with_store_model(model));
+                }
+                Err(err) => log::warn!("Пропуск модели {}: {}", model.name, err),
+            }
+        }
+        files.sort();
+
+        // Удаляем из кеша исчезнувшие файлы
+        let scanned_roots: Vec<&Path> = std::iter::once(self.root_dir.as_path())
+            .chain(stores.iter().map(|store| store.root.as_path()))
+            .collect();
+        self.cache.lock().retain(|path, _| {
+            !scanned_roots.iter().any(|root| path.starts_with(root))
+                || files.binary_search(path).is_ok()
+        });
+        entries
+    }
+
+    fn model_id(&self, path: &Path) -> Option<String> {
+        if path.file_name()? != MODEL_FILE_NAME && !is_first_shard(path) {
+            return None;
+        }
+        let relative = path.parent()?.strip_prefix(&self.root_dir).ok()?;
+        let id = ModelId::parse(&relative.to_string_lossy()).ok()?;
+        Some(id.as_str().to_string())
+    }
+
+    /// Возвращает сведения о GGUF файле, перечитывая заголовок только при изменении файла.
+    pub fn inspect(&self, path: &Path) -> Result<ModelCatalogEntry, ModelManagerError> {
+        let fs_meta = std::fs::metadata(path).map_err(|_| {
+            ModelManagerError::ModelFileMissing(path.to_string_lossy().into_owned())
+        })?;
+        let modified = fs_meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let stamp = CacheStamp {
+            modified,
+            len: fs_meta.len(),
+        };
+
+        if let Some((cached_stamp, entry)) = self.cache.lock().get(path) {
+            if *cached_stamp == stamp {
+                return Ok(entry.clone());
+            }
+        }
+
+        let gguf = MappedGguf::open(path)?;
+        let modified_secs = modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        // Для разбитой модели размер учитывает все части
+        let entry =
+            ModelCatalogEntry::from_content(path, gguf.file_len(), modified_secs, &gguf.content);
+
+        self.cache
+            .lock()
+            .insert(path.to_path_buf(), (stamp, entry.clone()));
+        Ok(entry)
+    }
+}
+
+/// Рекурсивно собирает GGUF файлы, следуя симлинкам (кеш Hugging Face хранит
+/// файлы снапшотов как ссылки на `blobs/`).
+/// Вторые и последующие части разбитых моделей пропускаются: модель представлена первой частью.
+pub(crate) fn collect_gguf_files(dir: &Path, depth: usize, out: &mut Vec<PathBuf>) {
+    if depth > MAX_SCAN_DEPTH {
+        return;
+    }
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = std::fs::metadata(&path).map(|meta| meta.file_type()) else {
+            continue;
+        };
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if file_type.is_dir() && !hidden {
+            collect_gguf_files(&path, depth + 1, out);
+        } else if file_type.is_file() && is_gguf(&path) && !is_secondary_shard(&path) {
+            out.push(path);
+        }
+    }
+}
+
+fn is_gguf(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("gguf"))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{basic_metadata, write_ollama_store, write_test_gguf};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_scan_reports_gguf_details() {
+        let tmp = tempdir().unwrap();
+        let model_path = tmp.path().join("qwen3").join("0.6b").join("model.gguf");
+        let mut metadata = basic_metadata("qwen3");
+        metadata.push((
+            "tokenizer.chat_template".into(),
+            gguf_file::Value::String("{{ messages }}".into()),
+        ));
+        write_test_gguf(
+            &model_path,
+            &metadata,
+            &[("token_embd.weight", &[8, 4]), ("output_norm.weight", &[4])],
+        );
+        std::fs::write(tmp.path().join("notes.txt"), b"not a model").unwrap();
+
+        let catalog = ModelCatalog::new(tmp.path());
+        let entries = catalog.scan();
+        assert_eq!(entries.len(), 1);
+
+        let entry = &entries[0];
+        assert_eq!(entry.path, model_path);
+        assert_eq!(entry.architecture.as_deref(), Some("qwen3"));
+        assert_eq!(entry.model_type, Some("Qwen3"));
+        assert_eq!(entry.name.as_deref(), Some("Test Model"));
+        assert_eq!(entry.license.as_deref(), Some("apache-2.0"));
+        assert_eq!(entry.parameter_count, 36);
+        assert_eq!(entry.quantization_types, vec!["F32".to_string()]);
+        assert_eq!(entry.context_length, Some(4096));
+        assert!(entry.has_chat_template);
+        assert!(!entry.has_embedded_tokenizer);
+        assert!(entry.repo_id.is_none());
+        assert_eq!(entry.model_id.as_deref(), Some("qwen3/0.6b"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_follows_hf_cache_symlinks() {
+        let tmp = tempdir().unwrap();
+        let repo = tmp.path().join("models--Qwen--Qwen3-0.6B-GGUF");
+        let blob = repo.join("blobs").join("1a2b3c");
+        write_test_gguf(&blob, &basic_metadata("qwen3"), &[("a", &[4])]);
+        let snapshot = repo.join("snapshots").join("abc");
+        std::fs::create_dir_all(&snapshot).unwrap();
+        std::os::unix::fs::symlink("../../blobs/1a2b3c", snapshot.join("model.gguf")).unwrap();
+
+        let entries = ModelCatalog::new(tmp.path()).scan();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, snapshot.join("model.gguf"));
+        assert_eq!(entries[0].repo_id.as_deref(), Some("Qwen/Qwen3-0.6B-GGUF"));
+    }
+
+    #[test]
+    fn test_inspect_refreshes_changed_file() {
+        let tmp = tempdir().unwrap();
+        let model_path = tmp.path().join("model.gguf");
+        write_test_gguf(&model_path, &basic_metadata("gemma3"), &[("a", &[4])]);
+
+        let catalog = ModelCatalog::new(tmp.path());
+        assert_eq!(catalog.inspect(&model_path).unwrap().parameter_count, 4);
+
+        write_test_gguf(
+            &model_path,
+            &basic_metadata("gemma3"),
+            &[("a", &[4]), ("b", &[64])],
+        );
+        assert_eq!(catalog.inspect(&model_path).unwrap().parameter_count, 68);
+
+        std::fs::write(&model_path, b"broken").unwrap();
+        assert!(catalog.inspect(&model_path).is_err());
+        assert!(catalog.scan().is_empty());
+    }
+
+    #[test]
+    fn test_scan_includes_external_store_models() {
+        let tmp = tempdir().unwrap();
+        let store_root = tmp.path().join("ollama");
+        let model_blob = write_ollama_store(&store_root);
+
+        let catalog = ModelCatalog::new(tmp.path().join("models"));
+        catalog.add_store(ExternalStore::new(StoreKind::Ollama, &store_root));
+        catalog.add_store(ExternalStore::new(StoreKind::Ollama, &store_root));
+        assert_eq!(catalog.stores().len(), 1);
+
+        let entries = catalog.scan();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.path, model_blob);
+        assert_eq!(entry.model_type, Some("Qwen3"));
+        assert_eq!(entry.store, Some(StoreKind::Ollama));
+        assert_eq!(entry.store_name.as_deref(), Some("qwen3:0.6b"));
+        assert!(entry.ollama.as_ref().unwrap().template.is_some());
+    }
+}
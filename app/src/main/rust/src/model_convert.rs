@@ -0,0 +1,778 @@
+//! Конвертация моделей в GGUF: переквантизация готового GGUF и преобразование
+//! safetensors из репозиториев Hugging Face (Qwen3, Gemma3).
+//! Тип квантизации выбирается по классу тензора (см. [`QuantizationPlan`]); метаданные
+//! токенизатора и chat template переносятся в новый файл. Тензоры обрабатываются
+//! по одному, поэтому в памяти одновременно находится только один тензор.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::{DType, Device, Tensor};
+use safetensors::SafeTensors;
+use serde::Serialize;
+use serde_json::Value as Json;
+
+use crate::gguf_limits::GgufLimits;
+use crate::gguf_reader::MappedGguf;
+use crate::gguf_writer::{GgufWriter, TensorLayout};
+use crate::load_progress::{LoadPhase, LoadTracker};
+use crate::model_manager::{ModelManagerError, ModelType};
+use crate::safetensors_model::{map_file, read_hf_config, safetensors_files, HfConfig};
+use crate::tokenizer_source::{chat_template_from_config, TOKENIZER_FILE_NAME};
+
+/// Ключ с типом квантизации файла (`llama_ftype`).
+pub const FILE_TYPE_KEY: &str = "general.file_type";
+const QUANTIZATION_VERSION_KEY: &str = "general.quantization_version";
+/// Версия формата k-quant блоков ggml.
+const QUANTIZATION_VERSION: u32 = 2;
+
+/// Типы тензоров, доступные для конвертации, и их имена.
+const DTYPE_NAMES: &[(&str, GgmlDType)] = &[
+    ("f32", GgmlDType::F32),
+    ("f16", GgmlDType::F16),
+    ("bf16", GgmlDType::BF16),
+    ("q4_0", GgmlDType::Q4_0),
+    ("q4_1", GgmlDType::Q4_1),
+    ("q5_0", GgmlDType::Q5_0),
+    ("q5_1", GgmlDType::Q5_1),
+    ("q8_0", GgmlDType::Q8_0),
+    ("q2_k", GgmlDType::Q2K),
+    ("q3_k", GgmlDType::Q3K),
+    ("q4_k", GgmlDType::Q4K),
+    ("q5_k", GgmlDType::Q5K),
+    ("q6_k", GgmlDType::Q6K),
+];
+
+/// Имя типа тензора в нижнем регистре, как в llama.cpp.
+pub fn dtype_name(dtype: GgmlDType) -> &'static str {
+    DTYPE_NAMES
+        .iter()
+        .find(|(_, known)| *known == dtype)
+        .map(|(name, _)| *name)
+        .unwrap_or("unknown")
+}
+
+fn parse_dtype(name: &str) -> Option<GgmlDType> {
+    DTYPE_NAMES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, dtype)| *dtype)
+}
+
+/// Класс тензора, по которому выбирается тип квантизации.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TensorClass {
+    /// `token_embd.weight`.
+    Embedding,
+    /// `output.weight`.
+    Output,
+    /// `attn_v`: наиболее чувствительная к квантизации часть внимания.
+    AttentionValue,
+    /// Остальные матрицы внимания.
+    Attention,
+    /// `ffn_down`.
+    FeedForwardDown,
+    /// Остальные матрицы MLP.
+    FeedForward,
+    /// Одномерные тензоры (нормализации, смещения) — всегда F32.
+    Norm,
+    Other,
+}
+
+impl TensorClass {
+    /// Определяет класс по имени и форме тензора GGUF.
+    pub fn of(name: &str, shape: &[usize]) -> Self {
+        if shape.len() <= 1 {
+            TensorClass::Norm
+        } else if name.starts_with("token_embd.") {
+            TensorClass::Embedding
+        } else if name.starts_with("output.") {
+            TensorClass::Output
+        } else if name.contains(".attn_v.") {
+            TensorClass::AttentionValue
+        } else if name.contains(".attn_") {
+            TensorClass::Attention
+        } else if name.contains(".ffn_down") {
+            TensorClass::FeedForwardDown
+        } else if name.contains(".ffn_") {
+            TensorClass::FeedForward
+        } else {
+            TensorClass::Other
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "embedding" => TensorClass::Embedding,
+            "output" => TensorClass::Output,
+            "attn_v" => TensorClass::AttentionValue,
+            "attention" => TensorClass::Attention,
+            "ffn_down" => TensorClass::FeedForwardDown,
+            "feed_forward" => TensorClass::FeedForward,
+            "other" => TensorClass::Other,
+            _ => return None,
+        })
+    }
+}
+
+/// Типы квантизации по классам тензоров.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizationPlan {
+    /// Тип для классов без отдельного выбора.
+    pub default: GgmlDType,
+    pub overrides: HashMap<TensorClass, GgmlDType>,
+    /// Значение `general.file_type` для выходного файла.
+    pub file_type: Option<u32>,
+}
+
+impl QuantizationPlan {
+    /// Пресет в терминах llama.cpp: `f32`, `f16`, `bf16`, `q8_0`, `q4_0`, `q4_1`,
+    /// `q5_0`, `q5_1`, `q4_k_m`, `q5_k_m`, `q6_k`.
+    pub fn preset(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        // В *_k_m пресетах чувствительные тензоры квантуются точнее
+        let (default, file_type, sensitive) = match name.as_str() {
+            "f32" => (GgmlDType::F32, 0, None),
+            "f16" => (GgmlDType::F16, 1, None),
+            "bf16" => (GgmlDType::BF16, 32, None),
+            "q4_0" => (GgmlDType::Q4_0, 2, None),
+            "q4_1" => (GgmlDType::Q4_1, 3, None),
+            "q8_0" => (GgmlDType::Q8_0, 7, None),
+            "q5_0" => (GgmlDType::Q5_0, 8, None),
+            "q5_1" => (GgmlDType::Q5_1, 9, None),
+            "q4_k_m" => (GgmlDType::Q4K, 15, Some(GgmlDType::Q6K)),
+            "q5_k_m" => (GgmlDType::Q5K, 17, Some(GgmlDType::Q6K)),
+            "q6_k" => (GgmlDType::Q6K, 18, None),
+            _ => return None,
+        };
+        let overrides = sensitive
+            .map(|dtype| {
+                [
+                    TensorClass::AttentionValue,
+                    TensorClass::FeedForwardDown,
+                    TensorClass::Output,
+                ]
+                .into_iter()
+                .map(|class| (class, dtype))
+                .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            default,
+            overrides,
+            file_type: Some(file_type),
+        })
+    }
+
+    /// Разбирает план вида `q4_k_m` или `q4_k_m,embedding=q8_0,output=f16`:
+    /// пресет и типы для отдельных классов тензоров.
+    pub fn parse(spec: &str) -> Result<Self, ModelManagerError> {
+        let invalid = |part: &str| {
+            ModelManagerError::Conversion(format!("некорректный план квантизации '{part}'"))
+        };
+        let mut parts = spec.split(',').map(str::trim);
+        let preset = parts.next().unwrap_or_default();
+        let mut plan = Self::preset(preset).ok_or_else(|| invalid(preset))?;
+        for part in parts {
+            let (class, dtype) = part.split_once('=').ok_or_else(|| invalid(part))?;
+            let class = TensorClass::parse(class.trim()).ok_or_else(|| invalid(part))?;
+            let dtype = parse_dtype(dtype.trim()).ok_or_else(|| invalid(part))?;
+            plan.overrides.insert(class, dtype);
+        }
+        Ok(plan)
+    }
+
+    /// Тип для тензора. Если длина строки не делится на размер блока выбранного типа,
+    /// используется Q8_0, а если и это невозможно — F16.
+    pub fn dtype_for(&self, name: &str, shape: &[usize]) -> GgmlDType {
+        let class = TensorClass::of(name, shape);
+        if class == TensorClass::Norm {
+            return GgmlDType::F32;
+        }
+        let chosen = self.overrides.get(&class).copied().unwrap_or(self.default);
+        let row = shape.last().copied().unwrap_or(0);
+        [chosen, GgmlDType::Q8_0, GgmlDType::F16]
+            .into_iter()
+            .find(|dtype| row % dtype.block_size() == 0)
+            .unwrap_or(GgmlDType::F16)
+    }
+}
+
+/// Итог конвертации.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversionReport {
+    pub output: PathBuf,
+    pub tensor_count: usize,
+    pub bytes_written: u64,
+    /// Число тензоров каждого типа в выходном файле.
+    pub tensor_types: BTreeMap<String, usize>,
+}
+
+/// Тензор, прочитанный из исходной модели.
+enum SourceTensor {
+    Quantized(QTensor),
+    Float(Tensor),
+}
+
+/// Конвертирует модель в GGUF по пути `output`. Директория или файл `.safetensors`
+/// считаются моделью Hugging Face, остальные файлы — GGUF. При ошибке или отмене
+/// недописанный файл удаляется.
+pub fn convert(
+    input: &Path,
+    output: &Path,
+    plan: &QuantizationPlan,
+    limits: &GgufLimits,
+    tracker: &mut LoadTracker,
+) -> Result<ConversionReport, ModelManagerError> {
+    let is_safetensors =
+        input.is_dir() || input.extension().is_some_and(|ext| ext == "safetensors");
+    let result = if is_safetensors {
+        convert_safetensors(input, output, plan, tracker)
+    } else {
+        convert_gguf(input, output, plan, limits, tracker)
+    };
+    if result.is_err() {
+        let _ = std::fs::remove_file(output);
+    }
+    result
+}
+
+/// Переквантизирует GGUF (в том числе разбитый на части). Все метаданные, кроме
+/// ключей `split.*` и типа файла, переносятся без изменений.
+pub fn convert_gguf(
+    input: &Path,
+    output: &Path,
+    plan: &QuantizationPlan,
+    limits: &GgufLimits,
+    tracker: &mut LoadTracker,
+) -> Result<ConversionReport, ModelManagerError> {
+    tracker.enter(LoadPhase::Header);
+    let MappedGguf {
+        content,
+        mut reader,
+    } = MappedGguf::open_with_limits(input, limits)?;
+
+    let mut metadata: Vec<(String, gguf_file::Value)> = content
+        .metadata
+        .iter()
+        .filter(|(key, _)| {
+            !key.starts_with("split.")
+                && key.as_str() != FILE_TYPE_KEY
+                && key.as_str() != QUANTIZATION_VERSION_KEY
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    metadata.sort_by(|a, b| a.0.cmp(&b.0));
+    push_file_type(&mut metadata, plan);
+
+    // Порядок тензоров исходного файла
+    let mut infos: Vec<_> = content.tensor_infos.iter().collect();
+    infos.sort_by_key(|(_, info)| info.offset);
+    let layouts = infos
+        .into_iter()
+        .map(|(name, info)| {
+            let shape = info.shape.dims().to_vec();
+            TensorLayout {
+                dtype: plan.dtype_for(name, &shape),
+                name: name.clone(),
+                shape,
+            }
+        })
+        .collect();
+
+    write_model(output, &metadata, layouts, tracker, |name| {
+        Ok(SourceTensor::Quantized(content.tensor(
+            &mut reader,
+            name,
+            &Device::Cpu,
+        )?))
+    })
+}
+
+/// Файл safetensors с тензором для записи под именем GGUF.
+struct PlannedTensor {
+    file: usize,
+    source_name: String,
+    layout: TensorLayout,
+}
+
+/// Конвертирует модель Hugging Face (директория с `config.json` и `*.safetensors`).
+/// `tokenizer.json` встраивается как `tokenizer.huggingface.json`, chat template берётся
+/// из `tokenizer_config.json` или `chat_template.jinja`.
+pub fn convert_safetensors(
+    input: &Path,
+    output: &Path,
+    plan: &QuantizationPlan,
+    tracker: &mut LoadTracker,
+) -> Result<ConversionReport, ModelManagerError> {
+    tracker.enter(LoadPhase::Header);
+    let (dir, files) = safetensors_files(input)?;
+    let HfConfig {
+        model_type, config, ..
+    } = read_hf_config(&dir)?;
+
+    let mmaps = files
+        .iter()
+        .map(|path| map_file(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut planned = Vec::new();
+    let mut seen = HashSet::new();
+    for (file, mmap) in mmaps.iter().enumerate() {
+        let tensors = SafeTensors::deserialize(mmap)
+            .map_err(|e| ModelManagerError::Corrupted(format!("{}: {e}", files[file].display())))?;
+        let mut names = tensors.names();
+        names.sort();
+        for source_name in names {
+            let Some(name) = gguf_tensor_name(source_name, model_type)? else {
+                continue;
+            };
+            if !seen.insert(name.clone()) {
+                return Err(ModelManagerError::Conversion(format!(
+                    "тензор '{name}' встречается несколько раз"
+                )));
+            }
+            let shape = tensors
+                .tensor(source_name)
+                .map_err(|e| ModelManagerError::Corrupted(e.to_string()))?
+                .shape()
+                .to_vec();
+            planned.push(PlannedTensor {
+                file,
+                source_name: source_name.clone(),
+                layout: TensorLayout {
+                    dtype: plan.dtype_for(&name, &shape),
+                    name,
+                    shape,
+                },
+            });
+        }
+    }
+
+    let mut metadata = hf_metadata(&dir, model_type, &config)?;
+    push_file_type(&mut metadata, plan);
+
+    let sources: HashMap<String, (usize, String)> = planned
+        .iter()
+        .map(|tensor| {
+            (
+                tensor.layout.name.clone(),
+                (tensor.file, tensor.source_name.clone()),
+            )
+        })
+        .collect();
+    let layouts = planned.into_iter().map(|tensor| tensor.layout).collect();
+    write_model(output, &metadata, layouts, tracker, |name| {
+        let (file, source_name) = &sources[name];
+        let tensors = SafeTensors::deserialize(&mmaps[*file])
+            .map_err(|e| ModelManagerError::Corrupted(e.to_string()))?;
+        let view = tensors
+            .tensor(source_name)
+            .map_err(|e| ModelManagerError::Corrupted(e.to_string()))?;
+        let dtype = match view.dtype() {
+            safetensors::Dtype::F32 => DType::F32,
+            safetensors::Dtype::F16 => DType::F16,
+            safetensors::Dtype::BF16 => DType::BF16,
+            other => {
+                return Err(ModelManagerError::Conversion(format!(
+                    "тензор '{source_name}' имеет неподдерживаемый тип {other:?}"
+                )))
+            }
+        };
+        let mut tensor = Tensor::from_raw_buffer(view.data(), dtype, view.shape(), &Device::Cpu)?
+            .to_dtype(DType::F32)?;
+        // Gemma хранит веса RMSNorm как смещение от единицы, а GGUF — полный множитель
+        if model_type == ModelType::Gemma3 && name.ends_with("norm.weight") {
+            tensor = (tensor + 1.0)?;
+        }
+        Ok(SourceTensor::Float(tensor))
+    })
+}
+
+/// Записывает тензоры в новый GGUF, квантуя каждый в тип из его описания.
+fn write_model(
+    output: &Path,
+    metadata: &[(String, gguf_file::Value)],
+    layouts: Vec<TensorLayout>,
+    tracker: &mut LoadTracker,
+    mut load: impl FnMut(&str) -> Result<SourceTensor, ModelManagerError>,
+) -> Result<ConversionReport, ModelManagerError> {
+    let tensor_count = layouts.len();
+    let bytes_total = layouts
+        .iter()
+        .map(|layout| layout.size_in_bytes() as u64)
+        .sum();
+    let mut tensor_types = BTreeMap::new();
+    for layout in &layouts {
+        *tensor_types
+            .entry(dtype_name(layout.dtype).to_string())
+            .or_default() += 1;
+    }
+
+    let file = std::fs::File::create(output).map_err(|e| ModelManagerError::Io(e.to_string()))?;
+    let mut writer = GgufWriter::new(BufWriter::new(file), metadata, layouts)?;
+    tracker.enter_tensor_phase(LoadPhase::Convert, tensor_count, bytes_total);
+    while let Some(layout) = writer.next_layout().cloned() {
+        if tracker.is_cancelled() {
+            return Err(ModelManagerError::Cancelled);
+        }
+        let quantized = match load(&layout.name)? {
+            SourceTensor::Quantized(tensor) if tensor.dtype() == layout.dtype => tensor,
+            SourceTensor::Quantized(tensor) => {
+                QTensor::quantize(&tensor.dequantize(&Device::Cpu)?, layout.dtype)?
+            }
+            SourceTensor::Float(tensor) => QTensor::quantize(&tensor, layout.dtype)?,
+        };
+        writer.write_tensor(&layout.name, &quantized.data()?)?;
+        tracker.add_tensor(layout.size_in_bytes() as u64);
+    }
+    let (out, bytes_written) = writer.finish()?;
+    out.into_inner()
+        .map_err(|e| ModelManagerError::Io(e.to_string()))?
+        .sync_all()
+        .map_err(|e| ModelManagerError::Io(e.to_string()))?;
+
+    Ok(ConversionReport {
+        output: output.to_path_buf(),
+        tensor_count,
+        bytes_written,
+        tensor_types,
+    })
+}
+
+fn push_file_type(metadata: &mut Vec<(String, gguf_file::Value)>, plan: &QuantizationPlan) {
+    if let Some(file_type) = plan.file_type {
+        metadata.push((FILE_TYPE_KEY.to_string(), gguf_file::Value::U32(file_type)));
+    }
+    metadata.push((
+        QUANTIZATION_VERSION_KEY.to_string(),
+        gguf_file::Value::U32(QUANTIZATION_VERSION),
+    ));
+}
+
+/// Имя тензора GGUF для тензора Hugging Face. `None` — тензор не нужен для текстовой модели.
+fn gguf_tensor_name(
+    name: &str,
+    model_type: ModelType,
+) -> Result<Option<String>, ModelManagerError> {
+    let unknown = || ModelManagerError::Conversion(format!("неизвестный тензор '{name}'"));
+    let name = name.strip_prefix("language_model.").unwrap_or(name);
+    if name.starts_with("vision_tower.")
+        || name.starts_with("multi_modal_projector.")
+        || name.ends_with(".rotary_emb.inv_freq")
+    {
+        return Ok(None);
+    }
+    let mapped = match name {
+        "model.embed_tokens.weight" => "token_embd.weight".to_string(),
+        "lm_head.weight" => "output.weight".to_string(),
+        "model.norm.weight" => "output_norm.weight".to_string(),
+        _ => {
+            let rest = name.strip_prefix("model.layers.").ok_or_else(unknown)?;
+            let (layer, rest) = rest.split_once('.').ok_or_else(unknown)?;
+            let layer: usize = layer.parse().map_err(|_| unknown())?;
+            let suffix = match (rest, model_type) {
+                ("self_attn.q_proj.weight", _) => "attn_q.weight",
+                ("self_attn.k_proj.weight", _) => "attn_k.weight",
+                ("self_attn.v_proj.weight", _) => "attn_v.weight",
+                ("self_attn.o_proj.weight", _) => "attn_output.weight",
+                ("self_attn.q_norm.weight", _) => "attn_q_norm.weight",
+                ("self_attn.k_norm.weight", _) => "attn_k_norm.weight",
+                ("input_layernorm.weight", _) => "attn_norm.weight",
+                ("post_attention_layernorm.weight", ModelType::Qwen3) => "ffn_norm.weight",
+                ("post_attention_layernorm.weight", ModelType::Gemma3) => {
+                    "post_attention_norm.weight"
+                }
+                ("pre_feedforward_layernorm.weight", ModelType::Gemma3) => "ffn_norm.weight",
+                ("post_feedforward_layernorm.weight", ModelType::Gemma3) => "post_ffw_norm.weight",
+                ("mlp.gate_proj.weight", _) => "ffn_gate.weight",
+                ("mlp.up_proj.weight", _) => "ffn_up.weight",
+                ("mlp.down_proj.weight", _) => "ffn_down.weight",
+                _ => return Err(unknown()),
+            };
+            format!("blk.{layer}.{suffix}")
+        }
+    };
+    Ok(Some(mapped))
+}
+
+/// Метаданные GGUF из `config.json`, токенизатора и chat template.
+fn hf_metadata(
+    dir: &Path,
+    model_type: ModelType,
+    config: &Json,
+) -> Result<Vec<(String, gguf_file::Value)>, ModelManagerError> {
+    use gguf_file::Value;
+
+    let architecture = match model_type {
+        ModelType::Qwen3 => "qwen3",
+        ModelType::Gemma3 => "gemma3",
+    };
+    let number = |key: &str| config.get(key).and_then(Json::as_f64);
+    let required = |key: &str| {
+        number(key).ok_or_else(|| {
+            ModelManagerError::Conversion(format!("в config.json нет параметра '{key}'"))
+        })
+    };
+
+    let hidden_size = required("hidden_size")?;
+    let head_count = required("num_attention_heads")?;
+    let head_dim = number("head_dim").unwrap_or(hidden_size / head_count);
+    let mut hyper = vec![
+        (
+            "block_count",
+            Value::U32(required("num_hidden_layers")? as u32),
+        ),
+        (
+            "context_length",
+            Value::U32(required("max_position_embeddings")? as u32),
+        ),
+        ("embedding_length", Value::U32(hidden_size as u32)),
+        (
+            "feed_forward_length",
+            Value::U32(required("intermediate_size")? as u32),
+        ),
+        ("attention.head_count", Value::U32(head_count as u32)),
+        (
+            "attention.head_count_kv",
+            Value::U32(number("num_key_value_heads").unwrap_or(head_count) as u32),
+        ),
+        ("attention.key_length", Value::U32(head_dim as u32)),
+        ("attention.value_length", Value::U32(head_dim as u32)),
+        (
+            "attention.layer_norm_rms_epsilon",
+            Value::F32(number("rms_norm_eps").unwrap_or(1e-6) as f32),
+        ),
+        (
+            "rope.freq_base",
+            Value::F32(number("rope_theta").unwrap_or(10_000.0) as f32),
+        ),
+    ];
+    if model_type == ModelType::Gemma3 {
+        hyper.push((
+            "attention.sliding_window",
+            Value::U32(required("sliding_window")? as u32),
+        ));
+        hyper.push((
+            "rope.local_freq_base",
+            Value::F32(number("rope_local_base_freq").unwrap_or(10_000.0) as f32),
+        ));
+        if let Some(pattern) = number("sliding_window_pattern") {
+            hyper.push(("attention.sliding_window_type", Value::U32(pattern as u32)));
+        }
+    }
+
+    let mut metadata = vec![(
+        "general.architecture".to_string(),
+        Value::String(architecture.to_string()),
+    )];
+    if let Some(name) = dir.file_name() {
+        metadata.push((
+            "general.name".to_string(),
+            Value::String(name.to_string_lossy().into_owned()),
+        ));
+    }
+    metadata.extend(
+        hyper
+            .into_iter()
+            .map(|(key, value)| (format!("{architecture}.{key}"), value)),
+    );
+
+    for (config_key, gguf_key) in [
+        ("bos_token_id", "tokenizer.ggml.bos_token_id"),
+        ("eos_token_id", "tokenizer.ggml.eos_token_id"),
+    ] {
+        // eos_token_id может быть списком; в GGUF записывается первый
+        let id = match config.get(config_key) {
+            Some(Json::Array(ids)) => ids.first().and_then(Json::as_u64),
+            Some(id) => id.as_u64(),
+            None => None,
+        };
+        if let Some(id) = id {
+            metadata.push((gguf_key.to_string(), Value::U32(id as u32)));
+        }
+    }
+
+    let tokenizer_path = dir.join(TOKENIZER_FILE_NAME);
+    if tokenizer_path.is_file() {
+        let json = std::fs::read_to_string(&tokenizer_path)
+            .map_err(|e| ModelManagerError::Io(e.to_string()))?;
+        metadata.push((
+            "tokenizer.huggingface.json".to_string(),
+            Value::String(json),
+        ));
+    }
+    if let Some(template) = hf_chat_template(dir)? {
+        metadata.push((
+            "tokenizer.chat_template".to_string(),
+            Value::String(template),
+        ));
+    }
+    Ok(metadata)
+}
+
+/// Chat template из `chat_template.jinja` или `tokenizer_config.json`.
+fn hf_chat_template(dir: &Path) -> Result<Option<String>, ModelManagerError> {
+    let jinja = dir.join("chat_template.jinja");
+    if jinja.is_file() {
+        return std::fs::read_to_string(&jinja)
+            .map(Some)
+            .map_err(|e| ModelManagerError::Io(e.to_string()));
+    }
+    Ok(chat_template_from_config(
+        &dir.join("tokenizer_config.json"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_progress::LoadCancellation;
+    use crate::test_support::{basic_metadata, write_test_gguf};
+    use tempfile::tempdir;
+
+    fn tracker() -> LoadTracker {
+        LoadTracker::new(None, LoadCancellation::default())
+    }
+
+    #[test]
+    fn test_requantizes_gguf_and_keeps_metadata() {
+        let tmp = tempdir().unwrap();
+        let input = tmp.path().join("input.gguf");
+        let mut metadata = basic_metadata("qwen3");
+        metadata.push((
+            "tokenizer.chat_template".into(),
+            gguf_file::Value::String("{{ messages }}".into()),
+        ));
+        metadata.push(("split.count".into(), gguf_file::Value::U16(1)));
+        write_test_gguf(
+            &input,
+            &metadata,
+            &[
+                ("token_embd.weight", &[4, 256]),
+                ("blk.0.attn_v.weight", &[4, 256]),
+                ("blk.0.attn_q.weight", &[4, 256]),
+                ("blk.0.ffn_up.weight", &[4, 48]),
+                ("blk.0.attn_norm.weight", &[256]),
+            ],
+        );
+
+        let output = tmp.path().join("output.gguf");
+        let plan = QuantizationPlan::parse("q4_k_m,embedding=q8_0").unwrap();
+        let report = convert(
+            &input,
+            &output,
+            &plan,
+            &GgufLimits::default(),
+            &mut tracker(),
+        )
+        .unwrap();
+        assert_eq!(report.tensor_count, 5);
+        assert_eq!(
+            report.bytes_written,
+            std::fs::metadata(&output).unwrap().len()
+        );
+
+        let gguf = MappedGguf::open(&output).unwrap();
+        let dtype = |name: &str| gguf.content.tensor_infos[name].ggml_dtype;
+        assert_eq!(dtype("token_embd.weight"), GgmlDType::Q8_0);
+        assert_eq!(dtype("blk.0.attn_v.weight"), GgmlDType::Q6K);
+        assert_eq!(dtype("blk.0.attn_q.weight"), GgmlDType::Q4K);
+        // Строка из 48 элементов не делится на блок Q4_K (256)
+        assert_eq!(dtype("blk.0.ffn_up.weight"), GgmlDType::F16);
+        assert_eq!(dtype("blk.0.attn_norm.weight"), GgmlDType::F32);
+
+        let metadata = &gguf.content.metadata;
+        assert_eq!(
+            metadata["tokenizer.chat_template"].to_string().unwrap(),
+            "{{ messages }}"
+        );
+        assert_eq!(metadata[FILE_TYPE_KEY].to_u32().unwrap(), 15);
+        assert!(!metadata.contains_key("split.count"));
+        assert!(matches!(
+            QuantizationPlan::parse("q4_k_m,embedding=q9"),
+            Err(ModelManagerError::Conversion(_))
+        ));
+    }
+
+    #[test]
+    fn test_converts_qwen3_safetensors() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path().join("Qwen3-Tiny");
+        std::fs::create_dir(&dir).unwrap();
+        let config = serde_json::json!({
+            "model_type": "qwen3",
+            "hidden_size": 32,
+            "num_attention_heads": 2,
+            "num_key_value_heads": 1,
+            "head_dim": 16,
+            "num_hidden_layers": 1,
+            "intermediate_size": 64,
+            "max_position_embeddings": 128,
+            "rms_norm_eps": 1e-6,
+            "rope_theta": 1_000_000.0,
+            "eos_token_id": [3, 4],
+        });
+        std::fs::write(dir.join("config.json"), config.to_string()).unwrap();
+        std::fs::write(
+            dir.join("tokenizer_config.json"),
+            r#"{"chat_template": "{% for m in messages %}{{ m.content }}{% endfor %}"}"#,
+        )
+        .unwrap();
+
+        let tensor = |shape: &[usize]| Tensor::ones(shape, DType::BF16, &Device::Cpu).unwrap();
+        let tensors = [
+            ("model.embed_tokens.weight", tensor(&[8, 32])),
+            ("model.norm.weight", tensor(&[32])),
+            ("model.layers.0.self_attn.q_proj.weight", tensor(&[32, 32])),
+            (
+                "model.layers.0.post_attention_layernorm.weight",
+                tensor(&[32]),
+            ),
+            ("model.layers.0.mlp.down_proj.weight", tensor(&[32, 64])),
+        ];
+        candle_core::safetensors::save(
+            &tensors
+                .iter()
+                .map(|(name, tensor)| (name.to_string(), tensor.clone()))
+                .collect(),
+            dir.join("model.safetensors"),
+        )
+        .unwrap();
+
+        let output = tmp.path().join("qwen3.gguf");
+        let report = convert(
+            &dir,
+            &output,
+            &QuantizationPlan::preset("q8_0").unwrap(),
+            &GgufLimits::default(),
+            &mut tracker(),
+        )
+        .unwrap();
+        assert_eq!(report.tensor_types["q8_0"], 3);
+        assert_eq!(report.tensor_types["f32"], 2);
+
+        let mut gguf = MappedGguf::open(&output).unwrap();
+        let metadata = &gguf.content.metadata;
+        assert_eq!(metadata["general.name"].to_string().unwrap(), "Qwen3-Tiny");
+        assert_eq!(metadata["qwen3.attention.key_length"].to_u32().unwrap(), 16);
+        assert_eq!(metadata["qwen3.rope.freq_base"].to_f32().unwrap(), 1e6);
+        assert_eq!(metadata["tokenizer.ggml.eos_token_id"].to_u32().unwrap(), 3);
+        assert!(crate::model_manager::chat_template_from_metadata(metadata).is_some());
+        assert!(gguf
+            .content
+            .tensor_infos
+            .contains_key("blk.0.ffn_norm.weight"));
+
+        let embedding = gguf
+            .content
+            .tensor(&mut gguf.reader, "token_embd.weight", &Device::Cpu)
+            .unwrap();
+        assert_eq!(embedding.shape().dims(), &[8, 32]);
+        let values = embedding.dequantize(&Device::Cpu).unwrap();
+        let values = values.flatten_all().unwrap().to_vec1::<f32>().unwrap();
+        assert!(values.iter().all(|v| (v - 1.0).abs() < 1e-2));
+    }
+}
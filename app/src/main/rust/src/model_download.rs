@@ -0,0 +1,780 @@
+//! Загрузка моделей с Hugging Face и зеркал.
+//! Файл докачивается через HTTP Range во временный `.part` файл хранилища,
+//! проверяется (структура GGUF и SHA-256) и атомарно переносится в хранилище.
+//! Рядом с `.part` хранится валидатор ответа (ETag или Last-Modified): докачка
+//! запрашивается с `If-Range`, и если файл на сервере изменился, сервер отдаёт его целиком.
+//! Транспорт абстрагирован трейтом [`HttpClient`]: на Android запросы выполняет
+//! `HttpURLConnection` через JNI, а [`StdHttpClient`] обслуживает `http://` зеркала и тесты.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::gguf_reader::MappedGguf;
+use crate::load_progress::{LoadPhase, LoadTracker};
+use crate::model_integrity::{normalize_sha256, sha256_file};
+use crate::model_manager::ModelManagerError;
+use crate::model_store::{ModelId, ModelStore};
+
+/// Основной адрес Hugging Face Hub.
+pub const DEFAULT_MIRROR: &str = "https://huggingface.co";
+
+/// Размер блока записи и шаг отчёта о прогрессе.
+const CHUNK_SIZE: usize = 1024 * 1024;
+const MAX_REDIRECTS: usize = 8;
+const USER_AGENT: &str = "oxide-lab-mobile";
+
+/// Ответ HTTP сервера. Имена заголовков приведены к нижнему регистру.
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub body: Box<dyn Read + Send>,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+/// Продолжение загрузки с `offset`, если файл на сервере всё ещё соответствует `validator`.
+#[derive(Debug, Clone, Copy)]
+pub struct ResumeFrom<'a> {
+    pub offset: u64,
+    /// Значение заголовка `If-Range`: ETag или дата Last-Modified.
+    pub validator: &'a str,
+}
+
+/// HTTP транспорт. Перенаправления не выполняются: их обрабатывает загрузчик,
+/// чтобы сохранить заголовки промежуточных ответов Hugging Face.
+pub trait HttpClient: Send + Sync {
+    /// Выполняет GET; при `resume` запрашивает байты начиная с `offset` с заголовком
+    /// `If-Range`.
+    fn get(&self, url: &str, resume: Option<ResumeFrom>)
+        -> Result<HttpResponse, ModelManagerError>;
+}
+
+/// Файл репозитория Hugging Face для загрузки в хранилище.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadRequest {
+    pub repo_id: String,
+    pub revision: Option<String>,
+    pub file_name: String,
+    /// Идентификатор модели в хранилище.
+    pub model_id: ModelId,
+    /// Ожидаемый SHA-256; без него используется хеш, который сообщает Hugging Face.
+    pub expected_sha256: Option<String>,
+}
+
+/// Итог загрузки.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DownloadReport {
+    pub path: PathBuf,
+    pub mirror: String,
+    pub bytes: u64,
+    /// С какого байта продолжена загрузка (0 — с начала).
+    pub resumed_from: u64,
+    pub sha256: Option<String>,
+}
+
+/// Строит URL вида `{mirror}/{repo}/resolve/{revision}/{file}`.
+pub fn resolve_url(
+    mirror: &str,
+    repo_id: &str,
+    revision: Option<&str>,
+    file_name: &str,
+) -> Result<String, ModelManagerError> {
+    crate::hf_cache::repo_dir_name(repo_id)?;
+    let revision = revision.unwrap_or(crate::hf_cache::DEFAULT_REVISION);
+    let safe = |part: &str| {
+        !part.is_empty()
+            && part.split('/').all(|segment| {
+                !segment.is_empty()
+                    && segment != "."
+                    && segment != ".."
+                    && segment
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            })
+    };
+    if !safe(revision) {
+        return Err(ModelManagerError::InvalidModelId(revision.to_string()));
+    }
+    if !safe(file_name) {
+        return Err(ModelManagerError::InvalidModelId(file_name.to_string()));
+    }
+    Ok(format!(
+        "{}/{repo_id}/resolve/{revision}/{file_name}",
+        mirror.trim_end_matches('/')
+    ))
+}
+
+/// Загружает файл в хранилище, перебирая зеркала по порядку.
+/// Отмена оставляет `.part` файл, и следующая попытка загрузить тот же файл той же
+/// ревизии продолжит загрузку с того же места. Занятый `model_id` отклоняется до загрузки.
+pub fn download(
+    client: &dyn HttpClient,
+    mirrors: &[String],
+    request: &DownloadRequest,
+    store: &ModelStore,
+    tracker: &mut LoadTracker,
+) -> Result<DownloadReport, ModelManagerError> {
+    store.ensure_free(&request.model_id)?;
+    let partial = partial_path(store, request)?;
+    let mut last_error = ModelManagerError::Download("не задано ни одного зеркала".to_string());
+
+    for mirror in mirrors {
+        let url = resolve_url(
+            mirror,
+            &request.repo_id,
+            request.revision.as_deref(),
+            &request.file_name,
+        )?;
+        match fetch(client, &url, &partial, tracker) {
+            Ok(fetched) => {
+                let sha256 = verify_partial(&partial, request, fetched.linked_sha256, tracker)?;
+                let path = store.commit(&partial, &request.model_id)?;
+                let _ = std::fs::remove_file(validator_path(&partial));
+                log::info!("Модель {} загружена с {}", request.model_id, mirror);
+                return Ok(DownloadReport {
+                    path,
+                    mirror: mirror.clone(),
+                    bytes: fetched.bytes,
+                    resumed_from: fetched.resumed_from,
+                    sha256,
+                });
+            }
+            Err(ModelManagerError::Cancelled) => return Err(ModelManagerError::Cancelled),
+            Err(err) => {
+                log::warn!("Зеркало {} недоступно: {}", mirror, err);
+                last_error = err;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// `.part` файл запроса: свой для каждого репозитория, ревизии и файла.
+fn partial_path(
+    store: &ModelStore,
+    request: &DownloadRequest,
+) -> Result<PathBuf, ModelManagerError> {
+    let revision = request
+        .revision
+        .as_deref()
+        .unwrap_or(crate::hf_cache::DEFAULT_REVISION);
+    let source = format!("{}/{revision}/{}", request.repo_id, request.file_name);
+    store.partial_path(&request.model_id, &source)
+}
+
+struct Fetched {
+    bytes: u64,
+    resumed_from: u64,
+    /// SHA-256 из заголовка `X-Linked-Etag` (файлы LFS на Hugging Face).
+    linked_sha256: Option<String>,
+}
+
+/// Файл с валидатором ответа, из которого скачана часть `partial`.
+fn validator_path(partial: &std::path::Path) -> PathBuf {
+    let mut path = partial.as_os_str().to_owned();
+    path.push(".etag");
+    PathBuf::from(path)
+}
+
+/// Валидатор для `If-Range`: сильный ETag, иначе Last-Modified.
+fn response_validator(response: &HttpResponse) -> Option<&str> {
+    response
+        .header("etag")
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| response.header("last-modified"))
+}
+
+fn fetch(
+    client: &dyn HttpClient,
+    url: &str,
+    partial: &std::path::Path,
+    tracker: &mut LoadTracker,
+) -> Result<Fetched, ModelManagerError> {
+    let existing = std::fs::metadata(partial)
+        .map(|meta| meta.len())
+        .unwrap_or(0);
+    // Без валидатора нельзя убедиться, что файл на сервере не изменился:
+    // такая часть скачивается заново
+    let validator = if existing > 0 {
+        std::fs::read_to_string(validator_path(partial)).ok()
+    } else {
+        None
+    };
+    let resume = validator.as_deref().map(|validator| ResumeFrom {
+        offset: existing,
+        validator,
+    });
+    let mut url = url.to_string();
+    let mut linked_sha256 = None;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let response = client.get(&url, resume)?;
+        if linked_sha256.is_none() {
+            linked_sha256 = response
+                .header("x-linked-etag")
+                .and_then(|etag| normalize_sha256(etag.trim_start_matches("W/").trim_matches('"')));
+        }
+        match response.status {
+            301 | 302 | 303 | 307 | 308 => {
+                let location = response.header("location").ok_or_else(|| {
+                    ModelManagerError::Download("перенаправление без Location".to_string())
+                })?;
+                url = join_location(&url, location);
+            }
+            // Файл уже докачан целиком: сервер не может отдать байты после конца
+            416 if resume.is_some() => {
+                return Ok(Fetched {
+                    bytes: existing,
+                    resumed_from: existing,
+                    linked_sha256,
+                })
+            }
+            200 | 206 => {
+                let resumed_from = if response.status == 206 {
+                    content_range_start(&response)
+                        .filter(|start| resume.is_some() && *start == existing)
+                        .ok_or_else(|| {
+                            ModelManagerError::Download("некорректный Content-Range".to_string())
+                        })?
+                } else {
+                    // Загрузка с начала: запоминаем валидатор нового ответа
+                    let validator = validator_path(partial);
+                    let saved = match response_validator(&response) {
+                        Some(value) => std::fs::write(&validator, value),
+                        None => std::fs::remove_file(&validator).or(Ok(())),
+                    };
+                    saved.map_err(|e| ModelManagerError::Io(e.to_string()))?;
+                    0
+                };
+                let total = response
+                    .header("content-length")
+                    .and_then(|len| len.parse::<u64>().ok())
+                    .map(|len| len + resumed_from);
+                let bytes = write_body(response.body, partial, resumed_from, total, tracker)?;
+                return Ok(Fetched {
+                    bytes,
+                    resumed_from,
+                    linked_sha256,
+                });
+            }
+            status => {
+                return Err(ModelManagerError::Download(format!(
+                    "HTTP {status} для {url}"
+                )))
+            }
+        }
+    }
+    Err(ModelManagerError::Download(
+        "слишком много перенаправлений".to_string(),
+    ))
+}
+
+fn content_range_start(response: &HttpResponse) -> Option<u64> {
+    // "bytes 100-199/200"
+    let range = response.header("content-range")?.strip_prefix("bytes ")?;
+    range.split('-').next()?.trim().parse().ok()
+}
+
+fn join_location(current: &str, location: &str) -> String {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        return location.to_string();
+    }
+    let origin_end = current
+        .find("://")
+        .and_then(|scheme| current[scheme + 3..].find('/').map(|i| scheme + 3 + i))
+        .unwrap_or(current.len());
+    if location.starts_with('/') {
+        format!("{}{}", &current[..origin_end], location)
+    } else {
+        let base = current
+            .rfind('/')
+            .filter(|i| *i >= origin_end)
+            .unwrap_or(origin_end);
+        format!("{}/{}", &current[..base], location)
+    }
+}
+
+fn write_body(
+    mut body: Box<dyn Read + Send>,
+    partial: &std::path::Path,
+    resumed_from: u64,
+    total: Option<u64>,
+    tracker: &mut LoadTracker,
+) -> Result<u64, ModelManagerError> {
+    let io = |e: std::io::Error| ModelManagerError::Io(e.to_string());
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(resumed_from > 0)
+        .write(true)
+        .truncate(resumed_from == 0)
+        .open(partial)
+        .map_err(io)?;
+
+    tracker.enter_bytes_phase(LoadPhase::Download, total.unwrap_or(0));
+    tracker.add_bytes(resumed_from);
+    let mut written = resumed_from;
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        if tracker.is_cancelled() {
+            let _ = file.sync_all();
+            return Err(ModelManagerError::Cancelled);
+        }
+        let read = body
+            .read(&mut buffer)
+            .map_err(|e| ModelManagerError::Download(e.to_string()))?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buffer[..read]).map_err(io)?;
+        written += read as u64;
+        tracker.add_bytes(read as u64);
+    }
+    file.sync_all().map_err(io)?;
+
+    if let Some(total) = total {
+        if written != total {
+            return Err(ModelManagerError::Download(format!(
+                "соединение прервано: получено {written} из {total} байт"
+            )));
+        }
+    }
+    Ok(written)
+}
+
+/// Проверяет скачанный файл. Повреждённый файл удаляется, чтобы следующая попытка
+/// началась заново, а не докачивала испорченные данные.
+fn verify_partial(
+    partial: &std::path::Path,
+    request: &DownloadRequest,
+    linked_sha256: Option<String>,
+    tracker: &mut LoadTracker,
+) -> Result<Option<String>, ModelManagerError> {
+    let expected = match &request.expected_sha256 {
+        Some(hash) => Some(
+            normalize_sha256(hash)
+                .ok_or_else(|| ModelManagerError::InvalidChecksum(hash.clone()))?,
+        ),
+        None => linked_sha256,
+    };
+    let result = MappedGguf::open(partial)
+        .map(|_| ())
+        .and_then(|_| match &expected {
+            Some(expected) => {
+                let actual = sha256_file(partial, tracker)?;
+                if &actual != expected {
+                    return Err(ModelManagerError::ChecksumMismatch {
+                        expected: expected.clone(),
+                        actual,
+                    });
+                }
+                Ok(Some(actual))
+            }
+            None => Ok(None),
+        });
+    if matches!(
+        result,
+        Err(ModelManagerError::ChecksumMismatch { .. })
+            | Err(ModelManagerError::Corrupted(_))
+            | Err(ModelManagerError::InvalidGguf(_))
+    ) {
+        let _ = std::fs::remove_file(partial);
+        let _ = std::fs::remove_file(validator_path(partial));
+    }
+    result
+}
+
+/// Простой HTTP/1.1 клиент на `std::net` для `http://` адресов
+/// (локальные зеркала и тестовые серверы). HTTPS не поддерживается.
+pub struct StdHttpClient {
+    pub timeout: Duration,
+}
+
+impl Default for StdHttpClient {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl HttpClient for StdHttpClient {
+    fn get(
+        &self,
+        url: &str,
+        resume: Option<ResumeFrom>,
+    ) -> Result<HttpResponse, ModelManagerError> {
+        let download_error = |message: String| ModelManagerError::Download(message);
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| download_error(format!("неподдерживаемая схема URL: {url}")))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let address = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{authority}:80")
+        };
+
+        let mut stream =
+            TcpStream::connect(&address).map_err(|e| download_error(format!("{address}: {e}")))?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(|e| download_error(e.to_string()))?;
+        let mut request = format!(
+            "GET {path} HTTP/1.1\r\nHost: {authority}\r\nUser-Agent: {USER_AGENT}\r\nConnection: close\r\n"
+        );
+        if let Some(resume) = resume {
+            request.push_str(&format!(
+                "Range: bytes={}-\r\nIf-Range: {}\r\n",
+                resume.offset, resume.validator
+            ));
+        }
+        request.push_str("\r\n");
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|e| download_error(e.to_string()))?;
+
+        let mut reader = BufReader::new(stream);
+        let mut status_line = String::new();
+        reader
+            .read_line(&mut status_line)
+            .map_err(|e| download_error(e.to_string()))?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| download_error(format!("некорректный ответ: {status_line:?}")))?;
+
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader
+                .read_line(&mut line)
+                .map_err(|e| download_error(e.to_string()))?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+            }
+        }
+
+        let chunked = headers
+            .get("transfer-encoding")
+            .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"));
+        let body: Box<dyn Read + Send> = if chunked {
+            headers.remove("content-length");
+            Box::new(ChunkedReader {
+                inner: reader,
+                remaining: 0,
+                done: false,
+            })
+        } else {
+            match headers
+                .get("content-length")
+                .and_then(|len| len.parse().ok())
+            {
+                Some(len) => Box::new(reader.take(len)),
+                None => Box::new(reader),
+            }
+        };
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// Декодер `Transfer-Encoding: chunked`.
+struct ChunkedReader<R> {
+    inner: R,
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut line = String::new();
+            self.inner.read_line(&mut line)?;
+            if line.trim().is_empty() {
+                // Завершающий CRLF предыдущего блока
+                line.clear();
+                self.inner.read_line(&mut line)?;
+            }
+            let size = line.trim().split(';').next().unwrap_or("");
+            self.remaining = u64::from_str_radix(size, 16).map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "некорректный chunk")
+            })?;
+            if self.remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let limit = buf.len().min(self.remaining as usize);
+        let read = self.inner.read(&mut buf[..limit])?;
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_progress::LoadCancellation;
+    use crate::sha256::Sha256;
+    use crate::test_support::{basic_metadata, write_test_gguf};
+    use parking_lot::Mutex;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    /// Локальный HTTP сервер, отдающий `payload` с поддержкой Range.
+    /// Пути из `missing` отвечают 404, путь `/redirect/...` перенаправляет на `/...`.
+    struct StandInServer {
+        base_url: String,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl StandInServer {
+        fn start(payload: Vec<u8>, sha256: String) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let base_url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let log = requests.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(mut stream) = stream else { break };
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut head = String::new();
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                            break;
+                        }
+                        head.push_str(&line);
+                    }
+                    log.lock().push(head.clone());
+                    let path = head.split_whitespace().nth(1).unwrap_or("/").to_string();
+                    let etag = format!("\"{sha256}\"");
+                    // Устаревший валидатор: сервер отдаёт файл целиком
+                    let fresh = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("If-Range: "))
+                        .is_none_or(|validator| validator == etag);
+                    let range_start = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Range: bytes="))
+                        .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok())
+                        .filter(|_| fresh)
+                        .unwrap_or(0);
+
+                    let response = if path.starts_with("/missing") {
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec()
+                    } else if let Some(target) = path.strip_prefix("/redirect") {
+                        format!(
+                            "HTTP/1.1 302 Found\r\nLocation: {target}\r\nX-Linked-Etag: \"{sha256}\"\r\nContent-Length: 0\r\n\r\n"
+                        )
+                        .into_bytes()
+                    } else if range_start > 0 {
+                        let body = &payload[range_start..];
+                        let mut response = format!(
+                            "HTTP/1.1 206 Partial Content\r\nETag: {etag}\r\nContent-Range: bytes {range_start}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                            payload.len() - 1,
+                            payload.len(),
+                            body.len()
+                        )
+                        .into_bytes();
+                        response.extend_from_slice(body);
+                        response
+                    } else {
+                        let mut response = format!(
+                            "HTTP/1.1 200 OK\r\nETag: {etag}\r\nContent-Length: {}\r\n\r\n",
+                            payload.len()
+                        )
+                        .into_bytes();
+                        response.extend_from_slice(&payload);
+                        response
+                    };
+                    let _ = stream.write_all(&response);
+                }
+            });
+            Self { base_url, requests }
+        }
+    }
+
+    fn model_payload(dir: &std::path::Path) -> (Vec<u8>, String) {
+        let path = dir.join("source.gguf");
+        write_test_gguf(&path, &basic_metadata("qwen3"), &[("w", &[64, 64])]);
+        let payload = std::fs::read(&path).unwrap();
+        let mut hasher = Sha256::new();
+        hasher.update(&payload);
+        (payload, hasher.finalize_hex())
+    }
+
+    fn request(expected_sha256: Option<String>) -> DownloadRequest {
+        DownloadRequest {
+            repo_id: "Qwen/Qwen3-0.6B-GGUF".to_string(),
+            revision: None,
+            file_name: "Qwen3-0.6B-Q8_0.gguf".to_string(),
+            model_id: ModelId::parse("qwen3/0.6b").unwrap(),
+            expected_sha256,
+        }
+    }
+
+    #[test]
+    fn test_resumes_partial_download_through_redirect() {
+        let tmp = tempdir().unwrap();
+        let (payload, sha256) = model_payload(tmp.path());
+        let server = StandInServer::start(payload.clone(), sha256.clone());
+        let store = ModelStore::new(tmp.path().join("models"));
+        let request = request(None);
+
+        // Первая половина уже скачана прерванной попыткой
+        let partial = partial_path(&store, &request).unwrap();
+        std::fs::write(&partial, &payload[..payload.len() / 2]).unwrap();
+        std::fs::write(validator_path(&partial), format!("\"{sha256}\"")).unwrap();
+
+        let mirrors = vec![format!("{}/redirect", server.base_url)];
+        let mut tracker = LoadTracker::new(None, LoadCancellation::default());
+        let report = download(
+            &StdHttpClient::default(),
+            &mirrors,
+            &request,
+            &store,
+            &mut tracker,
+        )
+        .unwrap();
+
+        assert_eq!(report.resumed_from, (payload.len() / 2) as u64);
+        assert_eq!(report.bytes, payload.len() as u64);
+        // Хеш взят из X-Linked-Etag ответа с перенаправлением
+        assert_eq!(report.sha256.as_deref(), Some(sha256.as_str()));
+        assert_eq!(std::fs::read(&report.path).unwrap(), payload);
+        assert_eq!(report.path, store.resolve(&request.model_id).unwrap());
+        assert!(!partial.exists());
+        assert!(!validator_path(&partial).exists());
+
+        let requests = server.requests.lock();
+        assert!(requests[0]
+            .starts_with("GET /redirect/Qwen/Qwen3-0.6B-GGUF/resolve/main/Qwen3-0.6B-Q8_0.gguf "));
+        assert!(requests[1].contains(&format!("Range: bytes={}-", payload.len() / 2)));
+        assert!(requests[1].contains(&format!("If-Range: \"{sha256}\"")));
+        drop(requests);
+
+        // Занятый идентификатор отклоняется до обращения к сети
+        let result = download(
+            &StdHttpClient::default(),
+            &mirrors,
+            &request,
+            &store,
+            &mut tracker,
+        );
+        assert!(matches!(
+            result,
+            Err(ModelManagerError::ModelAlreadyExists(_))
+        ));
+        assert_eq!(server.requests.lock().len(), 2);
+        assert!(!partial.exists());
+    }
+
+    #[test]
+    fn test_restarts_download_when_file_changed() {
+        let tmp = tempdir().unwrap();
+        let (payload, sha256) = model_payload(tmp.path());
+        let server = StandInServer::start(payload.clone(), sha256);
+        let store = ModelStore::new(tmp.path().join("models"));
+        let request = request(None);
+
+        // Часть другой ревизии не используется для докачки
+        let other = DownloadRequest {
+            revision: Some("v2".to_string()),
+            ..request.clone()
+        };
+        assert_ne!(
+            partial_path(&store, &request).unwrap(),
+            partial_path(&store, &other).unwrap()
+        );
+
+        // Часть прежней версии файла: валидатор устарел, сервер отдаёт файл целиком
+        let partial = partial_path(&store, &request).unwrap();
+        std::fs::write(&partial, vec![0xAB; payload.len() / 2]).unwrap();
+        std::fs::write(validator_path(&partial), "\"outdated\"").unwrap();
+
+        let mut tracker = LoadTracker::new(None, LoadCancellation::default());
+        let report = download(
+            &StdHttpClient::default(),
+            std::slice::from_ref(&server.base_url),
+            &request,
+            &store,
+            &mut tracker,
+        )
+        .unwrap();
+        assert_eq!(report.resumed_from, 0);
+        assert_eq!(std::fs::read(&report.path).unwrap(), payload);
+        assert!(server.requests.lock()[0].contains("If-Range: \"outdated\""));
+    }
+
+    #[test]
+    fn test_falls_back_to_next_mirror_and_rejects_bad_checksum() {
+        let tmp = tempdir().unwrap();
+        let (payload, sha256) = model_payload(tmp.path());
+        let server = StandInServer::start(payload, sha256);
+        let store = ModelStore::new(tmp.path().join("models"));
+        let mirrors = vec![
+            format!("{}/missing", server.base_url),
+            server.base_url.clone(),
+        ];
+
+        let bad = request(Some("0".repeat(64)));
+        let mut tracker = LoadTracker::new(None, LoadCancellation::default());
+        let result = download(
+            &StdHttpClient::default(),
+            &mirrors,
+            &bad,
+            &store,
+            &mut tracker,
+        );
+        assert!(matches!(
+            result,
+            Err(ModelManagerError::ChecksumMismatch { .. })
+        ));
+        // Испорченный файл не остаётся ни в хранилище, ни во временной директории
+        assert!(store.resolve(&bad.model_id).is_err());
+        assert!(!partial_path(&store, &bad).unwrap().exists());
+        assert_eq!(server.requests.lock().len(), 2);
+
+        let cancellation = LoadCancellation::default();
+        cancellation.cancel();
+        let mut tracker = LoadTracker::new(None, cancellation);
+        let result = download(
+            &StdHttpClient::default(),
+            &mirrors,
+            &request(None),
+            &store,
+            &mut tracker,
+        );
+        assert!(matches!(result, Err(ModelManagerError::Cancelled)));
+
+        assert!(resolve_url(DEFAULT_MIRROR, "Qwen/x", None, "../etc/passwd").is_err());
+    }
+}
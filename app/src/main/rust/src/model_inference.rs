@@ -2,15 +2,20 @@
 //! Предоставляет движок, поддерживающий настройку параметров генерации
 //! и потоковый вывод токенов в Android через JNI.
 
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use candle_core::Device;
+use parking_lot::Mutex;
 use thiserror::Error;
 
+use crate::batch::{self, BatchCallback};
+use crate::candidates::{self, Candidate, CandidateMode};
 use crate::model_manager::{ActiveModel, LoadedModelSnapshot};
-use candle_transformers::models::quantized_gemma3::ModelWeights as QuantizedGemma3;
-use candle_transformers::models::quantized_qwen3::ModelWeights as QuantizedQwen3;
+use crate::model_weights::{Gemma3Weights, Qwen3Weights};
+use crate::repetition::{DryConfig, LoopDetection, RepetitionGuard};
+use crate::speculative::{self, DraftModel, PromptLookup, SpeculativeStats, TokenSink};
 
 /// Ошибки движка инференса.
 #[derive(Debug, Error)]
@@ -22,13 +27,20 @@ pub enum InferenceError {
 }
 
 /// Настройки генерации текста.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct GenerationConfig {
     pub max_tokens: usize,
     pub temperature: f32,
     pub top_p: f32,
     pub repeat_penalty: f32,
     pub seed: u64,
+    /// Спекулятивное декодирование с черновиками из контекста; `None` — обычное.
+    pub prompt_lookup: Option<PromptLookup>,
+    /// DRY-штраф за повторы; `None` — отключён.
+    pub dry: Option<DryConfig>,
+    /// Детектор циклов в ответе; `None` — отключён.
+    pub loop_detection: Option<LoopDetection>,
 }
 
 impl Default for GenerationConfig {
@@ -39,6 +51,35 @@ impl Default for GenerationConfig {
             top_p: 0.9,
             repeat_penalty: 1.1,
             seed: 299792458,
+            prompt_lookup: None,
+            dry: None,
+            loop_detection: None,
+        }
+    }
+}
+
+/// Причина завершения генерации.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// Модель выдала токен конца ответа.
+    Eos,
+    /// Достигнут лимит токенов или контекста.
+    Length,
+    /// Генерация остановлена пользователем.
+    Cancelled,
+    /// Обнаружено зацикливание.
+    Loop,
+}
+
+impl FinishReason {
+    /// Возвращает имя причины для передачи в Kotlin.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Eos => "eos",
+            Self::Length => "length",
+            Self::Cancelled => "cancelled",
+            Self::Loop => "loop",
         }
     }
 }
@@ -53,11 +94,34 @@ pub trait StreamCallback: Send + Sync {
     fn on_error(&self, error: &str);
 }
 
+/// Предельная длина контекста при генерации.
+const MAX_CONTEXT_LENGTH: usize = 2048;
+
+/// Токены конца ответа: EOS Qwen3 и маркеры конца реплики, если они есть в словаре.
+fn stop_tokens(tokenizer: &tokenizers::Tokenizer) -> Vec<u32> {
+    let mut tokens = vec![151645u32];
+    for marker in ["<|im_end|>", "<end_of_turn>", "<eos>"] {
+        tokens.extend(tokenizer.token_to_id(marker));
+    }
+    tokens
+}
+
+/// Системный промпт, используемый, если персона не задана.
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful assistant.";
+
 /// Движок инференса для загруженной модели.
+#[derive(Clone)]
 pub struct InferenceEngine {
     model_snapshot: LoadedModelSnapshot,
     config: GenerationConfig,
+    system_prompt: String,
+    template_vars: BTreeMap<String, serde_json::Value>,
     stop_flag: Arc<AtomicBool>,
+    /// Остановка генераций именно этого движка, например при выводе его из работы.
+    cancel_flag: Arc<AtomicBool>,
+    draft: Option<DraftModel>,
+    speculative_stats: Arc<Mutex<Option<SpeculativeStats>>>,
+    finish_reason: Arc<Mutex<Option<FinishReason>>>,
 }
 
 impl InferenceEngine {
@@ -66,15 +130,77 @@ impl InferenceEngine {
         Self {
             model_snapshot,
             config: GenerationConfig::default(),
+            system_prompt: DEFAULT_SYSTEM_PROMPT.to_string(),
+            template_vars: BTreeMap::new(),
             stop_flag: Arc::new(AtomicBool::new(false)),
+            cancel_flag: Arc::new(AtomicBool::new(false)),
+            draft: None,
+            speculative_stats: Arc::new(Mutex::new(None)),
+            finish_reason: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Движок для другой модели с теми же параметрами, системным промптом и флагом остановки.
+    pub fn with_model(&self, model_snapshot: LoadedModelSnapshot) -> Self {
+        Self {
+            model_snapshot,
+            config: self.config.clone(),
+            system_prompt: self.system_prompt.clone(),
+            template_vars: self.template_vars.clone(),
+            stop_flag: self.stop_flag.clone(),
+            cancel_flag: Arc::new(AtomicBool::new(false)),
+            draft: self.draft.clone(),
+            speculative_stats: self.speculative_stats.clone(),
+            finish_reason: self.finish_reason.clone(),
         }
     }
 
+    /// Использует общий флаг остановки: `stop_generation` любого движка с этим флагом
+    /// останавливает и генерацию на этом.
+    pub fn set_stop_flag(&mut self, stop_flag: Arc<AtomicBool>) {
+        self.stop_flag = stop_flag;
+    }
+
     /// Обновляет параметры генерации.
     pub fn set_generation_params(&mut self, config: GenerationConfig) {
         self.config = config;
     }
 
+    /// Текущие параметры генерации.
+    pub fn generation_params(&self) -> &GenerationConfig {
+        &self.config
+    }
+
+    /// Устанавливает системный промпт и дополнительные переменные chat template.
+    pub fn set_system_prompt(
+        &mut self,
+        system_prompt: impl Into<String>,
+        template_vars: BTreeMap<String, serde_json::Value>,
+    ) {
+        self.system_prompt = system_prompt.into();
+        self.template_vars = template_vars;
+    }
+
+    /// Задаёт черновую модель для спекулятивного декодирования; `None` отключает его.
+    pub fn set_draft_model(&mut self, draft: Option<DraftModel>) {
+        self.draft = draft;
+    }
+
+    /// Черновая модель, если задана.
+    pub fn draft_model(&self) -> Option<&DraftModel> {
+        self.draft.as_ref()
+    }
+
+    /// Статистика последней спекулятивной генерации.
+    pub fn speculative_stats(&self) -> Option<SpeculativeStats> {
+        self.speculative_stats.lock().clone()
+    }
+
+    /// Причина завершения последней обычной генерации; `None` после спекулятивной.
+    pub fn finish_reason(&self) -> Option<FinishReason> {
+        *self.finish_reason.lock()
+    }
+
     /// Устанавливает флаг остановки генерации.
     pub fn stop_generation(&self) {
         log::info!("Setting generation stop flag to true");
@@ -82,6 +208,11 @@ impl InferenceEngine {
         log::info!("Generation stop flag set successfully");
     }
 
+    /// Останавливает генерации этого движка и его копий, не трогая общий флаг остановки.
+    pub fn cancel_generations(&self) {
+        self.cancel_flag.store(true, Ordering::SeqCst);
+    }
+
     /// Сбрасывает флаг остановки генерации.
     pub fn reset_stop_flag(&self) {
         self.stop_flag.store(false, Ordering::SeqCst);
@@ -89,7 +220,7 @@ impl InferenceEngine {
 
     /// Проверяет, установлена ли остановка генерации.
     pub fn is_stop_requested(&self) -> bool {
-        self.stop_flag.load(Ordering::SeqCst)
+        self.stop_flag.load(Ordering::SeqCst) || self.cancel_flag.load(Ordering::SeqCst)
     }
 
     /// Выполняет генерацию и возвращает полный результат строкой.
@@ -101,6 +232,8 @@ impl InferenceEngine {
         log::info!("Starting text generation for prompt: {}", prompt);
         // Сбрасываем флаг остановки в начале генерации
         self.reset_stop_flag();
+        *self.finish_reason.lock() = None;
+        *self.speculative_stats.lock() = None;
         log::info!("Stop flag reset, beginning generation");
 
         let model = self.model_snapshot.model();
@@ -116,21 +249,189 @@ impl InferenceEngine {
         }
     }
 
+    /// Генерирует несколько вариантов ответа на `prompt` (`n` выборок или beam search)
+    /// с общей обработкой промпта. Варианты отсортированы по убыванию оценки.
+    pub fn generate_candidates(
+        &self,
+        prompt: &str,
+        mode: CandidateMode,
+    ) -> Result<Vec<Candidate>, InferenceError> {
+        self.reset_stop_flag();
+        let tokenizer = self.model_snapshot.tokenizer();
+        let tokens = self.tokenize_prompt(prompt)?;
+        let stop_tokens = stop_tokens(tokenizer);
+        let repetition = RepetitionGuard::new(
+            self.config.dry.as_ref(),
+            self.config.loop_detection,
+            tokenizer,
+        );
+        let stop_conditions = batch::StopConditions {
+            tokens: &stop_tokens,
+            max_context: MAX_CONTEXT_LENGTH,
+            repetition: &repetition,
+        };
+        let device = self.model_snapshot.device();
+        let stop = || self.is_stop_requested();
+        // Варианты строятся на копии весов со своим KV-кешем: кеш чата не сбрасывается,
+        // а мьютекс модели не удерживается всю генерацию
+        let scored = match self.model_snapshot.model() {
+            ActiveModel::Qwen(model) => {
+                let mut model = model.lock().unwrap().clone();
+                candidates::generate(
+                    &mut model,
+                    device,
+                    &tokens,
+                    &self.config,
+                    mode,
+                    &stop_conditions,
+                    stop,
+                )
+            }
+            ActiveModel::Gemma(model) => {
+                let mut model = model.lock().unwrap().clone();
+                candidates::generate(
+                    &mut model,
+                    device,
+                    &tokens,
+                    &self.config,
+                    mode,
+                    &stop_conditions,
+                    stop,
+                )
+            }
+        }
+        .map_err(|e| InferenceError::Backend(e.to_string()))?;
+
+        let mut result = Vec::with_capacity(scored.len());
+        for candidate in scored {
+            let text = tokenizer
+                .decode(&candidate.tokens, true)
+                .map_err(|e| InferenceError::Backend(e.to_string()))?;
+            result.push(Candidate {
+                text,
+                tokens: candidate.tokens,
+                logprob: candidate.logprob,
+                score: candidate.score,
+                finished: candidate.finished,
+            });
+        }
+        result.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(result)
+    }
+
+    /// Генерирует ответы на несколько промптов пакетами не больше `max_batch_size`.
+    /// Текст каждой последовательности стримится в `callback` по её номеру во входном списке.
+    pub fn generate_batch(
+        &self,
+        prompts: &[String],
+        max_batch_size: usize,
+        callback: Option<Arc<dyn BatchCallback>>,
+    ) -> Result<Vec<String>, InferenceError> {
+        self.reset_stop_flag();
+        let tokenizer = self.model_snapshot.tokenizer();
+        let stop_tokens = stop_tokens(tokenizer);
+        let repetition = RepetitionGuard::new(
+            self.config.dry.as_ref(),
+            self.config.loop_detection,
+            tokenizer,
+        );
+        let stop = batch::StopConditions {
+            tokens: &stop_tokens,
+            max_context: MAX_CONTEXT_LENGTH,
+            repetition: &repetition,
+        };
+        let device = self.model_snapshot.device();
+        let mut stream = BatchStream {
+            engine: self,
+            tokenizer,
+            callback: callback.as_deref(),
+            base: 0,
+            texts: vec![String::new(); prompts.len()],
+            generated: vec![Vec::new(); prompts.len()],
+        };
+        // Пакеты идут на копии весов со своим KV-кешем: кеш чата не сбрасывается,
+        // а мьютекс модели не удерживается всю генерацию
+        let mut model = match self.model_snapshot.model() {
+            ActiveModel::Qwen(model) => ActiveWeights::Qwen(model.lock().unwrap().clone()),
+            ActiveModel::Gemma(model) => ActiveWeights::Gemma(model.lock().unwrap().clone()),
+        };
+
+        for (chunk_index, chunk) in prompts.chunks(max_batch_size.max(1)).enumerate() {
+            stream.base = chunk_index * max_batch_size.max(1);
+            let tokens = chunk
+                .iter()
+                .map(|prompt| self.tokenize_prompt(prompt))
+                .collect::<Result<Vec<_>, _>>()?;
+            // Зерно последовательности зависит от её номера во всём списке, а не в пакете
+            let config = GenerationConfig {
+                seed: self.config.seed.wrapping_add(stream.base as u64),
+                ..self.config.clone()
+            };
+            let should_stop = || self.is_stop_requested();
+            match &mut model {
+                ActiveWeights::Qwen(Qwen3Weights::Quantized(model)) => {
+                    batch::generate(model, &tokens, &config, &stop, should_stop, &mut stream)
+                }
+                ActiveWeights::Qwen(model) => batch::generate_serial(
+                    model,
+                    device,
+                    &tokens,
+                    &config,
+                    &stop,
+                    should_stop,
+                    &mut stream,
+                ),
+                ActiveWeights::Gemma(model) => batch::generate_serial(
+                    model,
+                    device,
+                    &tokens,
+                    &config,
+                    &stop,
+                    should_stop,
+                    &mut stream,
+                ),
+            }
+            .map_err(|e| InferenceError::Backend(e.to_string()))?;
+
+            if self.is_stop_requested() {
+                break;
+            }
+        }
+        Ok(stream.texts)
+    }
+
+    /// Промпт с применённым chat template, если он доступен.
+    fn format_prompt(&self, prompt: &str) -> Result<String, InferenceError> {
+        match self.model_snapshot.chat_template() {
+            Some(chat_template) => apply_chat_template(
+                chat_template,
+                &self.system_prompt,
+                prompt,
+                &self.template_vars,
+            ),
+            None => Ok(prompt.to_string()),
+        }
+    }
+
+    fn tokenize_prompt(&self, prompt: &str) -> Result<Vec<u32>, InferenceError> {
+        let formatted_prompt = self.format_prompt(prompt)?;
+        self.model_snapshot
+            .tokenizer()
+            .encode(formatted_prompt.as_str(), true)
+            .map(|encoding| encoding.get_ids().to_vec())
+            .map_err(|e| InferenceError::Backend(e.to_string()))
+    }
+
     fn generate_with_qwen3(
         &self,
-        model: Arc<std::sync::Mutex<QuantizedQwen3>>,
+        model: Arc<std::sync::Mutex<Qwen3Weights>>,
         tokenizer: &tokenizers::Tokenizer,
         prompt: &str,
         callback: Option<Arc<dyn StreamCallback>>,
     ) -> Result<String, InferenceError> {
         use candle_transformers::generation::LogitsProcessor;
 
-        // Применяем chat template если он доступен
-        let formatted_prompt = if let Some(chat_template) = self.model_snapshot.chat_template() {
-            apply_chat_template(chat_template, prompt)?
-        } else {
-            prompt.to_string()
-        };
+        let formatted_prompt = self.format_prompt(prompt)?;
 
         let tokens = tokenizer
             .encode(formatted_prompt.as_str(), true)
@@ -141,6 +442,23 @@ impl InferenceEngine {
         log::info!("Qwen3 - Prompt tokens: {}", tokens.len());
         log::info!("Qwen3 - Formatted prompt: {}", formatted_prompt);
 
+        let speculative = match (&self.draft, self.config.prompt_lookup) {
+            (Some(draft), _) => {
+                self.generate_speculative(&model, draft, tokenizer, &tokens, callback.clone())
+            }
+            (None, Some(lookup)) => self.generate_with_prompt_lookup(
+                &model,
+                lookup,
+                tokenizer,
+                &tokens,
+                callback.clone(),
+            ),
+            (None, None) => None,
+        };
+        if let Some(result) = speculative {
+            return result;
+        }
+
         let mut logits_processor = LogitsProcessor::new(
             self.config.seed,
             Some(self.config.temperature as f64),
@@ -178,6 +496,12 @@ impl InferenceEngine {
         let mut current_pos = tokens.len();
         let max_context_length = 2048; // Limit context to avoid memory issues
         let eos_token = 151645u32; // Qwen3 EOS token
+        let mut guard = RepetitionGuard::new(
+            self.config.dry.as_ref(),
+            self.config.loop_detection,
+            tokenizer,
+        );
+        let mut finish_reason = FinishReason::Length;
 
         for _ in 0..self.config.max_tokens {
             // Проверяем флаг остановки на каждой итерации
@@ -185,6 +509,7 @@ impl InferenceEngine {
             log::debug!("Checking stop flag: {}", stop_requested);
             if stop_requested {
                 log::info!("Generation stopped by user request");
+                finish_reason = FinishReason::Cancelled;
                 break;
             }
 
@@ -219,6 +544,9 @@ impl InferenceEngine {
             } else {
                 logits_raw
             };
+            let logits = guard
+                .apply(&logits, &all_tokens, tokens.len())
+                .map_err(|e| InferenceError::Backend(e.to_string()))?;
 
             let next_token = logits_processor
                 .sample(&logits)
@@ -230,12 +558,14 @@ impl InferenceEngine {
             if self.is_stop_requested() {
                 log::info!("Generation stopped by user request immediately after token generation");
                 // Не добавляем токен в список, если генерация остановлена
+                finish_reason = FinishReason::Cancelled;
                 break;
             }
 
             // Stop if we generated EOS token
             if next_token == eos_token {
                 log::info!("EOS token generated, stopping generation");
+                finish_reason = FinishReason::Eos;
                 break;
             }
 
@@ -254,6 +584,13 @@ impl InferenceEngine {
             }
 
             if next_token == *tokenizer.get_vocab(true).get("<|im_end|>").unwrap_or(&0) {
+                finish_reason = FinishReason::Eos;
+                break;
+            }
+
+            if guard.observe(&all_tokens[tokens.len()..]) {
+                log::info!("Repetition loop detected, stopping generation");
+                finish_reason = FinishReason::Loop;
                 break;
             }
         }
@@ -261,6 +598,7 @@ impl InferenceEngine {
         if let Some(cb) = callback {
             cb.on_complete();
         }
+        *self.finish_reason.lock() = Some(finish_reason);
 
         log::info!(
             "Qwen3 - Generated {} tokens, result length: {}",
@@ -272,9 +610,126 @@ impl InferenceEngine {
         Ok(generated_text)
     }
 
+    /// Спекулятивная генерация с черновой моделью. `None`, если пара моделей её
+    /// не поддерживает (нужны квантованные Qwen3 из GGUF) — тогда используется
+    /// обычное декодирование.
+    fn generate_speculative(
+        &self,
+        model: &Arc<std::sync::Mutex<Qwen3Weights>>,
+        draft: &DraftModel,
+        tokenizer: &tokenizers::Tokenizer,
+        tokens: &[u32],
+        callback: Option<Arc<dyn StreamCallback>>,
+    ) -> Option<Result<String, InferenceError>> {
+        let ActiveModel::Qwen(draft_model) = draft.snapshot().model() else {
+            log::warn!("Черновая модель не Qwen3, спекулятивное декодирование отключено");
+            return None;
+        };
+        if Arc::ptr_eq(model, draft_model)
+            || draft.snapshot().tokenizer().get_vocab_size(true) != tokenizer.get_vocab_size(true)
+        {
+            log::warn!("Черновая модель несовместима, спекулятивное декодирование отключено");
+            return None;
+        }
+        let mut target_guard = model.lock().unwrap();
+        let mut draft_guard = draft_model.lock().unwrap();
+        let (Qwen3Weights::Quantized(target), Qwen3Weights::Quantized(draft_weights)) =
+            (&mut *target_guard, &mut *draft_guard)
+        else {
+            log::warn!("Спекулятивное декодирование поддерживается только для GGUF моделей");
+            return None;
+        };
+
+        let result = self.stream_speculative(tokenizer, tokens, callback, |sink| {
+            speculative::generate(
+                target,
+                draft_weights,
+                draft.draft_tokens(),
+                tokens,
+                &self.config,
+                MAX_CONTEXT_LENGTH,
+                sink,
+            )
+        });
+        Some(result)
+    }
+
+    /// Спекулятивная генерация с черновиками из контекста. `None` для моделей
+    /// из safetensors — тогда используется обычное декодирование.
+    fn generate_with_prompt_lookup(
+        &self,
+        model: &Arc<std::sync::Mutex<Qwen3Weights>>,
+        lookup: PromptLookup,
+        tokenizer: &tokenizers::Tokenizer,
+        tokens: &[u32],
+        callback: Option<Arc<dyn StreamCallback>>,
+    ) -> Option<Result<String, InferenceError>> {
+        let mut guard = model.lock().unwrap();
+        let Qwen3Weights::Quantized(target) = &mut *guard else {
+            log::warn!("Prompt lookup поддерживается только для GGUF моделей");
+            return None;
+        };
+        let result = self.stream_speculative(tokenizer, tokens, callback, |sink| {
+            speculative::generate_with_prompt_lookup(
+                target,
+                lookup,
+                tokens,
+                &self.config,
+                MAX_CONTEXT_LENGTH,
+                sink,
+            )
+        });
+        Some(result)
+    }
+
+    /// Общая часть спекулятивных режимов: потоковый вывод, остановка по EOS
+    /// и `<|im_end|>`, защита от повторов, сохранение статистики и причины завершения.
+    fn stream_speculative(
+        &self,
+        tokenizer: &tokenizers::Tokenizer,
+        tokens: &[u32],
+        callback: Option<Arc<dyn StreamCallback>>,
+        generate: impl FnOnce(&mut SpeculativeSink) -> candle_core::Result<SpeculativeStats>,
+    ) -> Result<String, InferenceError> {
+        let mut sink = SpeculativeSink {
+            engine: self,
+            tokenizer,
+            callback: callback.as_deref(),
+            im_end: tokenizer.token_to_id("<|im_end|>"),
+            prompt_len: tokens.len(),
+            all_tokens: tokens.to_vec(),
+            generated_text: tokenizer.decode(tokens, true).unwrap_or_default(),
+            guard: RepetitionGuard::new(
+                self.config.dry.as_ref(),
+                self.config.loop_detection,
+                tokenizer,
+            ),
+            finish_reason: FinishReason::Length,
+        };
+        let stats = generate(&mut sink).map_err(|e| InferenceError::Backend(e.to_string()))?;
+        let SpeculativeSink {
+            generated_text,
+            finish_reason,
+            ..
+        } = sink;
+
+        if let Some(cb) = callback {
+            cb.on_complete();
+        }
+        *self.finish_reason.lock() = Some(finish_reason);
+        log::info!(
+            "Qwen3 - Speculative: {} tokens, {} target passes, acceptance rate {:.2}",
+            stats.generated,
+            stats.target_passes,
+            stats.acceptance_rate
+        );
+        *self.speculative_stats.lock() = Some(stats);
+        Ok(generated_text)
+    }
+
     fn generate_with_gemma3(
         &self,
-        model: Arc<std::sync::Mutex<QuantizedGemma3>>,
+        model: Arc<std::sync::Mutex<Gemma3Weights>>,
         tokenizer: &tokenizers::Tokenizer,
         prompt: &str,
         callback: Option<Arc<dyn StreamCallback>>,
@@ -282,12 +737,7 @@ impl InferenceEngine {
         // Аналогичная логика для Gemma3, адаптированная под её API
         use candle_transformers::generation::LogitsProcessor;
 
-        // Применяем chat template если он доступен
-        let formatted_prompt = if let Some(chat_template) = self.model_snapshot.chat_template() {
-            apply_chat_template(chat_template, prompt)?
-        } else {
-            prompt.to_string()
-        };
+        let formatted_prompt = self.format_prompt(prompt)?;
 
         let tokens = tokenizer
             .encode(formatted_prompt.as_str(), true)
@@ -331,7 +781,13 @@ impl InferenceEngine {
         // Start generation from the current position
         let mut current_pos = tokens.len();
         let max_context_length = 2048; // Limit context to avoid memory issues
-        let eos_token = 151645u32; // Qwen3 EOS token
+        let stop_tokens = stop_tokens(tokenizer);
+        let mut guard = RepetitionGuard::new(
+            self.config.dry.as_ref(),
+            self.config.loop_detection,
+            tokenizer,
+        );
+        let mut finish_reason = FinishReason::Length;
 
         for _ in 0..self.config.max_tokens {
             // Проверяем флаг остановки на каждой итерации
@@ -339,6 +795,7 @@ impl InferenceEngine {
             log::debug!("Checking stop flag: {}", stop_requested);
             if stop_requested {
                 log::info!("Generation stopped by user request");
+                finish_reason = FinishReason::Cancelled;
                 break;
             }
 
@@ -373,23 +830,28 @@ impl InferenceEngine {
             } else {
                 logits_raw
             };
+            let logits = guard
+                .apply(&logits, &all_tokens, tokens.len())
+                .map_err(|e| InferenceError::Backend(e.to_string()))?;
 
             let next_token = logits_processor
                 .sample(&logits)
                 .map_err(|e| InferenceError::Backend(e.to_string()))?;
 
-            log::info!("Generated token: {}, EOS token: {}", next_token, eos_token);
+            log::info!("Generated token: {}", next_token);
 
             // Немедленная проверка флага остановки
             if self.is_stop_requested() {
                 log::info!("Generation stopped by user request immediately after token generation");
                 // Не добавляем токен в список, если генерация остановлена
+                finish_reason = FinishReason::Cancelled;
                 break;
             }
 
-            // Stop if we generated EOS token
-            if next_token == eos_token {
-                log::info!("EOS token generated, stopping generation");
+            // Конец хода: EOS, `<|im_end|>` или `<end_of_turn>`
+            if stop_tokens.contains(&next_token) {
+                log::info!("Stop token generated, stopping generation");
+                finish_reason = FinishReason::Eos;
                 break;
             }
 
@@ -407,7 +869,9 @@ impl InferenceEngine {
                 }
             }
 
-            if next_token == *tokenizer.get_vocab(true).get("<|im_end|>").unwrap_or(&0) {
+            if guard.observe(&all_tokens[tokens.len()..]) {
+                log::info!("Repetition loop detected, stopping generation");
+                finish_reason = FinishReason::Loop;
                 break;
             }
         }
@@ -415,6 +879,7 @@ impl InferenceEngine {
         if let Some(cb) = callback {
             cb.on_complete();
         }
+        *self.finish_reason.lock() = Some(finish_reason);
 
         log::info!(
             "Gemma3 - Generated {} tokens, result length: {}",
@@ -430,18 +895,221 @@ impl InferenceEngine {
     pub fn device(&self) -> &Device {
         self.model_snapshot.device()
     }
+
+    /// Считает токены текста без специальных токенов (для счётчика в поле ввода).
+    pub fn count_tokens(&self, text: &str) -> Result<usize, InferenceError> {
+        Ok(self.tokenize(text)?.len())
+    }
+
+    /// Кодирует текст токенизатором загруженной модели без специальных токенов.
+    pub fn tokenize(&self, text: &str) -> Result<Vec<u32>, InferenceError> {
+        self.model_snapshot
+            .tokenizer()
+            .encode(text, false)
+            .map(|encoding| encoding.get_ids().to_vec())
+            .map_err(|e| InferenceError::Backend(e.to_string()))
+    }
+
+    /// Декодирует идентификаторы токенов, сохраняя специальные токены.
+    pub fn detokenize(&self, ids: &[u32]) -> Result<String, InferenceError> {
+        self.model_snapshot
+            .tokenizer()
+            .decode(ids, false)
+            .map_err(|e| InferenceError::Backend(e.to_string()))
+    }
+
+    /// Рендерит промпт для сообщений без запуска генерации.
+    /// Если системного сообщения нет, добавляется системный промпт персоны — как при генерации.
+    pub fn render_prompt(
+        &self,
+        messages: &[ChatMessage],
+    ) -> Result<RenderedPrompt, InferenceError> {
+        let mut all_messages = Vec::with_capacity(messages.len() + 1);
+        if !messages.iter().any(|message| message.role == "system") {
+            all_messages.push(ChatMessage::new("system", self.system_prompt.as_str()));
+        }
+        all_messages.extend_from_slice(messages);
+        render_prompt(
+            self.model_snapshot.tokenizer(),
+            self.model_snapshot.chat_template(),
+            &all_messages,
+            &self.template_vars,
+        )
+    }
+}
+
+/// Копия весов активной модели.
+enum ActiveWeights {
+    Qwen(Qwen3Weights),
+    Gemma(Gemma3Weights),
+}
+
+/// Получатель токенов пакетной генерации: текст каждой последовательности стримится
+/// по её номеру во входном списке, завершение сообщается сразу.
+struct BatchStream<'a> {
+    engine: &'a InferenceEngine,
+    tokenizer: &'a tokenizers::Tokenizer,
+    callback: Option<&'a dyn BatchCallback>,
+    /// Номер первого промпта текущего пакета.
+    base: usize,
+    texts: Vec<String>,
+    generated: Vec<Vec<u32>>,
+}
+
+impl batch::BatchSink for &mut BatchStream<'_> {
+    fn emit(&mut self, index: usize, token: u32) -> bool {
+        let index = self.base + index;
+        self.generated[index].push(token);
+        if let Ok(decoded) = self.tokenizer.decode(&self.generated[index], true) {
+            if let Some(text) = decoded.strip_prefix(self.texts[index].as_str()) {
+                if !text.is_empty() {
+                    if let Some(cb) = self.callback {
+                        cb.on_token(index, text);
+                    }
+                    self.texts[index].push_str(text);
+                }
+            }
+        }
+        !self.engine.is_stop_requested()
+    }
+
+    fn complete(&mut self, index: usize) {
+        let index = self.base + index;
+        if let Some(cb) = self.callback {
+            cb.on_sequence_complete(index, &self.texts[index]);
+        }
+    }
 }
 
-/// Структура для представления сообщения в чате
-#[derive(serde::Serialize)]
-struct ChatMessage {
-    role: String,
-    content: String,
+/// Получатель токенов спекулятивной генерации: потоковый вывод текста, остановка
+/// по EOS, `<|im_end|>` и запросу пользователя, DRY-штраф и детектор циклов.
+struct SpeculativeSink<'a> {
+    engine: &'a InferenceEngine,
+    tokenizer: &'a tokenizers::Tokenizer,
+    callback: Option<&'a dyn StreamCallback>,
+    im_end: Option<u32>,
+    prompt_len: usize,
+    all_tokens: Vec<u32>,
+    generated_text: String,
+    guard: RepetitionGuard,
+    finish_reason: FinishReason,
+}
+
+impl TokenSink for SpeculativeSink<'_> {
+    fn penalize(&self, logits: &mut [f32], context: &[u32]) {
+        self.guard.penalize(logits, context, self.prompt_len);
+    }
+
+    fn emit(&mut self, token: u32) -> bool {
+        if self.engine.is_stop_requested() {
+            self.finish_reason = FinishReason::Cancelled;
+            return false;
+        }
+        if token == 151645 {
+            self.finish_reason = FinishReason::Eos;
+            return false;
+        }
+        self.all_tokens.push(token);
+        if let Ok(decoded) = self.tokenizer.decode(&self.all_tokens, true) {
+            if let Some(text) = decoded.strip_prefix(&self.generated_text) {
+                if !text.is_empty() {
+                    if let Some(cb) = self.callback {
+                        cb.on_token(text);
+                    }
+                    self.generated_text.push_str(text);
+                }
+            }
+        }
+        if Some(token) == self.im_end {
+            self.finish_reason = FinishReason::Eos;
+            return false;
+        }
+        if self.guard.observe(&self.all_tokens[self.prompt_len..]) {
+            log::info!("Repetition loop detected, stopping generation");
+            self.finish_reason = FinishReason::Loop;
+            return false;
+        }
+        true
+    }
+}
+
+/// Сообщение диалога для chat template.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    /// Создаёт сообщение с указанной ролью (`system`, `user`, `assistant`).
+    pub fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: content.into(),
+        }
+    }
+}
+
+/// Промпт в том виде, в каком он подаётся модели, и число его токенов.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RenderedPrompt {
+    pub prompt: String,
+    pub token_count: usize,
+}
+
+/// Рендерит промпт для сообщений и считает его токены так же, как перед генерацией.
+/// Без chat template модели подаётся текст последнего сообщения пользователя.
+pub fn render_prompt(
+    tokenizer: &tokenizers::Tokenizer,
+    chat_template: Option<&str>,
+    messages: &[ChatMessage],
+    template_vars: &BTreeMap<String, serde_json::Value>,
+) -> Result<RenderedPrompt, InferenceError> {
+    let prompt = match chat_template {
+        Some(chat_template) => render_chat_template(chat_template, messages, template_vars)?,
+        None => last_user_message(messages).to_string(),
+    };
+    let token_count = tokenizer
+        .encode(prompt.as_str(), true)
+        .map_err(|e| InferenceError::Backend(e.to_string()))?
+        .len();
+    Ok(RenderedPrompt {
+        prompt,
+        token_count,
+    })
+}
+
+fn last_user_message(messages: &[ChatMessage]) -> &str {
+    messages
+        .iter()
+        .rev()
+        .find(|message| message.role == "user")
+        .map(|message| message.content.as_str())
+        .unwrap_or("")
 }
 
 /// Применяет chat template для форматирования сообщения пользователя.
+/// `template_vars` передаются в контекст шаблона наряду с `messages`.
+fn apply_chat_template(
+    chat_template: &str,
+    system_prompt: &str,
+    user_message: &str,
+    template_vars: &BTreeMap<String, serde_json::Value>,
+) -> Result<String, InferenceError> {
+    let messages = [
+        ChatMessage::new("system", system_prompt),
+        ChatMessage::new("user", user_message),
+    ];
+    render_chat_template(chat_template, &messages, template_vars)
+}
+
+/// Рендерит chat template для списка сообщений.
 /// Использует MiniJinja для полноценной поддержки Jinja2 шаблонов.
-fn apply_chat_template(chat_template: &str, user_message: &str) -> Result<String, InferenceError> {
+fn render_chat_template(
+    chat_template: &str,
+    messages: &[ChatMessage],
+    template_vars: &BTreeMap<String, serde_json::Value>,
+) -> Result<String, InferenceError> {
     log::info!("Applying chat template: {}", chat_template);
 
     use minijinja::{context, Environment};
@@ -451,18 +1119,6 @@ fn apply_chat_template(chat_template: &str, user_message: &str) -> Result<String
 
     // Проверяем, является ли это Jinja2 шаблоном
     if chat_template.contains("{{messages}}") || chat_template.contains("{%") {
-        // Создаем список сообщений для шаблона
-        let messages = vec![
-            ChatMessage {
-                role: "system".to_string(),
-                content: "You are a helpful assistant.".to_string(),
-            },
-            ChatMessage {
-                role: "user".to_string(),
-                content: user_message.to_string(),
-            },
-        ];
-
         // Добавляем шаблон в окружение
         env.add_template("chat", chat_template)
             .map_err(|e| InferenceError::Backend(format!("Failed to add chat template: {}", e)))?;
@@ -476,6 +1132,7 @@ fn apply_chat_template(chat_template: &str, user_message: &str) -> Result<String
         let ctx = context! {
             messages => messages,
             add_generation_prompt => true,
+            ..minijinja::Value::from_serialize(template_vars)
         };
 
         // Рендерим шаблон
@@ -487,6 +1144,7 @@ fn apply_chat_template(chat_template: &str, user_message: &str) -> Result<String
         Ok(formatted)
     } else if chat_template.contains("<|user|>") && chat_template.contains("<|assistant|>") {
         // Простая замена плейсхолдеров для специальных токенов
+        let user_message = last_user_message(messages);
         let formatted = chat_template
             .replace("{{user_message}}", user_message)
             .replace("{{query}}", user_message)
@@ -495,11 +1153,14 @@ fn apply_chat_template(chat_template: &str, user_message: &str) -> Result<String
     } else {
         // Fallback на простое форматирование для Qwen/HF моделей
         log::warn!("Unknown chat template format, using fallback formatting");
-        let system_message = "You are a helpful assistant.";
-        let formatted = format!(
-            "<|im_start|>system\n{}\n<|im_end|>\n<|im_start|>user\n{}\n<|im_end|>\n<|im_start|>assistant\n",
-            system_message, user_message
-        );
+        let mut formatted = String::new();
+        for message in messages {
+            formatted.push_str(&format!(
+                "<|im_start|>{}\n{}\n<|im_end|>\n",
+                message.role, message.content
+            ));
+        }
+        formatted.push_str("<|im_start|>assistant\n");
         Ok(formatted)
     }
 }
@@ -515,4 +1176,111 @@ mod tests {
         assert!((config.temperature - 0.7).abs() < f32::EPSILON);
         assert_eq!(config.seed, 299792458);
     }
+
+    /// Движок квантованной Qwen3, KV-кеш которой заполнен пятью токенами, как после чата.
+    fn engine_with_filled_cache(dir: &std::path::Path) -> InferenceEngine {
+        use crate::model_manager::ModelManager;
+        use crate::test_support::write_qwen3_gguf;
+
+        let path = dir.join("qwen/model.gguf");
+        write_qwen3_gguf(&path, 0);
+        let manager = ModelManager::new(dir, Device::Cpu);
+        manager.load_model_from_path(&path).unwrap();
+        let mut engine = InferenceEngine::new(manager.current_model().unwrap());
+        engine.set_generation_params(GenerationConfig {
+            max_tokens: 3,
+            ..GenerationConfig::default()
+        });
+        let ActiveModel::Qwen(model) = engine.model_snapshot.model() else {
+            unreachable!()
+        };
+        let input = candle_core::Tensor::new(&[1u32, 4, 2, 3, 1], &Device::Cpu)
+            .unwrap()
+            .unsqueeze(0)
+            .unwrap();
+        model.lock().unwrap().forward(&input, 0).unwrap();
+        engine
+    }
+
+    fn chat_kv_len(engine: &InferenceEngine) -> usize {
+        match engine.model_snapshot.model() {
+            ActiveModel::Qwen(model) => match &*model.lock().unwrap() {
+                Qwen3Weights::Quantized(model) => model.kv_len(),
+                Qwen3Weights::Full(_) => unreachable!(),
+            },
+            ActiveModel::Gemma(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_candidates_keep_chat_kv_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let engine = engine_with_filled_cache(tmp.path());
+        assert_eq!(chat_kv_len(&engine), 5);
+
+        let candidates = engine
+            .generate_candidates("hello a b", CandidateMode::Sample { n: 2 })
+            .unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(chat_kv_len(&engine), 5);
+    }
+
+    #[test]
+    fn test_batch_keeps_chat_kv_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let engine = engine_with_filled_cache(tmp.path());
+
+        let prompts = vec!["hello a b".to_string(), "a".to_string()];
+        let texts = engine.generate_batch(&prompts, 1, None).unwrap();
+        assert_eq!(texts.len(), 2);
+        assert_eq!(chat_kv_len(&engine), 5);
+    }
+
+    #[test]
+    fn test_chat_template_uses_system_prompt_and_vars() {
+        let template = "{% for m in messages %}[{{ m.role }}:{{ m.content }}]{% endfor %}\
+                        {% if enable_thinking %}<think>{% endif %}";
+        let mut vars = BTreeMap::new();
+        vars.insert("enable_thinking".to_string(), serde_json::Value::Bool(true));
+
+        let rendered = apply_chat_template(template, "Be terse.", "hi", &vars).unwrap();
+        assert_eq!(rendered, "[system:Be terse.][user:hi]<think>");
+    }
+
+    #[test]
+    fn test_render_prompt_counts_template_tokens() {
+        let tokenizer = tokenizers::Tokenizer::from_bytes(
+            serde_json::json!({
+                "version": "1.0",
+                "added_tokens": [],
+                "pre_tokenizer": {"type": "Whitespace"},
+                "model": {
+                    "type": "WordLevel",
+                    "vocab": {"[UNK]": 0, "[": 1, "]": 2, "user": 3, "hi": 4},
+                    "unk_token": "[UNK]",
+                },
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let messages = [
+            ChatMessage::new("user", "hi"),
+            ChatMessage::new("assistant", "hello there"),
+            ChatMessage::new("user", "bye"),
+        ];
+        let template = "{% for m in messages %}[ {{ m.role }} {{ m.content }} ]{% endfor %}";
+
+        let rendered =
+            render_prompt(&tokenizer, Some(template), &messages, &BTreeMap::new()).unwrap();
+        assert_eq!(
+            rendered.prompt,
+            "[ user hi ][ assistant hello there ][ user bye ]"
+        );
+        assert_eq!(rendered.token_count, 11);
+
+        // Без шаблона модели подаётся последнее сообщение пользователя
+        let raw = render_prompt(&tokenizer, None, &messages, &BTreeMap::new()).unwrap();
+        assert_eq!(raw.prompt, "bye");
+        assert_eq!(raw.token_count, 1);
+    }
 }
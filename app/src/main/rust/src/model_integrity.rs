@@ -0,0 +1,362 @@
+//! Проверка целостности GGUF файлов.
+//! Структурная проверка (магия, версия, границы тензоров) выполняется перед каждой
+//! загрузкой и ничего не выделяет под веса; SHA-256 считается потоково и только
+//! при наличии ожидаемого хеша.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use candle_core::quantized::gguf_file;
+use serde::Serialize;
+
+use crate::gguf_reader::MappedGguf;
+use crate::gguf_split::SplitName;
+use crate::hf_cache;
+use crate::load_progress::{LoadPhase, LoadTracker};
+use crate::model_manager::ModelManagerError;
+use crate::sha256::Sha256;
+
+/// Магическая строка в начале GGUF файла.
+pub const GGUF_MAGIC: [u8; 4] = *b"GGUF";
+
+/// Версии формата, которые умеет читать candle.
+const SUPPORTED_VERSIONS: std::ops::RangeInclusive<u32> = 1..=3;
+
+/// Размер блока чтения при подсчёте контрольной суммы.
+const HASH_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Расширение файла с контрольной суммой в формате `sha256sum`.
+const SHA256_SIDECAR_EXTENSION: &str = "sha256";
+
+/// Откуда взят ожидаемый SHA-256.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumSource {
+    /// Передан вызывающей стороной.
+    Caller,
+    /// Файл `<model>.sha256` рядом с моделью.
+    Sidecar,
+    /// Имя LFS блоба в кеше Hugging Face.
+    HfCache,
+}
+
+/// Результат проверки модели.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VerifyReport {
+    pub path: PathBuf,
+    pub file_len: u64,
+    pub gguf_version: u32,
+    pub tensor_count: usize,
+    /// Фактический SHA-256 файла `path`, если он считался.
+    pub sha256: Option<String>,
+    pub checksum_source: Option<ChecksumSource>,
+    /// Все части разбитой модели по порядку; пусто для модели из одного файла.
+    pub shards: Vec<ShardReport>,
+}
+
+/// Результат проверки одной части разбитой модели.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShardReport {
+    pub path: PathBuf,
+    pub sha256: Option<String>,
+    pub checksum_source: Option<ChecksumSource>,
+}
+
+/// Проверяет магию и версию по первым байтам файла. Возвращает версию формата.
+pub fn check_header(bytes: &[u8]) -> Result<u32, ModelManagerError> {
+    if bytes.len() < 8 {
+        return Err(ModelManagerError::Corrupted(
+            "файл короче заголовка GGUF".to_string(),
+        ));
+    }
+    if bytes[..4] != GGUF_MAGIC {
+        return Err(ModelManagerError::Corrupted(
+            "отсутствует сигнатура GGUF".to_string(),
+        ));
+    }
+    let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(ModelManagerError::Corrupted(format!(
+            "неподдерживаемая версия GGUF {version}"
+        )));
+    }
+    Ok(version)
+}
+
+/// Проверяет, что данные каждого тензора целиком лежат внутри файла.
+pub fn check_tensor_bounds(
+    content: &gguf_file::Content,
+    file_len: u64,
+) -> Result<(), ModelManagerError> {
+    for (name, info) in &content.tensor_infos {
+        let block_size = info.ggml_dtype.block_size() as u64;
+        let elem_count = info
+            .shape
+            .dims()
+            .iter()
+            .try_fold(1u64, |count, &dim| count.checked_mul(dim as u64));
+        let size = elem_count
+            .filter(|count| count % block_size == 0)
+            .and_then(|count| (count / block_size).checked_mul(info.ggml_dtype.type_size() as u64));
+        let end = size.and_then(|size| {
+            content
+                .tensor_data_offset
+                .checked_add(info.offset)?
+                .checked_add(size)
+        });
+        match end {
+            Some(end) if end <= file_len => {}
+            _ => {
+                return Err(ModelManagerError::Corrupted(format!(
+                    "тензор '{name}' выходит за пределы файла ({file_len} байт)"
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Ожидаемый SHA-256 для файла: `<model>.sha256` рядом с ним или имя блоба кеша Hugging Face.
+pub fn expected_sha256(path: &Path) -> Option<(String, ChecksumSource)> {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(SHA256_SIDECAR_EXTENSION);
+    if let Ok(text) = std::fs::read_to_string(PathBuf::from(sidecar)) {
+        // Формат `sha256sum`: "<hex>  <имя файла>"
+        if let Some(hash) = text.split_whitespace().next().and_then(normalize_sha256) {
+            return Some((hash, ChecksumSource::Sidecar));
+        }
+    }
+    hf_cache::lfs_sha256(path).map(|hash| (hash, ChecksumSource::HfCache))
+}
+
+/// Приводит hex-строку SHA-256 к нижнему регистру; `None`, если строка некорректна.
+pub fn normalize_sha256(hash: &str) -> Option<String> {
+    let hash = hash.trim();
+    (hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| hash.to_ascii_lowercase())
+}
+
+/// Потоково считает SHA-256 файла, сообщая о прогрессе и проверяя отмену.
+pub fn sha256_file(path: &Path, tracker: &mut LoadTracker) -> Result<String, ModelManagerError> {
+    tracker.enter_bytes_phase(LoadPhase::Checksum, file_len(path));
+    hash_file(path, tracker)
+}
+
+fn file_len(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|meta| meta.len()).unwrap_or(0)
+}
+
+/// Считает SHA-256 файла внутри уже начатой фазы `Checksum`.
+fn hash_file(path: &Path, tracker: &mut LoadTracker) -> Result<String, ModelManagerError> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|_| ModelManagerError::ModelFileMissing(path.to_string_lossy().into_owned()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE];
+    loop {
+        if tracker.is_cancelled() {
+            return Err(ModelManagerError::Cancelled);
+        }
+        let read = file
+            .read(&mut buffer)
+            .map_err(|e| ModelManagerError::Io(e.to_string()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        tracker.add_bytes(read as u64);
+    }
+    Ok(hasher.finalize_hex())
+}
+
+/// Сверяет SHA-256 файла с ожидаемым.
+fn check_sha256(
+    path: &Path,
+    expected: &str,
+    tracker: &mut LoadTracker,
+) -> Result<String, ModelManagerError> {
+    let actual = hash_file(path, tracker)?;
+    if actual != expected {
+        return Err(ModelManagerError::ChecksumMismatch {
+            expected: expected.to_string(),
+            actual,
+        });
+    }
+    Ok(actual)
+}
+
+/// Полная проверка модели: структура GGUF и, если известен ожидаемый хеш, SHA-256.
+/// `expected_sha256` имеет приоритет над файлом `.sha256` и хешем кеша Hugging Face.
+/// У разбитой модели проверяются все части, каждая — по своему `.sha256` или хешу
+/// кеша; `expected_sha256` относится к части `path`.
+pub fn verify_model(
+    path: &Path,
+    expected_sha256: Option<&str>,
+    tracker: &mut LoadTracker,
+) -> Result<VerifyReport, ModelManagerError> {
+    let expected = match expected_sha256 {
+        Some(hash) => Some((
+            normalize_sha256(hash)
+                .ok_or_else(|| ModelManagerError::InvalidChecksum(hash.to_string()))?,
+            ChecksumSource::Caller,
+        )),
+        None => self::expected_sha256(path),
+    };
+
+    // Дешёвая структурная проверка выполняется до чтения всего файла
+    tracker.enter(LoadPhase::Header);
+    let gguf = MappedGguf::open(path)?;
+    let gguf_version = check_header(gguf.reader.header_bytes())?;
+
+    let shard_paths = SplitName::parse(path)
+        .map(|split| split.shard_paths())
+        .unwrap_or_default();
+    let is_target = |shard: &Path| shard.file_name() == path.file_name();
+    let checked: Vec<(PathBuf, Option<(String, ChecksumSource)>)> = if shard_paths.is_empty() {
+        vec![(path.to_path_buf(), expected)]
+    } else {
+        shard_paths
+            .into_iter()
+            .map(|shard| {
+                let expected = if is_target(&shard) {
+                    expected.clone()
+                } else {
+                    self::expected_sha256(&shard)
+                };
+                (shard, expected)
+            })
+            .collect()
+    };
+
+    let hashed_len = checked
+        .iter()
+        .filter(|(_, expected)| expected.is_some())
+        .map(|(path, _)| file_len(path))
+        .sum();
+    if hashed_len > 0 {
+        tracker.enter_bytes_phase(LoadPhase::Checksum, hashed_len);
+    }
+    let mut shards = Vec::with_capacity(checked.len());
+    for (shard, expected) in checked {
+        let sha256 = match &expected {
+            Some((expected, _)) => Some(check_sha256(&shard, expected, tracker)?),
+            None => None,
+        };
+        shards.push(ShardReport {
+            path: shard,
+            sha256,
+            checksum_source: expected.map(|(_, source)| source),
+        });
+    }
+    let target = if shards.len() == 1 {
+        shards.pop()
+    } else {
+        shards.iter().find(|shard| is_target(&shard.path)).cloned()
+    };
+    let (sha256, checksum_source) = target
+        .map(|shard| (shard.sha256, shard.checksum_source))
+        .unwrap_or_default();
+
+    Ok(VerifyReport {
+        path: path.to_path_buf(),
+        file_len: gguf.file_len(),
+        gguf_version,
+        tensor_count: gguf.content.tensor_infos.len(),
+        sha256,
+        checksum_source,
+        shards,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_progress::LoadCancellation;
+    use crate::test_support::{basic_metadata, write_split_gguf, write_test_gguf};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_truncated_file_is_reported_as_corrupted() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("model.gguf");
+        write_test_gguf(&path, &basic_metadata("qwen3"), &[("w", &[64, 64])]);
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 100).unwrap();
+
+        assert!(matches!(
+            MappedGguf::open(&path),
+            Err(ModelManagerError::Corrupted(_))
+        ));
+
+        std::fs::write(&path, b"GGML\x03\0\0\0").unwrap();
+        assert!(matches!(
+            MappedGguf::open(&path),
+            Err(ModelManagerError::Corrupted(_))
+        ));
+        assert!(check_header(b"GGUF\x09\0\0\0").is_err());
+    }
+
+    #[test]
+    fn test_verifies_sidecar_checksum() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("model.gguf");
+        write_test_gguf(&path, &basic_metadata("qwen3"), &[("w", &[4])]);
+        let mut hasher = Sha256::new();
+        hasher.update(&std::fs::read(&path).unwrap());
+        let hash = hasher.finalize_hex();
+        std::fs::write(
+            tmp.path().join("model.gguf.sha256"),
+            format!("{}  model.gguf\n", hash.to_uppercase()),
+        )
+        .unwrap();
+
+        let mut tracker = LoadTracker::new(None, LoadCancellation::default());
+        let report = verify_model(&path, None, &mut tracker).unwrap();
+        assert_eq!(report.sha256.as_deref(), Some(hash.as_str()));
+        assert_eq!(report.checksum_source, Some(ChecksumSource::Sidecar));
+        assert_eq!(report.tensor_count, 1);
+        assert_eq!(tracker.progress().bytes_total, report.file_len);
+
+        let wrong = "0".repeat(64);
+        assert!(matches!(
+            verify_model(&path, Some(&wrong), &mut tracker),
+            Err(ModelManagerError::ChecksumMismatch { .. })
+        ));
+        assert!(matches!(
+            verify_model(&path, Some("xyz"), &mut tracker),
+            Err(ModelManagerError::InvalidChecksum(_))
+        ));
+    }
+
+    #[test]
+    fn test_verifies_every_shard_of_split_model() {
+        let tmp = tempdir().unwrap();
+        let shards = write_split_gguf(tmp.path(), "model", &[&[("a", &[4])], &[("b", &[4])]]);
+        let mut hasher = Sha256::new();
+        hasher.update(&std::fs::read(&shards[1]).unwrap());
+        let hash = hasher.finalize_hex();
+        let sidecar = tmp.path().join("model-00002-of-00002.gguf.sha256");
+
+        // Неверный хеш второй части обнаруживается при проверке по первой
+        std::fs::write(&sidecar, "0".repeat(64)).unwrap();
+        let mut tracker = LoadTracker::new(None, LoadCancellation::default());
+        assert!(matches!(
+            verify_model(&shards[0], None, &mut tracker),
+            Err(ModelManagerError::ChecksumMismatch { .. })
+        ));
+
+        std::fs::write(&sidecar, &hash).unwrap();
+        let report = verify_model(&shards[0], None, &mut tracker).unwrap();
+        assert_eq!(report.tensor_count, 2);
+        assert_eq!(report.sha256, None);
+        assert_eq!(report.shards.len(), 2);
+        assert_eq!(report.shards[1].sha256.as_deref(), Some(hash.as_str()));
+        assert_eq!(
+            report.shards[1].checksum_source,
+            Some(ChecksumSource::Sidecar)
+        );
+        let shard_len = std::fs::metadata(&shards[1]).unwrap().len();
+        assert_eq!(tracker.progress().bytes_total, shard_len);
+    }
+}
@@ -1,16 +1,43 @@
 //! Модуль управления загрузкой моделей GGUF (Qwen3/Gemma3).
 //! Обеспечивает ленивую загрузку, выгрузку и кеширование метаданных.
+//! Несколько моделей могут одновременно оставаться в памяти (см. [`crate::model_pool`]).
 
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use candle_core::quantized::gguf_file;
-use candle_core::Device;
+use candle_core::{DType, Device};
 use candle_transformers::models::quantized_gemma3::ModelWeights as QuantizedGemma3;
-use candle_transformers::models::quantized_qwen3::ModelWeights as QuantizedQwen3;
 use parking_lot::RwLock;
+use serde::Serialize;
 use thiserror::Error;
 
+use crate::external_stores::ExternalStore;
+use crate::gguf_editor::{self, MetadataEdit};
+use crate::gguf_limits::{GgufFormatError, GgufLimits};
+use crate::gguf_reader::MappedGguf;
+use crate::hf_cache;
+use crate::load_progress::{
+    CancellationGroup, LoadPhase, LoadProgressCallback, LoadTracker, ProgressReader,
+};
+use crate::model_catalog::{ModelCatalog, ModelCatalogEntry};
+use crate::model_convert::{self, ConversionReport, QuantizationPlan};
+use crate::model_download::{self, DownloadReport, DownloadRequest, HttpClient, DEFAULT_MIRROR};
+use crate::model_integrity::{self, VerifyReport};
+use crate::model_memory::{
+    recommend_quantization, MemoryEstimate, QuantizationRecommendation, DEFAULT_CONTEXT_LENGTH,
+};
+use crate::model_pool::{ModelPool, DEFAULT_MAX_RESIDENT_MODELS};
+use crate::model_source::ModelSource;
+use crate::model_store::{ModelId, ModelStore};
+use crate::model_weights::{Gemma3Weights, Qwen3Weights};
+use crate::quantized_qwen3::ModelWeights as QuantizedQwen3;
+use crate::safetensors_model::{SafetensorsCheckpoint, CONFIG_FILE_NAME};
+use crate::speculative::check_compatible;
+use crate::tokenizer_source::resolve_tokenizer;
+
 /// Поддерживаемые типы моделей.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ModelType {
@@ -28,6 +55,15 @@ impl ModelType {
             ModelType::Gemma3 => "Gemma3",
         }
     }
+
+    /// Определяет тип модели по ключу `general.architecture`.
+    pub fn from_architecture(architecture: &str) -> Option<Self> {
+        match architecture {
+            "qwen3" => Some(ModelType::Qwen3),
+            "gemma3" => Some(ModelType::Gemma3),
+            _ => None,
+        }
+    }
 }
 
 /// Ошибки менеджера моделей.
@@ -39,10 +75,46 @@ pub enum ModelManagerError {
     ModelFileMissing(String),
     #[error("Файл токенизатора '{0}' не найден")]
     TokenizerMissing(String),
+    #[error("Некорректный идентификатор модели '{0}'")]
+    InvalidModelId(String),
+    #[error("Модель '{0}' уже существует")]
+    ModelAlreadyExists(String),
+    #[error("Модель '{0}' загружена и не может быть изменена")]
+    ModelInUse(String),
+    #[error("Модель '{0}' принадлежит внешнему хранилищу и не может быть изменена")]
+    ExternalModel(String),
+    #[error("Модель '{0}' не загружена в память")]
+    ModelNotResident(String),
+    #[error("Путь '{0}' находится вне хранилища моделей")]
+    OutsideStore(String),
+    #[error("Ошибка файловой системы: {0}")]
+    Io(String),
+    #[error("Файл модели повреждён: {0}")]
+    Corrupted(String),
+    #[error("Некорректный заголовок GGUF: {0}")]
+    InvalidGguf(#[from] GgufFormatError),
+    #[error("Некорректная контрольная сумма '{0}'")]
+    InvalidChecksum(String),
+    #[error("Контрольная сумма не совпадает: ожидалась {expected}, получена {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("Некорректная разбивка модели на части: {0}")]
+    InvalidSplit(String),
+    #[error("Ошибка загрузки: {0}")]
+    Download(String),
+    #[error("Ошибка конвертации: {0}")]
+    Conversion(String),
+    #[error("Некорректные метаданные: {0}")]
+    InvalidMetadata(String),
     #[error("Ошибка Candle: {0}")]
     Candle(String),
     #[error("Ошибка инициализации модели: {0}")]
     Initialization(String),
+    #[error("Черновая модель несовместима с основной: {0}")]
+    IncompatibleDraftModel(String),
+    #[error("Загрузка модели отменена")]
+    Cancelled,
+    #[error("Недостаточно памяти для модели: требуется {required} байт, бюджет {budget} байт")]
+    InsufficientMemory { required: u64, budget: u64 },
 }
 
 impl From<candle_core::Error> for ModelManagerError {
@@ -51,11 +123,72 @@ impl From<candle_core::Error> for ModelManagerError {
     }
 }
 
+/// Ищет chat template в различных возможных ключах метаданных GGUF.
+pub(crate) fn chat_template_from_metadata(
+    metadata: &HashMap<String, gguf_file::Value>,
+) -> Option<String> {
+    metadata
+        .get("tokenizer.chat_template")
+        .or_else(|| metadata.get("chat_template"))
+        .or_else(|| metadata.get("tokenizer.ggml.chat_template"))
+        .and_then(|value| {
+            if let gguf_file::Value::String(template) = value {
+                Some(template.clone())
+            } else {
+                None
+            }
+        })
+}
+
+/// Определяет тип модели по `general.architecture`, а для файлов без этого ключа — по имени.
+/// Блобы Ollama не имеют осмысленного имени, поэтому метаданные проверяются первыми.
+pub(crate) fn detect_model_type(
+    model_path: &Path,
+    metadata: &HashMap<String, gguf_file::Value>,
+) -> Result<ModelType, ModelManagerError> {
+    let from_metadata = metadata
+        .get("general.architecture")
+        .and_then(|value| value.to_string().ok())
+        .and_then(|architecture| ModelType::from_architecture(architecture));
+    let file_name = model_path.to_string_lossy().to_lowercase();
+    let model_type = from_metadata.or(if file_name.contains("qwen") {
+        Some(ModelType::Qwen3)
+    } else if file_name.contains("gemma") {
+        Some(ModelType::Gemma3)
+    } else {
+        None
+    });
+
+    match model_type {
+        Some(model_type) => {
+            log::info!("Detected model type: {}", model_type.as_str());
+            Ok(model_type)
+        }
+        None => {
+            log::info!("Cannot determine model type from filename");
+            Err(ModelManagerError::Initialization(
+                "Cannot determine model type from filename".to_string(),
+            ))
+        }
+    }
+}
+
 /// Обертка активной модели (Qwen3/Gemma3).
 #[derive(Debug, Clone)]
 pub enum ActiveModel {
-    Qwen(Arc<std::sync::Mutex<QuantizedQwen3>>),
-    Gemma(Arc<std::sync::Mutex<QuantizedGemma3>>),
+    Qwen(Arc<std::sync::Mutex<Qwen3Weights>>),
+    Gemma(Arc<std::sync::Mutex<Gemma3Weights>>),
+}
+
+impl ActiveModel {
+    /// Одни и те же ли это веса.
+    fn same(&self, other: &ActiveModel) -> bool {
+        match (self, other) {
+            (ActiveModel::Qwen(a), ActiveModel::Qwen(b)) => Arc::ptr_eq(a, b),
+            (ActiveModel::Gemma(a), ActiveModel::Gemma(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
 }
 
 /// Информация о загруженной модели.
@@ -66,6 +199,9 @@ pub struct LoadedModel {
     tokenizer: tokenizers::Tokenizer,
     device: Device,
     chat_template: Option<String>,
+    /// Канонический путь к GGUF файлу или директории safetensors; `None` для дескрипторов
+    /// и буферов.
+    path: Option<PathBuf>,
 }
 
 impl LoadedModel {
@@ -95,11 +231,41 @@ impl LoadedModel {
     }
 }
 
+/// Черновая модель спекулятивного декодирования. Она не входит в пул, но её файлы
+/// так же нельзя изменять, пока она загружена, а память учитывается в бюджете.
+struct DraftSlot {
+    path: Option<PathBuf>,
+    bytes: u64,
+}
+
+/// Наблюдатель за сменой активной модели. Вызывается под блокировкой пула, поэтому
+/// другие потоки видят новую активную модель только вместе с его реакцией на неё.
+pub trait ActiveModelListener: Send + Sync {
+    /// Активной стала `model`; `None` — активной модели больше нет.
+    fn on_active_model_changed(&self, model: Option<LoadedModelSnapshot>);
+}
+
 /// Менеджер моделей с ленивой загрузкой.
 pub struct ModelManager {
     root_dir: PathBuf,
     device: Device,
-    inner: RwLock<Option<LoadedModel>>,
+    pool: RwLock<ModelPool<LoadedModel>>,
+    draft: RwLock<Option<DraftSlot>>,
+    active_listener: RwLock<Option<Arc<dyn ActiveModelListener>>>,
+    /// Счётчик загрузок из дескрипторов и буферов для их идентификаторов в пуле.
+    source_sequence: AtomicU64,
+    max_resident_models: RwLock<usize>,
+    catalog: ModelCatalog,
+    store: ModelStore,
+    context_length: RwLock<usize>,
+    memory_budget: RwLock<Option<u64>>,
+    tokenizer_override: RwLock<Option<PathBuf>>,
+    gguf_limits: RwLock<GgufLimits>,
+    load_cancellation: CancellationGroup,
+    verify_cancellation: CancellationGroup,
+    download_mirrors: RwLock<Vec<String>>,
+    download_cancellation: CancellationGroup,
+    conversion_cancellation: CancellationGroup,
 }
 
 impl ModelManager {
@@ -108,8 +274,214 @@ impl ModelManager {
         Self {
             root_dir: root_dir.as_ref().to_path_buf(),
             device,
-            inner: RwLock::new(None),
+            pool: RwLock::new(ModelPool::default()),
+            draft: RwLock::new(None),
+            active_listener: RwLock::new(None),
+            source_sequence: AtomicU64::new(0),
+            max_resident_models: RwLock::new(DEFAULT_MAX_RESIDENT_MODELS),
+            catalog: ModelCatalog::new(&root_dir),
+            store: ModelStore::new(root_dir.as_ref()),
+            context_length: RwLock::new(DEFAULT_CONTEXT_LENGTH),
+            memory_budget: RwLock::new(None),
+            tokenizer_override: RwLock::new(None),
+            gguf_limits: RwLock::new(GgufLimits::default()),
+            load_cancellation: CancellationGroup::default(),
+            verify_cancellation: CancellationGroup::default(),
+            download_mirrors: RwLock::new(vec![DEFAULT_MIRROR.to_string()]),
+            download_cancellation: CancellationGroup::default(),
+            conversion_cancellation: CancellationGroup::default(),
+        }
+    }
+
+    /// Задаёт длину контекста, под которую оценивается KV-кеш.
+    pub fn set_context_length(&self, context_length: usize) {
+        *self.context_length.write() = context_length;
+    }
+
+    /// Возвращает длину контекста, под которую оценивается KV-кеш.
+    pub fn context_length(&self) -> usize {
+        *self.context_length.read()
+    }
+
+    /// Задаёт бюджет памяти в байтах для всех загруженных моделей; `None` отключает проверку.
+    pub fn set_memory_budget(&self, budget: Option<u64>) {
+        *self.memory_budget.write() = budget;
+    }
+
+    /// Возвращает текущий бюджет памяти.
+    pub fn memory_budget(&self) -> Option<u64> {
+        *self.memory_budget.read()
+    }
+
+    /// Задаёт наблюдателя за сменой активной модели.
+    pub fn set_active_model_listener(&self, listener: Option<Arc<dyn ActiveModelListener>>) {
+        *self.active_listener.write() = listener;
+    }
+
+    /// Изменяет пул; если активная модель сменилась, наблюдатель узнаёт об этом
+    /// до снятия блокировки.
+    fn update_pool<R>(&self, change: impl FnOnce(&mut ModelPool<LoadedModel>) -> R) -> R {
+        let mut pool = self.pool.write();
+        // Копия держит прежние веса, поэтому их адрес не достанется новой модели
+        let before = pool.active().map(|loaded| loaded.model.clone());
+        let result = change(&mut pool);
+        let changed = match (&before, pool.active()) {
+            (Some(before), Some(after)) => !before.same(&after.model),
+            (before, after) => before.is_some() != after.is_some(),
+        };
+        if changed {
+            if let Some(listener) = self.active_listener.read().as_ref() {
+                listener.on_active_model_changed(pool.active().map(LoadedModelSnapshot::new));
+            }
+        }
+        result
+    }
+
+    /// Задаёт, сколько моделей может одновременно находиться в памяти (не меньше одной).
+    /// Лишние модели выгружаются, начиная с давно не использованных.
+    pub fn set_max_resident_models(&self, max_models: usize) {
+        let max_models = max_models.max(1);
+        *self.max_resident_models.write() = max_models;
+        self.update_pool(|pool| {
+            let active = pool.active_id().map(str::to_string);
+            pool.shrink_to(max_models, active.as_deref());
+        });
+    }
+
+    /// Сколько моделей может одновременно находиться в памяти.
+    pub fn max_resident_models(&self) -> usize {
+        *self.max_resident_models.read()
+    }
+
+    /// Задаёт путь к токенизатору, который используется вместо найденного рядом
+    /// с моделью или встроенного в GGUF; `None` возвращает автоматический выбор.
+    pub fn set_tokenizer_override(&self, path: Option<PathBuf>) {
+        *self.tokenizer_override.write() = path;
+    }
+
+    /// Возвращает заданный путь к токенизатору.
+    pub fn tokenizer_override(&self) -> Option<PathBuf> {
+        self.tokenizer_override.read().clone()
+    }
+
+    /// Задаёт ограничения для разбора заголовков GGUF при загрузке моделей.
+    pub fn set_gguf_limits(&self, limits: GgufLimits) {
+        *self.gguf_limits.write() = limits;
+    }
+
+    /// Текущие ограничения для разбора заголовков GGUF.
+    pub fn gguf_limits(&self) -> GgufLimits {
+        *self.gguf_limits.read()
+    }
+
+    /// Задаёт базовые адреса зеркал Hugging Face в порядке приоритета.
+    /// Пустой список возвращает основной адрес.
+    pub fn set_download_mirrors(&self, mirrors: Vec<String>) {
+        let mirrors = if mirrors.is_empty() {
+            vec![DEFAULT_MIRROR.to_string()]
+        } else {
+            mirrors
+        };
+        *self.download_mirrors.write() = mirrors;
+    }
+
+    /// Текущие зеркала для загрузки моделей.
+    pub fn download_mirrors(&self) -> Vec<String> {
+        self.download_mirrors.read().clone()
+    }
+
+    /// Оценивает память, необходимую для загрузки GGUF файла.
+    pub fn estimate_memory(&self, model_path: &Path) -> Result<MemoryEstimate, ModelManagerError> {
+        let gguf = MappedGguf::open(model_path)?;
+        Ok(MemoryEstimate::from_content(
+            &gguf.content,
+            self.context_length(),
+        ))
+    }
+
+    /// Подбирает квантование, помещающееся в доступную RAM устройства.
+    pub fn recommend_quantization(
+        &self,
+        candidates: &[PathBuf],
+        available_ram: u64,
+    ) -> Result<QuantizationRecommendation, ModelManagerError> {
+        let estimates = candidates
+            .iter()
+            .map(|path| Ok((path.clone(), self.estimate_memory(path)?)))
+            .collect::<Result<Vec<_>, ModelManagerError>>()?;
+        Ok(recommend_quantization(estimates, available_ram))
+    }
+
+    /// Отказывает в загрузке, если оценка памяти превышает бюджет; возвращает оценку.
+    fn check_memory_budget(
+        &self,
+        content: &gguf_file::Content,
+        reserved: u64,
+    ) -> Result<u64, ModelManagerError> {
+        let estimate = MemoryEstimate::from_content(content, self.context_length());
+        self.check_required_memory(estimate.total_bytes(), reserved)
+    }
+
+    /// Сравнивает требуемую память с бюджетом, если он задан. `reserved` — память
+    /// моделей, которые не будут выгружены ради новой.
+    fn check_required_memory(
+        &self,
+        required: u64,
+        reserved: u64,
+    ) -> Result<u64, ModelManagerError> {
+        let Some(budget) = self.memory_budget() else {
+            return Ok(required);
+        };
+        log::info!(
+            "Оценка памяти модели: {} байт, занято {} байт (бюджет {} байт)",
+            required,
+            reserved,
+            budget
+        );
+        let total = required.saturating_add(reserved);
+        if total > budget {
+            return Err(ModelManagerError::InsufficientMemory {
+                required: total,
+                budget,
+            });
         }
+        Ok(required)
+    }
+
+    /// Память черновой модели, которая не выгружается ради моделей пула.
+    fn draft_bytes(&self) -> u64 {
+        self.draft.read().as_ref().map_or(0, |draft| draft.bytes)
+    }
+
+    /// Возвращает корневую директорию моделей.
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
+    }
+
+    /// Возвращает список установленных моделей в корневой директории.
+    pub fn list_models(&self) -> Vec<ModelCatalogEntry> {
+        self.catalog.scan()
+    }
+
+    /// Возвращает сведения о GGUF файле по указанному пути.
+    pub fn model_info(&self, model_path: &Path) -> Result<ModelCatalogEntry, ModelManagerError> {
+        self.catalog.inspect(model_path)
+    }
+
+    /// Подключает внешнее хранилище моделей (Ollama, LM Studio) к каталогу.
+    pub fn add_external_store(&self, store: ExternalStore) {
+        log::info!("External model store: {:?} {:?}", store.kind, store.root);
+        self.catalog.add_store(store);
+    }
+
+    /// Находит GGUF файл репозитория `org/name` в кеше Hugging Face внутри корневой директории.
+    pub fn resolve_hf_model(
+        &self,
+        repo_id: &str,
+        revision: Option<&str>,
+        file_name: Option<&str>,
+    ) -> Result<PathBuf, ModelManagerError> {
+        hf_cache::resolve_model_file(&self.root_dir, repo_id, revision, file_name)
     }
 
     /// Загружает модель указанного типа.
@@ -118,423 +490,604 @@ impl ModelManager {
         model_type: ModelType,
         variant: &str,
     ) -> Result<(), ModelManagerError> {
-        let model_dir = self.model_dir(model_type, variant);
-        let model_path = self.validate_model_path(&model_dir)?;
-        let tokenizer_path = self.validate_tokenizer_path(&model_dir)?;
-
-        let mut tokenizer = tokenizers::Tokenizer::from_file(&tokenizer_path)
-            .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-        tokenizer.with_pre_tokenizer(Some(
-            tokenizers::pre_tokenizers::byte_level::ByteLevel::default(),
-        ));
-        tokenizer.with_decoder(Some(tokenizers::decoders::byte_level::ByteLevel::default()));
-
-        // Извлекаем chat template из метаданных GGUF файла
-        let chat_template = {
-            let mut reader = std::fs::File::open(&model_path)
-                .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-            let content = gguf_file::Content::read(&mut reader)
-                .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-            let metadata = &content.metadata;
-
-            // Ищем chat template в различных возможных ключах
-            metadata
-                .get("tokenizer.chat_template")
-                .or_else(|| metadata.get("chat_template"))
-                .or_else(|| metadata.get("tokenizer.ggml.chat_template"))
-                .and_then(|value| {
-                    if let gguf_file::Value::String(template) = value {
-                        Some(template.clone())
-                    } else {
-                        None
-                    }
-                })
-        };
-
-        let loaded_model = match model_type {
-            ModelType::Qwen3 => {
-                // Прочитаем файл заново для модели
-                let mut reader = std::fs::File::open(&model_path)
-                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-                let content = gguf_file::Content::read(&mut reader)
-                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-                let qwen = QuantizedQwen3::from_gguf(content, &mut reader, &self.device)
-                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-                ActiveModel::Qwen(Arc::new(std::sync::Mutex::new(qwen)))
-            }
-            ModelType::Gemma3 => {
-                // Прочитаем файл заново для модели
-                let mut reader = std::fs::File::open(&model_path)
-                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-                let content = gguf_file::Content::read(&mut reader)
-                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-                let gemma = QuantizedGemma3::from_gguf(content, &mut reader, &self.device)
-                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-                ActiveModel::Gemma(Arc::new(std::sync::Mutex::new(gemma)))
-            }
-        };
-
-        let loaded = LoadedModel {
-            model_type,
-            model: loaded_model,
-            tokenizer,
-            device: self.device.clone(),
-            chat_template,
-        };
+        self.load_model_with_progress(model_type, variant, None)
+    }
 
-        *self.inner.write() = Some(loaded);
+    /// Загружает модель указанного типа, сообщая о ходе загрузки.
+    /// Вариант проверяется как идентификатор хранилища (`qwen3/<variant>`).
+    /// При отмене или ошибке загруженные модели остаются без изменений.
+    pub fn load_model_with_progress(
+        &self,
+        model_type: ModelType,
+        variant: &str,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<(), ModelManagerError> {
+        let id = ModelId::for_variant(model_type, variant)?;
+        let model_path = self.store.resolve(&id)?;
+        self.load_file(&model_path, Some(model_type), progress)?;
         Ok(())
     }
 
+    /// Загружает модель хранилища по идентификатору; тип определяется по метаданным GGUF.
+    pub fn load_stored_model(
+        &self,
+        id: &ModelId,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
+        let model_path = self.store.resolve(id)?;
+        self.load_file(&model_path, None, progress)
+    }
+
     /// Загружает модель из указанного пути к GGUF файлу.
     /// Тип модели и токенизатор определяются автоматически из метаданных GGUF.
     pub fn load_model_from_path(&self, model_path: &Path) -> Result<ModelType, ModelManagerError> {
+        self.load_model_from_path_with_progress(model_path, None)
+    }
+
+    /// Загружает модель из GGUF файла, сообщая о ходе загрузки.
+    /// Модель, уже находящаяся в памяти, становится активной без повторной загрузки.
+    /// При отмене или ошибке загруженные модели остаются без изменений.
+    pub fn load_model_from_path_with_progress(
+        &self,
+        model_path: &Path,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
         log::info!(
             "ModelManager::load_model_from_path called with path: {:?}",
             model_path
         );
+        self.load_file(model_path, None, progress)
+    }
 
-        // Определяем тип модели по имени файла
-        let model_type = if model_path.to_string_lossy().to_lowercase().contains("qwen") {
-            log::info!("Detected model type: Qwen3");
-            ModelType::Qwen3
-        } else if model_path
-            .to_string_lossy()
-            .to_lowercase()
-            .contains("gemma")
-        {
-            log::info!("Detected model type: Gemma3");
-            ModelType::Gemma3
-        } else {
-            log::info!("Cannot determine model type from filename");
-            return Err(ModelManagerError::Initialization(
-                "Cannot determine model type from filename".to_string(),
-            ));
-        };
-
-        // Сначала читаем GGUF файл для извлечения метаданных и токенизатора
-        let mut reader = std::fs::File::open(model_path)
-            .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-        let content = gguf_file::Content::read(&mut reader)
-            .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-
-        // Извлекаем токенизатор из метаданных GGUF файла
-        let tokenizer =
-            Self::extract_tokenizer_from_gguf_metadata(&content.metadata).ok_or_else(|| {
-                ModelManagerError::TokenizerMissing(
-                    "No tokenizer found in GGUF metadata".to_string(),
-                )
-            })?;
-
-        // Извлекаем chat template из метаданных
-        let chat_template = content
-            .metadata
-            .get("tokenizer.chat_template")
-            .or_else(|| content.metadata.get("chat_template"))
-            .or_else(|| content.metadata.get("tokenizer.ggml.chat_template"))
-            .and_then(|value| {
-                if let gguf_file::Value::String(template) = value {
-                    Some(template.clone())
-                } else {
-                    None
-                }
-            });
+    /// Загружает модель из пути, файлового дескриптора или буфера в памяти.
+    /// Тип модели определяется по метаданным GGUF; для дескриптора и буфера токенизатор
+    /// берётся из явно заданного пути или метаданных.
+    pub fn load_model_from_source(
+        &self,
+        source: ModelSource,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
+        log::info!("ModelManager::load_model_from_source: {}", source.name());
+        self.load_source(source, None, progress)
+    }
 
-        // Теперь читаем файл заново для загрузки модели
-        let mut reader = std::fs::File::open(model_path)
-            .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
+    fn load_file(
+        &self,
+        model_path: &Path,
+        model_type: Option<ModelType>,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
+        self.load_source(
+            ModelSource::Path(model_path.to_path_buf()),
+            model_type,
+            progress,
+        )
+    }
 
-        let loaded_model = match model_type {
-            ModelType::Qwen3 => {
-                let qwen = QuantizedQwen3::from_gguf(content, &mut reader, &self.device)
-                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-                ActiveModel::Qwen(Arc::new(std::sync::Mutex::new(qwen)))
+    /// Общий путь загрузки. Без `model_type` тип определяется по метаданным и имени файла.
+    fn load_source(
+        &self,
+        source: ModelSource,
+        model_type: Option<ModelType>,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
+        let mut tracker = self.start_load(progress);
+        let model_path = source.path().map(Path::to_path_buf);
+        let source_name = source.name();
+        let canonical_path = model_path
+            .as_ref()
+            .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()));
+        // Дескриптор или буфер нельзя опознать повторно (разные участки одного APK,
+        // разные буферы), поэтому каждая такая загрузка — отдельная модель пула
+        let resident_id = match &canonical_path {
+            Some(path) => path.to_string_lossy().into_owned(),
+            None => {
+                let sequence = self.source_sequence.fetch_add(1, Ordering::Relaxed);
+                format!("{source_name}#{sequence}")
             }
-            ModelType::Gemma3 => {
-                // Прочитаем структуру заново, так как чтение consume reader.
-                let mut reader = std::fs::File::open(model_path)
-                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-                let content = gguf_file::Content::read(&mut reader)
-                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-                let gemma = QuantizedGemma3::from_gguf(content, &mut reader, &self.device)
-                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
-                ActiveModel::Gemma(Arc::new(std::sync::Mutex::new(gemma)))
+        };
+        if canonical_path.is_some() {
+            if let Some(model_type) = self.reuse_resident(&resident_id, &mut tracker) {
+                return Ok(model_type);
             }
+        }
+
+        let (loaded, required) =
+            self.read_gguf_model(source, model_type, self.draft_bytes(), &mut tracker)?;
+        let model_type = loaded.model_type;
+        self.insert_resident(resident_id, loaded, required);
+        tracker.enter(LoadPhase::Done);
+        Ok(model_type)
+    }
+
+    /// Загружает черновую модель для спекулятивного декодирования и заменяет ею прежнюю.
+    /// Модель не попадает в пул и не становится активной: у неё собственные веса и KV-кеш,
+    /// даже если тот же файл уже загружен как основная модель. Словарь черновой модели
+    /// должен совпадать со словарём активной. Её память входит в бюджет: ради неё
+    /// выгружаются давно не использованные модели пула, кроме активной.
+    pub fn load_draft_model(
+        &self,
+        model_path: &Path,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<LoadedModelSnapshot, ModelManagerError> {
+        let mut tracker = self.start_load(progress);
+        let source = ModelSource::Path(model_path.to_path_buf());
+        let (loaded, bytes) =
+            self.read_gguf_model(source, None, self.active_bytes(), &mut tracker)?;
+        let snapshot = LoadedModelSnapshot::new(&loaded);
+        if let Some(target) = self.current_model() {
+            check_compatible(&target, &snapshot)
+                .map_err(ModelManagerError::IncompatibleDraftModel)?;
+        }
+        *self.draft.write() = Some(DraftSlot {
+            path: loaded.path,
+            bytes,
+        });
+        let budget = self.memory_budget();
+        self.update_pool(|pool| {
+            let active = pool.active_id().map(str::to_string);
+            pool.evict_for(bytes, budget, active.as_deref());
+        });
+        tracker.enter(LoadPhase::Done);
+        Ok(snapshot)
+    }
+
+    /// Память активной модели пула.
+    fn active_bytes(&self) -> u64 {
+        let pool = self.pool.read();
+        let active = pool.active_id();
+        let bytes = pool
+            .iter()
+            .find(|(id, _, _)| Some(*id) == active)
+            .map_or(0, |(_, _, bytes)| bytes);
+        bytes
+    }
+
+    /// Забывает черновую модель; её файлы снова можно изменять.
+    pub fn unload_draft_model(&self) {
+        *self.draft.write() = None;
+    }
+
+    /// Читает GGUF модель; возвращает её и оценку занимаемой памяти.
+    fn read_gguf_model(
+        &self,
+        source: ModelSource,
+        model_type: Option<ModelType>,
+        reserved: u64,
+        tracker: &mut LoadTracker,
+    ) -> Result<(LoadedModel, u64), ModelManagerError> {
+        let model_path = source.path().map(Path::to_path_buf);
+        let source_name = source.name();
+        let canonical_path = model_path
+            .as_ref()
+            .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()));
+
+        // Заголовок GGUF разбирается один раз; веса читаются из того же mmap
+        tracker.enter(LoadPhase::Header);
+        let gguf = MappedGguf::from_source(source, &self.gguf_limits())?;
+        let model_type = match model_type {
+            Some(model_type) => model_type,
+            None => detect_model_type(Path::new(&source_name), &gguf.content.metadata)?,
         };
+        let required = self.check_memory_budget(&gguf.content, reserved)?;
+        tracker.set_totals(&gguf.content);
+        Self::check_cancelled(tracker)?;
+
+        // Токенизатор и chat template: явный путь, файлы рядом с моделью или метаданные GGUF
+        tracker.enter(LoadPhase::Tokenizer);
+        let resolved = resolve_tokenizer(
+            model_path.as_deref(),
+            self.tokenizer_override().as_deref(),
+            &gguf.content.metadata,
+        )?;
+        Self::check_cancelled(tracker)?;
+
+        let loaded_model = self.build_model(model_type, gguf, tracker)?;
 
         let loaded = LoadedModel {
             model_type,
             model: loaded_model,
-            tokenizer,
+            tokenizer: resolved.tokenizer,
             device: self.device.clone(),
-            chat_template,
+            chat_template: resolved.chat_template,
+            path: canonical_path,
         };
 
-        *self.inner.write() = Some(loaded);
-        Ok(model_type)
+        Ok((loaded, required))
     }
 
-    /// Извлекает токенизатор из метаданных GGUF файла.
-    fn extract_tokenizer_from_gguf_metadata(
-        metadata: &std::collections::HashMap<String, gguf_file::Value>,
-    ) -> Option<tokenizers::Tokenizer> {
-        // Отладка: выводим все ключи метаданных
-        log::info!("GGUF metadata keys (total: {}):", metadata.len());
-        for key in metadata.keys() {
-            log::info!("  Key: {}", key);
-            // Выведем значения для всех ключей (не только токенизаторных)
-            match metadata.get(key) {
-                Some(gguf_file::Value::String(s)) => log::info!("    String value: {}", s),
-                Some(gguf_file::Value::Array(arr)) if arr.len() <= 10 => {
-                    log::info!(
-                        "    Array with {} elements: {:?}",
-                        arr.len(),
-                        arr.iter()
-                            .take(10)
-                            .map(|v| match v {
-                                gguf_file::Value::String(s) =>
-                                    format!("'{}'", s.chars().take(30).collect::<String>()),
-                                gguf_file::Value::U32(n) => format!("U32:{}", n),
-                                gguf_file::Value::F32(n) => format!("F32:{:.3}", n),
-                                gguf_file::Value::U64(n) => format!("U64:{}", n),
-                                _ => format!("{:?}", v),
-                            })
-                            .collect::<Vec<_>>()
-                    );
-                }
-                Some(gguf_file::Value::Array(arr)) => {
-                    log::info!("    Array with {} elements (showing first 3)", arr.len());
-                    for (i, v) in arr.iter().take(3).enumerate() {
-                        match v {
-                            gguf_file::Value::String(s) => log::info!(
-                                "      [{}]: '{}'",
-                                i,
-                                s.chars().take(50).collect::<String>()
-                            ),
-                            gguf_file::Value::U32(n) => log::info!("      [{}]: U32:{}", i, n),
-                            gguf_file::Value::F32(n) => log::info!("      [{}]: F32:{:.3}", i, n),
-                            _ => log::info!("      [{}]: {:?}", i, v),
-                        }
-                    }
-                }
-                Some(v) => log::info!("    Value: {:?}", v),
-                None => {}
-            }
-        }
-
-        // Пробуем разные возможные ключи для токенов (включая SentencePiece формат)
-        let mut tokens = metadata
-            .get("tokenizer.ggml.tokens")
-            .or_else(|| metadata.get("tokenizer.huggingface.tokenizer.tokens"))
-            .or_else(|| metadata.get("spm.vocab"))
-            .or_else(|| metadata.get("vocab"))
-            .or_else(|| metadata.get("tokenizer.tokens"))
-            .or_else(|| metadata.get("tokenizer.spm.vocab"));
-
-        // Если не нашли стандартные ключи, ищем любые ключи с "token"
-        if tokens.is_none() {
-            for key in metadata.keys() {
-                if key.to_lowercase().contains("token")
-                    && (key.to_lowercase().ends_with("vocab")
-                        || key.to_lowercase().ends_with("tokens")
-                        || key.to_lowercase().contains("vocab")
-                        || key.to_lowercase().contains("tokens"))
-                {
-                    log::info!("Found potential token key: {}", key);
-                    tokens = metadata.get(key);
-                    if tokens.is_some() {
-                        break;
-                    }
-                }
+    /// Загружает неквантованную модель Hugging Face из директории с `config.json`,
+    /// `tokenizer.json` и `*.safetensors`. Без `dtype` веса загружаются в `torch_dtype`,
+    /// а уже находящаяся в памяти модель становится активной без повторной загрузки.
+    pub fn load_safetensors_model(
+        &self,
+        model_dir: &Path,
+        dtype: Option<DType>,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ModelType, ModelManagerError> {
+        let mut tracker = self.start_load(progress);
+
+        tracker.enter(LoadPhase::Header);
+        let checkpoint = SafetensorsCheckpoint::open(model_dir, dtype)?;
+        let dir = checkpoint.dir();
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let resident_id = dir.to_string_lossy().into_owned();
+        if dtype.is_none() {
+            if let Some(model_type) = self.reuse_resident(&resident_id, &mut tracker) {
+                return Ok(model_type);
             }
         }
+        let model_type = checkpoint.model_type();
+        let required = self.check_required_memory(
+            checkpoint.estimate_bytes(self.context_length())?,
+            self.draft_bytes(),
+        )?;
+        Self::check_cancelled(&tracker)?;
+
+        tracker.enter(LoadPhase::Tokenizer);
+        let resolved = resolve_tokenizer(
+            Some(&checkpoint.dir().join(CONFIG_FILE_NAME)),
+            self.tokenizer_override().as_deref(),
+            &HashMap::new(),
+        )?;
+        Self::check_cancelled(&tracker)?;
+
+        tracker.enter(LoadPhase::Tensors);
+        let model = checkpoint.build(&self.device)?;
+        Self::check_cancelled(&tracker)?;
 
-        let tokens = match tokens {
-            Some(gguf_file::Value::Array(arr)) => {
-                log::info!("Found token array with {} elements", arr.len());
-                let mut tokens = Vec::new();
-                for value in arr {
-                    if let gguf_file::Value::String(s) = value {
-                        let processed = if s == "<0x0A>" {
-                            "\n".to_string()
-                        } else {
-                            s.clone()
-                        };
-                        tokens.push(processed);
-                    }
-                }
-                log::info!("Processed {} tokens", tokens.len());
-                tokens
-            }
-            _ => {
-                log::info!("No token array found, trying serialized tokenizer");
-                // Пробуем найти сериализованный токенизатор (JSON) - разные возможные ключи
-                let possible_keys = vec![
-                    "tokenizer.ggml.tokenizer",
-                    "tokenizer.huggingface.tokenizer",
-                    "tokenizer",
-                    "tokenizer_config",
-                    "tokenizer.json",
-                ];
-
-                for key in possible_keys {
-                    if let Some(gguf_file::Value::String(json_str)) = metadata.get(key) {
-                        log::info!("Found serialized tokenizer JSON in key: {}", key);
-                        match tokenizers::Tokenizer::from_bytes(json_str.as_bytes()) {
-                            Ok(tokenizer) => {
-                                log::info!("Successfully parsed tokenizer from key: {}", key);
-                                return Some(tokenizer);
-                            }
-                            Err(e) => {
-                                log::error!(
-                                    "Failed to parse serialized tokenizer from {}: {}",
-                                    key,
-                                    e
-                                );
-                            }
-                        }
-                    }
-                }
-
-                log::info!("No serialized tokenizer found in any key");
-                return None;
-            }
+        let loaded = LoadedModel {
+            model_type,
+            model,
+            tokenizer: resolved.tokenizer,
+            device: self.device.clone(),
+            chat_template: resolved.chat_template,
+            path: Some(dir),
         };
 
-        // Извлекаем scores (могут отсутствовать)
-        let scores = match metadata.get("tokenizer.ggml.scores") {
-            Some(gguf_file::Value::Array(arr)) => {
-                let mut scores = Vec::new();
-                for value in arr {
-                    if let gguf_file::Value::F32(s) = value {
-                        scores.push(*s as f64);
-                    }
-                }
-                scores
-            }
-            _ => {
-                log::info!("No tokenizer.ggml.scores found, using default scores");
-                // Создаем scores по умолчанию (все равны 0.0)
-                vec![0.0; tokens.len()]
-            }
+        self.insert_resident(resident_id, loaded, required);
+        tracker.enter(LoadPhase::Done);
+        Ok(model_type)
+    }
+
+    /// Копирует GGUF файл в хранилище под идентификатором `id`.
+    pub fn import_model(&self, source: &Path, id: &ModelId) -> Result<PathBuf, ModelManagerError> {
+        self.store.import(source, id)
+    }
+
+    /// Переименовывает модель хранилища; загруженную модель переименовать нельзя.
+    pub fn rename_model(&self, from: &ModelId, to: &ModelId) -> Result<(), ModelManagerError> {
+        // Блокировка удерживается до конца операции, чтобы загрузка не завершилась между
+        // проверкой и переносом директории
+        let pool = self.pool.read();
+        self.ensure_not_loaded(&pool, from)?;
+        self.store.rename(from, to)
+    }
+
+    /// Удаляет модель из хранилища; загруженную модель удалить нельзя.
+    pub fn delete_model(&self, id: &ModelId) -> Result<(), ModelManagerError> {
+        let pool = self.pool.read();
+        self.ensure_not_loaded(&pool, id)?;
+        self.store.delete(id)
+    }
+
+    /// Читает метаданные GGUF модели.
+    pub fn model_metadata(
+        &self,
+        model_path: &Path,
+    ) -> Result<HashMap<String, gguf_file::Value>, ModelManagerError> {
+        gguf_editor::read_metadata(model_path, &self.gguf_limits())
+    }
+
+    /// Изменяет метаданные GGUF модели, не затрагивая веса. Загруженную модель изменить
+    /// нельзя, как и модели внешних хранилищ и кеша Hugging Face: их файлы опознаются
+    /// по содержимому (блобы по sha256, симлинки снапшотов).
+    pub fn edit_metadata(
+        &self,
+        model_path: &Path,
+        edits: &[MetadataEdit],
+    ) -> Result<PathBuf, ModelManagerError> {
+        let pool = self.pool.read();
+        let header_file = gguf_editor::header_file(model_path);
+        self.ensure_own_file(&header_file)?;
+        let same = |a: &Path, b: &Path| match (a.canonicalize(), b.canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => a == b,
         };
+        let in_use = self
+            .loaded_paths(&pool)
+            .iter()
+            .any(|path| same(&gguf_editor::header_file(path), &header_file));
+        if in_use {
+            return Err(ModelManagerError::ModelInUse(
+                model_path.to_string_lossy().into_owned(),
+            ));
+        }
+        gguf_editor::edit_metadata(model_path, edits, &self.gguf_limits())
+    }
+
+    /// Отказывает для файлов внешних хранилищ, кеша Hugging Face и симлинков на них.
+    fn ensure_own_file(&self, file: &Path) -> Result<(), ModelManagerError> {
+        let external = || ModelManagerError::ExternalModel(file.to_string_lossy().into_owned());
+        let is_symlink = std::fs::symlink_metadata(file).is_ok_and(|meta| meta.is_symlink());
+        if is_symlink || hf_cache::repo_id_from_path(file).is_some() {
+            return Err(external());
+        }
+        let canonical = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        let in_store = self.catalog.stores().iter().any(|store| {
+            let root = store
+                .root
+                .canonicalize()
+                .unwrap_or_else(|_| store.root.clone());
+            canonical.starts_with(root)
+        });
+        if in_store || hf_cache::repo_id_from_path(&canonical).is_some() {
+            return Err(external());
+        }
+        Ok(())
+    }
+
+    /// Скачивает файл модели в хранилище, продолжая прерванную загрузку.
+    /// Ход загрузки и проверки хеша передаётся в `progress`; отмена — через `cancel_download`.
+    pub fn download_model(
+        &self,
+        request: &DownloadRequest,
+        client: &dyn HttpClient,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<DownloadReport, ModelManagerError> {
+        let mut tracker = LoadTracker::new(progress, self.download_cancellation.start());
+        let mirrors = self.download_mirrors();
+        let report =
+            model_download::download(client, &mirrors, request, &self.store, &mut tracker)?;
+        tracker.enter(LoadPhase::Done);
+        Ok(report)
+    }
+
+    /// Запрашивает отмену текущей загрузки из сети. Скачанная часть сохраняется.
+    pub fn cancel_download(&self) {
+        log::info!("Model download cancellation requested");
+        self.download_cancellation.cancel();
+    }
 
-        // Выбираем тип токенизатора: BPE если есть merges, иначе Unigram
-        let tokenizer = if let Some(gguf_file::Value::Array(merges_arr)) =
-            metadata.get("tokenizer.ggml.merges")
+    /// Конвертирует GGUF или модель safetensors в новый GGUF в хранилище под `id`.
+    /// Ход конвертации передаётся в `progress`; отмена — через `cancel_conversion`.
+    pub fn convert_model(
+        &self,
+        input: &Path,
+        id: &ModelId,
+        plan: &QuantizationPlan,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<ConversionReport, ModelManagerError> {
+        let mut tracker = LoadTracker::new(progress, self.conversion_cancellation.start());
+        let staging = self.store.staging_file()?;
+        let mut report =
+            model_convert::convert(input, &staging, plan, &self.gguf_limits(), &mut tracker)?;
+        report.output = self.store.commit(&staging, id).inspect_err(|_| {
+            let _ = std::fs::remove_file(&staging);
+        })?;
+        tracker.enter(LoadPhase::Done);
+        Ok(report)
+    }
+
+    /// Запрашивает отмену текущей конвертации.
+    pub fn cancel_conversion(&self) {
+        log::info!("Model conversion cancellation requested");
+        self.conversion_cancellation.cancel();
+    }
+
+    /// Хранилище моделей в корневой директории.
+    pub fn store(&self) -> &ModelStore {
+        &self.store
+    }
+
+    fn ensure_not_loaded(
+        &self,
+        pool: &ModelPool<LoadedModel>,
+        id: &ModelId,
+    ) -> Result<(), ModelManagerError> {
+        let model_dir = self.store.contained(&self.store.model_dir(id))?;
+        if self
+            .loaded_paths(pool)
+            .iter()
+            .any(|path| path.starts_with(&model_dir))
         {
-            log::info!("Creating BPE tokenizer with {} merges", merges_arr.len());
-
-            // Извлекаем merges для BPE
-            let mut merges = Vec::new();
-            for value in merges_arr {
-                if let gguf_file::Value::String(s) = value {
-                    // Merge правило имеет формат "a b"
-                    let parts: Vec<&str> = s.split(' ').collect();
-                    if parts.len() == 2 {
-                        merges.push((parts[0].to_string(), parts[1].to_string()));
-                    }
-                }
-            }
+            return Err(ModelManagerError::ModelInUse(id.to_string()));
+        }
+        Ok(())
+    }
 
-            // Создаем vocab как HashMap<String, u32> для BPE
-            let mut vocab = std::collections::HashMap::new();
-            for (i, token) in tokens.into_iter().enumerate() {
-                vocab.insert(token, i as u32);
-            }
+    /// Пути моделей пула и черновой модели.
+    fn loaded_paths(&self, pool: &ModelPool<LoadedModel>) -> Vec<PathBuf> {
+        let draft = self.draft.read();
+        pool.iter()
+            .filter_map(|(_, loaded, _)| loaded.path.clone())
+            .chain(draft.as_ref().and_then(|draft| draft.path.clone()))
+            .collect()
+    }
 
-            // Создаем JSON конфигурацию для токенизатора
-            let tokenizer_config = serde_json::json!({
-                "version": "1.0",
-                "model": {
-                    "type": "BPE",
-                    "vocab": vocab,
-                    "merges": merges
-                },
-                "added_tokens": []
-            });
+    /// Проверяет GGUF файл: структуру и, если известен ожидаемый хеш, SHA-256.
+    /// Ход подсчёта хеша передаётся в `progress`; отмена — через `cancel_verification`.
+    pub fn verify_model(
+        &self,
+        model_path: &Path,
+        expected_sha256: Option<&str>,
+        progress: Option<Arc<dyn LoadProgressCallback>>,
+    ) -> Result<VerifyReport, ModelManagerError> {
+        let mut tracker = LoadTracker::new(progress, self.verify_cancellation.start());
+        let report = model_integrity::verify_model(model_path, expected_sha256, &mut tracker)?;
+        tracker.enter(LoadPhase::Done);
+        Ok(report)
+    }
 
-            let tokenizer_bytes = tokenizer_config.to_string().into_bytes();
-            let mut tokenizer = tokenizers::Tokenizer::from_bytes(tokenizer_bytes).ok()?;
-            tokenizer.with_pre_tokenizer(Some(
-                tokenizers::pre_tokenizers::byte_level::ByteLevel::default(),
-            ));
-            tokenizer.with_decoder(Some(tokenizers::decoders::byte_level::ByteLevel::default()));
-            tokenizer
-        } else {
-            log::info!("Creating Unigram tokenizer (no merges found)");
-
-            // Создаем простой токенизатор с Unigram моделью
-            use std::collections::HashMap;
-            let mut vocab_with_scores = HashMap::new();
-            for (i, token) in tokens.into_iter().enumerate() {
-                let score = scores.get(i).copied().unwrap_or(0.0);
-                vocab_with_scores.insert(token, (i as u32, score));
+    /// Запрашивает отмену текущей проверки целостности.
+    pub fn cancel_verification(&self) {
+        log::info!("Model verification cancellation requested");
+        self.verify_cancellation.cancel();
+    }
+
+    /// Запрашивает отмену текущих загрузок модели, в том числе черновой.
+    pub fn cancel_load(&self) {
+        log::info!("Model loading cancellation requested");
+        self.load_cancellation.cancel();
+    }
+
+    fn start_load(&self, progress: Option<Arc<dyn LoadProgressCallback>>) -> LoadTracker {
+        LoadTracker::new(progress, self.load_cancellation.start())
+    }
+
+    fn check_cancelled(tracker: &LoadTracker) -> Result<(), ModelManagerError> {
+        if tracker.is_cancelled() {
+            return Err(ModelManagerError::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Делает модель пула активной, если она уже загружена.
+    fn reuse_resident(&self, id: &str, tracker: &mut LoadTracker) -> Option<ModelType> {
+        let model_type = self.update_pool(|pool| {
+            if !pool.activate(id) {
+                return None;
             }
+            pool.active().map(|loaded| loaded.model_type)
+        })?;
+        log::info!(
+            "Модель '{}' уже загружена, используется без повторной загрузки",
+            id
+        );
+        tracker.enter(LoadPhase::Done);
+        Some(model_type)
+    }
 
-            // Конвертируем HashMap в Vec<(String, f64)> для Unigram::from
-            let vocab_vec: Vec<(String, f64)> = vocab_with_scores
-                .into_iter()
-                .map(|(token, (_id, score))| (token, score))
-                .collect();
-
-            let unigram =
-                tokenizers::models::unigram::Unigram::from(vocab_vec, None, false).ok()?;
-            let mut tokenizer = tokenizers::Tokenizer::new(unigram);
-            tokenizer.with_pre_tokenizer(Some(
-                tokenizers::pre_tokenizers::byte_level::ByteLevel::default(),
-            ));
-            tokenizer.with_decoder(Some(tokenizers::decoders::byte_level::ByteLevel::default()));
-            tokenizer
-        };
+    /// Добавляет построенную модель в пул. Давно не использованные модели выгружаются
+    /// только теперь, чтобы отменённая или неудачная загрузка не затронула пул; пока
+    /// веса строятся, бюджет памяти может ненадолго превышаться. Активная модель
+    /// выгружается по бюджету лишь после того, как её заменит новая. Память черновой
+    /// модели считается занятой.
+    fn insert_resident(&self, id: String, loaded: LoadedModel, bytes: u64) {
+        let budget = self.memory_budget();
+        let max_models = self.max_resident_models();
+        let draft_bytes = self.draft_bytes();
+        self.update_pool(|pool| {
+            let active = pool.active_id().map(str::to_string);
+            pool.evict_for(bytes + draft_bytes, budget, active.as_deref());
+            pool.insert(id.clone(), loaded, bytes, max_models);
+            pool.evict_for(draft_bytes, budget, Some(&id));
+        });
+    }
 
-        Some(tokenizer)
+    /// Строит веса модели из уже разобранного GGUF.
+    /// Частично прочитанные тензоры освобождаются при ошибке или отмене.
+    fn build_model(
+        &self,
+        model_type: ModelType,
+        gguf: MappedGguf,
+        tracker: &mut LoadTracker,
+    ) -> Result<ActiveModel, ModelManagerError> {
+        tracker.enter(LoadPhase::Tensors);
+        let MappedGguf {
+            content,
+            mut reader,
+        } = gguf;
+        let mut reader = ProgressReader::new(&mut reader, &content, tracker);
+        let model = match model_type {
+            ModelType::Qwen3 => {
+                QuantizedQwen3::from_gguf(content, &mut reader, &self.device).map(|qwen| {
+                    ActiveModel::Qwen(Arc::new(std::sync::Mutex::new(Qwen3Weights::Quantized(
+                        qwen,
+                    ))))
+                })
+            }
+            ModelType::Gemma3 => QuantizedGemma3::from_gguf(content, &mut reader, &self.device)
+                .map(|gemma| {
+                    ActiveModel::Gemma(Arc::new(std::sync::Mutex::new(Gemma3Weights::Quantized(
+                        gemma,
+                    ))))
+                }),
+        };
+        match model {
+            Ok(model) => Ok(model),
+            Err(_) if tracker.is_cancelled() => Err(ModelManagerError::Cancelled),
+            Err(e) => Err(ModelManagerError::Initialization(e.to_string())),
+        }
     }
 
-    /// Выгружает текущую модель.
+    /// Выгружает активную модель; остальные модели пула остаются в памяти.
     pub fn unload_model(&self) {
-        *self.inner.write() = None;
+        self.update_pool(|pool| {
+            if let Some(id) = pool.active_id().map(str::to_string) {
+                pool.remove(&id);
+            }
+        });
+    }
+
+    /// Выгружает все модели.
+    pub fn unload_all_models(&self) {
+        self.update_pool(|pool| pool.clear());
+    }
+
+    /// Выгружает модель пула по идентификатору.
+    pub fn unload_resident_model(&self, id: &str) -> Result<(), ModelManagerError> {
+        self.update_pool(|pool| pool.remove(id))
+            .map(drop)
+            .ok_or_else(|| ModelManagerError::ModelNotResident(id.to_string()))
     }
 
-    /// Проверяет, загружена ли модель.
+    /// Проверяет, загружена ли активная модель.
     pub fn is_loaded(&self) -> bool {
-        self.inner.read().is_some()
+        self.pool.read().active().is_some()
     }
 
     /// Возвращает активную модель, если она загружена.
     pub fn current_model(&self) -> Option<LoadedModelSnapshot> {
-        self.inner.read().as_ref().map(LoadedModelSnapshot::new)
+        self.pool.read().active().map(LoadedModelSnapshot::new)
     }
 
-    fn model_dir(&self, model_type: ModelType, variant: &str) -> PathBuf {
-        let folder = match model_type {
-            ModelType::Qwen3 => "qwen3",
-            ModelType::Gemma3 => "gemma3",
-        };
-        self.root_dir.join(folder).join(variant)
+    /// Делает модель пула активной.
+    pub fn activate_model(&self, id: &str) -> Result<ModelType, ModelManagerError> {
+        self.update_pool(|pool| {
+            if !pool.activate(id) {
+                return None;
+            }
+            pool.active().map(|loaded| loaded.model_type)
+        })
+        .ok_or_else(|| ModelManagerError::ModelNotResident(id.to_string()))
     }
 
-    fn validate_model_path(&self, model_dir: &Path) -> Result<PathBuf, ModelManagerError> {
-        let gguf_path = model_dir.join("model.gguf");
-        if !gguf_path.exists() {
-            return Err(ModelManagerError::ModelFileMissing(
-                gguf_path.to_string_lossy().into_owned(),
-            ));
-        }
-        Ok(gguf_path)
+    /// Возвращает модель пула по идентификатору, не меняя активную.
+    pub fn resident_model(&self, id: &str) -> Result<LoadedModelSnapshot, ModelManagerError> {
+        self.pool
+            .write()
+            .get(id)
+            .map(LoadedModelSnapshot::new)
+            .ok_or_else(|| ModelManagerError::ModelNotResident(id.to_string()))
     }
 
-    fn validate_tokenizer_path(&self, model_dir: &Path) -> Result<PathBuf, ModelManagerError> {
-        let tokenizer = model_dir.join("tokenizer.json");
-        if !tokenizer.exists() {
-            return Err(ModelManagerError::TokenizerMissing(
-                tokenizer.to_string_lossy().into_owned(),
-            ));
-        }
-        Ok(tokenizer)
+    /// Модели в памяти, от недавно использованных к давно не использованным.
+    pub fn resident_models(&self) -> Vec<ResidentModel> {
+        let pool = self.pool.read();
+        let active = pool.active_id();
+        pool.iter()
+            .map(|(id, loaded, memory_bytes)| ResidentModel {
+                id: id.to_string(),
+                model_type: loaded.model_type.as_str(),
+                memory_bytes,
+                active: active == Some(id),
+            })
+            .collect()
     }
 }
 
+/// Модель, находящаяся в памяти.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResidentModel {
+    /// Идентификатор: канонический путь к модели или имя источника с номером загрузки.
+    pub id: String,
+    pub model_type: &'static str,
+    /// Оценка занимаемой памяти в байтах.
+    pub memory_bytes: u64,
+    pub active: bool,
+}
+
 /// Снимок загруженной модели для потокобезопасного доступа.
 #[derive(Clone)]
 pub struct LoadedModelSnapshot {
@@ -605,12 +1158,24 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_variant_cannot_escape_root() {
+        let tmp = tempdir().unwrap();
+        let manager = ModelManager::new(tmp.path().join("models"), Device::Cpu);
+        let result = manager.load_model(ModelType::Qwen3, "../../../sdcard/x");
+        assert!(matches!(result, Err(ModelManagerError::InvalidModelId(_))));
+    }
+
     #[test]
     fn test_missing_tokenizer_file() {
         let tmp = tempdir().unwrap();
         let model_dir = tmp.path().join("qwen3").join("0.6b");
-        fs::create_dir_all(&model_dir).unwrap();
-        fs::write(model_dir.join("model.gguf"), b"fake").unwrap();
+        // Ни tokenizer.json рядом с моделью, ни словаря в метаданных GGUF
+        crate::test_support::write_test_gguf(
+            &model_dir.join("model.gguf"),
+            &crate::test_support::basic_metadata("qwen3"),
+            &[("token_embd.weight", &[4, 4])],
+        );
 
         let manager = ModelManager::new(tmp.path(), Device::Cpu);
         let result = manager.load_model(ModelType::Qwen3, "0.6b");
@@ -619,4 +1184,304 @@ mod tests {
             Err(ModelManagerError::TokenizerMissing(_))
         ));
     }
+
+    #[test]
+    fn test_detects_model_type_from_architecture() {
+        let tmp = tempdir().unwrap();
+        // Блоб Ollama: ни расширения, ни имени семейства в пути
+        let blob_path = tmp.path().join("blobs").join("sha256-0123");
+        crate::test_support::write_test_gguf(
+            &blob_path,
+            &crate::test_support::basic_metadata("qwen3"),
+            &[("token_embd.weight", &[4, 4])],
+        );
+        let gguf = MappedGguf::open(&blob_path).unwrap();
+        assert_eq!(
+            detect_model_type(&blob_path, &gguf.content.metadata).unwrap(),
+            ModelType::Qwen3
+        );
+
+        let empty = HashMap::new();
+        assert_eq!(
+            detect_model_type(Path::new("gemma-3-1b.gguf"), &empty).unwrap(),
+            ModelType::Gemma3
+        );
+        assert!(detect_model_type(Path::new("model.gguf"), &empty).is_err());
+    }
+
+    #[test]
+    fn test_load_refused_over_memory_budget() {
+        let tmp = tempdir().unwrap();
+        let model_path = tmp.path().join("qwen3-test.gguf");
+        crate::test_support::write_test_gguf(
+            &model_path,
+            &crate::test_support::basic_metadata("qwen3"),
+            &[("token_embd.weight", &[64, 64])],
+        );
+
+        let manager = ModelManager::new(tmp.path(), Device::Cpu);
+        manager.set_memory_budget(Some(1024 * 1024));
+        let result = manager.load_model_from_path(&model_path);
+        assert!(matches!(
+            result,
+            Err(ModelManagerError::InsufficientMemory { budget, .. }) if budget == 1024 * 1024
+        ));
+        assert!(!manager.is_loaded());
+    }
+
+    struct CancelOnHeader(CancellationGroup);
+
+    impl LoadProgressCallback for CancelOnHeader {
+        fn on_progress(&self, progress: &crate::load_progress::LoadProgress) {
+            if progress.phase == LoadPhase::Header {
+                self.0.cancel();
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancelled_load_keeps_manager_unloaded() {
+        let tmp = tempdir().unwrap();
+        let model_path = tmp.path().join("qwen3-test.gguf");
+        crate::test_support::write_test_gguf(
+            &model_path,
+            &crate::test_support::basic_metadata("qwen3"),
+            &[("token_embd.weight", &[8, 8])],
+        );
+
+        let manager = ModelManager::new(tmp.path(), Device::Cpu);
+        let callback = Arc::new(CancelOnHeader(manager.load_cancellation.clone()));
+        let result = manager.load_model_from_path_with_progress(&model_path, Some(callback));
+        assert!(matches!(result, Err(ModelManagerError::Cancelled)));
+        assert!(!manager.is_loaded());
+    }
+
+    struct CancelOnTensors(CancellationGroup);
+
+    impl LoadProgressCallback for CancelOnTensors {
+        fn on_progress(&self, progress: &crate::load_progress::LoadProgress) {
+            if progress.phase == LoadPhase::Tensors {
+                self.0.cancel();
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancelled_load_keeps_resident_models() {
+        let tmp = tempdir().unwrap();
+        let resident = tmp.path().join("resident");
+        crate::test_support::write_qwen3_safetensors(&resident);
+        let model_path = tmp.path().join("qwen3-test.gguf");
+        crate::test_support::write_test_gguf(
+            &model_path,
+            &crate::test_support::basic_metadata("qwen3"),
+            &[("token_embd.weight", &[8, 8])],
+        );
+
+        let manager = ModelManager::new(tmp.path(), Device::Cpu);
+        manager
+            .load_safetensors_model(&resident, None, None)
+            .unwrap();
+        // Бюджет вмещает новую модель, но не обе сразу
+        let content =
+            gguf_file::Content::read(&mut std::fs::File::open(&model_path).unwrap()).unwrap();
+        let required =
+            MemoryEstimate::from_content(&content, manager.context_length()).total_bytes();
+        let resident_bytes = manager.pool.read().total_bytes();
+        manager.set_memory_budget(Some(resident_bytes + required - 1));
+        let before = manager.resident_models();
+
+        let callback = Arc::new(CancelOnTensors(manager.load_cancellation.clone()));
+        let result = manager.load_model_from_path_with_progress(&model_path, Some(callback));
+        assert!(result.is_err());
+        assert_eq!(manager.resident_models(), before);
+        assert!(manager.is_loaded());
+    }
+
+    #[test]
+    fn test_loads_safetensors_model() {
+        let tmp = tempdir().unwrap();
+        let model_dir = tmp.path().join("qwen3-hf");
+        crate::test_support::write_qwen3_safetensors(&model_dir);
+
+        let manager = ModelManager::new(tmp.path(), Device::Cpu);
+        let model_type = manager
+            .load_safetensors_model(&model_dir, Some(DType::F32), None)
+            .unwrap();
+        assert_eq!(model_type, ModelType::Qwen3);
+        let snapshot = manager.current_model().unwrap();
+        assert!(matches!(snapshot.model(), ActiveModel::Qwen(_)));
+        assert_eq!(snapshot.tokenizer().token_to_id("hello"), Some(1));
+    }
+
+    #[test]
+    fn test_keeps_resident_models_within_limits() {
+        let tmp = tempdir().unwrap();
+        let small = tmp.path().join("small");
+        let smart = tmp.path().join("smart");
+        crate::test_support::write_qwen3_safetensors(&small);
+        crate::test_support::write_qwen3_safetensors(&smart);
+
+        let manager = ModelManager::new(tmp.path(), Device::Cpu);
+        manager.set_context_length(16);
+        manager.set_max_resident_models(2);
+        manager.load_safetensors_model(&small, None, None).unwrap();
+        manager.load_safetensors_model(&smart, None, None).unwrap();
+        let resident = manager.resident_models();
+        assert_eq!(resident.len(), 2);
+        assert!(resident[0].active && resident[0].id.ends_with("smart"));
+        let small_id = resident[1].id.clone();
+
+        // Повторная загрузка активирует модель из памяти
+        manager.load_safetensors_model(&small, None, None).unwrap();
+        assert_eq!(manager.resident_models()[0].id, small_id);
+        assert!(manager.resident_model(&small_id).is_ok());
+
+        // Бюджет на одну модель: давно не использованная "smart" вытесняется
+        let bytes = resident[0].memory_bytes;
+        manager.set_memory_budget(Some(bytes * 3 / 2));
+        manager.unload_resident_model(&small_id).unwrap();
+        manager.load_safetensors_model(&small, None, None).unwrap();
+        let resident = manager.resident_models();
+        assert_eq!(resident.len(), 1);
+        assert_eq!(resident[0].id, small_id);
+        assert!(matches!(
+            manager.activate_model("missing"),
+            Err(ModelManagerError::ModelNotResident(_))
+        ));
+    }
+
+    #[test]
+    fn test_buffer_sources_get_distinct_resident_ids() {
+        let tmp = tempdir().unwrap();
+        let first = tmp.path().join("first/model.gguf");
+        let second = tmp.path().join("second/model.gguf");
+        crate::test_support::write_qwen3_gguf(&first, 0);
+        crate::test_support::write_qwen3_gguf(&second, 1);
+
+        let manager = ModelManager::new(tmp.path(), Device::Cpu);
+        manager.set_max_resident_models(2);
+        manager.set_tokenizer_override(Some(tmp.path().join("first/tokenizer.json")));
+        for path in [&first, &second] {
+            let source = ModelSource::Buffer(std::fs::read(path).unwrap());
+            manager.load_model_from_source(source, None).unwrap();
+        }
+        let resident = manager.resident_models();
+        assert_eq!(resident.len(), 2);
+        assert_ne!(resident[0].id, resident[1].id);
+        assert!(resident[0].active);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_refuses_to_edit_external_models() {
+        let tmp = tempdir().unwrap();
+        let edit = [MetadataEdit::Set(
+            "general.name".to_string(),
+            gguf_file::Value::String("renamed".to_string()),
+        )];
+        let write = |path: &Path| {
+            crate::test_support::write_test_gguf(
+                path,
+                &crate::test_support::basic_metadata("qwen3"),
+                &[("w", &[4])],
+            )
+        };
+
+        let ollama = tmp.path().join("ollama");
+        let blob = ollama.join("blobs/sha256-0123");
+        write(&blob);
+        let manager = ModelManager::new(tmp.path().join("models"), Device::Cpu);
+        manager.add_external_store(ExternalStore::new(
+            crate::external_stores::StoreKind::Ollama,
+            &ollama,
+        ));
+        let before = std::fs::read(&blob).unwrap();
+        assert!(matches!(
+            manager.edit_metadata(&blob, &edit),
+            Err(ModelManagerError::ExternalModel(_))
+        ));
+        assert_eq!(std::fs::read(&blob).unwrap(), before);
+
+        // Снапшот кеша Hugging Face — симлинк на блоб
+        let repo = tmp.path().join("models/models--Qwen--Qwen3-0.6B-GGUF");
+        write(&repo.join("blobs/abc"));
+        let snapshot = repo.join("snapshots/main/model.gguf");
+        std::fs::create_dir_all(snapshot.parent().unwrap()).unwrap();
+        std::os::unix::fs::symlink(repo.join("blobs/abc"), &snapshot).unwrap();
+        assert!(matches!(
+            manager.edit_metadata(&snapshot, &edit),
+            Err(ModelManagerError::ExternalModel(_))
+        ));
+        assert!(snapshot.is_symlink());
+
+        let own = tmp.path().join("models/qwen3/own/model.gguf");
+        write(&own);
+        manager.edit_metadata(&own, &edit).unwrap();
+    }
+
+    #[test]
+    fn test_draft_model_counts_against_memory_budget() {
+        let tmp = tempdir().unwrap();
+        let small = tmp.path().join("small/model.gguf");
+        let smart = tmp.path().join("smart/model.gguf");
+        let draft = tmp.path().join("draft/model.gguf");
+        for (seed, path) in [&small, &smart, &draft].into_iter().enumerate() {
+            crate::test_support::write_qwen3_gguf(path, seed as u32);
+        }
+
+        let manager = ModelManager::new(tmp.path(), Device::Cpu);
+        manager.set_context_length(16);
+        manager.set_max_resident_models(2);
+        manager.load_model_from_path(&small).unwrap();
+        manager.load_model_from_path(&smart).unwrap();
+        let bytes = manager.resident_models()[0].memory_bytes;
+        let content = gguf_file::Content::read(&mut std::fs::File::open(&draft).unwrap()).unwrap();
+        let draft_bytes =
+            MemoryEstimate::from_content(&content, manager.context_length()).total_bytes();
+
+        // Активная модель не выгружается ради черновой
+        manager.set_memory_budget(Some(bytes + draft_bytes - 1));
+        assert!(matches!(
+            manager.load_draft_model(&draft, None),
+            Err(ModelManagerError::InsufficientMemory { .. })
+        ));
+        assert_eq!(manager.resident_models().len(), 2);
+
+        // Черновая модель вытесняет давно не использованную "small"
+        manager.set_memory_budget(Some(bytes * 2 + draft_bytes - 1));
+        manager.load_draft_model(&draft, None).unwrap();
+        let resident = manager.resident_models();
+        assert_eq!(resident.len(), 1);
+        assert!(resident[0].id.ends_with("smart/model.gguf"));
+
+        // Без черновой модели обе поместились бы; с ней "smart" вытесняется
+        manager.load_model_from_path(&small).unwrap();
+        let resident = manager.resident_models();
+        assert_eq!(resident.len(), 1);
+        assert!(resident[0].id.ends_with("small/model.gguf"));
+    }
+
+    #[test]
+    fn test_loaded_draft_model_cannot_be_deleted() {
+        let tmp = tempdir().unwrap();
+        let manager = ModelManager::new(tmp.path(), Device::Cpu);
+        let id = ModelId::parse("qwen3/draft").unwrap();
+        let draft = manager.store().model_dir(&id).join("model.gguf");
+        crate::test_support::write_qwen3_gguf(&draft, 1);
+
+        manager.load_draft_model(&draft, None).unwrap();
+        let renamed = ModelId::parse("qwen3/renamed").unwrap();
+        assert!(matches!(
+            manager.rename_model(&id, &renamed),
+            Err(ModelManagerError::ModelInUse(_))
+        ));
+        assert!(matches!(
+            manager.delete_model(&id),
+            Err(ModelManagerError::ModelInUse(_))
+        ));
+
+        manager.unload_draft_model();
+        manager.delete_model(&id).unwrap();
+    }
 }
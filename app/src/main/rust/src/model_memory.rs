@@ -0,0 +1,247 @@
+//! Оценка потребления памяти до загрузки модели.
+//! Размер весов считается по описаниям тензоров из заголовка GGUF,
+//! размер KV-кеша — по параметрам внимания и длине контекста.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use candle_core::quantized::gguf_file;
+use serde::Serialize;
+
+/// Длина контекста по умолчанию, используемая при оценке KV-кеша.
+pub const DEFAULT_CONTEXT_LENGTH: usize = 2048;
+
+/// Фиксированный запас на буферы вычислений, токенизатор и аллокатор.
+pub const RUNTIME_OVERHEAD_BYTES: u64 = 128 * 1024 * 1024;
+
+/// Доля доступной RAM, которую допускается занять моделью.
+/// Остаток оставляется системе и UI, чтобы не сработал low-memory killer.
+pub const RAM_HEADROOM_FRACTION: f64 = 0.8;
+
+/// Размер элемента KV-кеша: на CPU кеш хранится в F32.
+const KV_CACHE_ELEMENT_SIZE: u64 = 4;
+
+/// Оценка памяти, необходимой для загрузки модели.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MemoryEstimate {
+    /// Квантованные веса в том виде, в каком они лежат в файле.
+    pub weights_bytes: u64,
+    /// Тензоры, которые модель деквантует при загрузке (эмбеддинги).
+    pub dequantized_bytes: u64,
+    pub kv_cache_bytes: u64,
+    pub overhead_bytes: u64,
+    pub context_length: usize,
+}
+
+impl MemoryEstimate {
+    /// Оценивает память по заголовку GGUF для заданной длины контекста.
+    /// Длина контекста ограничивается значением `{arch}.context_length` модели.
+    pub fn from_content(content: &gguf_file::Content, context_length: usize) -> Self {
+        let mut weights_bytes = 0u64;
+        let mut dequantized_bytes = 0u64;
+        for (name, info) in &content.tensor_infos {
+            let elems = info.shape.elem_count() as u64;
+            let block_size = info.ggml_dtype.block_size() as u64;
+            weights_bytes += elems / block_size * info.ggml_dtype.type_size() as u64;
+            // Эмбеддинги токенов хранятся в деквантованном F32 виде
+            if name.starts_with("token_embd") {
+                dequantized_bytes += elems * 4;
+            }
+        }
+
+        let kv = KvCacheShape::from_metadata(&content.metadata);
+        let context_length = kv
+            .max_context_length
+            .map(|max| context_length.min(max))
+            .unwrap_or(context_length);
+
+        Self {
+            weights_bytes,
+            dequantized_bytes,
+            kv_cache_bytes: kv.bytes_for(context_length),
+            overhead_bytes: RUNTIME_OVERHEAD_BYTES,
+            context_length,
+        }
+    }
+
+    /// Суммарная оценка в байтах.
+    pub fn total_bytes(&self) -> u64 {
+        self.weights_bytes + self.dequantized_bytes + self.kv_cache_bytes + self.overhead_bytes
+    }
+
+    /// Помещается ли модель в указанный объём доступной RAM с учётом запаса.
+    pub fn fits_in(&self, available_ram: u64) -> bool {
+        self.total_bytes() as f64 <= available_ram as f64 * RAM_HEADROOM_FRACTION
+    }
+}
+
+/// Параметры внимания, определяющие размер KV-кеша.
+#[derive(Debug, Clone, Copy, Default)]
+struct KvCacheShape {
+    block_count: u64,
+    head_count_kv: u64,
+    key_length: u64,
+    value_length: u64,
+    max_context_length: Option<usize>,
+}
+
+impl KvCacheShape {
+    fn from_metadata(metadata: &HashMap<String, gguf_file::Value>) -> Self {
+        let Some(arch) = metadata
+            .get("general.architecture")
+            .and_then(|v| v.to_string().ok())
+        else {
+            return Self::default();
+        };
+        let get = |key: &str| {
+            metadata
+                .get(&format!("{arch}.{key}"))
+                .and_then(|v| v.to_u64().ok())
+        };
+
+        let head_count = get("attention.head_count").unwrap_or(0);
+        let head_count_kv = get("attention.head_count_kv").unwrap_or(head_count);
+        let default_head_dim = match (get("embedding_length"), head_count) {
+            (Some(embedding), heads) if heads > 0 => embedding / heads,
+            _ => 0,
+        };
+        let key_length = get("attention.key_length").unwrap_or(default_head_dim);
+
+        Self {
+            block_count: get("block_count").unwrap_or(0),
+            head_count_kv,
+            key_length,
+            value_length: get("attention.value_length").unwrap_or(key_length),
+            max_context_length: get("context_length").map(|v| v as usize),
+        }
+    }
+
+    fn bytes_for(&self, context_length: usize) -> u64 {
+        self.block_count
+            * self.head_count_kv
+            * (self.key_length + self.value_length)
+            * context_length as u64
+            * KV_CACHE_ELEMENT_SIZE
+    }
+}
+
+/// Результат подбора квантования для одного кандидата.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuantizationFit {
+    pub path: PathBuf,
+    pub estimate: MemoryEstimate,
+    pub total_bytes: u64,
+    pub fits: bool,
+}
+
+/// Рекомендация по выбору квантования.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuantizationRecommendation {
+    /// Самый «тяжёлый» (и, как правило, самый точный) из помещающихся вариантов.
+    pub recommended: Option<PathBuf>,
+    /// Все кандидаты, отсортированные по убыванию требуемой памяти.
+    pub candidates: Vec<QuantizationFit>,
+}
+
+/// Выбирает наиболее качественный вариант, помещающийся в доступную RAM.
+pub fn recommend_quantization(
+    candidates: Vec<(PathBuf, MemoryEstimate)>,
+    available_ram: u64,
+) -> QuantizationRecommendation {
+    let mut fits: Vec<QuantizationFit> = candidates
+        .into_iter()
+        .map(|(path, estimate)| QuantizationFit {
+            total_bytes: estimate.total_bytes(),
+            fits: estimate.fits_in(available_ram),
+            path,
+            estimate,
+        })
+        .collect();
+    fits.sort_by_key(|fit| std::cmp::Reverse(fit.total_bytes));
+
+    QuantizationRecommendation {
+        recommended: fits.iter().find(|fit| fit.fits).map(|fit| fit.path.clone()),
+        candidates: fits,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{basic_metadata, write_test_gguf};
+    use tempfile::tempdir;
+
+    fn read_content(path: &std::path::Path) -> gguf_file::Content {
+        let mut reader = std::fs::File::open(path).unwrap();
+        gguf_file::Content::read(&mut reader).unwrap()
+    }
+
+    fn qwen_metadata() -> Vec<(String, gguf_file::Value)> {
+        let mut metadata = basic_metadata("qwen3");
+        metadata.extend([
+            ("qwen3.block_count".into(), gguf_file::Value::U32(2)),
+            (
+                "qwen3.attention.head_count".into(),
+                gguf_file::Value::U32(4),
+            ),
+            (
+                "qwen3.attention.head_count_kv".into(),
+                gguf_file::Value::U32(2),
+            ),
+            (
+                "qwen3.attention.key_length".into(),
+                gguf_file::Value::U32(8),
+            ),
+        ]);
+        metadata
+    }
+
+    #[test]
+    fn test_estimate_counts_weights_and_kv_cache() {
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("model.gguf");
+        write_test_gguf(
+            &path,
+            &qwen_metadata(),
+            &[
+                ("token_embd.weight", &[16, 8]),
+                ("blk.0.attn_q.weight", &[8, 8]),
+            ],
+        );
+
+        let estimate = MemoryEstimate::from_content(&read_content(&path), 1024);
+        assert_eq!(estimate.weights_bytes, (16 * 8 + 8 * 8) * 4);
+        assert_eq!(estimate.dequantized_bytes, 16 * 8 * 4);
+        // 2 слоя * 2 KV-головы * (8 + 8) * 1024 позиций * 4 байта
+        assert_eq!(estimate.kv_cache_bytes, 2 * 2 * 16 * 1024 * 4);
+        assert_eq!(estimate.context_length, 1024);
+
+        // Длина контекста ограничивается параметрами модели
+        let clamped = MemoryEstimate::from_content(&read_content(&path), 1 << 20);
+        assert_eq!(clamped.context_length, 4096);
+    }
+
+    #[test]
+    fn test_recommend_picks_largest_fitting() {
+        let estimate = |weights_mb: u64| MemoryEstimate {
+            weights_bytes: weights_mb << 20,
+            dequantized_bytes: 0,
+            kv_cache_bytes: 0,
+            overhead_bytes: 0,
+            context_length: DEFAULT_CONTEXT_LENGTH,
+        };
+        let candidates = vec![
+            (PathBuf::from("q4.gguf"), estimate(400)),
+            (PathBuf::from("q8.gguf"), estimate(800)),
+            (PathBuf::from("f16.gguf"), estimate(1500)),
+        ];
+
+        let recommendation = recommend_quantization(candidates, 1024 << 20);
+        assert_eq!(recommendation.recommended, Some(PathBuf::from("q8.gguf")));
+        assert_eq!(recommendation.candidates[0].path, PathBuf::from("f16.gguf"));
+        assert!(!recommendation.candidates[0].fits);
+
+        let none = recommend_quantization(vec![(PathBuf::from("f16.gguf"), estimate(1500))], 1);
+        assert!(none.recommended.is_none());
+    }
+}
@@ -0,0 +1,239 @@
+//! Пул загруженных моделей: несколько моделей остаются в памяти в пределах бюджета RAM
+//! и ограничения на их число. При нехватке места выгружаются модели, которые дольше всех
+//! не использовались (LRU). Одна из моделей пула активна — с ней работает движок по умолчанию.
+
+/// Модели пула по умолчанию: одна, как при последовательной загрузке.
+pub const DEFAULT_MAX_RESIDENT_MODELS: usize = 1;
+
+struct PoolEntry<T> {
+    id: String,
+    value: T,
+    bytes: u64,
+    last_used: u64,
+}
+
+/// Пул моделей с вытеснением по LRU.
+pub struct ModelPool<T> {
+    entries: Vec<PoolEntry<T>>,
+    active: Option<String>,
+    clock: u64,
+}
+
+impl<T> Default for ModelPool<T> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            active: None,
+            clock: 0,
+        }
+    }
+}
+
+impl<T> ModelPool<T> {
+    /// Число моделей в пуле.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Пуст ли пул.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Оценка памяти всех моделей пула.
+    pub fn total_bytes(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.bytes).sum()
+    }
+
+    /// Находится ли модель в пуле.
+    pub fn contains(&self, id: &str) -> bool {
+        self.position(id).is_some()
+    }
+
+    /// Возвращает модель и отмечает её использование.
+    pub fn get(&mut self, id: &str) -> Option<&T> {
+        let index = self.position(id)?;
+        self.clock += 1;
+        let entry = &mut self.entries[index];
+        entry.last_used = self.clock;
+        Some(&entry.value)
+    }
+
+    /// Активная модель.
+    pub fn active(&self) -> Option<&T> {
+        let id = self.active.as_deref()?;
+        self.position(id).map(|index| &self.entries[index].value)
+    }
+
+    /// Идентификатор активной модели.
+    pub fn active_id(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    /// Делает модель пула активной; `false`, если её нет в пуле.
+    pub fn activate(&mut self, id: &str) -> bool {
+        if self.get(id).is_none() {
+            return false;
+        }
+        self.active = Some(id.to_string());
+        true
+    }
+
+    /// Выгружает модели, начиная с давно не использованных, пока `bytes` новой модели
+    /// не поместятся в `budget`; модель `keep` не выгружается. Возвращает идентификаторы
+    /// выгруженных моделей.
+    pub fn evict_for(
+        &mut self,
+        bytes: u64,
+        budget: Option<u64>,
+        keep: Option<&str>,
+    ) -> Vec<String> {
+        let mut evicted = Vec::new();
+        let Some(budget) = budget else {
+            return evicted;
+        };
+        while self.total_bytes() + bytes > budget {
+            match self.evict_lru(keep) {
+                Some(id) => evicted.push(id),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Добавляет модель (заменяя модель с тем же идентификатором) и делает её активной.
+    /// Если моделей больше `max_models`, выгружает давно не использованные.
+    pub fn insert(&mut self, id: String, value: T, bytes: u64, max_models: usize) -> Vec<String> {
+        self.remove(&id);
+        self.clock += 1;
+        self.entries.push(PoolEntry {
+            id: id.clone(),
+            value,
+            bytes,
+            last_used: self.clock,
+        });
+        self.active = Some(id.clone());
+        self.shrink_to(max_models, Some(&id))
+    }
+
+    /// Выгружает давно не использованные модели, пока их не станет не больше `max_models`
+    /// (но не меньше одной); модель `keep` не выгружается.
+    pub fn shrink_to(&mut self, max_models: usize, keep: Option<&str>) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.entries.len() > max_models.max(1) {
+            match self.evict_lru(keep) {
+                Some(id) => evicted.push(id),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Удаляет модель из пула.
+    pub fn remove(&mut self, id: &str) -> Option<T> {
+        let index = self.position(id)?;
+        if self.active.as_deref() == Some(id) {
+            self.active = None;
+        }
+        Some(self.entries.remove(index).value)
+    }
+
+    /// Удаляет все модели.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.active = None;
+    }
+
+    /// Модели пула: идентификатор, модель и оценка памяти, от недавно использованных
+    /// к давно не использованным.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &T, u64)> {
+        let mut entries: Vec<&PoolEntry<T>> = self.entries.iter().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_used));
+        entries
+            .into_iter()
+            .map(|entry| (entry.id.as_str(), &entry.value, entry.bytes))
+    }
+
+    fn position(&self, id: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.id == id)
+    }
+
+    fn evict_lru(&mut self, keep: Option<&str>) -> Option<String> {
+        let index = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| Some(entry.id.as_str()) != keep)
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(index, _)| index)?;
+        let id = self.entries[index].id.clone();
+        self.remove(&id);
+        log::info!("Модель '{}' выгружена из пула", id);
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut pool = ModelPool::default();
+        assert!(pool.insert("small".into(), 1, 100, 3).is_empty());
+        assert!(pool.insert("big".into(), 2, 300, 3).is_empty());
+        assert!(pool.insert("medium".into(), 3, 200, 3).is_empty());
+        assert_eq!(pool.active_id(), Some("medium"));
+
+        // Обращение к "small" делает давно не использованной "big"
+        assert!(pool.activate("small"));
+        assert_eq!(
+            pool.evict_for(150, Some(600), None),
+            vec!["big".to_string()]
+        );
+        assert_eq!(pool.total_bytes(), 300);
+
+        assert_eq!(
+            pool.insert("tiny".into(), 4, 10, 2),
+            vec!["medium".to_string()]
+        );
+        let ids: Vec<&str> = pool.iter().map(|(id, _, _)| id).collect();
+        assert_eq!(ids, ["tiny", "small"]);
+        assert_eq!(pool.active(), Some(&4));
+    }
+
+    #[test]
+    fn test_reinsert_shrink_and_remove() {
+        let mut pool = ModelPool::default();
+        pool.insert("a".into(), 1, 10, 2);
+        pool.insert("a".into(), 2, 20, 2);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.get("a"), Some(&2));
+        assert!(pool.evict_for(1_000, None, None).is_empty());
+        pool.insert("b".into(), 3, 30, 2);
+        assert_eq!(pool.shrink_to(0, None), vec!["a".to_string()]);
+        assert_eq!(pool.active_id(), Some("b"));
+        pool.insert("a".into(), 2, 20, 2);
+
+        assert_eq!(pool.remove("a"), Some(2));
+        assert_eq!(pool.active(), None);
+        assert!(!pool.activate("a"));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_budget_eviction_keeps_active_model() {
+        let mut pool = ModelPool::default();
+        pool.insert("old".into(), 1, 100, 3);
+        pool.insert("active".into(), 2, 300, 3);
+
+        // Активная модель не выгружается, даже если бюджет не достигнут
+        let active = pool.active_id().map(str::to_string);
+        assert_eq!(
+            pool.evict_for(200, Some(400), active.as_deref()),
+            vec!["old".to_string()]
+        );
+        assert_eq!(pool.active(), Some(&2));
+        assert_eq!(pool.total_bytes(), 300);
+    }
+}
@@ -0,0 +1,199 @@
+//! Источник GGUF данных для загрузки модели: путь, файловый дескриптор или буфер.
+//! Дескрипторы нужны для файлов, выбранных через Storage Access Framework: модель
+//! читается напрямую из `content://` без копирования в личное хранилище приложения.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
+
+use crate::model_manager::ModelManagerError;
+
+/// Откуда читать GGUF файл.
+#[derive(Debug)]
+pub enum ModelSource {
+    /// Файл в файловой системе.
+    Path(PathBuf),
+    /// Открытый файловый дескриптор. Дескриптор дублируется, владение остаётся
+    /// у вызывающей стороны. `length: None` — до конца файла.
+    #[cfg(unix)]
+    Fd {
+        fd: std::os::fd::RawFd,
+        offset: u64,
+        length: Option<u64>,
+    },
+    /// GGUF данные в памяти.
+    Buffer(Vec<u8>),
+}
+
+/// Байты GGUF файла: отображение в память или собственный буфер.
+pub enum GgufBytes {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl AsRef<[u8]> for GgufBytes {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            GgufBytes::Mapped(mmap) => mmap,
+            GgufBytes::Owned(bytes) => bytes,
+        }
+    }
+}
+
+impl std::ops::Deref for GgufBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_ref()
+    }
+}
+
+impl ModelSource {
+    /// Путь к файлу, если источник — файл в файловой системе.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            ModelSource::Path(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Имя источника для логов и определения типа модели по имени файла.
+    /// Для дескриптора используется путь, на который он указывает, если его удаётся узнать.
+    pub fn name(&self) -> String {
+        match self {
+            ModelSource::Path(path) => path.to_string_lossy().into_owned(),
+            #[cfg(unix)]
+            ModelSource::Fd { fd, .. } => std::fs::read_link(format!("/proc/self/fd/{fd}"))
+                .map(|target| target.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| format!("fd:{fd}")),
+            ModelSource::Buffer(_) => "buffer".to_string(),
+        }
+    }
+
+    /// Читает источник: файлы и дескрипторы отображаются в память, буфер используется как есть.
+    pub fn into_bytes(self) -> Result<GgufBytes, ModelManagerError> {
+        match self {
+            ModelSource::Path(path) => {
+                let file = std::fs::File::open(&path).map_err(|_| {
+                    ModelManagerError::ModelFileMissing(path.to_string_lossy().into_owned())
+                })?;
+                map_region(&file, 0, None)
+            }
+            #[cfg(unix)]
+            ModelSource::Fd { fd, offset, length } => {
+                // SAFETY: вызывающая сторона гарантирует, что дескриптор открыт на время
+                // вызова; дальше используется собственная копия.
+                let owned = unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) }
+                    .try_clone_to_owned()
+                    .map_err(|e| ModelManagerError::Io(format!("fd {fd}: {e}")))?;
+                map_region(&std::fs::File::from(owned), offset, length)
+            }
+            ModelSource::Buffer(bytes) => Ok(GgufBytes::Owned(bytes)),
+        }
+    }
+}
+
+/// Отображает в память участок файла. Каналы и сокеты, которые нельзя отобразить,
+/// вычитываются в буфер.
+fn map_region(
+    file: &std::fs::File,
+    offset: u64,
+    length: Option<u64>,
+) -> Result<GgufBytes, ModelManagerError> {
+    let io = |e: std::io::Error| ModelManagerError::Io(e.to_string());
+    let metadata = file.metadata().map_err(io)?;
+    if !metadata.is_file() {
+        log::info!("Источник модели не является файлом, данные читаются в память");
+        let mut reader = file;
+        std::io::copy(&mut reader.take(offset), &mut std::io::sink()).map_err(io)?;
+        let mut bytes = Vec::new();
+        match length {
+            Some(length) => reader.take(length).read_to_end(&mut bytes),
+            None => reader.read_to_end(&mut bytes),
+        }
+        .map_err(io)?;
+        return Ok(GgufBytes::Owned(bytes));
+    }
+
+    let file_len = metadata.len();
+    let length = length.unwrap_or(file_len.saturating_sub(offset));
+    if offset.checked_add(length).is_none_or(|end| end > file_len) {
+        return Err(ModelManagerError::Corrupted(format!(
+            "участок {offset}+{length} выходит за пределы файла ({file_len} байт)"
+        )));
+    }
+    // SAFETY: файл открыт только на чтение; изменение модели на диске во время
+    // загрузки не поддерживается так же, как и при обычном чтении.
+    let mmap = unsafe {
+        memmap2::MmapOptions::new()
+            .offset(offset)
+            .len(length as usize)
+            .map(file)
+    }
+    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
+    #[cfg(unix)]
+    {
+        // Тензоры читаются последовательно: подсказываем ядру агрессивный read-ahead
+        // и раннее освобождение уже прочитанных страниц.
+        let _ = mmap.advise(memmap2::Advice::Sequential);
+    }
+    Ok(GgufBytes::Mapped(mmap))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{basic_metadata, write_test_gguf};
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fd_region_at_unaligned_offset() {
+        use std::io::Write;
+        use std::os::fd::AsRawFd;
+
+        let tmp = tempdir().unwrap();
+        let model = tmp.path().join("model.gguf");
+        write_test_gguf(&model, &basic_metadata("qwen3"), &[("w", &[4])]);
+        let payload = std::fs::read(&model).unwrap();
+
+        // Модель внутри контейнера, как в AssetFileDescriptor
+        let container = tmp.path().join("container.bin");
+        let mut file = std::fs::File::create(&container).unwrap();
+        file.write_all(&[7u8; 1001]).unwrap();
+        file.write_all(&payload).unwrap();
+        file.write_all(&[9u8; 13]).unwrap();
+
+        let file = std::fs::File::open(&container).unwrap();
+        let source = ModelSource::Fd {
+            fd: file.as_raw_fd(),
+            offset: 1001,
+            length: Some(payload.len() as u64),
+        };
+        assert!(source.name().ends_with("container.bin"));
+        let bytes = source.into_bytes().unwrap();
+        assert_eq!(&*bytes, payload.as_slice());
+
+        let out_of_bounds = ModelSource::Fd {
+            fd: file.as_raw_fd(),
+            offset: 1001,
+            length: Some(payload.len() as u64 + 14),
+        };
+        assert!(matches!(
+            out_of_bounds.into_bytes(),
+            Err(ModelManagerError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn test_buffer_source_is_used_as_is() {
+        let source = ModelSource::Buffer(b"GGUF".to_vec());
+        assert_eq!(source.path(), None);
+        assert_eq!(&*source.into_bytes().unwrap(), b"GGUF");
+        assert!(matches!(
+            ModelSource::Path(PathBuf::from("/nonexistent/model.gguf")).into_bytes(),
+            Err(ModelManagerError::ModelFileMissing(_))
+        ));
+    }
+}
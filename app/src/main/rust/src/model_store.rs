@@ -0,0 +1,428 @@
+//! Хранилище моделей в корневой директории.
+//! Модели адресуются проверенными идентификаторами вида `qwen3/0.6b`: каждый
+//! идентификатор соответствует директории с файлом `model.gguf` или частями
+//! `model-0000N-of-0000M.gguf` разбитой модели. Все пути
+//! канонизируются и проверяются на принадлежность хранилищу, а импорт,
+//! переименование и удаление выполняются через атомарный `rename`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::gguf_split::{shard_file_name, SplitName};
+use crate::model_manager::{ModelManagerError, ModelType};
+
+/// Имя файла весов внутри директории модели.
+pub const MODEL_FILE_NAME: &str = "model.gguf";
+/// Префикс имён частей разбитой модели внутри директории модели.
+pub const SHARD_PREFIX: &str = "model";
+
+/// Временная директория для импорта; скрытые директории не попадают в каталог.
+const STAGING_DIR: &str = ".staging";
+/// Директория, куда модель переносится перед удалением.
+const TRASH_DIR: &str = ".trash";
+
+const MAX_ID_LEN: usize = 128;
+const MAX_ID_SEGMENTS: usize = 4;
+
+/// Проверенный идентификатор модели: 1–4 сегмента из `[A-Za-z0-9._-]`,
+/// разделённых `/`, без `.`/`..` и скрытых имён.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ModelId(String);
+
+impl ModelId {
+    /// Проверяет идентификатор.
+    pub fn parse(id: &str) -> Result<Self, ModelManagerError> {
+        let segments: Vec<&str> = id.split('/').collect();
+        let valid = id.len() <= MAX_ID_LEN
+            && segments.len() <= MAX_ID_SEGMENTS
+            && segments.iter().all(|segment| is_valid_segment(segment));
+        if !valid {
+            return Err(ModelManagerError::InvalidModelId(id.to_string()));
+        }
+        Ok(Self(id.to_string()))
+    }
+
+    /// Идентификатор варианта семейства: `qwen3/<variant>` или `gemma3/<variant>`.
+    /// Вариант должен быть одним сегментом.
+    pub fn for_variant(model_type: ModelType, variant: &str) -> Result<Self, ModelManagerError> {
+        if !is_valid_segment(variant) {
+            return Err(ModelManagerError::InvalidModelId(variant.to_string()));
+        }
+        let family = match model_type {
+            ModelType::Qwen3 => "qwen3",
+            ModelType::Gemma3 => "gemma3",
+        };
+        Self::parse(&format!("{family}/{variant}"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ModelId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn is_valid_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && !segment.starts_with('.')
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Хранилище моделей с проверкой путей.
+pub struct ModelStore {
+    root: PathBuf,
+    /// Счётчик для уникальных имён временных файлов и директорий.
+    sequence: AtomicU64,
+}
+
+impl ModelStore {
+    /// Создаёт хранилище в указанной директории.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Корневая директория хранилища.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Директория модели (без проверки существования).
+    pub fn model_dir(&self, id: &ModelId) -> PathBuf {
+        self.root.join(id.as_str())
+    }
+
+    /// Возвращает канонический путь к `model.gguf` (или к первой части разбитой модели),
+    /// убедившись, что он не ведёт за пределы хранилища (в том числе через симлинки).
+    pub fn resolve(&self, id: &ModelId) -> Result<PathBuf, ModelManagerError> {
+        let dir = self.model_dir(id);
+        let path = model_file(&dir).ok_or_else(|| {
+            ModelManagerError::ModelFileMissing(
+                dir.join(MODEL_FILE_NAME).to_string_lossy().into_owned(),
+            )
+        })?;
+        self.contained(&path)
+    }
+
+    /// Копирует GGUF файл в хранилище под идентификатором `id`.
+    /// Файл сначала пишется во временную директорию и затем переносится одним `rename`,
+    /// поэтому частично скопированная модель никогда не видна в каталоге.
+    pub fn import(&self, source: &Path, id: &ModelId) -> Result<PathBuf, ModelManagerError> {
+        if !source.is_file() {
+            return Err(ModelManagerError::ModelFileMissing(
+                source.to_string_lossy().into_owned(),
+            ));
+        }
+        self.prepare_target(id)?;
+        if let Some(split) = SplitName::parse(source) {
+            return self.import_split(&split, id);
+        }
+
+        let staging = self.scratch_path(STAGING_DIR)?;
+        if let Err(err) = copy_synced(source, &staging) {
+            let _ = std::fs::remove_file(&staging);
+            return Err(io_error(err));
+        }
+        let path = self.commit(&staging, id).inspect_err(|_| {
+            let _ = std::fs::remove_file(&staging);
+        })?;
+        log::info!("Модель {} импортирована из {:?}", id, source);
+        Ok(path)
+    }
+
+    /// Импортирует все части разбитой модели: части копируются во временную директорию
+    /// под именами `model-0000N-of-0000M.gguf`, которая затем переносится целиком.
+    fn import_split(&self, split: &SplitName, id: &ModelId) -> Result<PathBuf, ModelManagerError> {
+        let sources = split.shard_paths();
+        if let Some(missing) = sources.iter().find(|path| !path.is_file()) {
+            return Err(ModelManagerError::ModelFileMissing(
+                missing.to_string_lossy().into_owned(),
+            ));
+        }
+        let staging = self.scratch_path(STAGING_DIR)?;
+        let copied = std::fs::create_dir(&staging).and_then(|_| {
+            sources.iter().zip(1..).try_for_each(|(source, index)| {
+                let name = shard_file_name(SHARD_PREFIX, index, split.count);
+                copy_synced(source, &staging.join(name))
+            })
+        });
+        let result = copied.map_err(io_error).and_then(|_| {
+            let target_dir = self.prepare_target(id)?;
+            std::fs::rename(&staging, &target_dir).map_err(io_error)?;
+            Ok(target_dir.join(shard_file_name(SHARD_PREFIX, 1, split.count)))
+        });
+        match result {
+            Ok(path) => {
+                log::info!("Модель {} импортирована из {} частей", id, split.count);
+                Ok(path)
+            }
+            Err(err) => {
+                let _ = std::fs::remove_dir_all(&staging);
+                Err(err)
+            }
+        }
+    }
+
+    /// Путь для докачки модели во временной директории. Имя детерминировано
+    /// идентификатором и источником `source` (например, репозиторием, ревизией и файлом),
+    /// чтобы прерванная загрузка продолжилась только с того же файла.
+    pub fn partial_path(&self, id: &ModelId, source: &str) -> Result<PathBuf, ModelManagerError> {
+        let dir = self.root.join(STAGING_DIR);
+        std::fs::create_dir_all(&dir).map_err(io_error)?;
+        let mut hasher = crate::sha256::Sha256::new();
+        hasher.update(source.as_bytes());
+        let source = hasher.finalize_hex();
+        Ok(dir.join(format!(
+            "{}@{}.part",
+            id.as_str().replace('/', "@"),
+            &source[..16]
+        )))
+    }
+
+    /// Проверяет, что идентификатор `id` ещё не занят, до долгой загрузки или конвертации.
+    pub fn ensure_free(&self, id: &ModelId) -> Result<(), ModelManagerError> {
+        self.prepare_target(id).map(drop)
+    }
+
+    /// Новый временный файл для модели, которая будет перенесена в хранилище через [`Self::commit`].
+    pub fn staging_file(&self) -> Result<PathBuf, ModelManagerError> {
+        self.scratch_path(STAGING_DIR)
+    }
+
+    /// Переносит готовый файл из временной директории в хранилище под идентификатором `id`.
+    pub fn commit(&self, staged: &Path, id: &ModelId) -> Result<PathBuf, ModelManagerError> {
+        let target_dir = self.prepare_target(id)?;
+        let target = target_dir.join(MODEL_FILE_NAME);
+        let result =
+            std::fs::create_dir(&target_dir).and_then(|_| std::fs::rename(staged, &target));
+        if let Err(err) = result {
+            let _ = std::fs::remove_dir(&target_dir);
+            return Err(io_error(err));
+        }
+        Ok(target)
+    }
+
+    /// Переименовывает модель. Целевой идентификатор не должен быть занят.
+    pub fn rename(&self, from: &ModelId, to: &ModelId) -> Result<(), ModelManagerError> {
+        let source_dir = self.existing_dir(from)?;
+        let target_dir = self.prepare_target(to)?;
+        std::fs::rename(&source_dir, &target_dir).map_err(io_error)?;
+        log::info!("Модель {} переименована в {}", from, to);
+        Ok(())
+    }
+
+    /// Удаляет модель: директория атомарно переносится в корзину и затем стирается.
+    pub fn delete(&self, id: &ModelId) -> Result<(), ModelManagerError> {
+        let dir = self.existing_dir(id)?;
+        let trash = self.scratch_path(TRASH_DIR)?;
+        std::fs::rename(&dir, &trash).map_err(io_error)?;
+        if let Err(err) = std::fs::remove_dir_all(&trash) {
+            log::warn!("Не удалось очистить {:?}: {}", trash, err);
+        }
+        log::info!("Модель {} удалена", id);
+        Ok(())
+    }
+
+    /// Канонизирует путь и проверяет, что он лежит внутри хранилища.
+    pub fn contained(&self, path: &Path) -> Result<PathBuf, ModelManagerError> {
+        let root = self.root.canonicalize().map_err(io_error)?;
+        let canonical = path.canonicalize().map_err(|_| {
+            ModelManagerError::ModelFileMissing(path.to_string_lossy().into_owned())
+        })?;
+        if !canonical.starts_with(&root) {
+            return Err(ModelManagerError::OutsideStore(
+                path.to_string_lossy().into_owned(),
+            ));
+        }
+        Ok(canonical)
+    }
+
+    /// Каноническая директория существующей модели.
+    fn existing_dir(&self, id: &ModelId) -> Result<PathBuf, ModelManagerError> {
+        let dir = self.model_dir(id);
+        if model_file(&dir).is_none() {
+            return Err(ModelManagerError::ModelFileMissing(
+                dir.join(MODEL_FILE_NAME).to_string_lossy().into_owned(),
+            ));
+        }
+        self.contained(&dir)
+    }
+
+    /// Создаёт родительские директории для новой модели и возвращает её путь.
+    fn prepare_target(&self, id: &ModelId) -> Result<PathBuf, ModelManagerError> {
+        let target_dir = self.model_dir(id);
+        if target_dir.exists() {
+            return Err(ModelManagerError::ModelAlreadyExists(id.to_string()));
+        }
+        let parent = target_dir.parent().unwrap_or(&self.root);
+        std::fs::create_dir_all(parent).map_err(io_error)?;
+        let parent = self.contained(parent)?;
+        Ok(parent.join(target_dir.file_name().unwrap_or_default()))
+    }
+
+    /// Уникальный путь во служебной директории хранилища.
+    fn scratch_path(&self, dir: &str) -> Result<PathBuf, ModelManagerError> {
+        let dir = self.root.join(dir);
+        std::fs::create_dir_all(&dir).map_err(io_error)?;
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        Ok(dir.join(format!("{}-{}", std::process::id(), sequence)))
+    }
+}
+
+/// Файл модели в директории: `model.gguf` или первая часть `model-00001-of-0000M.gguf`.
+pub fn model_file(dir: &Path) -> Option<PathBuf> {
+    let single = dir.join(MODEL_FILE_NAME);
+    if single.is_file() {
+        return Some(single);
+    }
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| is_first_shard(path) && path.is_file())
+}
+
+/// Путь — первая часть разбитой модели хранилища.
+pub fn is_first_shard(path: &Path) -> bool {
+    SplitName::parse(path).is_some_and(|split| {
+        split.index == 1
+            && split
+                .prefix
+                .file_name()
+                .is_some_and(|name| name == SHARD_PREFIX)
+    })
+}
+
+fn copy_synced(source: &Path, target: &Path) -> std::io::Result<()> {
+    let mut reader = std::fs::File::open(source)?;
+    let mut writer = std::fs::File::create(target)?;
+    std::io::copy(&mut reader, &mut writer)?;
+    writer.sync_all()
+}
+
+fn io_error(err: std::io::Error) -> ModelManagerError {
+    ModelManagerError::Io(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{basic_metadata, write_split_gguf, write_test_gguf};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rejects_ids_escaping_store() {
+        for id in [
+            "../x",
+            "qwen3/..",
+            "/abs",
+            "a//b",
+            ".hidden",
+            "a/b/c/d/e",
+            "",
+        ] {
+            assert!(
+                matches!(
+                    ModelId::parse(id),
+                    Err(ModelManagerError::InvalidModelId(_))
+                ),
+                "{id}"
+            );
+        }
+        assert!(ModelId::for_variant(ModelType::Qwen3, "../../../sdcard/x").is_err());
+        assert_eq!(
+            ModelId::for_variant(ModelType::Gemma3, "1b-it")
+                .unwrap()
+                .as_str(),
+            "gemma3/1b-it"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_rejects_symlink_outside_store() {
+        let tmp = tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        write_test_gguf(
+            &outside.join(MODEL_FILE_NAME),
+            &basic_metadata("qwen3"),
+            &[("w", &[4])],
+        );
+        let store = ModelStore::new(tmp.path().join("store"));
+        std::fs::create_dir_all(store.root().join("qwen3")).unwrap();
+        std::os::unix::fs::symlink(&outside, store.root().join("qwen3").join("link")).unwrap();
+
+        let id = ModelId::parse("qwen3/link").unwrap();
+        assert!(matches!(
+            store.resolve(&id),
+            Err(ModelManagerError::OutsideStore(_))
+        ));
+    }
+
+    #[test]
+    fn test_import_rename_delete() {
+        let tmp = tempdir().unwrap();
+        let source = tmp.path().join("download.gguf");
+        write_test_gguf(&source, &basic_metadata("qwen3"), &[("w", &[4])]);
+        let store = ModelStore::new(tmp.path().join("store"));
+        let id = ModelId::parse("qwen3/0.6b").unwrap();
+
+        store.import(&source, &id).unwrap();
+        assert!(store.resolve(&id).is_ok());
+        assert!(matches!(
+            store.import(&source, &id),
+            Err(ModelManagerError::ModelAlreadyExists(_))
+        ));
+        // Временные файлы не остаются в служебной директории
+        let staging = std::fs::read_dir(store.root().join(STAGING_DIR)).unwrap();
+        assert_eq!(staging.count(), 0);
+
+        let renamed = ModelId::parse("qwen3/custom").unwrap();
+        store.rename(&id, &renamed).unwrap();
+        assert!(store.resolve(&id).is_err());
+
+        store.delete(&renamed).unwrap();
+        assert!(!store.model_dir(&renamed).exists());
+        assert!(matches!(
+            store.delete(&renamed),
+            Err(ModelManagerError::ModelFileMissing(_))
+        ));
+    }
+
+    #[test]
+    fn test_import_split_model() {
+        let tmp = tempdir().unwrap();
+        let shards = write_split_gguf(
+            &tmp.path().join("download"),
+            "Qwen3-8B-Q4_K_M",
+            &[&[("a", &[4])], &[("b", &[4])]],
+        );
+        let store = ModelStore::new(tmp.path().join("store"));
+        let id = ModelId::parse("qwen3/8b").unwrap();
+
+        let path = store.import(&shards[1], &id).unwrap();
+        assert!(path.ends_with("model-00001-of-00002.gguf"));
+        assert!(store
+            .model_dir(&id)
+            .join("model-00002-of-00002.gguf")
+            .is_file());
+        assert_eq!(store.resolve(&id).unwrap(), path.canonicalize().unwrap());
+
+        let catalog = crate::model_catalog::ModelCatalog::new(store.root());
+        let entries = catalog.scan();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].model_id.as_deref(), Some("qwen3/8b"));
+
+        store.delete(&id).unwrap();
+        assert!(store.resolve(&id).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+//! Веса моделей: квантованные из GGUF или полные (F16/BF16/F32) из safetensors.
+//! Обе реализации возвращают логиты последней позиции в одной форме и в F32,
+//! поэтому движок инференса не зависит от формата весов.
+
+use candle_core::{DType, Result, Tensor};
+use candle_transformers::models::quantized_gemma3::ModelWeights as QuantizedGemma3;
+use candle_transformers::models::{gemma3, qwen3};
+
+use crate::quantized_qwen3::ModelWeights as QuantizedQwen3;
+
+/// Веса Qwen3.
+#[derive(Debug, Clone)]
+pub enum Qwen3Weights {
+    Quantized(QuantizedQwen3),
+    Full(qwen3::ModelForCausalLM),
+}
+
+impl Qwen3Weights {
+    /// Логиты последней позиции формы `[batch, vocab]`.
+    pub fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        match self {
+            Qwen3Weights::Quantized(model) => model.forward(input, offset),
+            Qwen3Weights::Full(model) => last_logits(model.forward(input, offset)?),
+        }
+    }
+
+    /// Очищает KV-кеш перед новой последовательностью.
+    pub fn clear_kv_cache(&mut self) {
+        match self {
+            Qwen3Weights::Quantized(model) => model.clear_kv_cache(),
+            Qwen3Weights::Full(model) => model.clear_kv_cache(),
+        }
+    }
+}
+
+/// Веса Gemma3.
+#[derive(Debug, Clone)]
+pub enum Gemma3Weights {
+    Quantized(QuantizedGemma3),
+    Full(gemma3::Model),
+}
+
+impl Gemma3Weights {
+    /// Логиты последней позиции формы `[batch, vocab]`.
+    pub fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        match self {
+            Gemma3Weights::Quantized(model) => model.forward(input, offset),
+            Gemma3Weights::Full(model) => last_logits(model.forward(input, offset)?),
+        }
+    }
+
+    /// Очищает KV-кеш перед новой последовательностью.
+    pub fn clear_kv_cache(&mut self) {
+        match self {
+            // Квантованная Gemma3 сама сбрасывает кеш на позиции 0
+            Gemma3Weights::Quantized(_) => {}
+            Gemma3Weights::Full(model) => model.clear_kv_cache(),
+        }
+    }
+}
+
+/// Полные модели candle возвращают `[batch, 1, vocab]` в типе весов.
+fn last_logits(logits: Tensor) -> Result<Tensor> {
+    logits.squeeze(1)?.to_dtype(DType::F32)
+}
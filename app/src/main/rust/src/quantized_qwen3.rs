@@ -0,0 +1,533 @@
+//! Квантованная Qwen3 из GGUF на основе реализации candle (`quantized_qwen3`).
+//! В отличие от неё возвращает логиты всех позиций (проверка черновика одним проходом)
+//! и позволяет откатить KV-кеш до заданной длины (отброшенные токены черновика).
+
+use std::io::{Read, Seek};
+use std::sync::Arc;
+
+use candle_core::quantized::{gguf_file, QTensor};
+use candle_core::{DType, Device, Module, Result, Tensor};
+use candle_nn::{Activation, Embedding};
+use candle_transformers::models::with_tracing::QMatMul;
+use candle_transformers::quantized_nn::RmsNorm;
+use candle_transformers::utils::repeat_kv;
+
+struct Gguf<'a, R: Read + Seek> {
+    content: gguf_file::Content,
+    reader: &'a mut R,
+    device: Device,
+}
+
+impl<R: Read + Seek> Gguf<'_, R> {
+    fn tensor(&mut self, name: &str) -> Result<QTensor> {
+        self.content.tensor(self.reader, name, &self.device)
+    }
+
+    fn qmatmul(&mut self, name: &str) -> Result<QMatMul> {
+        QMatMul::from_weights(self.tensor(name)?.into())
+    }
+
+    fn rms_norm(&mut self, name: &str, eps: f64) -> Result<RmsNorm> {
+        RmsNorm::from_qtensor(self.tensor(name)?, eps)
+    }
+
+    fn metadata_u32(&self, key: &str) -> Result<usize> {
+        match self.content.metadata.get(key) {
+            Some(value) => Ok(value.to_u32()? as usize),
+            None => candle_core::bail!("cannot find {key} in metadata"),
+        }
+    }
+
+    fn metadata_f32(&self, key: &str) -> Result<f64> {
+        match self.content.metadata.get(key) {
+            Some(value) => Ok(value.to_f32()? as f64),
+            None => candle_core::bail!("cannot find {key} in metadata"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Mlp {
+    gate_proj: QMatMul,
+    up_proj: QMatMul,
+    down_proj: QMatMul,
+}
+
+impl Module for Mlp {
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        let gate = self.gate_proj.forward(x)?.apply(&Activation::Silu)?;
+        let up = self.up_proj.forward(x)?;
+        self.down_proj.forward(&(gate * up)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RotaryEmbedding {
+    sin: Tensor,
+    cos: Tensor,
+}
+
+impl RotaryEmbedding {
+    fn new(
+        dtype: DType,
+        head_dim: usize,
+        max_len: usize,
+        theta: f64,
+        dev: &Device,
+    ) -> Result<Self> {
+        let inv_freq: Vec<_> = (0..head_dim)
+            .step_by(2)
+            .map(|i| 1f32 / theta.powf(i as f64 / head_dim as f64) as f32)
+            .collect();
+        let inv_freq_len = inv_freq.len();
+        let inv_freq = Tensor::from_vec(inv_freq, (1, inv_freq_len), dev)?.to_dtype(dtype)?;
+        let t = Tensor::arange(0u32, max_len as u32, dev)?
+            .to_dtype(dtype)?
+            .reshape((max_len, 1))?;
+        let freqs = t.matmul(&inv_freq)?;
+        Ok(Self {
+            sin: freqs.sin()?,
+            cos: freqs.cos()?,
+        })
+    }
+
+    /// Углы для `len` позиций подряд начиная с `offset`: `[len, D/2]`.
+    fn range(&self, offset: usize, len: usize) -> Result<Rope> {
+        Ok(Rope {
+            cos: self.cos.narrow(0, offset, len)?,
+            sin: self.sin.narrow(0, offset, len)?,
+        })
+    }
+
+    /// Углы для своих позиций каждой последовательности пакета: `[B, L, D/2]`.
+    fn gather(&self, positions: &Tensor) -> Result<Rope> {
+        let (b, l) = positions.dims2()?;
+        let flat = positions.flatten_all()?;
+        let half = self.cos.dim(1)?;
+        Ok(Rope {
+            cos: self.cos.index_select(&flat, 0)?.reshape((b, l, half))?,
+            sin: self.sin.index_select(&flat, 0)?.reshape((b, l, half))?,
+        })
+    }
+}
+
+/// Углы RoPE для текущего прохода.
+struct Rope {
+    cos: Tensor,
+    sin: Tensor,
+}
+
+impl Rope {
+    /// RoPE для q, k формы `B x H x L x D`.
+    fn apply(&self, q: &Tensor, k: &Tensor) -> Result<(Tensor, Tensor)> {
+        let cos = self.cos.to_dtype(q.dtype())?;
+        let sin = self.sin.to_dtype(q.dtype())?;
+        let q = candle_nn::rotary_emb::rope(&q.contiguous()?, &cos, &sin)?;
+        let k = candle_nn::rotary_emb::rope(&k.contiguous()?, &cos, &sin)?;
+        Ok((q, k))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Attention {
+    q_proj: QMatMul,
+    k_proj: QMatMul,
+    v_proj: QMatMul,
+    o_proj: QMatMul,
+    q_norm: RmsNorm,
+    k_norm: RmsNorm,
+    num_heads: usize,
+    num_kv_heads: usize,
+    head_dim: usize,
+    /// Ключи и значения формы `B x H_kv x T x D`.
+    kv_cache: Option<(Tensor, Tensor)>,
+}
+
+impl Attention {
+    fn forward(&mut self, x: &Tensor, mask: Option<&Tensor>, rope: &Rope) -> Result<Tensor> {
+        let (b, l, _) = x.dims3()?;
+        let q = self
+            .q_proj
+            .forward(x)?
+            .reshape((b, l, self.num_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        let k = self
+            .k_proj
+            .forward(x)?
+            .reshape((b, l, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?;
+        let v = self
+            .v_proj
+            .forward(x)?
+            .reshape((b, l, self.num_kv_heads, self.head_dim))?
+            .transpose(1, 2)?;
+
+        let q = self.q_norm.forward(&q.flatten(0, 2)?)?.reshape((
+            b,
+            self.num_heads,
+            l,
+            self.head_dim,
+        ))?;
+        let k = self.k_norm.forward(&k.flatten(0, 2)?)?.reshape((
+            b,
+            self.num_kv_heads,
+            l,
+            self.head_dim,
+        ))?;
+        let (q, k) = rope.apply(&q, &k)?;
+
+        let (k, v) = match &self.kv_cache {
+            Some((cached_k, cached_v)) => (
+                Tensor::cat(&[cached_k, &k], 2)?,
+                Tensor::cat(&[cached_v, &v], 2)?,
+            ),
+            None => (k, v),
+        };
+        self.kv_cache = Some((k.clone(), v.clone()));
+
+        let groups = self.num_heads / self.num_kv_heads;
+        let k = repeat_kv(k, groups)?.contiguous()?;
+        let v = repeat_kv(v, groups)?.contiguous()?;
+        let scale = 1.0 / (self.head_dim as f64).sqrt();
+        let mut scores = (q.matmul(&k.transpose(2, 3)?)? * scale)?;
+        if let Some(mask) = mask {
+            scores = scores.broadcast_add(&mask.to_dtype(scores.dtype())?)?;
+        }
+        let probs = candle_nn::ops::softmax_last_dim(&scores)?;
+        let context =
+            probs
+                .matmul(&v)?
+                .transpose(1, 2)?
+                .reshape((b, l, self.num_heads * self.head_dim))?;
+        self.o_proj.forward(&context)
+    }
+
+    fn truncate_kv_cache(&mut self, len: usize) -> Result<()> {
+        if let Some((k, v)) = &self.kv_cache {
+            if len == 0 {
+                self.kv_cache = None;
+            } else if len < k.dim(2)? {
+                self.kv_cache = Some((k.narrow(2, 0, len)?, v.narrow(2, 0, len)?));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Layer {
+    attention: Attention,
+    mlp: Mlp,
+    attn_norm: RmsNorm,
+    ffn_norm: RmsNorm,
+}
+
+impl Layer {
+    fn forward(&mut self, x: &Tensor, mask: Option<&Tensor>, rope: &Rope) -> Result<Tensor> {
+        let h = self
+            .attention
+            .forward(&self.attn_norm.forward(x)?, mask, rope)?;
+        let x = (x + h)?;
+        let h = self.ffn_norm.forward(&x)?.apply(&self.mlp)?;
+        x + h
+    }
+}
+
+/// Веса квантованной Qwen3 с KV-кешем.
+#[derive(Debug, Clone)]
+pub struct ModelWeights {
+    embed_tokens: Embedding,
+    layers: Vec<Layer>,
+    norm: RmsNorm,
+    lm_head: QMatMul,
+    rotary: Arc<RotaryEmbedding>,
+    device: Device,
+    dtype: DType,
+}
+
+impl ModelWeights {
+    /// Читает веса из GGUF (архитектура `qwen3`).
+    pub fn from_gguf<R: Read + Seek>(
+        content: gguf_file::Content,
+        reader: &mut R,
+        device: &Device,
+    ) -> Result<Self> {
+        let mut gg = Gguf {
+            content,
+            reader,
+            device: device.clone(),
+        };
+        let num_heads = gg.metadata_u32("qwen3.attention.head_count")?;
+        let num_kv_heads = gg.metadata_u32("qwen3.attention.head_count_kv")?;
+        let head_dim = gg.metadata_u32("qwen3.attention.key_length")?;
+        let num_layers = gg.metadata_u32("qwen3.block_count")?;
+        let hidden_size = gg.metadata_u32("qwen3.embedding_length")?;
+        let max_len = gg.metadata_u32("qwen3.context_length")?;
+        let eps = gg.metadata_f32("qwen3.attention.layer_norm_rms_epsilon")?;
+        let rope_theta = gg.metadata_f32("qwen3.rope.freq_base")?;
+        let dtype = match gg.content.metadata.get("general.dtype") {
+            Some(value) if matches!(value.to_u32(), Ok(0)) => DType::F32,
+            _ => DType::F16,
+        };
+
+        let embed_tokens = Embedding::new(
+            gg.tensor("token_embd.weight")?.dequantize(device)?,
+            hidden_size,
+        );
+        let rotary = Arc::new(RotaryEmbedding::new(
+            dtype, head_dim, max_len, rope_theta, device,
+        )?);
+        let mut layers = Vec::with_capacity(num_layers);
+        for i in 0..num_layers {
+            let prefix = format!("blk.{i}");
+            let attention = Attention {
+                q_proj: gg.qmatmul(&format!("{prefix}.attn_q.weight"))?,
+                k_proj: gg.qmatmul(&format!("{prefix}.attn_k.weight"))?,
+                v_proj: gg.qmatmul(&format!("{prefix}.attn_v.weight"))?,
+                o_proj: gg.qmatmul(&format!("{prefix}.attn_output.weight"))?,
+                q_norm: gg.rms_norm(&format!("{prefix}.attn_q_norm.weight"), eps)?,
+                k_norm: gg.rms_norm(&format!("{prefix}.attn_k_norm.weight"), eps)?,
+                num_heads,
+                num_kv_heads,
+                head_dim,
+                kv_cache: None,
+            };
+            let mlp = Mlp {
+                gate_proj: gg.qmatmul(&format!("{prefix}.ffn_gate.weight"))?,
+                up_proj: gg.qmatmul(&format!("{prefix}.ffn_up.weight"))?,
+                down_proj: gg.qmatmul(&format!("{prefix}.ffn_down.weight"))?,
+            };
+            layers.push(Layer {
+                attention,
+                mlp,
+                attn_norm: gg.rms_norm(&format!("{prefix}.attn_norm.weight"), eps)?,
+                ffn_norm: gg.rms_norm(&format!("{prefix}.ffn_norm.weight"), eps)?,
+            });
+        }
+        let norm = gg.rms_norm("output_norm.weight", eps)?;
+        // Без output.weight используются связанные с эмбеддингами веса
+        let lm_head = match gg.tensor("output.weight") {
+            Ok(tensor) => tensor,
+            Err(_) => gg.tensor("token_embd.weight")?,
+        };
+        Ok(Self {
+            embed_tokens,
+            layers,
+            norm,
+            lm_head: QMatMul::from_weights(lm_head.into())?,
+            rotary,
+            device: device.clone(),
+            dtype,
+        })
+    }
+
+    /// Логиты последней позиции формы `[batch, vocab]`.
+    /// Проход с `offset == 0` начинает новую последовательность и сбрасывает KV-кеш.
+    pub fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        let hidden = self.hidden_states(input, offset)?;
+        let l = hidden.dim(1)?;
+        self.lm_head
+            .forward(&hidden.narrow(1, l - 1, 1)?)?
+            .squeeze(1)
+    }
+
+    /// Логиты всех позиций формы `[batch, seq, vocab]`.
+    pub fn forward_all(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        let hidden = self.hidden_states(input, offset)?;
+        self.lm_head.forward(&hidden)
+    }
+
+    /// Логиты последней позиции для пакета последовательностей `[batch, vocab]`.
+    /// `positions` — позиции токенов `[batch, seq]` (у каждой последовательности свои,
+    /// например при отступе слева), `mask` — аддитивная маска внимания
+    /// `[batch, 1, seq, kv_len]` с учётом уже накопленного KV-кеша.
+    pub fn forward_batch(
+        &mut self,
+        input: &Tensor,
+        positions: &Tensor,
+        mask: &Tensor,
+    ) -> Result<Tensor> {
+        let rope = self.rotary.gather(positions)?;
+        let mask = mask.to_dtype(self.dtype)?;
+        let hidden = self.run_layers(input, Some(&mask), &rope)?;
+        let l = hidden.dim(1)?;
+        self.lm_head
+            .forward(&hidden.narrow(1, l - 1, 1)?)?
+            .squeeze(1)
+    }
+
+    /// Устройство, на котором размещены веса.
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Число позиций в KV-кеше.
+    pub fn kv_len(&self) -> usize {
+        self.layers
+            .first()
+            .and_then(|layer| layer.attention.kv_cache.as_ref())
+            .and_then(|(k, _)| k.dim(2).ok())
+            .unwrap_or(0)
+    }
+
+    /// Откатывает KV-кеш до первых `len` позиций.
+    pub fn truncate_kv_cache(&mut self, len: usize) -> Result<()> {
+        for layer in &mut self.layers {
+            layer.attention.truncate_kv_cache(len)?;
+        }
+        Ok(())
+    }
+
+    /// Оставляет в KV-кеше только строки пакета `rows` (завершённые последовательности
+    /// больше не участвуют в проходах).
+    pub fn select_batch_rows(&mut self, rows: &Tensor) -> Result<()> {
+        for layer in &mut self.layers {
+            if let Some((k, v)) = &layer.attention.kv_cache {
+                layer.attention.kv_cache =
+                    Some((k.index_select(rows, 0)?, v.index_select(rows, 0)?));
+            }
+        }
+        Ok(())
+    }
+
+    /// Очищает KV-кеш.
+    pub fn clear_kv_cache(&mut self) {
+        for layer in &mut self.layers {
+            layer.attention.kv_cache = None;
+        }
+    }
+
+    fn hidden_states(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        if offset == 0 {
+            self.clear_kv_cache();
+        }
+        let l = input.dim(1)?;
+        let mask = if l == 1 {
+            None
+        } else {
+            Some(self.causal_mask(l, offset)?)
+        };
+        let rope = self.rotary.range(offset, l)?;
+        self.run_layers(input, mask.as_ref(), &rope)
+    }
+
+    fn run_layers(&mut self, input: &Tensor, mask: Option<&Tensor>, rope: &Rope) -> Result<Tensor> {
+        let mut h = self.embed_tokens.forward(input)?;
+        for layer in &mut self.layers {
+            h = layer.forward(&h, mask, rope)?;
+        }
+        self.norm.forward(&h)
+    }
+
+    fn causal_mask(&self, tgt: usize, offset: usize) -> Result<Tensor> {
+        let mask: Vec<f32> = (0..tgt)
+            .flat_map(|i| {
+                (0..tgt + offset).map(move |j| {
+                    if j <= i + offset {
+                        0.
+                    } else {
+                        f32::NEG_INFINITY
+                    }
+                })
+            })
+            .collect();
+        Tensor::from_slice(&mask, (1, 1, tgt, tgt + offset), &self.device)?.to_dtype(self.dtype)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write_qwen3_gguf;
+    use std::io::Cursor;
+
+    fn load_model(dir: &std::path::Path) -> ModelWeights {
+        let path = dir.join("qwen3.gguf");
+        write_qwen3_gguf(&path, 0);
+        let mut reader = Cursor::new(std::fs::read(&path).unwrap());
+        let content = gguf_file::Content::read(&mut reader).unwrap();
+        ModelWeights::from_gguf(content, &mut reader, &Device::Cpu).unwrap()
+    }
+
+    #[test]
+    fn test_forward_all_matches_incremental_forward() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut model = load_model(tmp.path());
+        let input = Tensor::new(&[[1u32, 5, 3]], &Device::Cpu).unwrap();
+        let all = model.forward_all(&input, 0).unwrap();
+        assert_eq!(all.dims(), &[1, 3, 8]);
+        assert_eq!(model.kv_len(), 3);
+
+        model.clear_kv_cache();
+        let mut last = None;
+        for (pos, token) in [1u32, 5, 3].into_iter().enumerate() {
+            let input = Tensor::new(&[[token]], &Device::Cpu).unwrap();
+            last = Some(model.forward(&input, pos).unwrap());
+        }
+        let expected = all
+            .get(0)
+            .unwrap()
+            .get(2)
+            .unwrap()
+            .to_vec1::<f32>()
+            .unwrap();
+        let actual = last.unwrap().get(0).unwrap().to_vec1::<f32>().unwrap();
+        for (a, b) in expected.iter().zip(&actual) {
+            assert!((a - b).abs() < 1e-3, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn test_truncate_rolls_back_kv_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut model = load_model(tmp.path());
+        let prefix = Tensor::new(&[[1u32, 5]], &Device::Cpu).unwrap();
+        model.forward(&prefix, 0).unwrap();
+        let next = Tensor::new(&[[3u32]], &Device::Cpu).unwrap();
+        let expected = model.forward(&next, 2).unwrap().to_vec2::<f32>().unwrap();
+
+        // Отброшенный черновик не влияет на следующий шаг
+        let draft = Tensor::new(&[[3u32, 7, 2]], &Device::Cpu).unwrap();
+        model.truncate_kv_cache(2).unwrap();
+        model.forward_all(&draft, 2).unwrap();
+        model.truncate_kv_cache(2).unwrap();
+        assert_eq!(model.kv_len(), 2);
+        let actual = model.forward(&next, 2).unwrap().to_vec2::<f32>().unwrap();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_logits_match_upstream_quantized_qwen3() {
+        use candle_transformers::models::quantized_qwen3::ModelWeights as Upstream;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let mut model = load_model(tmp.path());
+        let mut reader = Cursor::new(std::fs::read(tmp.path().join("qwen3.gguf")).unwrap());
+        let content = gguf_file::Content::read(&mut reader).unwrap();
+        let mut upstream = Upstream::from_gguf(content, &mut reader, &Device::Cpu).unwrap();
+
+        // Промпт, затем пошаговое декодирование с накоплением KV-кеша
+        let steps: [&[u32]; 3] = [&[1, 5, 3, 2], &[4], &[6]];
+        let mut offset = 0;
+        for tokens in steps {
+            let input = Tensor::new(tokens, &Device::Cpu)
+                .unwrap()
+                .unsqueeze(0)
+                .unwrap();
+            let expected = upstream
+                .forward(&input, offset)
+                .unwrap()
+                .to_vec2::<f32>()
+                .unwrap();
+            let actual = model
+                .forward(&input, offset)
+                .unwrap()
+                .to_vec2::<f32>()
+                .unwrap();
+            for (a, b) in expected[0].iter().zip(&actual[0]) {
+                assert!((a - b).abs() < 1e-4, "{a} != {b} at offset {offset}");
+            }
+            offset += tokens.len();
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Защита от зацикливания генерации: DRY-штраф (Don't Repeat Yourself) за продолжение
+//! уже встречавшихся в контексте последовательностей и детектор повторяющихся циклов
+//! n-грамм в сгенерированном тексте.
+
+use std::collections::{HashMap, HashSet};
+
+use candle_core::{DType, Result, Tensor};
+
+/// Параметры DRY-сэмплера.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct DryConfig {
+    /// Множитель штрафа; 0 отключает DRY.
+    pub multiplier: f32,
+    /// Основание экспоненты: штраф растёт как `base^(длина повтора - allowed_length)`.
+    pub base: f32,
+    /// Длина повтора, который ещё не штрафуется.
+    pub allowed_length: usize,
+    /// Строки, через которые повтор не продолжается (учитываются те, что кодируются
+    /// одним токеном).
+    pub sequence_breakers: Vec<String>,
+}
+
+impl Default for DryConfig {
+    fn default() -> Self {
+        Self {
+            multiplier: 0.8,
+            base: 1.75,
+            allowed_length: 2,
+            sequence_breakers: ["\n", ":", "\"", "*"].map(String::from).to_vec(),
+        }
+    }
+}
+
+/// Реакция на обнаруженный цикл.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopAction {
+    /// Остановить генерацию с причиной `FinishReason::Loop`.
+    Stop,
+    /// Штрафовать продолжение цикла сильнее с каждым шагом; если не помогло
+    /// за `max_escalations` шагов — остановить.
+    Escalate,
+}
+
+/// Параметры детектора циклов.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct LoopDetection {
+    /// Наибольшая длина цикла в токенах.
+    pub max_period: usize,
+    /// Сколько повторов цикла подряд считается зацикливанием.
+    pub min_repeats: usize,
+    /// Сколько токенов должны покрывать повторы (защищает от ложных срабатываний
+    /// на коротких циклах вроде `---`).
+    pub min_span: usize,
+    pub action: LoopAction,
+    /// Прибавка к штрафу продолжения цикла за каждый шаг эскалации.
+    pub escalation_step: f32,
+    pub max_escalations: u32,
+}
+
+impl Default for LoopDetection {
+    fn default() -> Self {
+        Self {
+            max_period: 16,
+            min_repeats: 3,
+            min_span: 12,
+            action: LoopAction::Stop,
+            escalation_step: 2.0,
+            max_escalations: 8,
+        }
+    }
+}
+
+/// Штрафы DRY и детектор циклов для одной генерации. `Default` — без штрафов и детектора;
+/// копия начинает с того же состояния (для вариантов и последовательностей пакета).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RepetitionGuard {
+    dry: Option<DryConfig>,
+    breakers: HashSet<u32>,
+    loops: Option<LoopDetection>,
+    /// Длина цикла, обнаруженного на предыдущем шаге.
+    period: Option<usize>,
+    escalation: u32,
+}
+
+impl RepetitionGuard {
+    pub(crate) fn new(
+        dry: Option<&DryConfig>,
+        loops: Option<LoopDetection>,
+        tokenizer: &tokenizers::Tokenizer,
+    ) -> Self {
+        let dry = dry.filter(|dry| dry.multiplier > 0.0).cloned();
+        let breakers = dry
+            .iter()
+            .flat_map(|dry| &dry.sequence_breakers)
+            .filter_map(|breaker| tokenizer.encode(breaker.as_str(), false).ok())
+            .filter(|encoding| encoding.get_ids().len() == 1)
+            .map(|encoding| encoding.get_ids()[0])
+            .collect();
+        Self {
+            dry,
+            breakers,
+            loops,
+            period: None,
+            escalation: 0,
+        }
+    }
+
+    /// Штрафует логиты `[vocab]` для следующего токена. `context` — промпт
+    /// и сгенерированные токены, ответ начинается с `prompt_len`.
+    pub(crate) fn apply(
+        &self,
+        logits: &Tensor,
+        context: &[u32],
+        prompt_len: usize,
+    ) -> Result<Tensor> {
+        let penalties = self.penalties(context, prompt_len);
+        if penalties.is_empty() {
+            return Ok(logits.clone());
+        }
+        let mut values = logits.to_dtype(DType::F32)?.to_vec1::<f32>()?;
+        subtract(&mut values, penalties);
+        Tensor::from_vec(values, logits.dims(), logits.device())
+    }
+
+    /// То же, что `apply`, для логитов в срезе.
+    pub(crate) fn penalize(&self, logits: &mut [f32], context: &[u32], prompt_len: usize) {
+        subtract(logits, self.penalties(context, prompt_len));
+    }
+
+    fn penalties(&self, context: &[u32], prompt_len: usize) -> HashMap<u32, f32> {
+        let mut penalties = match &self.dry {
+            Some(dry) => dry_penalties(context, &self.breakers, dry),
+            None => HashMap::new(),
+        };
+        if let (Some(loops), Some(period)) = (&self.loops, self.period) {
+            if self.escalation > 0 && context.len() >= prompt_len + period {
+                let next = context[context.len() - period];
+                *penalties.entry(next).or_default() +=
+                    loops.escalation_step * self.escalation as f32;
+            }
+        }
+        penalties
+    }
+
+    /// Учитывает очередной токен ответа `generated`; `true` — генерацию нужно
+    /// остановить из-за цикла.
+    pub(crate) fn observe(&mut self, generated: &[u32]) -> bool {
+        let Some(loops) = self.loops else {
+            return false;
+        };
+        self.period = detect_cycle(generated, &loops);
+        if self.period.is_none() {
+            self.escalation = 0;
+            return false;
+        }
+        match loops.action {
+            LoopAction::Stop => true,
+            LoopAction::Escalate => {
+                self.escalation += 1;
+                self.escalation > loops.max_escalations
+            }
+        }
+    }
+}
+
+fn subtract(logits: &mut [f32], penalties: HashMap<u32, f32>) {
+    for (token, penalty) in penalties {
+        if let Some(value) = logits.get_mut(token as usize) {
+            *value -= penalty;
+        }
+    }
+}
+
+/// Штрафы DRY: токен, продолжающий повтор конца контекста длиной `n >= allowed_length`,
+/// получает `multiplier * base^(n - allowed_length)`.
+fn dry_penalties(context: &[u32], breakers: &HashSet<u32>, dry: &DryConfig) -> HashMap<u32, f32> {
+    let mut longest: HashMap<u32, usize> = HashMap::new();
+    let Some((&last, _)) = context.split_last() else {
+        return HashMap::new();
+    };
+    if breakers.contains(&last) {
+        return HashMap::new();
+    }
+    let end = context.len() - 1;
+    for i in (0..end).filter(|&i| context[i] == last) {
+        let next = context[i + 1];
+        if breakers.contains(&next) {
+            continue;
+        }
+        // Длина общего суффикса контекста и префикса, заканчивающегося в `i`
+        let mut length = 1;
+        while length <= i {
+            let token = context[i - length];
+            if breakers.contains(&token) || token != context[end - length] {
+                break;
+            }
+            length += 1;
+        }
+        let entry = longest.entry(next).or_default();
+        *entry = (*entry).max(length);
+    }
+    longest
+        .into_iter()
+        .filter(|&(_, length)| length >= dry.allowed_length)
+        .map(|(token, length)| {
+            let exponent = (length - dry.allowed_length) as i32;
+            (token, dry.multiplier * dry.base.powi(exponent))
+        })
+        .collect()
+}
+
+/// Длина цикла, которым заканчиваются `tokens`, если он повторился достаточно раз.
+fn detect_cycle(tokens: &[u32], loops: &LoopDetection) -> Option<usize> {
+    (1..=loops.max_period).find(|&period| {
+        let repeats = loops.min_repeats.max(loops.min_span.div_ceil(period));
+        let span = period * repeats;
+        span <= tokens.len() && {
+            let tail = &tokens[tokens.len() - span..];
+            tail.iter().skip(period).zip(tail).all(|(a, b)| a == b)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dry_penalizes_repeated_continuation() {
+        let dry = DryConfig::default();
+        // Конец контекста «1 2 3» уже встречался и продолжался токеном 9
+        let context = [1, 2, 3, 9, 1, 2, 3];
+        let penalties = dry_penalties(&context, &HashSet::new(), &dry);
+        assert_eq!(penalties.len(), 1);
+        assert!((penalties[&9] - 0.8 * 1.75).abs() < 1e-6);
+
+        // Разделитель обрывает совпадение, повтор короче allowed_length
+        let penalties = dry_penalties(&context, &HashSet::from([2]), &dry);
+        assert!(penalties.is_empty());
+
+        let guard = RepetitionGuard {
+            dry: Some(dry),
+            breakers: HashSet::new(),
+            loops: None,
+            period: None,
+            escalation: 0,
+        };
+        let logits = Tensor::zeros(10, DType::F32, &candle_core::Device::Cpu).unwrap();
+        let logits = guard.apply(&logits, &context, 0).unwrap();
+        let values = logits.to_vec1::<f32>().unwrap();
+        assert!(values[9] < 0.0);
+        assert!(values[..9].iter().all(|&value| value == 0.0));
+    }
+
+    #[test]
+    fn test_loop_detection_stops_or_escalates() {
+        let loops = LoopDetection::default();
+        let cycle: Vec<u32> = [5, 6, 7].repeat(4);
+        assert_eq!(detect_cycle(&cycle, &loops), Some(3));
+        assert_eq!(detect_cycle(&cycle[1..], &loops), None);
+        assert_eq!(detect_cycle(&[4; 11], &loops), None);
+        assert_eq!(detect_cycle(&[4; 12], &loops), Some(1));
+
+        let mut guard = RepetitionGuard {
+            dry: None,
+            breakers: HashSet::new(),
+            loops: Some(loops),
+            period: None,
+            escalation: 0,
+        };
+        assert!(!guard.observe(&cycle[1..]));
+        assert!(guard.observe(&cycle));
+
+        let mut guard = RepetitionGuard {
+            loops: Some(LoopDetection {
+                action: LoopAction::Escalate,
+                max_escalations: 2,
+                ..loops
+            }),
+            ..guard
+        };
+        assert!(!guard.observe(&cycle));
+        let logits = Tensor::zeros(10, DType::F32, &candle_core::Device::Cpu).unwrap();
+        let values = guard
+            .apply(&logits, &cycle, 0)
+            .unwrap()
+            .to_vec1::<f32>()
+            .unwrap();
+        // Продолжение цикла — токен 5
+        assert_eq!(values[5], -2.0);
+        assert!(!guard.observe(&cycle));
+        assert!(guard.observe(&cycle));
+
+        // Цикл прервался — эскалация сбрасывается
+        assert!(!guard.observe(&[1, 2, 3]));
+        assert_eq!(guard.escalation, 0);
+    }
+}
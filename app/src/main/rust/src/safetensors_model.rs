@@ -0,0 +1,290 @@
+//! Неквантованные модели Hugging Face: директория с `config.json`, `tokenizer.json`
+//! и весами `*.safetensors` (в том числе разбитыми на части). Веса отображаются в память
+//! и загружаются в F16/BF16/F32 через полные реализации моделей candle.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use candle_core::{DType, Device};
+use candle_nn::VarBuilder;
+use candle_transformers::models::{gemma3, qwen3};
+use memmap2::Mmap;
+use safetensors::SafeTensors;
+use serde_json::Value as Json;
+
+use crate::model_manager::{ActiveModel, ModelManagerError, ModelType};
+use crate::model_weights::{Gemma3Weights, Qwen3Weights};
+
+/// Имя файла конфигурации модели Hugging Face.
+pub const CONFIG_FILE_NAME: &str = "config.json";
+
+/// Разобранный `config.json`.
+pub(crate) struct HfConfig {
+    pub model_type: ModelType,
+    /// Параметры языковой модели (у мультимодальных моделей — вложенный `text_config`).
+    pub config: Json,
+    /// Префикс имён весов языковой модели в мультимодальном чекпойнте.
+    pub weight_prefix: Option<&'static str>,
+}
+
+/// Читает `config.json` и определяет архитектуру.
+pub(crate) fn read_hf_config(dir: &Path) -> Result<HfConfig, ModelManagerError> {
+    let config = read_json(&dir.join(CONFIG_FILE_NAME))?;
+    let (model_type, config, weight_prefix) = match config.get("model_type").and_then(Json::as_str)
+    {
+        Some("qwen3") => (ModelType::Qwen3, config, None),
+        Some("gemma3_text") => (ModelType::Gemma3, config, None),
+        // Мультимодальная Gemma3: параметры языковой модели во вложенном разделе
+        Some("gemma3") => (
+            ModelType::Gemma3,
+            config.get("text_config").cloned().unwrap_or(config),
+            Some("language_model"),
+        ),
+        other => {
+            return Err(ModelManagerError::Conversion(format!(
+                "неподдерживаемая архитектура '{}'",
+                other.unwrap_or("?")
+            )))
+        }
+    };
+    Ok(HfConfig {
+        model_type,
+        config,
+        weight_prefix,
+    })
+}
+
+/// Файлы весов модели: все `*.safetensors` директории по порядку или один указанный файл.
+/// Возвращает также директорию модели.
+pub(crate) fn safetensors_files(
+    input: &Path,
+) -> Result<(PathBuf, Vec<PathBuf>), ModelManagerError> {
+    let (dir, files) = if input.is_dir() {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(input)
+            .map_err(|e| ModelManagerError::Io(e.to_string()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "safetensors"))
+            .collect();
+        files.sort();
+        (input.to_path_buf(), files)
+    } else {
+        let dir = input.parent().unwrap_or(Path::new(".")).to_path_buf();
+        (dir, vec![input.to_path_buf()])
+    };
+    if files.is_empty() {
+        return Err(ModelManagerError::ModelFileMissing(format!(
+            "{}/*.safetensors",
+            dir.display()
+        )));
+    }
+    Ok((dir, files))
+}
+
+pub(crate) fn read_json(path: &Path) -> Result<Json, ModelManagerError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|_| ModelManagerError::ModelFileMissing(path.to_string_lossy().into_owned()))?;
+    serde_json::from_str(&text)
+        .map_err(|e| ModelManagerError::Conversion(format!("{}: {e}", path.display())))
+}
+
+pub(crate) fn map_file(path: &Path) -> Result<Mmap, ModelManagerError> {
+    let file = std::fs::File::open(path)
+        .map_err(|_| ModelManagerError::ModelFileMissing(path.to_string_lossy().into_owned()))?;
+    // SAFETY: файл открыт только на чтение и не изменяется, пока используется отображение.
+    unsafe { Mmap::map(&file) }.map_err(|e| ModelManagerError::Io(e.to_string()))
+}
+
+/// Тип весов по имени: `f16`, `bf16`, `f32` или имена PyTorch (`float16`, `bfloat16`, `float32`).
+pub fn parse_dtype(name: &str) -> Option<DType> {
+    match name.to_ascii_lowercase().as_str() {
+        "f16" | "float16" => Some(DType::F16),
+        "bf16" | "bfloat16" => Some(DType::BF16),
+        "f32" | "float32" => Some(DType::F32),
+        _ => None,
+    }
+}
+
+/// Как [`parse_dtype`], но с ошибкой для неизвестного имени.
+pub(crate) fn dtype_from_name(name: &str) -> Result<DType, ModelManagerError> {
+    parse_dtype(name)
+        .ok_or_else(|| ModelManagerError::Initialization(format!("неизвестный тип весов '{name}'")))
+}
+
+/// Чекпойнт Hugging Face, подготовленный к загрузке.
+pub struct SafetensorsCheckpoint {
+    dir: PathBuf,
+    files: Vec<PathBuf>,
+    config: HfConfig,
+    dtype: DType,
+}
+
+impl SafetensorsCheckpoint {
+    /// Открывает директорию модели. Без `dtype` используется `torch_dtype` из `config.json`.
+    pub fn open(dir: &Path, dtype: Option<DType>) -> Result<Self, ModelManagerError> {
+        let (dir, files) = safetensors_files(dir)?;
+        let config = read_hf_config(&dir)?;
+        let dtype = dtype
+            .or_else(|| {
+                ["torch_dtype", "dtype"]
+                    .iter()
+                    .find_map(|key| config.config.get(*key).and_then(Json::as_str))
+                    .and_then(parse_dtype)
+            })
+            .unwrap_or(DType::F32);
+        Ok(Self {
+            dir,
+            files,
+            config,
+            dtype,
+        })
+    }
+
+    /// Директория модели.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Архитектура модели.
+    pub fn model_type(&self) -> ModelType {
+        self.config.model_type
+    }
+
+    /// Тип, в котором загружаются веса.
+    pub fn dtype(&self) -> DType {
+        self.dtype
+    }
+
+    /// Оценка памяти: веса в выбранном типе и KV-кеш на `context_length` токенов.
+    pub fn estimate_bytes(&self, context_length: usize) -> Result<u64, ModelManagerError> {
+        let mut elements = 0u64;
+        for path in &self.files {
+            let mmap = map_file(path)?;
+            let tensors = SafeTensors::deserialize(&mmap)
+                .map_err(|e| ModelManagerError::Corrupted(format!("{}: {e}", path.display())))?;
+            elements += tensors
+                .tensors()
+                .iter()
+                .map(|(_, view)| view.shape().iter().product::<usize>() as u64)
+                .sum::<u64>();
+        }
+        let number = |key: &str| self.config.config.get(key).and_then(Json::as_u64);
+        let layers = number("num_hidden_layers").unwrap_or(0);
+        let heads = number("num_attention_heads").unwrap_or(1).max(1);
+        let kv_heads = number("num_key_value_heads").unwrap_or(heads);
+        let head_dim =
+            number("head_dim").unwrap_or_else(|| number("hidden_size").unwrap_or(0) / heads);
+        let kv_elements = 2 * layers * kv_heads * head_dim * context_length as u64;
+        Ok((elements + kv_elements) * self.dtype.size_in_bytes() as u64)
+    }
+
+    /// Строит модель на устройстве `device`.
+    pub(crate) fn build(&self, device: &Device) -> Result<ActiveModel, ModelManagerError> {
+        // SAFETY: файлы весов не изменяются, пока модель загружена.
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&self.files, self.dtype, device) }?;
+        let vb = match self.config.weight_prefix {
+            Some(prefix) => vb.pp(prefix),
+            None => vb,
+        };
+        let invalid_config =
+            |e: serde_json::Error| ModelManagerError::Initialization(format!("config.json: {e}"));
+        let config = &self.config.config;
+        let number = |key: &str| config.get(key).and_then(Json::as_u64).unwrap_or(0);
+        let model = match self.config.model_type {
+            ModelType::Qwen3 => {
+                let head_dim = number("hidden_size") / number("num_attention_heads").max(1);
+                let config = with_defaults(
+                    config,
+                    &[
+                        ("head_dim", Json::from(head_dim)),
+                        ("attention_bias", Json::Bool(false)),
+                        ("tie_word_embeddings", Json::Bool(false)),
+                        ("sliding_window", Json::Null),
+                        ("max_window_layers", Json::from(number("num_hidden_layers"))),
+                        ("use_sliding_window", Json::Bool(false)),
+                        ("hidden_act", Json::from("silu")),
+                    ],
+                );
+                let config: qwen3::Config =
+                    serde_json::from_value(config).map_err(invalid_config)?;
+                let model = qwen3::ModelForCausalLM::new(&config, vb)
+                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
+                ActiveModel::Qwen(Arc::new(std::sync::Mutex::new(Qwen3Weights::Full(model))))
+            }
+            ModelType::Gemma3 => {
+                let config = with_defaults(
+                    config,
+                    &[
+                        ("attention_bias", Json::Bool(false)),
+                        ("hidden_activation", Json::from("gelu_pytorch_tanh")),
+                        ("sliding_window_pattern", Json::from(6)),
+                        ("rope_local_base_freq", Json::from(10_000.0)),
+                        ("query_pre_attn_scalar", Json::from(number("head_dim"))),
+                    ],
+                );
+                let config: gemma3::Config =
+                    serde_json::from_value(config).map_err(invalid_config)?;
+                let model = gemma3::Model::new(false, &config, vb)
+                    .map_err(|e| ModelManagerError::Initialization(e.to_string()))?;
+                ActiveModel::Gemma(Arc::new(std::sync::Mutex::new(Gemma3Weights::Full(model))))
+            }
+        };
+        Ok(model)
+    }
+}
+
+/// Добавляет отсутствующие в `config.json` параметры со значениями по умолчанию Transformers.
+fn with_defaults(config: &Json, defaults: &[(&str, Json)]) -> Json {
+    let mut config = config.clone();
+    if let Json::Object(map) = &mut config {
+        for (key, value) in defaults {
+            map.entry(*key).or_insert_with(|| value.clone());
+        }
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write_qwen3_safetensors;
+    use candle_core::Tensor;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_builds_full_precision_qwen3() {
+        let tmp = tempdir().unwrap();
+        write_qwen3_safetensors(tmp.path());
+
+        let checkpoint = SafetensorsCheckpoint::open(tmp.path(), None).unwrap();
+        assert_eq!(checkpoint.model_type(), ModelType::Qwen3);
+        assert_eq!(checkpoint.dtype(), DType::BF16);
+        // 672 веса + KV-кеш 2 * 1 слой * 1 голова * 4 * 16 токенов, по 2 байта
+        assert_eq!(checkpoint.estimate_bytes(16).unwrap(), (672 + 128) * 2);
+
+        let checkpoint = SafetensorsCheckpoint::open(tmp.path(), Some(DType::F32)).unwrap();
+        let ActiveModel::Qwen(model) = checkpoint.build(&Device::Cpu).unwrap() else {
+            panic!("ожидалась модель Qwen3");
+        };
+        let input = Tensor::new(&[[1u32, 2, 3]], &Device::Cpu).unwrap();
+        let logits = model.lock().unwrap().forward(&input, 0).unwrap();
+        assert_eq!(logits.dims(), &[1, 8]);
+        assert_eq!(logits.dtype(), DType::F32);
+    }
+
+    #[test]
+    fn test_rejects_unknown_architecture() {
+        let tmp = tempdir().unwrap();
+        std::fs::write(
+            tmp.path().join(CONFIG_FILE_NAME),
+            r#"{"model_type": "llama"}"#,
+        )
+        .unwrap();
+        std::fs::write(tmp.path().join("model.safetensors"), b"").unwrap();
+        assert!(matches!(
+            SafetensorsCheckpoint::open(tmp.path(), None),
+            Err(ModelManagerError::Conversion(_))
+        ));
+        assert_eq!(parse_dtype("bfloat16"), Some(DType::BF16));
+        assert_eq!(parse_dtype("q4_0"), None);
+    }
+}
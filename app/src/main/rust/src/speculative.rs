@@ -0,0 +1,659 @@
+//! Спекулятивное декодирование: небольшая черновая модель с тем же токенизатором
+//! предлагает несколько токенов, целевая модель проверяет их одним проходом.
+//! Токены принимаются по правилу rejection sampling (принять `x` с вероятностью
+//! `min(1, p(x)/q(x))`, при отказе выбрать из нормированного `max(0, p - q)`),
+//! поэтому распределение результата совпадает с генерацией одной целевой моделью.
+
+use std::collections::HashSet;
+
+use candle_core::{DType, Device, Result, Tensor};
+use serde::{Deserialize, Serialize};
+
+use crate::model_inference::GenerationConfig;
+use crate::model_manager::LoadedModelSnapshot;
+use crate::quantized_qwen3::ModelWeights as QuantizedQwen3;
+
+/// Сколько токенов черновая модель предлагает за шаг по умолчанию.
+pub const DEFAULT_DRAFT_TOKENS: usize = 4;
+
+/// Черновая модель и число предлагаемых за шаг токенов.
+#[derive(Clone)]
+pub struct DraftModel {
+    snapshot: LoadedModelSnapshot,
+    draft_tokens: usize,
+}
+
+impl DraftModel {
+    /// Создаёт черновую модель; `draft_tokens` не меньше одного.
+    pub fn new(snapshot: LoadedModelSnapshot, draft_tokens: usize) -> Self {
+        Self {
+            snapshot,
+            draft_tokens: draft_tokens.max(1),
+        }
+    }
+
+    /// Снимок черновой модели.
+    pub fn snapshot(&self) -> &LoadedModelSnapshot {
+        &self.snapshot
+    }
+
+    /// Число предлагаемых за шаг токенов.
+    pub fn draft_tokens(&self) -> usize {
+        self.draft_tokens
+    }
+}
+
+/// Проверяет, что у черновой и целевой модели одинаковые словари.
+pub fn check_compatible(
+    target: &LoadedModelSnapshot,
+    draft: &LoadedModelSnapshot,
+) -> std::result::Result<(), String> {
+    if target.model_type() != draft.model_type() {
+        return Err(format!(
+            "архитектура {:?} отличается от {:?}",
+            draft.model_type(),
+            target.model_type()
+        ));
+    }
+    if target.tokenizer().get_vocab(true) != draft.tokenizer().get_vocab(true) {
+        return Err("словари токенизаторов различаются".to_string());
+    }
+    Ok(())
+}
+
+/// Черновики из контекста без черновой модели (prompt lookup): полезно, когда ответ
+/// копирует фрагменты промпта — пересказ, правка кода, ответы по документам.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PromptLookup {
+    /// Наибольшая длина n-грамма, по которому ищется совпадение.
+    pub max_ngram: usize,
+    /// Сколько токенов за найденным совпадением предлагается за шаг.
+    pub draft_tokens: usize,
+}
+
+impl Default for PromptLookup {
+    fn default() -> Self {
+        Self {
+            max_ngram: 3,
+            draft_tokens: 10,
+        }
+    }
+}
+
+/// Статистика последней спекулятивной генерации.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SpeculativeStats {
+    /// Токены, предложенные черновой моделью.
+    pub drafted: usize,
+    /// Принятые из них.
+    pub accepted: usize,
+    /// Проходы целевой модели на этапе генерации.
+    pub target_passes: usize,
+    /// Выданные токены.
+    pub generated: usize,
+    /// Доля принятых токенов черновика.
+    pub acceptance_rate: f32,
+}
+
+impl SpeculativeStats {
+    fn record(&mut self, drafted: usize, accepted: usize) {
+        self.drafted += drafted;
+        self.accepted += accepted;
+        self.target_passes += 1;
+        if self.drafted > 0 {
+            self.acceptance_rate = self.accepted as f32 / self.drafted as f32;
+        }
+    }
+}
+
+/// Токен черновика и распределение, из которого он выбран;
+/// `None` — детерминированное предложение (вероятность 1).
+struct Proposal {
+    token: u32,
+    probs: Option<Vec<f32>>,
+}
+
+/// Источник черновых токенов.
+trait Drafter {
+    /// Начинает новую последовательность, все токены которой, кроме последнего, в `prefix`.
+    fn start(&mut self, prefix: &[u32]) -> Result<()>;
+    /// До `limit` токенов продолжения `context`.
+    fn propose(
+        &mut self,
+        context: &[u32],
+        limit: usize,
+        sampler: &mut Sampler,
+    ) -> Result<Vec<Proposal>>;
+    /// Проверка завершена: `sequence` — принятая последовательность без последнего
+    /// выданного токена.
+    fn accept(&mut self, sequence: &[u32]) -> Result<()>;
+}
+
+/// Черновик от небольшой модели с собственным KV-кешем.
+struct ModelDrafter<'a> {
+    model: &'a mut QuantizedQwen3,
+    draft_tokens: usize,
+}
+
+impl Drafter for ModelDrafter<'_> {
+    fn start(&mut self, prefix: &[u32]) -> Result<()> {
+        self.model.clear_kv_cache();
+        if !prefix.is_empty() {
+            let input = token_tensor(prefix, &self.model.device().clone())?;
+            self.model.forward(&input, 0)?;
+        }
+        Ok(())
+    }
+
+    fn propose(
+        &mut self,
+        context: &[u32],
+        limit: usize,
+        sampler: &mut Sampler,
+    ) -> Result<Vec<Proposal>> {
+        let device = self.model.device().clone();
+        let base = context.len() - 1;
+        let mut context = context.to_vec();
+        let mut proposal = Vec::new();
+        for i in 0..self.draft_tokens.min(limit) {
+            let input = token_tensor(&context[context.len() - 1..], &device)?;
+            let logits = self.model.forward(&input, base + i)?.squeeze(0)?;
+            let probs = sampler.probabilities(&logits_vec(&logits)?, &context);
+            let token = sampler.sample(&probs);
+            context.push(token);
+            proposal.push(Proposal {
+                token,
+                probs: Some(probs),
+            });
+        }
+        Ok(proposal)
+    }
+
+    fn accept(&mut self, sequence: &[u32]) -> Result<()> {
+        self.model.truncate_kv_cache(sequence.len())?;
+        // Токены, ещё не прошедшие через черновую модель
+        let cached = self.model.kv_len();
+        if cached < sequence.len() {
+            let input = token_tensor(&sequence[cached..], &self.model.device().clone())?;
+            self.model.forward(&input, cached)?;
+        }
+        Ok(())
+    }
+}
+
+/// Черновик из контекста (prompt lookup): последний n-грамм ищется среди более ранних
+/// токенов, и следующие за найденным вхождением токены предлагаются как продолжение.
+struct PromptLookupDrafter {
+    config: PromptLookup,
+}
+
+impl Drafter for PromptLookupDrafter {
+    fn start(&mut self, _prefix: &[u32]) -> Result<()> {
+        Ok(())
+    }
+
+    fn propose(
+        &mut self,
+        context: &[u32],
+        limit: usize,
+        _sampler: &mut Sampler,
+    ) -> Result<Vec<Proposal>> {
+        let limit = limit.min(self.config.draft_tokens);
+        Ok(lookup_continuation(context, self.config.max_ngram, limit)
+            .into_iter()
+            .map(|token| Proposal { token, probs: None })
+            .collect())
+    }
+
+    fn accept(&mut self, _sequence: &[u32]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Продолжение самого длинного (до `max_ngram`) суффикса `context`, встречавшегося раньше;
+/// при нескольких вхождениях берётся самое позднее.
+fn lookup_continuation(context: &[u32], max_ngram: usize, limit: usize) -> Vec<u32> {
+    for n in (1..=max_ngram.min(context.len().saturating_sub(1))).rev() {
+        let suffix = &context[context.len() - n..];
+        let found = (0..context.len() - n)
+            .rev()
+            .find(|&start| &context[start..start + n] == suffix);
+        if let Some(start) = found {
+            let from = start + n;
+            return context[from..(from + limit).min(context.len())].to_vec();
+        }
+    }
+    Vec::new()
+}
+
+/// Получатель токенов спекулятивной генерации.
+pub(crate) trait TokenSink {
+    /// Штрафует логиты целевой модели для токена после `context` (например, за повторы).
+    fn penalize(&self, _logits: &mut [f32], _context: &[u32]) {}
+
+    /// Принимает выданный токен; `false` останавливает генерацию.
+    fn emit(&mut self, token: u32) -> bool;
+}
+
+impl<F: FnMut(u32) -> bool> TokenSink for F {
+    fn emit(&mut self, token: u32) -> bool {
+        self(token)
+    }
+}
+
+/// Генерирует продолжение `prompt` с черновой моделью `draft`, предлагающей
+/// `draft_tokens` токенов за шаг. `sink` получает каждый выданный токен и может
+/// остановить генерацию (конец ответа или остановка). KV-кеши обеих моделей
+/// сбрасываются; длина последовательности не превышает `max_context`.
+pub(crate) fn generate(
+    target: &mut QuantizedQwen3,
+    draft: &mut QuantizedQwen3,
+    draft_tokens: usize,
+    prompt: &[u32],
+    config: &GenerationConfig,
+    max_context: usize,
+    sink: &mut impl TokenSink,
+) -> Result<SpeculativeStats> {
+    let mut drafter = ModelDrafter {
+        model: draft,
+        draft_tokens: draft_tokens.max(1),
+    };
+    run(target, &mut drafter, prompt, config, max_context, sink)
+}
+
+/// Генерирует продолжение `prompt`, предлагая черновики из уже имеющегося контекста
+/// (`config.prompt_lookup`); без совпадений шаг совпадает с обычным декодированием.
+pub(crate) fn generate_with_prompt_lookup(
+    target: &mut QuantizedQwen3,
+    lookup: PromptLookup,
+    prompt: &[u32],
+    config: &GenerationConfig,
+    max_context: usize,
+    sink: &mut impl TokenSink,
+) -> Result<SpeculativeStats> {
+    let mut drafter = PromptLookupDrafter { config: lookup };
+    run(target, &mut drafter, prompt, config, max_context, sink)
+}
+
+fn run(
+    target: &mut QuantizedQwen3,
+    drafter: &mut impl Drafter,
+    prompt: &[u32],
+    config: &GenerationConfig,
+    max_context: usize,
+    sink: &mut impl TokenSink,
+) -> Result<SpeculativeStats> {
+    let mut stats = SpeculativeStats::default();
+    let device = target.device().clone();
+    let device = &device;
+    let Some((_, prefix)) = prompt.split_last() else {
+        return Ok(stats);
+    };
+    // Кеши всегда содержат все токены, кроме последнего
+    target.clear_kv_cache();
+    if !prefix.is_empty() {
+        target.forward(&token_tensor(prefix, device)?, 0)?;
+    }
+    drafter.start(prefix)?;
+
+    let mut sampler = Sampler::new(config);
+    let mut tokens = prompt.to_vec();
+    'generation: while stats.generated < config.max_tokens && tokens.len() < max_context {
+        let base = tokens.len() - 1;
+        // Вместе с итоговым токеном шаг выдаёт не больше оставшегося лимита
+        let limit = (config.max_tokens - stats.generated).min(max_context - tokens.len()) - 1;
+        let proposal = drafter.propose(&tokens, limit, &mut sampler)?;
+        let proposed: Vec<u32> = proposal.iter().map(|draft| draft.token).collect();
+
+        // Проверка всех предложенных токенов одним проходом целевой модели
+        let mut input = vec![tokens[base]];
+        input.extend(&proposed);
+        let logits = target
+            .forward_all(&token_tensor(&input, device)?, base)?
+            .squeeze(0)?
+            .to_dtype(DType::F32)?
+            .to_vec2::<f32>()?;
+        let mut context = tokens.clone();
+        let mut correction = None;
+        for (i, draft) in proposal.iter().enumerate() {
+            let p = target_probabilities(&sampler, sink, &logits[i], &context);
+            let index = draft.token as usize;
+            let q = match &draft.probs {
+                Some(q) => q.clone(),
+                None => one_hot(p.len(), index),
+            };
+            if accepts(p[index], q[index], sampler.uniform()) {
+                context.push(draft.token);
+            } else {
+                correction = Some(sampler.sample(&residual(&p, &q)));
+                break;
+            }
+        }
+        let accepted = context.len() - tokens.len();
+        let next = match correction {
+            Some(token) => token,
+            None => {
+                let p = target_probabilities(&sampler, sink, &logits[proposal.len()], &context);
+                sampler.sample(&p)
+            }
+        };
+        stats.record(proposal.len(), accepted);
+
+        // Откат кешей к принятой части: отброшенные токены черновика не видны дальше
+        target.truncate_kv_cache(context.len())?;
+        drafter.accept(&context)?;
+
+        for &token in proposed[..accepted].iter().chain(std::iter::once(&next)) {
+            tokens.push(token);
+            if !sink.emit(token) {
+                break 'generation;
+            }
+            stats.generated += 1;
+        }
+    }
+    Ok(stats)
+}
+
+/// Правило принятия токена черновика: с вероятностью `min(1, p / q)`. Токен, которому
+/// черновик дал нулевую вероятность, отвергается и заменяется выборкой из остатка.
+fn accepts(p: f32, q: f32, uniform: f32) -> bool {
+    q > 0.0 && uniform < p / q
+}
+
+/// Распределение целевой модели после штрафов `sink`.
+fn target_probabilities(
+    sampler: &Sampler,
+    sink: &impl TokenSink,
+    logits: &[f32],
+    context: &[u32],
+) -> Vec<f32> {
+    let mut logits = logits.to_vec();
+    sink.penalize(&mut logits, context);
+    sampler.probabilities(&logits, context)
+}
+
+fn one_hot(len: usize, index: usize) -> Vec<f32> {
+    let mut probs = vec![0.0; len];
+    probs[index] = 1.0;
+    probs
+}
+
+fn token_tensor(tokens: &[u32], device: &Device) -> Result<Tensor> {
+    Tensor::new(tokens, device)?.unsqueeze(0)
+}
+
+fn logits_vec(logits: &Tensor) -> Result<Vec<f32>> {
+    logits.to_dtype(DType::F32)?.to_vec1::<f32>()
+}
+
+/// Нормированное `max(0, p - q)`; если разность нулевая, возвращается `p`.
+fn residual(p: &[f32], q: &[f32]) -> Vec<f32> {
+    let diff: Vec<f32> = p.iter().zip(q).map(|(p, q)| (p - q).max(0.0)).collect();
+    let total: f32 = diff.iter().sum();
+    if total <= 0.0 {
+        return p.to_vec();
+    }
+    diff.into_iter().map(|value| value / total).collect()
+}
+
+/// Выборка токенов с теми же штрафом за повтор, температурой и top-p, что и у
+/// `LogitsProcessor`, но с явными вероятностями для правила принятия.
+struct Sampler {
+    state: u64,
+    temperature: f32,
+    top_p: f32,
+    repeat_penalty: f32,
+}
+
+impl Sampler {
+    fn new(config: &GenerationConfig) -> Self {
+        Self {
+            state: config.seed,
+            temperature: config.temperature,
+            top_p: config.top_p,
+            repeat_penalty: config.repeat_penalty,
+        }
+    }
+
+    /// Распределение следующего токена после `context`.
+    fn probabilities(&self, logits: &[f32], context: &[u32]) -> Vec<f32> {
+        let mut logits = logits.to_vec();
+        if self.repeat_penalty != 1.0 {
+            let seen: HashSet<u32> = context.iter().copied().collect();
+            for token in seen {
+                if let Some(logit) = logits.get_mut(token as usize) {
+                    if *logit >= 0.0 {
+                        *logit /= self.repeat_penalty;
+                    } else {
+                        *logit *= self.repeat_penalty;
+                    }
+                }
+            }
+        }
+
+        if self.temperature < 1e-7 {
+            let best = logits
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(index, _)| index)
+                .unwrap_or(0);
+            return one_hot(logits.len(), best);
+        }
+
+        let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let mut probs: Vec<f32> = logits
+            .iter()
+            .map(|logit| ((logit - max) / self.temperature).exp())
+            .collect();
+        normalize(&mut probs);
+        if self.top_p > 0.0 && self.top_p < 1.0 {
+            let mut order: Vec<usize> = (0..probs.len()).collect();
+            order.sort_by(|&a, &b| probs[b].total_cmp(&probs[a]));
+            let mut cumulative = 0.0;
+            for index in order {
+                if cumulative >= self.top_p {
+                    probs[index] = 0.0;
+                } else {
+                    cumulative += probs[index];
+                }
+            }
+            normalize(&mut probs);
+        }
+        probs
+    }
+
+    fn sample(&mut self, probs: &[f32]) -> u32 {
+        let target = self.uniform() * probs.iter().sum::<f32>();
+        let mut cumulative = 0.0;
+        let mut last = 0;
+        for (index, &prob) in probs.iter().enumerate() {
+            if prob <= 0.0 {
+                continue;
+            }
+            cumulative += prob;
+            last = index;
+            if target < cumulative {
+                break;
+            }
+        }
+        last as u32
+    }
+
+    /// Равномерное число из `[0, 1)` (SplitMix64).
+    fn uniform(&mut self) -> f32 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+fn normalize(probs: &mut [f32]) {
+    let total: f32 = probs.iter().sum();
+    if total > 0.0 {
+        probs.iter_mut().for_each(|prob| *prob /= total);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::write_qwen3_gguf;
+    use candle_core::quantized::gguf_file;
+    use std::io::Cursor;
+
+    fn load_model(dir: &std::path::Path, seed: u32) -> QuantizedQwen3 {
+        let path = dir.join(format!("qwen3-{seed}.gguf"));
+        write_qwen3_gguf(&path, seed);
+        let mut reader = Cursor::new(std::fs::read(&path).unwrap());
+        let content = gguf_file::Content::read(&mut reader).unwrap();
+        QuantizedQwen3::from_gguf(content, &mut reader, &Device::Cpu).unwrap()
+    }
+
+    fn greedy_reference(model: &mut QuantizedQwen3, prompt: &[u32], count: usize) -> Vec<u32> {
+        let mut tokens = prompt.to_vec();
+        let logits = model
+            .forward(&token_tensor(prompt, &Device::Cpu).unwrap(), 0)
+            .unwrap();
+        let mut next = logits
+            .squeeze(0)
+            .unwrap()
+            .argmax(0)
+            .unwrap()
+            .to_scalar::<u32>();
+        for _ in 0..count {
+            let token = next.unwrap();
+            tokens.push(token);
+            let input = token_tensor(&[token], &Device::Cpu).unwrap();
+            let logits = model.forward(&input, tokens.len() - 1).unwrap();
+            next = logits
+                .squeeze(0)
+                .unwrap()
+                .argmax(0)
+                .unwrap()
+                .to_scalar::<u32>();
+        }
+        tokens.split_off(prompt.len())
+    }
+
+    #[test]
+    fn test_greedy_output_matches_target_model() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut target = load_model(tmp.path(), 0);
+        let mut draft = load_model(tmp.path(), 1);
+        let config = GenerationConfig {
+            max_tokens: 10,
+            temperature: 0.0,
+            repeat_penalty: 1.0,
+            ..GenerationConfig::default()
+        };
+        let prompt = [1u32, 4, 2];
+        let expected = greedy_reference(&mut target.clone(), &prompt, 10);
+
+        let mut output = Vec::new();
+        let stats = generate(
+            &mut target,
+            &mut draft,
+            3,
+            &prompt,
+            &config,
+            2048,
+            &mut |token| {
+                output.push(token);
+                true
+            },
+        )
+        .unwrap();
+        assert_eq!(output, expected);
+        assert_eq!(stats.generated, 10);
+
+        // Токен, на котором получатель остановил генерацию, не считается выданным
+        let mut emitted = 0;
+        let stats = generate(
+            &mut target,
+            &mut draft,
+            3,
+            &prompt,
+            &config,
+            2048,
+            &mut |_| {
+                emitted += 1;
+                emitted < 4
+            },
+        )
+        .unwrap();
+        assert_eq!(stats.generated, 3);
+
+        // Черновик, совпадающий с целевой моделью, принимается целиком
+        let mut same = target.clone();
+        let stats = generate(
+            &mut target,
+            &mut same,
+            4,
+            &prompt,
+            &config,
+            2048,
+            &mut |_| true,
+        )
+        .unwrap();
+        assert_eq!(stats.acceptance_rate, 1.0);
+        assert_eq!(stats.target_passes, 2);
+
+        // Черновики из контекста тоже не меняют результат
+        let prompt = [1u32, 4, 2, 3, 1, 4];
+        let expected = greedy_reference(&mut target.clone(), &prompt, 10);
+        let mut output = Vec::new();
+        let lookup = PromptLookup::default();
+        let stats =
+            generate_with_prompt_lookup(&mut target, lookup, &prompt, &config, 2048, &mut |t| {
+                output.push(t);
+                true
+            })
+            .unwrap();
+        assert_eq!(output, expected);
+        assert!(stats.drafted > 0);
+    }
+
+    #[test]
+    fn test_lookup_continuation_prefers_longest_recent_match() {
+        let context = [7, 1, 2, 9, 5, 1, 2, 3, 4, 8, 2, 3];
+        // Суффикс [2, 3] встречался раньше: предлагается то, что шло за ним
+        assert_eq!(lookup_continuation(&context, 3, 2), vec![4, 8]);
+        assert_eq!(lookup_continuation(&context, 3, 10), vec![4, 8, 2, 3]);
+        assert_eq!(lookup_continuation(&[1, 2, 3], 3, 4), Vec::<u32>::new());
+        assert_eq!(lookup_continuation(&[5, 6, 5], 1, 4), vec![6, 5]);
+    }
+
+    #[test]
+    fn test_residual_and_sampler_distribution() {
+        let p = [0.5, 0.3, 0.2];
+        let q = [0.7, 0.1, 0.2];
+        let r = residual(&p, &q);
+        assert!((r[1] - 1.0).abs() < 1e-6);
+        assert_eq!(residual(&p, &p), p.to_vec());
+        assert!(accepts(0.3, 0.6, 0.4));
+        assert!(!accepts(0.3, 0.6, 0.6));
+        // Нулевая вероятность черновика — отказ, а не деление на ноль
+        assert!(!accepts(0.3, 0.0, 0.0));
+
+        let config = GenerationConfig {
+            temperature: 1.0,
+            top_p: 0.6,
+            repeat_penalty: 1.0,
+            ..GenerationConfig::default()
+        };
+        let mut sampler = Sampler::new(&config);
+        let logits = [2.0f32.ln(), 1.0f32.ln(), 1.0f32.ln()];
+        // top-p оставляет самый вероятный токен и следующий за ним
+        let probs = sampler.probabilities(&logits, &[]);
+        assert!((probs[0] - 2.0 / 3.0).abs() < 1e-5);
+        assert!((probs[1] - 1.0 / 3.0).abs() < 1e-5);
+        assert_eq!(probs[2], 0.0);
+        let hits = (0..3000).filter(|_| sampler.sample(&probs) == 0).count();
+        assert!((1850..2150).contains(&hits), "{hits}");
+    }
+}
@@ -0,0 +1,275 @@
+//! Общие вспомогательные функции для тестов: генерация небольших GGUF файлов
+//! и чекпойнтов safetensors.
+
+use std::path::{Path, PathBuf};
+
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::{DType, Device, Tensor};
+
+/// Записывает GGUF файл с указанными метаданными и F32-тензорами заданной формы.
+pub fn write_test_gguf(
+    path: &Path,
+    metadata: &[(String, gguf_file::Value)],
+    tensors: &[(&str, &[usize])],
+) {
+    write_filled_gguf(path, metadata, tensors, 0.0);
+}
+
+/// Записывает разбитую на части модель `<prefix>-0000N-of-0000M.gguf`.
+/// Тензоры части N заполнены значением N; возвращает пути частей по порядку.
+pub fn write_split_gguf(dir: &Path, prefix: &str, shards: &[&[(&str, &[usize])]]) -> Vec<PathBuf> {
+    let count = shards.len() as u32;
+    let tensor_count: usize = shards.iter().map(|tensors| tensors.len()).sum();
+    shards
+        .iter()
+        .enumerate()
+        .map(|(index, tensors)| {
+            let mut metadata = if index == 0 {
+                basic_metadata("qwen3")
+            } else {
+                Vec::new()
+            };
+            metadata.push(("split.no".into(), gguf_file::Value::U16(index as u16)));
+            metadata.push(("split.count".into(), gguf_file::Value::U16(count as u16)));
+            metadata.push((
+                "split.tensors.count".into(),
+                gguf_file::Value::I32(tensor_count as i32),
+            ));
+            let path = dir.join(crate::gguf_split::shard_file_name(
+                prefix,
+                index as u32 + 1,
+                count,
+            ));
+            write_filled_gguf(&path, &metadata, tensors, (index + 1) as f32);
+            path
+        })
+        .collect()
+}
+
+fn write_filled_gguf(
+    path: &Path,
+    metadata: &[(String, gguf_file::Value)],
+    tensors: &[(&str, &[usize])],
+    value: f32,
+) {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).unwrap();
+    }
+    let qtensors: Vec<(&str, QTensor)> = tensors
+        .iter()
+        .map(|(name, shape)| {
+            let tensor = Tensor::full(value, *shape, &Device::Cpu).unwrap();
+            (*name, QTensor::quantize(&tensor, GgmlDType::F32).unwrap())
+        })
+        .collect();
+
+    let metadata_refs: Vec<(&str, &gguf_file::Value)> =
+        metadata.iter().map(|(k, v)| (k.as_str(), v)).collect();
+    let tensor_refs: Vec<(&str, &QTensor)> = qtensors.iter().map(|(k, v)| (*k, v)).collect();
+
+    let mut file = std::fs::File::create(path).unwrap();
+    gguf_file::write(&mut file, &metadata_refs, &tensor_refs).unwrap();
+}
+
+/// Метаданные минимальной модели заданной архитектуры.
+pub fn basic_metadata(architecture: &str) -> Vec<(String, gguf_file::Value)> {
+    vec![
+        (
+            "general.architecture".into(),
+            gguf_file::Value::String(architecture.to_string()),
+        ),
+        (
+            "general.name".into(),
+            gguf_file::Value::String("Test Model".into()),
+        ),
+        (
+            "general.license".into(),
+            gguf_file::Value::String("apache-2.0".into()),
+        ),
+        (
+            format!("{architecture}.context_length"),
+            gguf_file::Value::U32(4096),
+        ),
+    ]
+}
+
+fn ollama_digest(byte: char) -> String {
+    byte.to_string().repeat(64)
+}
+
+/// Создаёт хранилище Ollama с одной моделью `qwen3:0.6b`; возвращает путь к блобу весов.
+pub fn write_ollama_store(root: &Path) -> PathBuf {
+    let blobs = root.join("blobs");
+    let model_blob = blobs.join(format!("sha256-{}", ollama_digest('a')));
+    write_test_gguf(&model_blob, &basic_metadata("qwen3"), &[("w", &[4])]);
+    std::fs::write(
+        blobs.join(format!("sha256-{}", ollama_digest('b'))),
+        "{{ .Prompt }}",
+    )
+    .unwrap();
+    std::fs::write(
+        blobs.join(format!("sha256-{}", ollama_digest('c'))),
+        r#"{"stop": ["<|im_end|>"], "temperature": 0.6}"#,
+    )
+    .unwrap();
+
+    let manifest_dir = root.join("manifests/registry.ollama.ai/library/qwen3");
+    std::fs::create_dir_all(&manifest_dir).unwrap();
+    let layer = |media_type: &str, byte: char| {
+        serde_json::json!({
+            "mediaType": format!("application/vnd.ollama.image.{media_type}"),
+            "digest": format!("sha256:{}", ollama_digest(byte)),
+            "size": 0,
+        })
+    };
+    let manifest = serde_json::json!({
+        "schemaVersion": 2,
+        "layers": [layer("model", 'a'), layer("template", 'b'), layer("params", 'c')],
+    });
+    std::fs::write(manifest_dir.join("0.6b"), manifest.to_string()).unwrap();
+    // Манифест без блоба весов пропускается
+    let broken = serde_json::json!({"layers": [layer("model", 'd')]});
+    std::fs::write(manifest_dir.join("broken"), broken.to_string()).unwrap();
+    model_blob
+}
+
+/// Крошечная модель Qwen3 в формате Hugging Face: `config.json`, `tokenizer.json`
+/// и `model.safetensors` с весами из единиц в BF16.
+pub fn write_qwen3_safetensors(dir: &Path) {
+    std::fs::create_dir_all(dir).unwrap();
+    let config = serde_json::json!({
+        "model_type": "qwen3",
+        "vocab_size": 8,
+        "hidden_size": 8,
+        "intermediate_size": 16,
+        "num_hidden_layers": 1,
+        "num_attention_heads": 2,
+        "num_key_value_heads": 1,
+        "head_dim": 4,
+        "max_position_embeddings": 32,
+        "rms_norm_eps": 1e-6,
+        "rope_theta": 10000.0,
+        "tie_word_embeddings": true,
+        "torch_dtype": "bfloat16",
+    });
+    std::fs::write(dir.join("config.json"), config.to_string()).unwrap();
+    let tokenizer = serde_json::json!({
+        "version": "1.0",
+        "added_tokens": [],
+        "pre_tokenizer": {"type": "Whitespace"},
+        "model": {"type": "WordLevel", "vocab": {"[UNK]": 0, "hello": 1}, "unk_token": "[UNK]"},
+    });
+    std::fs::write(dir.join("tokenizer.json"), tokenizer.to_string()).unwrap();
+
+    let ones = |shape: &[usize]| Tensor::ones(shape, DType::BF16, &Device::Cpu).unwrap();
+    let prefix = "model.layers.0";
+    let tensors = [
+        ("model.embed_tokens.weight".to_string(), ones(&[8, 8])),
+        ("model.norm.weight".to_string(), ones(&[8])),
+        (format!("{prefix}.input_layernorm.weight"), ones(&[8])),
+        (
+            format!("{prefix}.post_attention_layernorm.weight"),
+            ones(&[8]),
+        ),
+        (format!("{prefix}.self_attn.q_proj.weight"), ones(&[8, 8])),
+        (format!("{prefix}.self_attn.k_proj.weight"), ones(&[4, 8])),
+        (format!("{prefix}.self_attn.v_proj.weight"), ones(&[4, 8])),
+        (format!("{prefix}.self_attn.o_proj.weight"), ones(&[8, 8])),
+        (format!("{prefix}.self_attn.q_norm.weight"), ones(&[4])),
+        (format!("{prefix}.self_attn.k_norm.weight"), ones(&[4])),
+        (format!("{prefix}.mlp.gate_proj.weight"), ones(&[16, 8])),
+        (format!("{prefix}.mlp.up_proj.weight"), ones(&[16, 8])),
+        (format!("{prefix}.mlp.down_proj.weight"), ones(&[8, 16])),
+    ];
+    candle_core::safetensors::save(
+        &tensors.into_iter().collect(),
+        dir.join("model.safetensors"),
+    )
+    .unwrap();
+}
+
+/// Крошечная квантованная Qwen3 (словарь из 8 токенов, один слой) с различающимися
+/// весами и `tokenizer.json` рядом с файлом. Разные `seed` дают разные модели с общим
+/// токенизатором.
+pub fn write_qwen3_gguf(path: &Path, seed: u32) {
+    let dir = path.parent().unwrap();
+    std::fs::create_dir_all(dir).unwrap();
+    let words = ["[UNK]", "hello", "a", "b", "c", "d", "e", "f"];
+    let vocab: serde_json::Map<String, serde_json::Value> = words
+        .iter()
+        .enumerate()
+        .map(|(id, word)| (word.to_string(), id.into()))
+        .collect();
+    let tokenizer = serde_json::json!({
+        "version": "1.0",
+        "added_tokens": [],
+        "pre_tokenizer": {"type": "Whitespace"},
+        "model": {"type": "WordLevel", "vocab": vocab, "unk_token": "[UNK]"},
+        "decoder": {"type": "WordPiece", "prefix": "##", "cleanup": false},
+    });
+    std::fs::write(dir.join("tokenizer.json"), tokenizer.to_string()).unwrap();
+
+    let mut metadata = basic_metadata("qwen3");
+    for (key, value) in [
+        ("attention.head_count", 2),
+        ("attention.head_count_kv", 1),
+        ("attention.key_length", 4),
+        ("block_count", 1),
+        ("embedding_length", 8),
+    ] {
+        metadata.push((format!("qwen3.{key}"), gguf_file::Value::U32(value)));
+    }
+    metadata.push((
+        "qwen3.attention.layer_norm_rms_epsilon".into(),
+        gguf_file::Value::F32(1e-6),
+    ));
+    metadata.push(("qwen3.rope.freq_base".into(), gguf_file::Value::F32(10000.)));
+
+    let layer = |name: &str| format!("blk.0.{name}");
+    let tensors = [
+        ("token_embd.weight".to_string(), vec![8, 8]),
+        ("output_norm.weight".to_string(), vec![8]),
+        (layer("attn_norm.weight"), vec![8]),
+        (layer("ffn_norm.weight"), vec![8]),
+        (layer("attn_q.weight"), vec![8, 8]),
+        (layer("attn_k.weight"), vec![4, 8]),
+        (layer("attn_v.weight"), vec![4, 8]),
+        (layer("attn_output.weight"), vec![8, 8]),
+        (layer("attn_q_norm.weight"), vec![4]),
+        (layer("attn_k_norm.weight"), vec![4]),
+        (layer("ffn_gate.weight"), vec![16, 8]),
+        (layer("ffn_up.weight"), vec![16, 8]),
+        (layer("ffn_down.weight"), vec![8, 16]),
+    ];
+    let qtensors: Vec<(&str, QTensor)> = tensors
+        .iter()
+        .enumerate()
+        .map(|(index, (name, shape))| {
+            let len: usize = shape.iter().product();
+            // Детерминированные псевдослучайные веса; нормы — около единицы
+            let values: Vec<f32> = (0..len)
+                .map(|i| {
+                    let x =
+                        ((i as u32 + 1) * 7919 + (index as u32 + 1) * 104_729 + seed * 31) as f32;
+                    let noise = (x * 0.618).sin();
+                    if shape.len() == 1 {
+                        1.0 + 0.1 * noise
+                    } else {
+                        noise
+                    }
+                })
+                .collect();
+            let tensor = Tensor::from_vec(values, shape.as_slice(), &Device::Cpu).unwrap();
+            (
+                name.as_str(),
+                QTensor::quantize(&tensor, GgmlDType::F32).unwrap(),
+            )
+        })
+        .collect();
+
+    let metadata_refs: Vec<(&str, &gguf_file::Value)> =
+        metadata.iter().map(|(k, v)| (k.as_str(), v)).collect();
+    let tensor_refs: Vec<(&str, &QTensor)> = qtensors.iter().map(|(k, v)| (*k, v)).collect();
+    let mut file = std::fs::File::create(path).unwrap();
+    gguf_file::write(&mut file, &metadata_refs, &tensor_refs).unwrap();
+}
@@ -1,33 +1,43 @@
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::chatbot::ChatBot;
-    use std::path::PathBuf;
+#![cfg(test)]
 
-    #[test]
-    fn test_chatbot_creation() {
-        let bot = ChatBot::new();
-        assert!(bot.process_message("test").contains("Processed with Candle"));
-    }
+use crate::chatbot::ChatBot;
+use crate::model_download::{DownloadRequest, StdHttpClient};
+use crate::model_manager::{ModelManager, ModelManagerError};
+use crate::model_store::ModelId;
 
-    #[test]
-    fn test_safe_download_model() {
-        // This test would require network access and proper cache directory
-        // For now, we'll just test that the function doesn't panic
-        let result = std::panic::catch_unwind(|| {
-            let bot = ChatBot::new();
-            bot.download_qwen3_06b_gguf()
-        });
-        
-        // The function should not panic, even if it fails due to network issues
-        assert!(result.is_ok());
-    }
+#[test]
+fn test_chatbot_creation() {
+    let bot = ChatBot::default();
+    assert!(!bot.is_model_loaded());
+}
+
+#[test]
+fn test_safe_download_model() {
+    // Недоступное зеркало: загрузка завершается ошибкой, а не паникой,
+    // и в хранилище не появляется модель
+    let tmp = tempfile::tempdir().unwrap();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mirror = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let manager = ModelManager::new(tmp.path(), candle_core::Device::Cpu);
+    manager.set_download_mirrors(vec![mirror]);
+    let request = DownloadRequest {
+        repo_id: "Qwen/Qwen3-0.6B-GGUF".to_string(),
+        revision: None,
+        file_name: "Qwen3-0.6B-Q8_0.gguf".to_string(),
+        model_id: ModelId::parse("qwen3/0.6b").unwrap(),
+        expected_sha256: None,
+    };
+    let result = manager.download_model(&request, &StdHttpClient::default(), None);
+
+    assert!(matches!(result, Err(ModelManagerError::Download(_))));
+    assert!(!tmp.path().join("qwen3").join("0.6b").exists());
+}
 
-    #[test]
-    fn test_path_validation() {
-        let valid_path = PathBuf::from("/tmp/test_cache");
-        let empty_path = PathBuf::from("");
-        
-        assert!(!empty_path.to_string_lossy().is_empty() || empty_path.to_string_lossy() == "");
-    }
+#[test]
+fn test_path_validation() {
+    assert!(ModelId::parse("qwen3/0.6b").is_ok());
+    assert!(ModelId::parse("").is_err());
+    assert!(ModelId::parse("../cache").is_err());
 }
@@ -0,0 +1,221 @@
+//! Выбор токенизатора и chat template для загружаемой модели.
+//! Порядок поиска: явно заданный путь, файлы `tokenizer.json`/`tokenizer_config.json`
+//! рядом с моделью, затем метаданные GGUF. Конвейер файла токенизатора не изменяется.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use candle_core::quantized::gguf_file;
+use serde::Serialize;
+use tokenizers::Tokenizer;
+
+use crate::gguf_tokenizer::tokenizer_from_metadata;
+use crate::model_manager::{chat_template_from_metadata, ModelManagerError};
+
+/// Имя файла токенизатора Hugging Face.
+pub const TOKENIZER_FILE_NAME: &str = "tokenizer.json";
+
+/// Имя файла конфигурации токенизатора с chat template.
+pub const TOKENIZER_CONFIG_FILE_NAME: &str = "tokenizer_config.json";
+
+/// Откуда был взят токенизатор.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenizerSource {
+    /// Путь, заданный пользователем.
+    Override(PathBuf),
+    /// `tokenizer.json` рядом с файлом модели.
+    Sidecar(PathBuf),
+    /// Словарь, встроенный в GGUF.
+    GgufMetadata,
+}
+
+/// Токенизатор и chat template, выбранные для модели.
+pub struct ResolvedTokenizer {
+    pub tokenizer: Tokenizer,
+    pub chat_template: Option<String>,
+    pub source: TokenizerSource,
+}
+
+/// Выбирает токенизатор для модели `model_path`; без пути (дескриптор или буфер)
+/// файлы рядом с моделью не ищутся.
+/// `override_path` может указывать на `tokenizer.json` или на содержащую его директорию.
+/// Chat template берётся из `tokenizer_config.json` рядом с токенизатором или моделью,
+/// а при его отсутствии — из метаданных GGUF.
+pub fn resolve_tokenizer(
+    model_path: Option<&Path>,
+    override_path: Option<&Path>,
+    metadata: &HashMap<String, gguf_file::Value>,
+) -> Result<ResolvedTokenizer, ModelManagerError> {
+    let model_dir = model_path.and_then(Path::parent).map(Path::to_path_buf);
+    let sidecar = model_dir
+        .as_ref()
+        .map(|dir| dir.join(TOKENIZER_FILE_NAME))
+        .filter(|path| path.is_file());
+
+    let (tokenizer, source) = match (override_path, sidecar) {
+        (Some(override_path), _) => {
+            let file = if override_path.is_dir() {
+                override_path.join(TOKENIZER_FILE_NAME)
+            } else {
+                override_path.to_path_buf()
+            };
+            if !file.is_file() {
+                return Err(ModelManagerError::TokenizerMissing(
+                    file.to_string_lossy().into_owned(),
+                ));
+            }
+            (load_tokenizer_file(&file)?, TokenizerSource::Override(file))
+        }
+        (None, Some(sidecar)) => (
+            load_tokenizer_file(&sidecar)?,
+            TokenizerSource::Sidecar(sidecar),
+        ),
+        (None, None) => (
+            tokenizer_from_metadata(metadata)?,
+            TokenizerSource::GgufMetadata,
+        ),
+    };
+    log::info!("Tokenizer source: {:?}", source);
+
+    let override_dir = match &source {
+        TokenizerSource::Override(file) => file.parent().map(Path::to_path_buf),
+        _ => None,
+    };
+    let chat_template = override_dir
+        .iter()
+        .chain(model_dir.iter())
+        .find_map(|dir| chat_template_from_config(&dir.join(TOKENIZER_CONFIG_FILE_NAME)))
+        .or_else(|| chat_template_from_metadata(metadata));
+
+    Ok(ResolvedTokenizer {
+        tokenizer,
+        chat_template,
+        source,
+    })
+}
+
+fn load_tokenizer_file(path: &Path) -> Result<Tokenizer, ModelManagerError> {
+    Tokenizer::from_file(path).map_err(|e| {
+        ModelManagerError::Initialization(format!("{}: {}", path.to_string_lossy(), e))
+    })
+}
+
+/// Читает `chat_template` из `tokenizer_config.json`.
+/// Поддерживается как строка, так и список именованных шаблонов (выбирается `default`).
+pub(crate) fn chat_template_from_config(path: &Path) -> Option<String> {
+    let data = std::fs::read(path).ok()?;
+    let config: serde_json::Value = match serde_json::from_slice(&data) {
+        Ok(config) => config,
+        Err(err) => {
+            log::warn!("Не удалось разобрать {:?}: {}", path, err);
+            return None;
+        }
+    };
+    match config.get("chat_template")? {
+        serde_json::Value::String(template) => Some(template.clone()),
+        serde_json::Value::Array(templates) => templates
+            .iter()
+            .find(|entry| entry.get("name").and_then(|name| name.as_str()) == Some("default"))
+            .or_else(|| templates.first())
+            .and_then(|entry| entry.get("template"))
+            .and_then(|template| template.as_str())
+            .map(str::to_string),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    /// Минимальный `tokenizer.json` с собственным пре-токенизатором.
+    fn write_tokenizer(path: &Path, word: &str) {
+        let tokenizer = json!({
+            "version": "1.0",
+            "added_tokens": [],
+            "pre_tokenizer": {"type": "Whitespace"},
+            "model": {
+                "type": "WordLevel",
+                "vocab": {"[UNK]": 0, word: 1},
+                "unk_token": "[UNK]",
+            },
+        });
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, tokenizer.to_string()).unwrap();
+    }
+
+    fn gguf_metadata() -> HashMap<String, gguf_file::Value> {
+        HashMap::from([(
+            "tokenizer.chat_template".to_string(),
+            gguf_file::Value::String("gguf template".into()),
+        )])
+    }
+
+    #[test]
+    fn test_sidecar_keeps_pipeline_and_config_template() {
+        let tmp = tempdir().unwrap();
+        let model_path = tmp.path().join("model.gguf");
+        write_tokenizer(&tmp.path().join(TOKENIZER_FILE_NAME), "sidecar");
+        std::fs::write(
+            tmp.path().join(TOKENIZER_CONFIG_FILE_NAME),
+            json!({"chat_template": [
+                {"name": "tool_use", "template": "tools"},
+                {"name": "default", "template": "config template"},
+            ]})
+            .to_string(),
+        )
+        .unwrap();
+
+        let resolved = resolve_tokenizer(Some(&model_path), None, &gguf_metadata()).unwrap();
+        assert!(matches!(resolved.source, TokenizerSource::Sidecar(_)));
+        assert_eq!(resolved.chat_template.as_deref(), Some("config template"));
+        // Пре-токенизатор файла сохранён: слова разделяются по пробелам
+        let encoding = resolved.tokenizer.encode("sidecar sidecar", false).unwrap();
+        assert_eq!(encoding.get_ids(), &[1, 1]);
+    }
+
+    #[test]
+    fn test_override_takes_precedence() {
+        let tmp = tempdir().unwrap();
+        let model_path = tmp.path().join("model").join("model.gguf");
+        write_tokenizer(
+            &tmp.path().join("model").join(TOKENIZER_FILE_NAME),
+            "sidecar",
+        );
+        let override_dir = tmp.path().join("override");
+        write_tokenizer(&override_dir.join(TOKENIZER_FILE_NAME), "override");
+
+        let resolved =
+            resolve_tokenizer(Some(&model_path), Some(&override_dir), &gguf_metadata()).unwrap();
+        assert_eq!(
+            resolved.source,
+            TokenizerSource::Override(override_dir.join(TOKENIZER_FILE_NAME))
+        );
+        assert_eq!(resolved.tokenizer.token_to_id("override"), Some(1));
+        assert_eq!(resolved.chat_template.as_deref(), Some("gguf template"));
+
+        let missing = resolve_tokenizer(
+            Some(&model_path),
+            Some(&tmp.path().join("absent.json")),
+            &gguf_metadata(),
+        );
+        assert!(matches!(
+            missing,
+            Err(ModelManagerError::TokenizerMissing(_))
+        ));
+    }
+
+    #[test]
+    fn test_falls_back_to_gguf_metadata() {
+        let tmp = tempdir().unwrap();
+        let result =
+            resolve_tokenizer(Some(&tmp.path().join("model.gguf")), None, &gguf_metadata());
+        assert!(matches!(
+            result,
+            Err(ModelManagerError::TokenizerMissing(_))
+        ));
+    }
+}